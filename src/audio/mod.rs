@@ -0,0 +1,108 @@
+use std::{io::Write, path::Path};
+
+use rayon::prelude::*;
+
+use crate::{
+    cli::AxisRange,
+    error::{Error, Result},
+    img,
+    io::atomic::AtomicFile,
+    node::ast::NodeAst,
+};
+
+/// The settings of how the red tree is turned into sound
+#[derive(Clone, Debug)]
+pub struct WavSettings {
+    /// The length of the sound in seconds
+    pub duration: f64,
+    /// The samples per second
+    pub sample_rate: u32,
+    /// The loudest a sample can be, from 0 to 1
+    pub amplitude: f64,
+    /// The range x sweeps over, from the start to the end of the sound
+    pub x_range: AxisRange,
+    /// The value of y, which stays the same
+    pub y: f64,
+    /// The value of t, which stays the same
+    pub t: f64,
+}
+
+/// Saves the red tree as a 16-bit mono WAV. See `get_samples()`
+pub fn gen_wav(path: &Path, settings: &WavSettings, ast: &NodeAst) -> Result<()> {
+    let pcm = to_pcm(get_samples(settings, ast), settings.amplitude);
+    let result = AtomicFile::create(path).and_then(|mut file| {
+        write_wav(&mut file, settings.sample_rate, &pcm)?;
+        file.commit()
+    });
+
+    result.map_err(|e| {
+        Error::Io(format!(
+            "Failed to write sound to {:?}.\nDetails: {}",
+            path, e
+        ))
+    })
+}
+
+/// Evaluates the red tree once for every sample, with x sweeping over its range while y and t
+/// stay the same
+fn get_samples(settings: &WavSettings, ast: &NodeAst) -> Vec<f64> {
+    let program = ast.compile();
+    let count = (settings.duration * settings.sample_rate as f64).round() as usize;
+    let sample = |i: usize| {
+        let x = settings.x_range.at(i as f64 / count as f64);
+        program.get_values(x, settings.y, settings.t)[0]
+    };
+
+    // Like the pixels of an image, samples using legacy rand are evaluated in order
+    match img::uses_rand(ast) {
+        true => (0..count).map(sample).collect(),
+        false => (0..count).into_par_iter().map(sample).collect(),
+    }
+}
+
+/// Converts the samples into 16-bit PCM. The average of the samples is subtracted first, so the
+/// sound has no DC offset, and the samples are then clamped to -1..1 and scaled by `amplitude`.
+/// NaN is silent
+fn to_pcm(samples: Vec<f64>, amplitude: f64) -> Vec<i16> {
+    let finite = samples.iter().filter(|x| x.is_finite());
+    let count = finite.clone().count();
+    let offset = match count {
+        0 => 0.,
+        count => finite.sum::<f64>() / count as f64,
+    };
+
+    samples
+        .into_iter()
+        .map(|x| match x.is_nan() {
+            true => 0,
+            false => ((x - offset).clamp(-1., 1.) * amplitude * i16::MAX as f64).round() as i16,
+        })
+        .collect()
+}
+
+/// Writes the samples as a mono PCM WAV with 16 bits per sample
+fn write_wav(writer: &mut impl Write, sample_rate: u32, pcm: &[i16]) -> std::io::Result<()> {
+    const CHANNELS: u16 = 1;
+    const BITS: u16 = 16;
+    let block_align = CHANNELS * BITS / 8;
+    let data_len = (pcm.len() * block_align as usize) as u32;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    // PCM
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&CHANNELS.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&BITS.to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    let bytes: Vec<u8> = pcm.iter().flat_map(|x| x.to_le_bytes()).collect();
+    writer.write_all(&bytes)
+}
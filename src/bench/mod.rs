@@ -0,0 +1,103 @@
+use std::{fmt::Display, time::Duration};
+
+/// The timings of one phase of a render, over every iteration of `--bench`
+#[derive(Clone, Debug, Default)]
+pub struct Phase {
+    pub times: Vec<Duration>,
+}
+
+impl Phase {
+    /// Gets the fastest time of the phase
+    pub fn min(&self) -> Duration {
+        self.times.iter().min().copied().unwrap_or_default()
+    }
+
+    /// Gets the average time of the phase
+    pub fn mean(&self) -> Duration {
+        match self.times.len() {
+            0 => Duration::ZERO,
+            len => self.times.iter().sum::<Duration>() / len as u32,
+        }
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"min_ms\": {}, \"mean_ms\": {}}}",
+            millis(self.min()),
+            millis(self.mean())
+        )
+    }
+}
+
+/// The results of `--bench`
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// The amount of pixels in a frame
+    pub pixels: u64,
+    /// The amount of frames rendered in each iteration
+    pub frames: u32,
+    /// Creating the AST, from the grammar or by parsing it
+    pub generation: Phase,
+    /// Evaluating the AST for every pixel
+    pub evaluation: Phase,
+    /// Encoding the rendered frames into an image, without writing it anywhere
+    pub encoding: Phase,
+}
+
+impl Report {
+    /// Gets the pixels evaluated per second, from the fastest and the average evaluation
+    pub fn pixels_per_second(&self) -> (f64, f64) {
+        let pixels = (self.pixels * self.frames as u64) as f64;
+        (
+            pixels / self.evaluation.min().as_secs_f64(),
+            pixels / self.evaluation.mean().as_secs_f64(),
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        let (best, mean) = self.pixels_per_second();
+        format!(
+            "{{\"iterations\": {}, \"pixels\": {}, \"frames\": {}, \"generation\": {}, \"evaluation\": {}, \"encoding\": {}, \"pixels_per_second\": {{\"best\": {:.0}, \"mean\": {:.0}}}}}",
+            self.evaluation.times.len(),
+            self.pixels,
+            self.frames,
+            self.generation.to_json(),
+            self.evaluation.to_json(),
+            self.encoding.to_json(),
+            best,
+            mean
+        )
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} iterations of {} pixels, {} frames each",
+            self.evaluation.times.len(),
+            self.pixels,
+            self.frames
+        )?;
+        writeln!(f, "{:<12}{:>12}{:>12}", "", "min", "mean")?;
+        for (name, phase) in [
+            ("generation", &self.generation),
+            ("evaluation", &self.evaluation),
+            ("encoding", &self.encoding),
+        ] {
+            writeln!(
+                f,
+                "{:<12}{:>10.3}ms{:>10.3}ms",
+                name,
+                millis(phase.min()),
+                millis(phase.mean())
+            )?;
+        }
+        let (best, mean) = self.pixels_per_second();
+        writeln!(f, "{:<12}{:>12.0}{:>12.0}", "pixels/s", best, mean)
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.
+}
@@ -1,11 +1,25 @@
-use std::path::PathBuf;
+use std::{f64::consts::TAU, fmt::Display, path::PathBuf, str::FromStr};
 
-use clap::Parser;
+use clap::{ArgAction, Parser, Subcommand, ValueEnum, ValueHint};
+
+use crate::{
+    img::{npy::FloatKind, pnm::PnmKind},
+    io::name::NameTemplate,
+};
+
+/// The exit codes, listed at the end of --help
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  Success
+  2  Invalid arguments, or arguments which can't be used together
+  3  A grammar which can't be used
+  4  An AST which can't be parsed
+  5  A file which can't be read or written, or an image which can't be encoded";
 
 /// Kroyer is a program used to create random pictures from a grammar file.
 /// It has barely any practical use cases, but can be fun to tinker around with.
 #[derive(Parser, Debug)]
-#[command(version, about)]
+#[command(version, about, after_help = EXIT_CODES, args_conflicts_with_subcommands = true)]
 pub struct Args {
     /// The grammar file to use. If none is used, it will use the default grammar.
     /// Convention is to use a file with the .kroyer file extension as the grammar file, but this
@@ -15,31 +29,303 @@ pub struct Args {
     /// The max depth that the AST can have
     #[arg(short, long, default_value = "10")]
     pub depth: usize,
-    /// The amount of frames that will be rendered when in gif mode. This will always create a
-    /// loop in the interval of 0 to 2 pi, this just sets the amount of steps between these two
-    /// values.
+    /// The max amount of nodes each channel of the AST can have. When used together with
+    /// --depth, whichever limit is hit first will stop the tree from growing
+    #[arg(long)]
+    pub max_nodes: Option<usize>,
+    /// Generates a single tree, which is used for all three channels, creating a grayscale image.
+    /// Dumped grayscale ASTs use a single 'L:' header
+    #[arg(long)]
+    pub grayscale: bool,
+    /// Generates a fourth tree for the alpha channel, making parts of the image transparent.
+    /// Gifs only have binary transparency, so their alpha is thresholded at half, and y4m and ppm
+    /// output have no alpha at all. Dumped ASTs give the tree under an 'A:' header
+    #[arg(long)]
+    pub alpha: bool,
+    /// How the values of the red, green and blue trees are interpreted when rendering
+    #[arg(long, value_enum, default_value_t = ColorSpace::Rgb)]
+    pub color_space: ColorSpace,
+    /// How values outside of the -1..1 range of the trees are mapped into it. When this isn't
+    /// set, they are clamped, except in EXR images, which keep the values as they are
+    #[arg(long, value_enum)]
+    pub tone_map: Option<ToneMap>,
+    /// Colors pixels where a tree gives NaN with this color, written like `ff00ff`, instead of
+    /// black. This makes it easy to spot them when debugging
+    #[arg(long)]
+    pub nan_color: Option<HexColor>,
+    /// Gamma encodes the colors with the exponent 1 / GAMMA, after tone mapping. Without it, the
+    /// values are written linearly, which makes midtones look darker than the math suggests.
+    /// EXR images are always linear
+    #[arg(long, conflicts_with = "srgb")]
+    pub gamma: Option<f64>,
+    /// Encodes the colors with the sRGB transfer function, after tone mapping, like --gamma
+    #[arg(long)]
+    pub srgb: bool,
+    /// Picks the colors from a palette, instead of the trees giving the red, green and blue. Only
+    /// the red tree is evaluated, and its value from -1 to 1 picks a color, from the first to the
+    /// last. The file is a list of hex colors, like `ff0000 00ff00`, or a PNG strip, read along
+    /// its longest side. Without --palette-smooth, gifs use the palette as it is, if it has at
+    /// most --gif-colors colors
+    #[arg(long, conflicts_with_all = ["color_space", "gamma", "srgb"])]
+    pub palette: Option<PathBuf>,
+    /// Blends the colors of --palette between its entries, instead of using the nearest one
+    #[arg(long, requires = "palette")]
+    pub palette_smooth: bool,
+    /// Only renders the tree of a single channel, as a grayscale image
+    #[arg(long, value_enum)]
+    pub channel: Option<Channel>,
+    /// Filters applied to the rendered image before it is encoded, in order, written like
+    /// `blur:1.5,sharpen:0.3,contrast:1.2`. The filters are blur:SIGMA, sharpen:AMOUNT and
+    /// contrast:FACTOR. Filters only see the rendered pixels, so the edges of a --region differ
+    /// from the whole image, and filtered images are never streamed. EXR images are unfiltered
+    #[arg(long, value_delimiter = ',')]
+    pub post: Vec<PostFilter>,
+    /// The amount of frames that will be rendered when in gif mode. The variable t goes from
+    /// --t-start to --t-end over the frames, as set by --t-mode, and this sets the amount of steps
+    /// it takes.
     #[arg(short, long, default_value = "255")]
     pub frames: u32,
+    /// How the variable t moves over the frames of a gif
+    #[arg(long, value_enum, default_value_t = TMode::Linear)]
+    pub t_mode: TMode,
+    /// The start of the range t moves over in an animation, as set by --t-mode
+    #[arg(long, default_value = "0", allow_negative_numbers = true)]
+    pub t_start: f64,
+    /// The end of the range t moves over in an animation. The default of 2 pi makes a loop, and
+    /// a part of it, like `--t-start 0 --t-end 1`, gives a slow motion segment of the animation,
+    /// or a sweep which doesn't loop
+    #[arg(long, default_value_t = TAU, allow_negative_numbers = true)]
+    pub t_end: f64,
+    /// The frames per second of a gif. Gifs store the delay between frames in hundredths of a
+    /// second, so the frame rate is rounded to fit
+    #[arg(long, default_value = "20")]
+    pub fps: f64,
+    /// Only renders the frames from START up to END with --format frames, written like `0..100`.
+    /// The files keep the numbers they have in the whole animation, so a long render can be
+    /// split into ranges, and rendered apart
+    #[arg(long)]
+    pub frame_range: Option<FrameRange>,
+    /// The max amount of colors in the palettes of a gif, from 2 to 256
+    #[arg(long, default_value = "256")]
+    pub gif_colors: usize,
+    /// How the colors of a gif are dithered, when they are reduced to its palettes
+    #[arg(long, value_enum, default_value_t = Dither::None)]
+    pub dither: Dither,
+    /// Uses a single palette for every frame of a gif, picked from a sample of the frames. This
+    /// avoids colors flickering between frames, and makes the file smaller
+    #[arg(long)]
+    pub gif_global_palette: bool,
+    /// The amount of threads used to render the image, or the frames of a gif, where 0 uses
+    /// every core. ASTs with the rand node are always rendered on a single thread with
+    /// --legacy-rand, so a seed always gives the same image
+    #[arg(long, default_value = "0")]
+    pub threads: usize,
+    /// Evaluates the trees of 8-bit images on the GPU, when kroyer is built with the `gpu`
+    /// feature. The GPU computes with 32-bit floats, and hashes rand nodes differently, so the
+    /// images are close to, but not the same as, the ones rendered on the CPU. kroyer renders on
+    /// the CPU instead, with a warning, if no GPU is found, or the image uses --samples,
+    /// --symmetry, --stats or --legacy-rand
+    #[arg(long)]
+    pub gpu: bool,
     /// The images width
     #[arg(long, default_value = "512")]
     pub width: u32,
     /// The images height
     #[arg(long, default_value = "512")]
     pub height: u32,
+    /// Anti-aliases the image by evaluating each pixel on an N by N grid, and averaging the
+    /// colors. This makes rendering N² times slower
+    #[arg(long, default_value = "1")]
+    pub samples: u32,
+    /// The range x goes over, from the left to the right edge of the image, written like
+    /// `-1..1`. The trees of a dumped AST don't include it, so it has to be set again when
+    /// rendering the dump
+    #[arg(long, default_value = "0..1", allow_hyphen_values = true)]
+    pub x_range: AxisRange,
+    /// The range y goes over, from the top to the bottom edge of the image, like --x-range
+    #[arg(long, default_value = "0..1", allow_hyphen_values = true)]
+    pub y_range: AxisRange,
+    /// Centers the image on x = 0 and y = 0, by setting both --x-range and --y-range to -1..1
+    #[arg(long, conflicts_with_all = ["x_range", "y_range"])]
+    pub centered: bool,
+    /// Renders the image with a symmetry: none, mirror-x, mirror-y, quad, or radial:N for N
+    /// copies of a wedge. Mirrors reflect the pixels of the image, so they don't depend on
+    /// --x-range and --y-range. Radial symmetry rotates x and y around the middle of the ranges,
+    /// so --centered puts it at x = 0, y = 0, and ranges with another aspect than the image
+    /// stretch the wedges
+    #[arg(long, default_value = "none")]
+    pub symmetry: Symmetry,
+    /// Stretches the values of the red, green and blue trees over their full range, so images
+    /// whose values only cover a sliver of it aren't flat. The lowest and highest values are
+    /// found on a grid of samples over the full image, before the image is rendered. Grammars
+    /// which use t are normalized over every frame, so animations don't flicker
+    #[arg(long)]
+    pub normalize: bool,
+    /// The percent of the lowest and highest values --normalize ignores, so a few outliers
+    /// don't keep the rest of the image flat, like 1 for the 1st and 99th percentiles
+    #[arg(long, default_value = "0", requires = "normalize")]
+    pub normalize_percentile: f64,
+    /// Saves the red tree as a 16-bit grayscale PNG heightmap, instead of the colors of the
+    /// image, where -1 is black and 1 is white. Grammars using t are saved at --still-t
+    #[arg(long, conflicts_with_all = ["palette", "color_space", "normal_map"])]
+    pub heightmap: bool,
+    /// Saves a tangent-space normal map of the heightmap of --heightmap as a PNG, in the RGB
+    /// encoding game engines expect, with green pointing up, like in OpenGL. The slopes are
+    /// found from the neighbouring pixels, and pixels at the edges use their nearest neighbours
+    #[arg(long, conflicts_with_all = ["palette", "color_space"])]
+    pub normal_map: bool,
+    /// How steep the surface of --normal-map is. At 1, the full range of the red tree rises as
+    /// high as the image is wide
+    #[arg(long, default_value = "1", requires = "normal_map")]
+    pub normal_strength: f64,
+    /// Renders only the part of the image at x,y that is w by h pixels, written like
+    /// `256,0,128,128`. The coordinates are the same as in the full --width by --height image, so
+    /// regions can re-render a detail of it, or be rendered apart and stitched together. With
+    /// --legacy-rand, use --freeze-rand for regions of rand nodes to stitch
+    #[arg(long)]
+    pub region: Option<Region>,
     /// Use a given seed. This assures that two images using the same grammar, and same seed, are
-    /// identical.
+    /// identical. The seed is read as a number if it is decimal, like `42`, or hex with a `0x`
+    /// prefix, like `0x2a`. Anything else, like `purple mountains 3` or `face`, is a phrase,
+    /// which is hashed into a seed, so the same phrase always gives the same image.
     /// When this argument is left empty, it will use STDIN to get the seed.
     #[arg(long)]
     pub seed: Option<Option<String>>,
-    /// Dumps the seed used to create the image into STDOUT. This can be passed to kroyer with --seed
-    /// to create the same image again
+    /// Uses a SHA-256 hash of the contents of the given file as the seed, so the same file always
+    /// gives the same image, like a fingerprint of it. Files of any size can be used, since they
+    /// are read a part at a time. --dump-seed prints the seed, which --seed reads
+    #[arg(long, conflicts_with = "seed")]
+    pub seed_from_file: Option<PathBuf>,
+    /// Renders this many images in one run. Each image gets its own seed, derived from the seed
+    /// of the run, so a batch with the same seed is always the same. The files are numbered, like
+    /// out-001.png, and --dump-seed prints the seed of every image
+    #[arg(long, default_value = "1")]
+    pub count: usize,
+    /// Renders a grid of ROWSxCOLS images, like `4x6`, each --width by --height, into a single
+    /// PNG, to quickly see what a grammar can make. The cells get the same seeds as the images
+    /// of --count, row by row from the top left, which is also the order --dump-seed prints them
+    #[arg(long, conflicts_with = "count")]
+    pub contact_sheet: Option<Grid>,
+    /// Draws the seed of each cell of --contact-sheet in its top left corner, wrapped to fit
+    #[arg(long, requires = "contact_sheet")]
+    pub label_cells: bool,
+    /// Breeds the AST with the AST in the given file, by swapping a randomly selected branch of
+    /// each channel with a branch from the same channel of the other AST. The resulting trees
+    /// are capped at --depth. Use --dump-ast to save the child, so it can be bred again
+    #[arg(long)]
+    pub breed: Option<PathBuf>,
+    /// Replaces the given amount of randomly selected branches of the AST with newly generated
+    /// ones. This is meant to be used with --ast, to explore variations of an existing image.
+    /// The replaced branches never overlap, and the mutations are reproducible with --seed
+    #[arg(long)]
+    pub mutate: Option<usize>,
+    /// The max depth of the branches generated by --mutate
+    #[arg(long, default_value = "3")]
+    pub mutate_depth: usize,
+    /// Allows --mutate to replace the whole tree of a channel
+    #[arg(long)]
+    pub mutate_root: bool,
+    /// Replaces every rand node in the AST with a literal, picked once from the seeded RNG.
+    /// Rand nodes otherwise give a value for every pixel which depends on the seed, so this makes
+    /// --dump-ast output recreate the exact same image with --ast, regardless of the seed. The
+    /// literals are picked after the AST has been generated, so using the seed from --dump-seed
+    /// with the same grammar and --freeze-rand also gives the same frozen AST
+    #[arg(long)]
+    pub freeze_rand: bool,
+    /// Makes rand nodes draw a new value from the seeded RNG for every pixel they are evaluated
+    /// at, like older versions of kroyer did. The values then depend on the order the pixels are
+    /// rendered in, so these ASTs are rendered on a single thread, and the frames of a gif get
+    /// unrelated noise
+    #[arg(long)]
+    pub legacy_rand: bool,
+    /// Generates the trees like the given grammar version does, instead of the version of the
+    /// grammar. Versions draw from the seed in different orders, so this recreates images of
+    /// seeds made with another version. Version 1 also implies --legacy-rand. Grammars without a
+    /// `@kroyer-version` header are version 2
+    #[arg(long, value_name = "VERSION")]
+    pub compat: Option<u32>,
+    /// Simplifies the AST before using it, by folding constant branches and removing identities
+    /// like `mult(x, 1)`. This doesn't change the resulting image, but makes --dump-ast tidier
+    /// and rendering faster
+    #[arg(long)]
+    pub simplify: bool,
+    /// Orders the operands of commutative nodes, like add and mult, in a fixed way, so equivalent
+    /// ASTs are dumped and hashed the same. This doesn't change the image
+    #[arg(long)]
+    pub canonical: bool,
+    /// Dumps the seed used to create the image into STDOUT, as hex with a `0x` prefix, on its own
+    /// line. This can be passed to kroyer with --seed, or piped into `--stdin-as seed`, to create the
+    /// same image again. With --verbose, the seed is labeled, seeds hashed from a phrase also
+    /// dump the phrase, and the grammar version the trees were generated with is dumped too
     #[arg(long)]
     pub dump_seed: bool,
+    /// Dumps a hash of the structure of the AST into STDOUT. ASTs with the same nodes have the
+    /// same hash, even if they came from different seeds, or were formatted differently.
+    /// The hash stays the same across kroyer versions
+    #[arg(long)]
+    pub dump_hash: bool,
     /// Dumps the AST used to create the image into STDOUT.
     /// To create this exact image, this can be passed to kroyer with the --ast flag,
     /// either via STDIN or via a file
     #[arg(long)]
     pub dump_ast: bool,
+    /// Makes --dump-ast and --dump-ast-file indent the AST, splitting expressions which are too
+    /// wide over several lines
+    #[arg(long)]
+    pub pretty: bool,
+    /// The max line width of ASTs dumped with --pretty
+    #[arg(long, default_value = "80")]
+    pub pretty_width: usize,
+    /// Makes --dump-ast and --dump-ast-file write the AST with infix operators, like
+    /// `x * 0.5 + sin(y)`. This is easier to read, but can't be read back with --ast
+    #[arg(long, conflicts_with = "pretty")]
+    pub infix: bool,
+    /// Dumps statistics about each channel of the AST into STDOUT, like the amount of nodes, the
+    /// depth, and an estimate of how expensive it is to render
+    #[arg(long)]
+    pub ast_stats: bool,
+    /// Reports every if statement in the AST whose condition always has the same result, with the
+    /// path to it and the branch which is never used
+    #[arg(long)]
+    pub lint_ast: bool,
+    /// Makes dumps which support it, like --ast-stats and --lint-ast, output JSON instead of text
+    #[arg(long)]
+    pub json: bool,
+    /// Dumps the seed of every image of --count, or cell of --contact-sheet, into STDOUT, without
+    /// rendering anything. Each line has the number of the image, like in the names of the files
+    /// of --count, and its seed, which renders that image on its own with --seed
+    #[arg(long)]
+    pub dump_derived_seeds: bool,
+    /// Writes the seed into the given file, in the format accepted by --seed
+    #[arg(long)]
+    pub dump_seed_file: Option<PathBuf>,
+    /// Writes the current grammar into the given file
+    #[arg(long)]
+    pub dump_grammar_file: Option<PathBuf>,
+    /// Writes the AST into the given file, in the format given by --ast-format. This file can be
+    /// passed to kroyer with --ast to create the image again
+    #[arg(long)]
+    pub dump_ast_file: Option<PathBuf>,
+    /// Exports the AST as source code in the given language, and writes it into the given file.
+    /// Supported languages are: wgsl, rust, python. `wav` instead writes the red tree as sound,
+    /// see --duration
+    #[arg(long, num_args = 2, value_names = ["LANGUAGE", "PATH"])]
+    pub export: Option<Vec<String>>,
+    /// The length in seconds of the sound of `--export wav`, which evaluates the red tree at
+    /// every sample, with x sweeping over --x-range, y in the middle of --y-range, and t at
+    /// --still-t. The average of the sound is removed, so it is centered on silence
+    #[arg(long, default_value = "5")]
+    pub duration: f64,
+    /// The samples per second of `--export wav`
+    #[arg(long, default_value = "44100")]
+    pub sample_rate: u32,
+    /// The loudest the sound of `--export wav` can be, from 0 to 1. Values of the red tree
+    /// outside of -1..1 are clamped before being scaled by it
+    #[arg(long, default_value = "0.8")]
+    pub amplitude: f64,
+    /// Allows the --dump-*-file and --export flags to overwrite existing files
+    #[arg(long)]
+    pub force: bool,
     /// Dumps kroyers default grammar into STDOUT.
     /// This flag will stop all other processes, and will not create an image.
     #[arg(long)]
@@ -56,16 +342,749 @@ pub struct Args {
     /// from STDIN
     #[arg(long)]
     pub ast: Option<Option<PathBuf>>,
+    /// Sets what STDIN is read as.
+    /// Without this flag, STDIN is read by --seed or --ast when they are left empty, and
+    /// otherwise as the grammar, unless a grammar file is given. Data piped into STDIN, which
+    /// nothing reads, is an error
+    #[arg(long, value_enum)]
+    pub stdin_as: Option<StdinAs>,
+    /// Checks an AST without creating an image, reporting every problem found in it.
+    /// This flag takes a file, which holds the AST, or if it is left empty, it will read the AST
+    /// from STDIN. Exits with 0 if the AST is valid, and 4 if it isn't
+    #[arg(long)]
+    pub validate_ast: Option<Option<PathBuf>>,
+    /// Prints a grammar where the weight of each node is how often it appears in an AST, so the
+    /// grammar generates images that resemble it. The output can be piped straight back into
+    /// kroyer. This flag takes a file, which holds the AST, or if it is left empty, it will read
+    /// the AST from STDIN
+    #[arg(long)]
+    pub ast_to_grammar: Option<Option<PathBuf>>,
+    /// The max nesting of an AST read with --ast or --breed. Very deeply nested ASTs can make
+    /// kroyer run out of stack space
+    #[arg(long, default_value = "1000")]
+    pub max_ast_depth: usize,
+    /// The max number of nodes in an AST read with --ast or --breed, where every use of a name
+    /// defined with "def" counts all of its nodes. Keeps huge ASTs from using up all the memory
+    #[arg(long, default_value = "1000000")]
+    pub max_ast_nodes: usize,
+    /// Keeps parsing an AST read with --ast or --breed after an error, so every problem in it is
+    /// reported at once, instead of only the first one
+    #[arg(long)]
+    pub all_errors: bool,
+    /// Ignores content after the last channel of an AST read with --ast or --breed with a
+    /// warning, instead of failing. Useful for files with a second dump accidentally appended
+    #[arg(long)]
+    pub lenient: bool,
+    /// Generates the channels missing from an AST read with --ast from the grammar, instead of
+    /// failing. This makes it possible to lock one channel, and keep exploring the others
+    #[arg(long)]
+    pub fill_missing: bool,
+    /// Renders a PNG or gif saved by kroyer again, from the seed, grammar, depth and range of t
+    /// in its metadata, or from its AST if it was saved with --embed-ast. Files with a sidecar
+    /// from --metadata, of any format, are rendered from the sidecar. Other flags, like
+    /// --mutate and --dump-ast, work as usual, but have to be set again to get the same image
+    #[arg(long, conflicts_with_all = ["file", "seed", "seed_from_file", "ast", "stdin_as", "depth", "t_start", "t_end", "still_t"])]
+    pub from_image: Option<PathBuf>,
+    /// Embeds the AST in the metadata of saved PNGs and gifs, next to the seed, grammar and
+    /// depth, so --from-image gives the same image, regardless of the flags it was made with
+    #[arg(long)]
+    pub embed_ast: bool,
+    /// Writes the metadata of every saved file as JSON next to it, named like `out.gif.json`,
+    /// with the seed, grammar, depth, size, frames, range of t, AST hash, version of kroyer and
+    /// how long the file took to render. This works for every format, unlike --embed-ast, and
+    /// its `schema_version` changes if the fields do. --format frames gets a single one, named
+    /// after --out
+    #[arg(long)]
+    pub metadata: bool,
+    /// The syntax used when reading an AST with --ast, and when dumping it with --dump-ast
+    #[arg(long, value_enum, default_value_t = AstFormat::Default)]
+    pub ast_format: AstFormat,
     /// Sets the path of the outputted image. Will default to out.png or out.gif, depending on if
     /// the variable t exists in the grammar rules.
     /// This can also be used to implicitally tell kroyer if it needs to use gif mode, by setting
-    /// the file extension to `.gif`
+    /// the file extension to `.gif`, or to make an animated PNG, with `.apng`.
+    /// `-` writes the image to STDOUT, like `--out - --format gif | gifsicle -O3 > final.gif`.
+    /// The dumps of flags like --dump-seed are printed to STDERR then, so they aren't mixed into
+    /// the image
     #[arg(short, long)]
     pub out: Option<PathBuf>,
-    /// Dumps the raw image bytes into STDOUT instead of saving it to a file
-    #[arg(long)]
+    /// Names the image from a template, instead of --out, so images aren't saved over each
+    /// other. The placeholders {seed}, {hash} (the structural hash of the AST), {date} (in UTC),
+    /// {n} (the number of the image in a batch of --count), {w} and {h} are replaced with the
+    /// values of the image, like `shots/{date}-{seed}-{w}x{h}.png`. The format is decided by the
+    /// extension, like with --out, and directories in the template are created. Every image of a
+    /// run has to get its own name, so the template of a batch needs {n}, {seed} or {hash}
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        conflicts_with_all = ["out", "dump_raw", "contact_sheet", "bench", "compare_seed", "diff_with", "warp_image"]
+    )]
+    pub name_template: Option<NameTemplate>,
+    /// The directory the image is saved in when --out and --name-template aren't set. It is
+    /// created if it doesn't exist
+    #[arg(
+        long,
+        value_name = "DIR",
+        value_hint = ValueHint::DirPath,
+        conflicts_with_all = ["out", "name_template"]
+    )]
+    pub out_dir: Option<PathBuf>,
+    /// The format of the outputted image. If this isn't set, it is decided by the extension of
+    /// --out, then by the format of the config file, and else by if the variable t exists in the
+    /// grammar rules
+    #[arg(long, value_enum)]
+    pub format: Option<OutputFormat>,
+    /// The samples of TIFF images: 8 or 16 bits per channel, or float, which keeps the values of
+    /// the trees without tone mapping, like EXR images
+    #[arg(long, value_enum, default_value_t = BitDepth::Eight)]
+    pub bit_depth: BitDepth,
+    /// Also saves a still image of the same AST to this path, at t = --still-t, like a
+    /// representative frame of a gif. The format is decided by the extension, like with --out,
+    /// and `-` writes it to STDOUT
+    #[arg(long, conflicts_with_all = ["contact_sheet", "bench"])]
+    pub also_still: Option<PathBuf>,
+    /// Also saves a gif of the same AST to this path, next to the image of --out. `-` writes it
+    /// to STDOUT
+    #[arg(long, conflicts_with_all = ["contact_sheet", "bench"])]
+    pub also_gif: Option<PathBuf>,
+    /// The value of t in still images, like the ones of --also-still, or grammars using t
+    /// rendered with --format still
+    #[arg(long, default_value = "0", allow_negative_numbers = true)]
+    pub still_t: f64,
+    /// Renders a still image at this value of t, even if the grammar uses t, like --still-t
+    /// together with --format still
+    #[arg(long, conflicts_with = "still_t", allow_negative_numbers = true)]
+    pub t: Option<f64>,
+    /// Dumps the raw image bytes into STDOUT instead of saving it to a file, as a PNG, or as a
+    /// GIF in gif mode. --out only decides the format then. Flags which print to STDOUT can't be
+    /// used with this
+    #[arg(long, conflicts_with_all = ["dump_seed", "dump_hash", "dump_grammar", "dump_ast", "ast_stats", "lint_ast"])]
     pub dump_raw: bool,
-    /// Makes kroyer output more logs, which otherwise would be witheld.
+    /// Renders still PNGs in bands of rows, which are compressed as they are rendered, so the
+    /// whole image never has to be in memory. The pixels are the same as without it. This is
+    /// always done for images over 4096 by 4096 pixels
+    #[arg(long)]
+    pub stream_encode: bool,
+    /// Renders the image without saving it, and prints how long generating the AST, evaluating
+    /// it and encoding the image took, and how many pixels were evaluated per second. Grammars
+    /// which use t are timed as gifs, or with `--format apng` or `--format frames`, as those.
+    /// Prints JSON with --json
+    #[arg(long, conflicts_with_all = ["count", "contact_sheet", "dump_raw"])]
+    pub bench: bool,
+    /// The number of times --bench renders the image. The fastest and the average time of each
+    /// phase are printed
+    #[arg(long, default_value = "5")]
+    pub bench_iterations: usize,
+    /// Shows a preview of the image in the terminal, scaled down to fit, after it is saved. The
+    /// kitty graphics protocol or sixels are used if the terminal has them, or else colored
+    /// characters. The preview is written to STDERR or the terminal, so STDOUT can be piped.
+    /// Grammars using t are previewed at --still-t
+    #[arg(long, conflicts_with = "bench")]
+    pub preview_term: bool,
+    /// Renders the image with --seed and with this seed, which is read like --seed, and saves the difference of each
+    /// channel between them as an image at --out, or diff.png. A summary of how much they differ
+    /// is printed, as JSON with --json. Grammars using t are compared at --still-t
+    #[arg(long, conflicts_with_all = ["count", "contact_sheet", "bench", "diff_with"])]
+    pub compare_seed: Option<String>,
+    /// Renders the image, and compares it with this image, like --compare-seed. The image has to
+    /// have the same size as the rendered one
+    #[arg(long, conflicts_with_all = ["count", "contact_sheet", "bench"])]
+    pub diff_with: Option<PathBuf>,
+    /// Writes an index.html into this directory, with a thumbnail of every image of the run,
+    /// linking to the image, and showing its seed, depth and AST. The thumbnails are saved in
+    /// its thumbs directory. The page is updated after every image, so it can be browsed while
+    /// a batch of --count is rendered
+    #[arg(
+        long,
+        value_hint = ValueHint::DirPath,
+        conflicts_with_all = ["contact_sheet", "bench", "compare_seed", "diff_with"]
+    )]
+    pub gallery: Option<PathBuf>,
+    /// Reads the image at INPUT, and saves it displaced by the AST at OUTPUT, instead of
+    /// rendering an image. Every pixel evaluates the trees like a rendered image the size of
+    /// INPUT, and takes its color from INPUT, moved by the red tree along x and the green tree
+    /// along y. See --warp-strength. A gif OUTPUT animates the warp over t, and other formats are
+    /// warped at --still-t
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["INPUT", "OUTPUT"],
+        conflicts_with_all = ["count", "contact_sheet", "bench", "compare_seed", "diff_with", "gallery"]
+    )]
+    pub warp_image: Option<Vec<PathBuf>>,
+    /// How far a tree value of 1 moves the pixels of --warp-image, as a fraction of its width or
+    /// height
+    #[arg(long, default_value = "0.1")]
+    pub warp_strength: f64,
+    /// How --warp-image picks pixels displaced outside of the image
+    #[arg(long, value_enum, default_value = "clamp")]
+    pub warp_edge: WarpEdge,
+    /// How --warp-image samples the image between its pixels
+    #[arg(long, value_enum, default_value = "bilinear")]
+    pub warp_filter: WarpFilter,
+    /// Makes the blue tree scale the brightness of --warp-image, from black at -1, through the
+    /// original colors at 0, to double the brightness at 1
+    #[arg(long)]
+    pub warp_brightness: bool,
+    /// Prints statistics of the raw values of each tree after rendering, before they are
+    /// normalized, tone mapped or clamped: the min, max, mean and standard deviation, how many
+    /// are NaN or outside of -1..1, and a histogram. Animations count every frame. The
+    /// statistics are printed as JSON with --json, and to STDERR when the image is written to
+    /// STDOUT
+    #[arg(long, conflicts_with_all = ["contact_sheet", "bench", "compare_seed", "diff_with"])]
+    pub stats: bool,
+    /// Makes kroyer log what it does to STDERR: the grammar used, the seed, the size of the
+    /// trees, how long each phase took, and the paths written. Use -vv to also log details for
+    /// debugging, like the format of each output
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+    /// Only logs errors, hiding warnings, like those about unknown grammar directives
     #[arg(short, long)]
-    pub verbose: bool,
+    pub quiet: bool,
+    /// The config file, which sets the defaults of --width, --height, --depth, --frames,
+    /// --out-dir, --tone-map and --format, as `key = value` lines, like `width = 1024`, or
+    /// `tone_map = "tanh"`. Flags always win over it. Without this,
+    /// `~/.config/kroyer/config.toml` is used if it exists, or the same file in $XDG_CONFIG_HOME.
+    /// Use `kroyer config dump` to see the settings used
+    #[arg(long, global = true, value_name = "PATH", value_hint = ValueHint::FilePath)]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// The commands kroyer can run instead of creating an image
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Prints a script which completes the arguments of kroyer in a shell, like
+    /// `kroyer completions bash > ~/.local/share/bash-completion/completions/kroyer`
+    Completions {
+        /// The shell the script is for
+        shell: clap_complete::Shell,
+    },
+    /// Works with the config file. See --config
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+/// The things `kroyer config` can do
+#[derive(Clone, Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Prints the settings used after the config file is read, in the format of a config file,
+    /// with where each value came from
+    Dump,
+}
+
+/// The things STDIN can be read as
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum StdinAs {
+    /// The grammar, like a grammar file
+    Grammar,
+    /// The seed, like --seed
+    Seed,
+    /// The AST, like --ast
+    Ast,
+}
+
+/// The syntaxes an AST can be written in
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum AstFormat {
+    /// Function calls, like `add(sin(x), mult(y, 0.3))`
+    Default,
+    /// S-expressions, like `(add (sin x) (mult y 0.3))`
+    Sexpr,
+}
+
+/// The languages an AST can be exported to with --export
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ExportLanguage {
+    /// A WGSL fragment shader
+    Wgsl,
+    /// A standalone Rust function
+    Rust,
+    /// A Python function using numpy
+    Python,
+    /// A 16-bit mono WAV of the red tree, instead of source code
+    Wav,
+}
+
+/// The ways the variable t can move over the frames of an animation, from the start to the end
+/// of its range
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum TMode {
+    /// Moves from the start towards the end, without reaching it, so the first frame follows the
+    /// last one seamlessly
+    Linear,
+    /// Swings between the start and the end like a sine wave, starting in the middle
+    Sine,
+    /// Moves from the start to the end in the first half of the frames, and back again in the
+    /// second half
+    Pingpong,
+}
+
+impl TMode {
+    /// Gets how far along the range t is, from 0 to 1, at `progress` through the loop, which
+    /// goes from 0 towards 1
+    pub fn position(&self, progress: f64) -> f64 {
+        match self {
+            TMode::Linear => progress,
+            TMode::Sine => ((progress * TAU).sin() + 1.) / 2.,
+            TMode::Pingpong => 1. - (progress * 2. - 1.).abs(),
+        }
+    }
+}
+
+/// The symmetries an image can be rendered with, written like `quad` or `radial:6`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Symmetry {
+    None,
+    /// The right half is the left half mirrored
+    MirrorX,
+    /// The bottom half is the top half mirrored
+    MirrorY,
+    /// Every quarter is the top left quarter mirrored
+    Quad,
+    /// The image is made of this many copies of a wedge, rotated around the middle
+    Radial(u32),
+}
+
+impl Symmetry {
+    /// Mirrors the pixel at `x` and `y` of a `width` by `height` image into the part which is
+    /// rendered, so mirrored pixels are exactly the same
+    pub fn mirror(&self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32) {
+        let mirror_x = x.min(width - 1 - x);
+        let mirror_y = y.min(height - 1 - y);
+        match self {
+            Symmetry::MirrorX => (mirror_x, y),
+            Symmetry::MirrorY => (x, mirror_y),
+            Symmetry::Quad => (mirror_x, mirror_y),
+            Symmetry::None | Symmetry::Radial(_) => (x, y),
+        }
+    }
+
+    /// Rotates the coordinates `x` and `y` into the first wedge around `center`, if the symmetry
+    /// is radial
+    pub fn rotate(&self, x: f64, y: f64, center: (f64, f64)) -> (f64, f64) {
+        let Symmetry::Radial(n) = self else {
+            return (x, y);
+        };
+        let (dx, dy) = (x - center.0, y - center.1);
+        let radius = dx.hypot(dy);
+        let angle = dy.atan2(dx).rem_euclid(TAU / *n as f64);
+        (
+            center.0 + radius * angle.cos(),
+            center.1 + radius * angle.sin(),
+        )
+    }
+}
+
+impl FromStr for Symmetry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("radial", n)) => match n.trim().parse::<u32>() {
+                Ok(n) if n > 0 => Ok(Self::Radial(n)),
+                _ => Err(format!("'{}' is not a positive whole number", n)),
+            },
+            None if s == "none" => Ok(Self::None),
+            None if s == "mirror-x" => Ok(Self::MirrorX),
+            None if s == "mirror-y" => Ok(Self::MirrorY),
+            None if s == "quad" => Ok(Self::Quad),
+            _ => Err(format!(
+                "unknown symmetry '{}'. Expected none, mirror-x, mirror-y, quad or radial:N",
+                s
+            )),
+        }
+    }
+}
+
+/// The range a coordinate goes over, across the image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AxisRange {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl AxisRange {
+    /// The range used by --centered
+    pub const CENTERED: Self = Self {
+        start: -1.,
+        end: 1.,
+    };
+
+    /// Gets the coordinate at `frac` through the range, where 0 is the start, and 1 the end
+    pub fn at(&self, frac: f64) -> f64 {
+        self.start + (self.end - self.start) * frac
+    }
+}
+
+impl FromStr for AxisRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((start, end)) = s.split_once("..") else {
+            return Err("expected a range like '0..1'".to_owned());
+        };
+        let parse = |x: &str| {
+            x.trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|x| x.is_finite())
+                .ok_or(format!("'{}' is not a finite number", x))
+        };
+        Ok(Self {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+}
+
+impl Display for AxisRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+/// A rectangle of pixels within an image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Vec<&str> = s.split(',').collect();
+        let [x, y, width, height] = values[..] else {
+            return Err("expected a region like '0,0,128,128'".to_owned());
+        };
+        let parse = |x: &str| {
+            x.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("'{}' is not a whole number", x))
+        };
+        let region = Self {
+            x: parse(x)?,
+            y: parse(y)?,
+            width: parse(width)?,
+            height: parse(height)?,
+        };
+        if region.width == 0 || region.height == 0 {
+            return Err("the width and height of a region can't be 0".to_owned());
+        }
+        Ok(region)
+    }
+}
+
+/// The rows and columns of a grid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Grid {
+    pub rows: u32,
+    pub cols: u32,
+}
+
+impl Grid {
+    /// Gets the amount of cells in the grid
+    pub fn cells(&self) -> usize {
+        self.rows as usize * self.cols as usize
+    }
+}
+
+impl FromStr for Grid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((rows, cols)) = s.split_once('x') else {
+            return Err("expected a grid like '4x6'".to_owned());
+        };
+        let parse = |x: &str| {
+            x.trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|x| *x > 0)
+                .ok_or(format!("'{}' is not a positive whole number", x))
+        };
+        Ok(Self {
+            rows: parse(rows)?,
+            cols: parse(cols)?,
+        })
+    }
+}
+
+/// A range of frames, from the first frame up to, but not including, the last
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl FromStr for FrameRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((start, end)) = s.split_once("..") else {
+            return Err("expected a range of frames like '0..100'".to_owned());
+        };
+        let parse = |x: &str| {
+            x.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("'{}' is not a whole number", x))
+        };
+        Ok(Self {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+}
+
+/// The formats an image can be outputted as
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// A still image at t = --still-t, in the format given by the extension of --out, or a PNG
+    Still,
+    /// An animated gif
+    Gif,
+    /// An animated PNG, which keeps every color, unlike a gif
+    Apng,
+    /// A YUV4MPEG2 video stream, written to STDOUT unless --out is set. Long animations can be
+    /// turned into a video with `kroyer --format y4m | ffmpeg -i - out.mp4`
+    Y4m,
+    /// An OpenEXR image at t = --still-t, with float colors. The colors are neither clamped nor rounded,
+    /// so values outside of the displayable range survive into compositing tools
+    Exr,
+    /// A binary PPM image, written to STDOUT unless --out is set. If the grammar uses t, every
+    /// frame is written as its own image, one after another
+    Ppm,
+    /// A PAM image, with an alpha channel, written like --format ppm
+    Pam,
+    /// Every frame of the animation as its own PNG, like frame-0001.png, to feed into ffmpeg or
+    /// an editor. --out is a template, where `%04d` is replaced with the number of the frame, or
+    /// which gets the number added before the extension. Its directory is created if needed
+    Frames,
+    /// A NumPy array of the red, green and blue values of the trees, as 32-bit floats in their
+    /// own range, shaped (height, width, 3). If the grammar uses t, every frame is in the same
+    /// array, shaped (frames, height, width, 3)
+    Npy,
+    /// The values of --format npy without its header, as little-endian 32-bit floats
+    F32raw,
+    /// A farbfeld image at t = --still-t, with 16 bits per channel, which the suckless tools
+    /// read and write
+    Farbfeld,
+    /// A TIFF image at t = --still-t, with the samples of --bit-depth
+    Tiff,
+}
+
+impl OutputFormat {
+    /// Gets the path the image is saved to, if --out isn't set
+    pub fn default_path(&self) -> &'static str {
+        match self {
+            OutputFormat::Still => "out.png",
+            OutputFormat::Gif => "out.gif",
+            OutputFormat::Apng => "out.png",
+            OutputFormat::Y4m => "out.y4m",
+            OutputFormat::Exr => "out.exr",
+            OutputFormat::Ppm => "out.ppm",
+            OutputFormat::Pam => "out.pam",
+            OutputFormat::Frames => "frames/frame-%04d.png",
+            OutputFormat::Npy => "out.npy",
+            OutputFormat::F32raw => "out.f32",
+            OutputFormat::Farbfeld => "out.ff",
+            OutputFormat::Tiff => "out.tiff",
+        }
+    }
+
+    /// Checks if images of this format are written to STDOUT when --out isn't set. Video streams
+    /// and netpbm images are meant to be piped
+    pub fn is_piped(&self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Y4m | OutputFormat::Ppm | OutputFormat::Pam
+        )
+    }
+
+    /// Gets the netpbm format this is, if any
+    pub fn pnm_kind(&self) -> Option<PnmKind> {
+        match self {
+            OutputFormat::Ppm => Some(PnmKind::Ppm),
+            OutputFormat::Pam => Some(PnmKind::Pam),
+            _ => None,
+        }
+    }
+
+    /// Gets the raw float format this is, if any
+    pub fn float_kind(&self) -> Option<FloatKind> {
+        match self {
+            OutputFormat::Npy => Some(FloatKind::Npy),
+            OutputFormat::F32raw => Some(FloatKind::F32Raw),
+            _ => None,
+        }
+    }
+}
+
+/// The color spaces the values of the trees can be interpreted in
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ColorSpace {
+    /// The trees give the red, green and blue of each pixel
+    Rgb,
+    /// The trees give the hue, saturation and value of each pixel. The hue wraps around instead
+    /// of being clamped, so smooth expressions cycle through the rainbow
+    Hsv,
+    /// The trees give the hue, saturation and lightness of each pixel, with the hue wrapping like
+    /// in hsv
+    Hsl,
+}
+
+/// The ways values outside of the range of the trees can be brought into it
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ToneMap {
+    /// Values outside of the range become the nearest edge of it
+    Clamp,
+    /// Values wrap around, so 1.5 becomes -0.5, giving repeating bands instead of flat patches
+    Wrap,
+    /// Values are smoothly compressed with tanh, so large values still keep some detail
+    Tanh,
+}
+
+impl ToneMap {
+    /// Maps a value of a tree into -1..1
+    pub fn apply(&self, value: f64) -> f64 {
+        match self {
+            ToneMap::Clamp => value.clamp(-1., 1.),
+            ToneMap::Wrap => (value + 1.).rem_euclid(2.) - 1.,
+            ToneMap::Tanh => value.tanh(),
+        }
+    }
+}
+
+/// A color written as a hex code, like `ff00ff` or `#ff00ff`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HexColor(pub [u8; 3]);
+
+impl FromStr for HexColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err("expected a hex color like 'ff00ff'".to_owned());
+        }
+
+        let mut color = [0; 3];
+        for (i, channel) in color.iter_mut().enumerate() {
+            *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .map_err(|_| format!("'{}' is not a hex color", s))?;
+        }
+        Ok(Self(color))
+    }
+}
+
+/// A filter applied to the rendered image before it is encoded, written like `blur:1.5`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PostFilter {
+    /// A gaussian blur with the given standard deviation in pixels
+    Blur(f32),
+    /// Adds the given amount of the difference between the image and a blurred copy of it
+    Sharpen(f32),
+    /// Scales the distance of each color from the middle gray by the given factor
+    Contrast(f32),
+}
+
+impl PostFilter {
+    /// The names of the filters, as written in --post
+    pub const NAMES: [&str; 3] = ["blur", "sharpen", "contrast"];
+}
+
+impl FromStr for PostFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s.split_once(':').unwrap_or((s, ""));
+        let value = value
+            .trim()
+            .parse::<f32>()
+            .ok()
+            .filter(|x| x.is_finite() && *x >= 0.)
+            .ok_or(format!(
+                "the filter '{}' needs a value of at least 0, like '{}:1.5'",
+                s,
+                name.trim()
+            ));
+        match name.trim() {
+            "blur" => Ok(Self::Blur(value?)),
+            "sharpen" => Ok(Self::Sharpen(value?)),
+            "contrast" => Ok(Self::Contrast(value?)),
+            name => Err(format!(
+                "unknown filter '{}'. The supported filters are: {}",
+                name,
+                Self::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// The sizes of the samples of an image
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum BitDepth {
+    /// 8-bit integers
+    #[value(name = "8")]
+    Eight,
+    /// 16-bit integers
+    #[value(name = "16")]
+    Sixteen,
+    /// 32-bit floats
+    Float,
+}
+
+/// The ways colors can be dithered when reduced to a palette
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Dither {
+    /// Spreads the error of each pixel onto its neighbours, which hides banding the best
+    FloydSteinberg,
+    /// Uses a fixed pattern, which doesn't crawl between the frames of an animation
+    Ordered,
+    /// Uses the closest color of the palette
+    None,
+}
+
+/// The ways --warp-image picks pixels outside of the image
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum WarpEdge {
+    /// Uses the nearest pixel on the edge
+    Clamp,
+    /// Wraps around to the other side, which tiles the image
+    Wrap,
+}
+
+/// The ways --warp-image samples the image between its pixels
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum WarpFilter {
+    /// Blends the four nearest pixels
+    Bilinear,
+    /// Uses the nearest pixel, which keeps hard edges
+    Nearest,
+}
+
+/// The color channels of an AST
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Channel {
+    R,
+    G,
+    B,
+}
+
+impl Channel {
+    /// Gets the index of the channel, as used by `NodeAst::channel()`
+    pub fn index(&self) -> usize {
+        match self {
+            Channel::R => 0,
+            Channel::G => 1,
+            Channel::B => 2,
+        }
+    }
 }
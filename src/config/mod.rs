@@ -0,0 +1,335 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use clap::{ArgMatches, ValueEnum, parser::ValueSource};
+
+use crate::{
+    cli::{Args, OutputFormat, ToneMap},
+    error::{Error, Result},
+    log,
+};
+
+/// The keys of the config file, which are named like the fields of `Args` they set
+const KEYS: [&str; 7] = [
+    "width", "height", "depth", "frames", "out_dir", "tone_map", "format",
+];
+
+/// The flags which decide the format of the image, so a format from the config file is only used
+/// when none of them are set
+const FORMAT_FLAGS: [&str; 5] = ["format", "out", "name_template", "heightmap", "normal_map"];
+
+/// Defaults read from the config file. Settings which aren't in the file are `None`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    /// The file the config was read from, if any
+    pub path: Option<PathBuf>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub depth: Option<usize>,
+    pub frames: Option<u32>,
+    pub out_dir: Option<PathBuf>,
+    pub tone_map: Option<ToneMap>,
+    pub format: Option<OutputFormat>,
+}
+
+/// Where the value of a setting came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    /// The built-in default, or no value for settings which are optional
+    Default,
+    /// The config file
+    Config,
+    /// A flag on the command line
+    Cli,
+}
+
+impl Source {
+    fn name(&self) -> &'static str {
+        match self {
+            Source::Default => "default",
+            Source::Config => "config",
+            Source::Cli => "command line",
+        }
+    }
+}
+
+/// Gets the path of the config file used without --config, which is `kroyer/config.toml` in
+/// `$XDG_CONFIG_HOME`, or in `~/.config`
+pub fn default_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .filter(|x| !x.is_empty())
+                .map(|x| Path::new(&x).join(".config"))
+        })?;
+    Some(dir.join("kroyer").join("config.toml"))
+}
+
+impl Config {
+    /// Reads the config file given by --config, which has to exist, or else the default one, if
+    /// it exists
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::read(path),
+            None => match default_path() {
+                Some(path) if path.is_file() => Self::read(&path),
+                _ => Ok(Self::default()),
+            },
+        }
+    }
+
+    /// Reads a config file
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            Error::Io(format!(
+                "Failed to read the config file {:?}.\nDetails: {}",
+                path, e
+            ))
+        })?;
+        log::info!("Using the config file {:?}", path);
+        Self::parse(&text)
+            .map(|config| Self {
+                path: Some(path.to_path_buf()),
+                ..config
+            })
+            .map_err(|e| Error::Usage(format!("Invalid config file {:?}. {}", path, e)))
+    }
+
+    /// Parses the text of a config file. It is a subset of TOML, with a `key = value` on each
+    /// line, where the values are whole numbers or strings
+    fn parse(text: &str) -> std::result::Result<Self, String> {
+        let mut config = Self::default();
+        let mut seen = vec![];
+        for (index, line) in text.lines().enumerate() {
+            let at = |e: String| format!("Line {}: {}", index + 1, e);
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                return Err(at("tables aren't supported".to_owned()));
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(at(format!("expected `key = value`, but got `{}`", line)));
+            };
+            let key = key.trim();
+            let value = parse_value(value).map_err(at)?;
+
+            if seen.contains(&key) {
+                return Err(at(format!("`{}` is set more than once", key)));
+            }
+            seen.push(key);
+            match key {
+                "width" => config.width = Some(value.number(key).map_err(at)?),
+                "height" => config.height = Some(value.number(key).map_err(at)?),
+                "depth" => config.depth = Some(value.number(key).map_err(at)?),
+                "frames" => config.frames = Some(value.number(key).map_err(at)?),
+                "out_dir" => config.out_dir = Some(value.string(key).map_err(at)?.into()),
+                "tone_map" => config.tone_map = Some(value.choice(key).map_err(at)?),
+                "format" => config.format = Some(value.choice(key).map_err(at)?),
+                _ => log::warning!(
+                    "Ignoring the unknown key `{}` on line {} of the config file. The keys are {}",
+                    key,
+                    index + 1,
+                    KEYS.join(", ")
+                ),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Sets the settings which weren't given on the command line to the values of the config
+    /// file. The built-in defaults of the flags are kept for settings the config doesn't have.
+    /// Returns where the value of each key came from
+    pub fn apply(&self, args: &mut Args, matches: &ArgMatches) -> Vec<(&'static str, Source)> {
+        let format_given = FORMAT_FLAGS
+            .iter()
+            .any(|id| matches.value_source(id) == Some(ValueSource::CommandLine));
+        vec![
+            layer(matches, "width", &mut args.width, self.width),
+            layer(matches, "height", &mut args.height, self.height),
+            layer(matches, "depth", &mut args.depth, self.depth),
+            layer(matches, "frames", &mut args.frames, self.frames),
+            layer(
+                matches,
+                "out_dir",
+                &mut args.out_dir,
+                self.out_dir.clone().map(Some),
+            ),
+            layer(
+                matches,
+                "tone_map",
+                &mut args.tone_map,
+                self.tone_map.map(Some),
+            ),
+            match format_given {
+                true => ("format", Source::Cli),
+                false => layer(matches, "format", &mut args.format, self.format.map(Some)),
+            },
+        ]
+    }
+}
+
+/// Sets `arg` to the value of the config file, unless its flag was given on the command line.
+/// Returns the key, with where the value came from
+fn layer<T>(
+    matches: &ArgMatches,
+    key: &'static str,
+    arg: &mut T,
+    value: Option<T>,
+) -> (&'static str, Source) {
+    if matches.value_source(key) == Some(ValueSource::CommandLine) {
+        return (key, Source::Cli);
+    }
+    match value {
+        Some(value) => {
+            *arg = value;
+            (key, Source::Config)
+        }
+        None => (key, Source::Default),
+    }
+}
+
+/// Writes the settings kroyer uses, after the config file is applied, in the format of a config
+/// file. Settings which aren't set are commented out
+pub fn dump(args: &Args, config: &Config, sources: &[(&'static str, Source)]) -> String {
+    let mut text = match &config.path {
+        Some(path) => format!("# The settings from the config file {:?}\n", path),
+        None => "# The built-in settings, since no config file was found\n".to_owned(),
+    };
+    for (key, source) in sources {
+        let value = match *key {
+            "width" => Some(args.width.to_string()),
+            "height" => Some(args.height.to_string()),
+            "depth" => Some(args.depth.to_string()),
+            "frames" => Some(args.frames.to_string()),
+            "out_dir" => args
+                .out_dir
+                .as_ref()
+                .map(|x| toml_string(&x.to_string_lossy())),
+            "tone_map" => args.tone_map.map(|x| toml_string(&choice_name(x))),
+            "format" => args.format.map(|x| toml_string(&choice_name(x))),
+            _ => unreachable!(),
+        };
+        let _ = match value {
+            Some(value) => writeln!(text, "{} = {}  # {}", key, value, source.name()),
+            None => writeln!(text, "# {} isn't set", key),
+        };
+    }
+    text
+}
+
+/// A value of the config file
+enum Value {
+    Number(String),
+    String(String),
+}
+
+impl Value {
+    fn number<T: std::str::FromStr>(&self, key: &str) -> std::result::Result<T, String> {
+        match self {
+            Value::Number(number) => number
+                .parse()
+                .map_err(|_| format!("`{}` can't be {}", key, number)),
+            Value::String(_) => Err(format!("`{}` has to be a whole number", key)),
+        }
+    }
+
+    fn string(&self, key: &str) -> std::result::Result<String, String> {
+        match self {
+            Value::String(string) => Ok(string.clone()),
+            Value::Number(_) => Err(format!("`{}` has to be a string", key)),
+        }
+    }
+
+    /// Gets the value of a setting which is one of the choices of a flag, like the values of
+    /// --tone-map
+    fn choice<T: ValueEnum>(&self, key: &str) -> std::result::Result<T, String> {
+        let string = self.string(key)?;
+        T::from_str(&string, false).map_err(|_| {
+            let names: Vec<String> = T::value_variants()
+                .iter()
+                .map(|x| format!("\"{}\"", choice_name(x.clone())))
+                .collect();
+            format!(
+                "`{}` can't be \"{}\". It can be {}",
+                key,
+                string,
+                names.join(", ")
+            )
+        })
+    }
+}
+
+/// Gets the name of a choice, as it is written on the command line
+fn choice_name<T: ValueEnum>(value: T) -> String {
+    value
+        .to_possible_value()
+        .map_or_else(String::new, |x| x.get_name().to_owned())
+}
+
+/// Parses the value of a line, after the `=`. A comment may follow it
+fn parse_value(text: &str) -> std::result::Result<Value, String> {
+    let text = text.trim_start();
+    let (value, rest) = match text.chars().next() {
+        Some('"') => {
+            let mut string = String::new();
+            let mut chars = text.char_indices().skip(1);
+            let end = loop {
+                match chars.next() {
+                    Some((index, '"')) => break index + 1,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, '"')) => string.push('"'),
+                        Some((_, '\\')) => string.push('\\'),
+                        Some((_, 'n')) => string.push('\n'),
+                        Some((_, 't')) => string.push('\t'),
+                        Some((_, c)) => return Err(format!("unknown escape \\{} in a string", c)),
+                        None => return Err("the string is never closed".to_owned()),
+                    },
+                    Some((_, c)) => string.push(c),
+                    None => return Err("the string is never closed".to_owned()),
+                }
+            };
+            (Value::String(string), &text[end..])
+        }
+        // Literal strings have no escapes
+        Some('\'') => match text[1..].find('\'') {
+            Some(end) => (Value::String(text[1..end + 1].to_owned()), &text[end + 2..]),
+            None => return Err("the string is never closed".to_owned()),
+        },
+        Some(c) if c.is_ascii_digit() || c == '+' || c == '-' => {
+            let end = text.find([' ', '\t', '#']).unwrap_or(text.len());
+            let number = text[..end].trim_start_matches('+').replace('_', "");
+            (Value::Number(number), &text[end..])
+        }
+        Some(_) => return Err(format!("`{}` is not a whole number or a string", text)),
+        None => return Err("the value is missing".to_owned()),
+    };
+
+    let rest = rest.trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(format!("unexpected `{}` after the value", rest));
+    }
+    Ok(value)
+}
+
+/// Writes a string as a TOML string, with quotes and escapes
+fn toml_string(text: &str) -> String {
+    let mut string = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => string.push_str("\\\""),
+            '\\' => string.push_str("\\\\"),
+            '\n' => string.push_str("\\n"),
+            '\t' => string.push_str("\\t"),
+            c => string.push(c),
+        }
+    }
+    string.push('"');
+    string
+}
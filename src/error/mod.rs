@@ -0,0 +1,48 @@
+use std::fmt::Display;
+
+use crate::log;
+
+/// The ways kroyer can fail. Each kind exits with its own code, so scripts can tell them apart
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// Arguments which are invalid, or can't be used together. Exits with 2, like the errors of
+    /// the argument parser
+    Usage(String),
+    /// A grammar which can't be used. Exits with 3
+    Grammar(String),
+    /// An AST which can't be parsed or used. Exits with 4
+    Ast(String),
+    /// A file which can't be read or written, or an image which can't be encoded. Exits with 5
+    Io(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Gets the code kroyer exits with for the error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Usage(_) => 2,
+            Error::Grammar(_) => 3,
+            Error::Ast(_) => 4,
+            Error::Io(_) => 5,
+        }
+    }
+
+    /// Logs the error, and exits with its code
+    pub fn exit(&self) -> ! {
+        log::error!("{}", self);
+        std::process::exit(self.exit_code())
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Usage(message)
+            | Error::Grammar(message)
+            | Error::Ast(message)
+            | Error::Io(message) => write!(f, "{}", message),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+pub mod python;
+pub mod rust;
+pub mod wgsl;
+
+use crate::node::{Node, Operator, ast::NodeAst};
+
+/// A language which an AST can be exported to. Backends only write the code for single nodes,
+/// given the code of their children, and wrap the finished expressions into a program
+pub trait CodegenBackend {
+    /// Writes a float literal
+    fn literal(&self, value: f64) -> String;
+
+    /// Writes a node, which isn't a literal, given the code of its children in the order of
+    /// `Node::children()`
+    fn node(&self, node: &Node, children: &[String]) -> String;
+
+    /// Wraps the expressions of the channels into a complete program. `channels` holds either a
+    /// single grayscale expression, or the expressions for red, green and blue
+    fn program(&self, channels: &[String]) -> String;
+}
+
+/// Gets the comparison of an if statement's operator, which is written the same in every
+/// language. `!=` is written as `==`, since `Operator::eval()` compares with `==` for it as well
+pub fn comparison(operator: &Operator) -> &'static str {
+    match operator {
+        Operator::LessThan => "<",
+        Operator::GreaterThan => ">",
+        Operator::Equals | Operator::NotEquals => "==",
+    }
+}
+
+/// Writes the expression for `node` and all of its branches
+pub fn export_node(backend: &impl CodegenBackend, node: &Node) -> String {
+    if let Node::Literal(value) = node {
+        return backend.literal(*value);
+    }
+
+    let children: Vec<String> = node
+        .children()
+        .into_iter()
+        .map(|child| export_node(backend, child))
+        .collect();
+
+    backend.node(node, &children)
+}
+
+/// Writes a program which evaluates `ast`
+pub fn export_ast(backend: &impl CodegenBackend, ast: &NodeAst) -> String {
+    let channels = if ast.grayscale {
+        vec![export_node(backend, &ast.r)]
+    } else {
+        vec![
+            export_node(backend, &ast.r),
+            export_node(backend, &ast.g),
+            export_node(backend, &ast.b),
+        ]
+    };
+
+    backend.program(&channels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Exports the red channel of an AST whose channels are all `source`
+    pub(super) fn export(backend: &impl CodegenBackend, source: &str) -> String {
+        let ast = NodeAst::parse_from_str(&format!("RGB: {}", source)).unwrap();
+        export_node(backend, &ast.r)
+    }
+
+    #[test]
+    fn not_equals_is_exported_like_equals() {
+        let (not_equals, equals) = ("(x != y ? 1 : t)", "(x == y ? 1 : t)");
+        let wgsl = wgsl::Wgsl;
+        assert_eq!(export(&wgsl, not_equals), export(&wgsl, equals));
+        let rust = rust::RustSource::new(0);
+        assert_eq!(export(&rust, not_equals), export(&rust, equals));
+        let python = python::Python::new(0);
+        assert_eq!(export(&python, not_equals), export(&python, equals));
+    }
+}
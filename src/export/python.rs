@@ -0,0 +1,322 @@
+use crate::node::Node;
+
+use super::{CodegenBackend, comparison};
+
+/// Exports ASTs as a Python module, with a `pixel(x, y, t)` function which evaluates the
+/// channels for whole numpy arrays of coordinates at once, like kroyer does per pixel.
+/// Random values come from a numpy generator seeded with `seed`, unless another seed is given
+pub struct Python {
+    seed: u64,
+}
+
+impl Python {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl CodegenBackend for Python {
+    fn literal(&self, value: f64) -> String {
+        if value.is_nan() {
+            "np.nan".to_owned()
+        } else if value.is_infinite() {
+            let sign = if value > 0. { "" } else { "-" };
+            format!("({}np.inf)", sign)
+        } else if value < 0. {
+            format!("({:?})", value)
+        } else {
+            format!("{:?}", value)
+        }
+    }
+
+    fn node(&self, node: &Node, children: &[String]) -> String {
+        let child = |i: usize| &children[i];
+
+        match node {
+            Node::X => "x".to_owned(),
+            Node::Y => "y".to_owned(),
+            Node::T => "t".to_owned(),
+            Node::Rand(_) => "rng.uniform(-1.0, 1.0, shape)".to_owned(),
+            Node::Literal(value) => self.literal(*value),
+            Node::Mult(..) => format!("({} * {})", child(0), child(1)),
+            // kroyer evaluates the operands of add and sub in reverse order
+            Node::Add(..) => format!("({} + {})", child(1), child(0)),
+            Node::Sub(..) => format!("({} - {})", child(1), child(0)),
+            Node::Div(..) => format!("_div({}, {})", child(0), child(1)),
+            Node::Pow(..) => format!("np.power({}, {})", child(0), child(1)),
+            Node::Sqrt(..) => format!("np.sqrt({})", child(0)),
+            // Rust's `%` keeps the sign of the dividend, like `np.fmod()`
+            Node::Mod(..) => format!("np.fmod({}, {})", child(0), child(1)),
+            // Rust's `max()` and `min()` ignore NaN, like `np.fmax()` and `np.fmin()`
+            Node::Max(..) => format!("np.fmax({}, {})", child(0), child(1)),
+            Node::Min(..) => format!("np.fmin({}, {})", child(0), child(1)),
+            Node::Sin(..) => format!("np.sin({})", child(0)),
+            Node::Cos(..) => format!("np.cos({})", child(0)),
+            Node::Tan(..) => format!("np.tan({})", child(0)),
+            Node::Abs(..) => format!("np.abs({})", child(0)),
+            Node::If(if_node) => {
+                let operator = comparison(if_node.operator());
+                format!(
+                    "np.where({} {} {}, {}, {})",
+                    child(0),
+                    operator,
+                    child(1),
+                    child(2),
+                    child(3)
+                )
+            }
+        }
+    }
+
+    fn program(&self, channels: &[String]) -> String {
+        let color = match channels {
+            [l] => format!(
+                "        l = np.broadcast_to({}, shape)\n        return np.stack([l, l, l], axis=-1)",
+                l
+            ),
+            [r, g, b] => format!(
+                "        return np.stack(
+            [
+                np.broadcast_to({}, shape),
+                np.broadcast_to({}, shape),
+                np.broadcast_to({}, shape),
+            ],
+            axis=-1,
+        )",
+                r, g, b
+            ),
+            _ => unreachable!("an AST has either one or three channels"),
+        };
+
+        format!(
+            "# Generated by kroyer
+import numpy as np
+
+SEED = {}
+
+
+def _div(lhs, rhs):
+    # kroyer divides by the machine epsilon instead of 0
+    rhs = np.asarray(rhs, dtype=np.float64)
+    return lhs / np.where(rhs != 0.0, rhs, np.finfo(np.float64).eps)
+
+
+def pixel(x, y, t, seed=SEED):
+    \"\"\"Gets the red, green and blue values of the pixels at the coordinates in the arrays `x`
+    and `y`, which go from 0 to 1 across the image, as an array with the shape (*x.shape, 3).
+    kroyer maps the values from -1..=1 to 0..=255, saturating values outside of that range\"\"\"
+    x = np.asarray(x, dtype=np.float64)
+    y = np.asarray(y, dtype=np.float64)
+    shape = np.broadcast_shapes(x.shape, y.shape)
+    rng = np.random.default_rng(seed)
+    with np.errstate(all=\"ignore\"):
+{}
+
+
+def render(width, height, t=0.0, seed=SEED):
+    \"\"\"Renders the image like kroyer, as an array of bytes with the shape (height, width, 3)\"\"\"
+    y, x = np.mgrid[0:height, 0:width]
+    values = pixel(x / width, y / height, t, seed)
+    with np.errstate(all=\"ignore\"):
+        channels = np.nan_to_num((values + 1.0) * 127.5, nan=0.0)
+    return np.clip(channels, 0.0, 255.0).astype(np.uint8)
+",
+            self.seed, color
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fmt::Write as _, process::Command};
+
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    use crate::{
+        export::{export_ast, tests::export},
+        grammar::Grammar,
+        node::{NodeType, approx_eq, ast::NodeAst},
+    };
+
+    use super::*;
+
+    #[test]
+    fn exports_each_node() {
+        let cases = [
+            ("x", "x"),
+            ("y", "y"),
+            ("t", "t"),
+            ("rand", "rng.uniform(-1.0, 1.0, shape)"),
+            ("0.5", "0.5"),
+            ("-0.5", "(-0.5)"),
+            ("inf", "(np.inf)"),
+            ("-inf", "(-np.inf)"),
+            ("NaN", "np.nan"),
+            ("mult(x, y)", "(x * y)"),
+            ("add(x, y)", "(y + x)"),
+            ("sub(x, y)", "(y - x)"),
+            ("div(x, y)", "_div(x, y)"),
+            ("pow(x, y)", "np.power(x, y)"),
+            ("sqrt(x)", "np.sqrt(x)"),
+            ("mod(x, y)", "np.fmod(x, y)"),
+            ("max(x, y)", "np.fmax(x, y)"),
+            ("min(x, y)", "np.fmin(x, y)"),
+            ("sin(x)", "np.sin(x)"),
+            ("cos(x)", "np.cos(x)"),
+            ("tan(x)", "np.tan(x)"),
+            ("abs(x)", "np.abs(x)"),
+            ("(x < y ? t : 0.5)", "np.where(x < y, t, 0.5)"),
+            ("(x > y ? t : 0.5)", "np.where(x > y, t, 0.5)"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(export(&Python::new(0), source), expected, "{}", source);
+        }
+    }
+
+    /// The export of `L: sin(mult(x, y))`, with a seed of 42
+    const PROGRAM: &str = r#"# Generated by kroyer
+import numpy as np
+
+SEED = 42
+
+
+def _div(lhs, rhs):
+    # kroyer divides by the machine epsilon instead of 0
+    rhs = np.asarray(rhs, dtype=np.float64)
+    return lhs / np.where(rhs != 0.0, rhs, np.finfo(np.float64).eps)
+
+
+def pixel(x, y, t, seed=SEED):
+    """Gets the red, green and blue values of the pixels at the coordinates in the arrays `x`
+    and `y`, which go from 0 to 1 across the image, as an array with the shape (*x.shape, 3).
+    kroyer maps the values from -1..=1 to 0..=255, saturating values outside of that range"""
+    x = np.asarray(x, dtype=np.float64)
+    y = np.asarray(y, dtype=np.float64)
+    shape = np.broadcast_shapes(x.shape, y.shape)
+    rng = np.random.default_rng(seed)
+    with np.errstate(all="ignore"):
+        l = np.broadcast_to(np.sin((x * y)), shape)
+        return np.stack([l, l, l], axis=-1)
+
+
+def render(width, height, t=0.0, seed=SEED):
+    """Renders the image like kroyer, as an array of bytes with the shape (height, width, 3)"""
+    y, x = np.mgrid[0:height, 0:width]
+    values = pixel(x / width, y / height, t, seed)
+    with np.errstate(all="ignore"):
+        channels = np.nan_to_num((values + 1.0) * 127.5, nan=0.0)
+    return np.clip(channels, 0.0, 255.0).astype(np.uint8)
+"#;
+
+    #[test]
+    fn exports_a_program() {
+        let ast = NodeAst::parse_from_str("L: sin(mult(x, y))").unwrap();
+        assert_eq!(export_ast(&Python::new(42), &ast), PROGRAM);
+
+        let ast = NodeAst::parse_from_str("R: x\nG: y\nB: t").unwrap();
+        let program = export_ast(&Python::new(42), &ast);
+        let channels = "np.broadcast_to(x, shape),
+                np.broadcast_to(y, shape),
+                np.broadcast_to(t, shape),";
+        assert!(program.contains(channels), "{}", program);
+    }
+
+    #[test]
+    fn exported_programs_give_the_values_of_kroyer() {
+        // Rand nodes draw from numpy's generator in the export, so only the other nodes can match
+        let nodes = NodeType::as_list()
+            .into_iter()
+            .filter(|x| *x != NodeType::Rand);
+        let mut grammar = Grammar::new(nodes.map(|x| (x, 1)).collect());
+        let asts: Vec<NodeAst> = (0..24)
+            .map(|seed| {
+                let mut rng = ChaCha20Rng::seed_from_u64(seed);
+                NodeAst::from_grammar_with(&mut grammar, 6, None, &mut rng)
+            })
+            .collect();
+        let points = [
+            (0., 0., 0.),
+            (0.25, 0.75, 1.),
+            (-1., 0.5, 3.5),
+            (1., -0.125, 6.),
+        ];
+
+        let dir = std::env::temp_dir().join(format!("kroyer-export-python-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (i, ast) in asts.iter().enumerate() {
+            let path = dir.join(format!("ast_{}.py", i));
+            std::fs::write(path, export_ast(&Python::new(0), ast)).unwrap();
+        }
+
+        // The programs are always compiled, but numpy is needed to run them
+        let python = |args: &[&str]| {
+            Command::new("python3")
+                .args(args)
+                .current_dir(&dir)
+                .output()
+                .expect("failed to run python3")
+        };
+        let compiled = python(&["-m", "compileall", "-q", "."]);
+        assert!(
+            compiled.status.success(),
+            "{}",
+            String::from_utf8_lossy(&compiled.stdout)
+        );
+        if !python(&["-c", "import numpy"]).status.success() {
+            std::fs::remove_dir_all(&dir).unwrap();
+            eprintln!("numpy isn't installed, so the exported programs weren't run");
+            return;
+        }
+
+        // The script prints the bits of the values of every AST at every point
+        let mut script = "import struct\n\ndef bits(value):\n    return struct.unpack(\"<Q\", struct.pack(\"<d\", value))[0]\n\n".to_owned();
+        for i in 0..asts.len() {
+            writeln!(script, "import ast_{}", i).unwrap();
+            for (x, y, t) in points {
+                writeln!(
+                    script,
+                    "print(*(f\"{{bits(v):x}}\" for v in ast_{}.pixel({:?}, {:?}, {:?})))",
+                    i, x, y, t
+                )
+                .unwrap();
+            }
+        }
+        let output = python(&["-c", &script]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut lines = stdout.lines();
+        for (i, ast) in asts.iter().enumerate() {
+            for (x, y, t) in points {
+                let values: Vec<f64> = lines
+                    .next()
+                    .unwrap()
+                    .split(' ')
+                    .map(|x| f64::from_bits(u64::from_str_radix(x, 16).unwrap()))
+                    .collect();
+                // numpy's sin, pow and the like can round differently than Rust's
+                for (channel, value) in values.into_iter().enumerate() {
+                    let expected = ast.channel(channel).get_value(x, y, t);
+                    assert!(
+                        approx_eq(value, expected),
+                        "channel {} of AST {} gave {} instead of {} at ({}, {}, {})\n{}",
+                        channel,
+                        i,
+                        value,
+                        expected,
+                        x,
+                        y,
+                        t,
+                        ast
+                    );
+                }
+            }
+        }
+    }
+}
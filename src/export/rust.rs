@@ -0,0 +1,313 @@
+use std::cell::Cell;
+
+use crate::node::Node;
+
+use super::{CodegenBackend, comparison};
+
+/// Exports ASTs as a standalone Rust function, `pixel(x, y, t)`, which evaluates the channels
+/// exactly like kroyer does. Random values come from an inline PRNG, seeded with `seed` and the
+/// arguments
+pub struct RustSource {
+    seed: u64,
+    /// If any exported node uses the PRNG
+    uses_rand: Cell<bool>,
+}
+
+impl RustSource {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            uses_rand: Cell::new(false),
+        }
+    }
+}
+
+impl CodegenBackend for RustSource {
+    fn literal(&self, value: f64) -> String {
+        if value.is_nan() {
+            "f64::NAN".to_owned()
+        } else if value.is_infinite() {
+            let sign = if value > 0. { "" } else { "-" };
+            format!("({}f64::INFINITY)", sign)
+        } else if value < 0. {
+            format!("({:?}_f64)", value)
+        } else {
+            format!("{:?}_f64", value)
+        }
+    }
+
+    fn node(&self, node: &Node, children: &[String]) -> String {
+        let child = |i: usize| &children[i];
+
+        match node {
+            Node::X => "x".to_owned(),
+            Node::Y => "y".to_owned(),
+            Node::T => "t".to_owned(),
+            Node::Rand(_) => {
+                self.uses_rand.set(true);
+                "rand()".to_owned()
+            }
+            Node::Literal(value) => self.literal(*value),
+            Node::Mult(..) => format!("({} * {})", child(0), child(1)),
+            // kroyer evaluates the operands of add and sub in reverse order
+            Node::Add(..) => format!("({} + {})", child(1), child(0)),
+            Node::Sub(..) => format!("({} - {})", child(1), child(0)),
+            Node::Div(..) => format!(
+                "({{ let rhs = {}; {} / if rhs != 0.0 {{ rhs }} else {{ f64::EPSILON }} }})",
+                child(1),
+                child(0)
+            ),
+            Node::Pow(..) => format!("{}.powf({})", child(0), child(1)),
+            Node::Sqrt(..) => format!("{}.sqrt()", child(0)),
+            Node::Mod(..) => format!("({} % {})", child(0), child(1)),
+            Node::Max(..) => format!("{}.max({})", child(0), child(1)),
+            Node::Min(..) => format!("{}.min({})", child(0), child(1)),
+            Node::Sin(..) => format!("{}.sin()", child(0)),
+            Node::Cos(..) => format!("{}.cos()", child(0)),
+            Node::Tan(..) => format!("{}.tan()", child(0)),
+            Node::Abs(..) => format!("{}.abs()", child(0)),
+            Node::If(if_node) => {
+                let operator = comparison(if_node.operator());
+                format!(
+                    "(if {} {} {} {{ {} }} else {{ {} }})",
+                    child(0),
+                    operator,
+                    child(1),
+                    child(2),
+                    child(3)
+                )
+            }
+        }
+    }
+
+    fn program(&self, channels: &[String]) -> String {
+        let rand = if self.uses_rand.get() {
+            format!(
+                "    // SplitMix64, seeded with the seed of the image and the arguments
+    let mut state = {:#x}_u64 ^ x.to_bits().rotate_left(17) ^ y.to_bits().rotate_left(37) ^ t.to_bits();
+    let mut rand = || {{
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1_u64 << 53) as f64 * 2.0 - 1.0
+    }};
+
+",
+                self.seed
+            )
+        } else {
+            String::new()
+        };
+
+        let color = match channels {
+            [l] => format!("    let l = {};\n    (l, l, l)", l),
+            [r, g, b] => format!(
+                "    (\n        {},\n        {},\n        {},\n    )",
+                r, g, b
+            ),
+            _ => unreachable!("an AST has either one or three channels"),
+        };
+
+        format!(
+            "// Generated by kroyer
+
+/// Gets the red, green and blue values of a pixel. `x` and `y` go from 0 to 1 across the image.
+/// kroyer maps the values from -1..=1 to 0..=255, saturating values outside of that range
+#[allow(unused_variables, unused_parens, unused_braces, clippy::all)]
+pub fn pixel(x: f64, y: f64, t: f64) -> (f64, f64, f64) {{
+{}{}
+}}
+",
+            rand, color
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fmt::Write as _, process::Command};
+
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    use crate::{
+        export::{export_ast, tests::export},
+        grammar::Grammar,
+        node::{NodeType, ast::NodeAst},
+    };
+
+    use super::*;
+
+    #[test]
+    fn exports_each_node() {
+        let cases = [
+            ("x", "x"),
+            ("y", "y"),
+            ("t", "t"),
+            ("rand", "rand()"),
+            ("0.5", "0.5_f64"),
+            ("-0.5", "(-0.5_f64)"),
+            ("inf", "(f64::INFINITY)"),
+            ("-inf", "(-f64::INFINITY)"),
+            ("NaN", "f64::NAN"),
+            ("mult(x, y)", "(x * y)"),
+            ("add(x, y)", "(y + x)"),
+            ("sub(x, y)", "(y - x)"),
+            (
+                "div(x, y)",
+                "({ let rhs = y; x / if rhs != 0.0 { rhs } else { f64::EPSILON } })",
+            ),
+            ("pow(x, y)", "x.powf(y)"),
+            ("sqrt(x)", "x.sqrt()"),
+            ("mod(x, y)", "(x % y)"),
+            ("max(x, y)", "x.max(y)"),
+            ("min(x, y)", "x.min(y)"),
+            ("sin(x)", "x.sin()"),
+            ("cos(x)", "x.cos()"),
+            ("tan(x)", "x.tan()"),
+            ("abs(x)", "x.abs()"),
+            ("(x < y ? t : 0.5)", "(if x < y { t } else { 0.5_f64 })"),
+            ("(x > y ? t : 0.5)", "(if x > y { t } else { 0.5_f64 })"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(export(&RustSource::new(0), source), expected, "{}", source);
+        }
+    }
+
+    /// The AST of `PROGRAM`
+    const PROGRAM_AST: &str = "R: sin(mult(x, y))\nG: rand\nB: inf";
+
+    /// The export of `PROGRAM_AST`, with a seed of 42
+    const PROGRAM: &str = "// Generated by kroyer
+
+/// Gets the red, green and blue values of a pixel. `x` and `y` go from 0 to 1 across the image.
+/// kroyer maps the values from -1..=1 to 0..=255, saturating values outside of that range
+#[allow(unused_variables, unused_parens, unused_braces, clippy::all)]
+pub fn pixel(x: f64, y: f64, t: f64) -> (f64, f64, f64) {
+    // SplitMix64, seeded with the seed of the image and the arguments
+    let mut state = 0x2a_u64 ^ x.to_bits().rotate_left(17) ^ y.to_bits().rotate_left(37) ^ t.to_bits();
+    let mut rand = || {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1_u64 << 53) as f64 * 2.0 - 1.0
+    };
+
+    (
+        (x * y).sin(),
+        rand(),
+        (f64::INFINITY),
+    )
+}
+";
+
+    #[test]
+    fn exports_a_program() {
+        let ast = NodeAst::parse_from_str(PROGRAM_AST).unwrap();
+        assert_eq!(export_ast(&RustSource::new(42), &ast), PROGRAM);
+
+        // Programs without rand nodes don't have the PRNG, and grayscale ones have one expression
+        let ast = NodeAst::parse_from_str("L: abs(t)").unwrap();
+        let program = export_ast(&RustSource::new(42), &ast);
+        assert!(!program.contains("SplitMix64"), "{}", program);
+        assert!(
+            program.ends_with("{\n    let l = t.abs();\n    (l, l, l)\n}\n"),
+            "{}",
+            program
+        );
+    }
+
+    #[test]
+    fn compiled_programs_give_the_values_of_kroyer() {
+        // Rand nodes draw from another PRNG in the export, so only the other nodes can match
+        let nodes = NodeType::as_list()
+            .into_iter()
+            .filter(|x| *x != NodeType::Rand);
+        let mut grammar = Grammar::new(nodes.map(|x| (x, 1)).collect());
+        let asts: Vec<NodeAst> = (0..24)
+            .map(|seed| {
+                let mut rng = ChaCha20Rng::seed_from_u64(seed);
+                NodeAst::from_grammar_with(&mut grammar, 6, None, &mut rng)
+            })
+            .collect();
+        let points = [
+            (0., 0., 0.),
+            (0.25, 0.75, 1.),
+            (-1., 0.5, 3.5),
+            (1., -0.125, 6.),
+        ];
+
+        // Every program is a module of a binary, which prints the bits of the values
+        let mut source = format!("mod program {{\n{}}}\n", PROGRAM);
+        for (i, ast) in asts.iter().enumerate() {
+            let program = export_ast(&RustSource::new(0), ast);
+            writeln!(source, "mod ast_{} {{\n{}}}", i, program).unwrap();
+        }
+        source.push_str("fn main() {\n    _ = program::pixel(0.0, 0.0, 0.0);\n");
+        for i in 0..asts.len() {
+            for (x, y, t) in points {
+                writeln!(
+                    source,
+                    "    let (r, g, b) = ast_{}::pixel({:?}, {:?}, {:?});",
+                    i, x, y, t
+                )
+                .unwrap();
+                source.push_str(
+                    "    println!(\"{:x} {:x} {:x}\", r.to_bits(), g.to_bits(), b.to_bits());\n",
+                );
+            }
+        }
+        source.push_str("}\n");
+
+        let dir = std::env::temp_dir().join(format!("kroyer-export-rust-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (main, binary) = (dir.join("main.rs"), dir.join("main"));
+        std::fs::write(&main, source).unwrap();
+        let rustc = std::env::var("RUSTC").unwrap_or("rustc".to_owned());
+        let compiled = Command::new(rustc)
+            .args(["--edition", "2024", "-C", "opt-level=0", "-o"])
+            .args([&binary, &main])
+            .output()
+            .expect("failed to run rustc");
+        assert!(
+            compiled.status.success(),
+            "{}",
+            String::from_utf8_lossy(&compiled.stderr)
+        );
+        let output = Command::new(&binary).output().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut lines = stdout.lines();
+        for (i, ast) in asts.iter().enumerate() {
+            for (x, y, t) in points {
+                let values: Vec<f64> = lines
+                    .next()
+                    .unwrap()
+                    .split(' ')
+                    .map(|x| f64::from_bits(u64::from_str_radix(x, 16).unwrap()))
+                    .collect();
+                for (channel, value) in values.into_iter().enumerate() {
+                    let expected = ast.channel(channel).get_value(x, y, t);
+                    assert!(
+                        value.to_bits() == expected.to_bits()
+                            || value.is_nan() && expected.is_nan(),
+                        "channel {} of AST {} gave {} instead of {} at ({}, {}, {})\n{}",
+                        channel,
+                        i,
+                        value,
+                        expected,
+                        x,
+                        y,
+                        t,
+                        ast
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use crate::node::Node;
+
+use super::{CodegenBackend, comparison};
+
+/// Exports ASTs as a WGSL fragment shader. The shader reads the resolution of the image and the
+/// time from a uniform, and colors every pixel like kroyer would
+pub struct Wgsl;
+
+/// The uniform the fragment shader reads the resolution, time and seed from
+const PARAMS: &str = "\
+struct Params {
+    resolution: vec2<f32>,
+    t: f32,
+    seed: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+";
+
+/// The functions used by the exported expressions, where WGSL differs from kroyer. Rand nodes
+/// read the seed from `params.seed`, so every shader using them declares a `params` with a seed
+pub const FUNCTIONS: &str = "
+// A PCG hash
+fn kroyer_hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Gets the value of a rand node at a point, from a hash of the point, the seed and the salt of
+// the node, like kroyer does. kroyer hashes the 64-bit coordinates, so the values differ from
+// the ones it renders, but are just as stable
+fn kroyer_rand(x: f32, y: f32, salt: u32) -> f32 {
+    var hash = kroyer_hash(params.seed ^ kroyer_hash(salt));
+    hash = kroyer_hash(hash ^ bitcast<u32>(x));
+    hash = kroyer_hash(hash ^ bitcast<u32>(y));
+    return f32(hash) / 4294967295.0 * 2.0 - 1.0;
+}
+
+fn kroyer_div(lhs: f32, rhs: f32) -> f32 {
+    if (rhs == 0.0) {
+        return lhs / 2.220446e-16;
+    }
+    return lhs / rhs;
+}
+
+// WGSL leaves pow() undefined for negative bases, so the sign is handled here
+fn kroyer_pow(base: f32, exponent: f32) -> f32 {
+    if (base >= 0.0) {
+        return pow(base, exponent);
+    }
+    if (fract(exponent) != 0.0) {
+        return bitcast<f32>(0x7fc00000u);
+    }
+    let value = pow(-base, exponent);
+    if (fract(exponent * 0.5) == 0.0) {
+        return value;
+    }
+    return -value;
+}
+";
+
+/// The functions only the fragment shader uses
+const FRAGMENT: &str = "
+// Maps a value from -1..=1 to a color channel, like kroyer does when rendering
+fn kroyer_channel(value: f32) -> f32 {
+    if (value != value) {
+        return 0.0;
+    }
+    return floor(clamp((value + 1.0) * 127.5, 0.0, 255.0)) / 255.0;
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    // A triangle which covers the whole screen
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+";
+
+impl CodegenBackend for Wgsl {
+    fn literal(&self, value: f64) -> String {
+        let value = value as f32;
+        if value.is_nan() {
+            "bitcast<f32>(0x7fc00000u)".to_owned()
+        } else if value.is_infinite() {
+            let bits = if value > 0. {
+                "0x7f800000u"
+            } else {
+                "0xff800000u"
+            };
+            format!("bitcast<f32>({})", bits)
+        } else if value < 0. {
+            format!("({:?})", value)
+        } else {
+            format!("{:?}", value)
+        }
+    }
+
+    fn node(&self, node: &Node, children: &[String]) -> String {
+        let child = |i: usize| &children[i];
+
+        match node {
+            Node::X => "x".to_owned(),
+            Node::Y => "y".to_owned(),
+            Node::T => "t".to_owned(),
+            Node::Rand(salt) => format!("kroyer_rand(x, y, {}u)", salt),
+            Node::Literal(value) => self.literal(*value),
+            Node::Mult(..) => format!("({} * {})", child(0), child(1)),
+            // kroyer evaluates the operands of add and sub in reverse order
+            Node::Add(..) => format!("({} + {})", child(1), child(0)),
+            Node::Sub(..) => format!("({} - {})", child(1), child(0)),
+            Node::Div(..) => format!("kroyer_div({}, {})", child(0), child(1)),
+            Node::Pow(..) => format!("kroyer_pow({}, {})", child(0), child(1)),
+            Node::Sqrt(..) => format!("sqrt({})", child(0)),
+            Node::Mod(..) => format!("({} % {})", child(0), child(1)),
+            Node::Max(..) => format!("max({}, {})", child(0), child(1)),
+            Node::Min(..) => format!("min({}, {})", child(0), child(1)),
+            Node::Sin(..) => format!("sin({})", child(0)),
+            Node::Cos(..) => format!("cos({})", child(0)),
+            Node::Tan(..) => format!("tan({})", child(0)),
+            Node::Abs(..) => format!("abs({})", child(0)),
+            Node::If(if_node) => {
+                let operator = comparison(if_node.operator());
+                format!(
+                    "select({}, {}, {} {} {})",
+                    child(3),
+                    child(2),
+                    child(0),
+                    operator,
+                    child(1)
+                )
+            }
+        }
+    }
+
+    fn program(&self, channels: &[String]) -> String {
+        let color = match channels {
+            [l] => format!(
+                "    let l = kroyer_channel({});\n    return vec3<f32>(l, l, l);",
+                l
+            ),
+            [r, g, b] => format!(
+                "    return vec3<f32>(\n        kroyer_channel({}),\n        kroyer_channel({}),\n        kroyer_channel({}),\n    );",
+                r, g, b
+            ),
+            _ => unreachable!("an AST has either one or three channels"),
+        };
+
+        format!(
+            "// Generated by kroyer
+{}{}{}
+// Gets the color of a pixel. `x` and `y` go from 0 to 1 across the image
+fn pixel(x: f32, y: f32, t: f32) -> vec3<f32> {{
+{}
+}}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {{
+    let coords = floor(position.xy) / params.resolution;
+    return vec4<f32>(pixel(coords.x, coords.y, params.t), 1.0);
+}}
+",
+            PARAMS, FUNCTIONS, FRAGMENT, color
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::tests::export;
+
+    use super::*;
+
+    #[test]
+    fn exports_each_node() {
+        let cases = [
+            ("x", "x"),
+            ("y", "y"),
+            ("t", "t"),
+            ("rand", "kroyer_rand(x, y, 0u)"),
+            ("0.5", "0.5"),
+            ("-0.5", "(-0.5)"),
+            ("mult(x, y)", "(x * y)"),
+            ("add(x, y)", "(y + x)"),
+            ("sub(x, y)", "(y - x)"),
+            ("div(x, y)", "kroyer_div(x, y)"),
+            ("pow(x, y)", "kroyer_pow(x, y)"),
+            ("sqrt(x)", "sqrt(x)"),
+            ("mod(x, y)", "(x % y)"),
+            ("max(x, y)", "max(x, y)"),
+            ("min(x, y)", "min(x, y)"),
+            ("sin(x)", "sin(x)"),
+            ("cos(x)", "cos(x)"),
+            ("tan(x)", "tan(x)"),
+            ("abs(x)", "abs(x)"),
+            ("(x < y ? t : 0.5)", "select(0.5, t, x < y)"),
+            ("(x > y ? t : 0.5)", "select(0.5, t, x > y)"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(export(&Wgsl, source), expected, "{}", source);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::path::{Component, Path, PathBuf};
+
+use image::{ImageBuffer, ImageFormat, Rgba, imageops};
+
+use crate::error::{Error, Result};
+
+/// The longest side of a thumbnail, in pixels
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// An image shown in the gallery
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// The path the image was saved to
+    pub image: PathBuf,
+    /// The seed of the image, in hex with a 0x prefix, as printed by --dump-seed
+    pub seed: String,
+    /// The depth the AST was generated with
+    pub depth: usize,
+    /// The AST, in the default format
+    pub ast: String,
+}
+
+/// An HTML page of the images of a run, written to `index.html` in its directory
+pub struct Gallery {
+    dir: PathBuf,
+    /// The entries, with the file name of their thumbnail in the thumbs directory, if any
+    entries: Vec<(Entry, Option<String>)>,
+}
+
+impl Gallery {
+    /// Creates the directory of the gallery, and its thumbs directory
+    pub fn new(dir: &Path) -> Result<Self> {
+        if let Err(e) = std::fs::create_dir_all(dir.join("thumbs")) {
+            return Err(Error::Io(format!(
+                "Failed to create gallery directory {:?}.\nDetails: {}",
+                dir, e
+            )));
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            entries: vec![],
+        })
+    }
+
+    /// Adds the image, and writes the page again. The thumbnail is scaled down from `img`, the
+    /// already rendered image, so the image isn't rendered twice. Images which weren't kept
+    /// after being encoded, like streamed or float images, are shown without one
+    pub fn add(
+        &mut self,
+        entry: Entry,
+        img: Option<&ImageBuffer<Rgba<u8>, Vec<u8>>>,
+    ) -> Result<()> {
+        let thumbnail = img
+            .map(|img| {
+                let name = format!("{:04}-{}.png", self.entries.len(), entry.seed);
+                let path = self.dir.join("thumbs").join(&name);
+                match thumbnail(img).save_with_format(&path, ImageFormat::Png) {
+                    Ok(()) => Ok(name),
+                    Err(e) => Err(Error::Io(format!(
+                        "Failed to save thumbnail {:?}.\nDetails: {}",
+                        path, e
+                    ))),
+                }
+            })
+            .transpose()?;
+        self.entries.push((entry, thumbnail));
+
+        let path = self.dir.join("index.html");
+        crate::io::write_file(&path, &self.to_html(), true).map_err(|e| {
+            Error::Io(format!(
+                "Failed to write gallery {:?}.\nDetails: {}",
+                path, e
+            ))
+        })
+    }
+
+    fn to_html(&self) -> String {
+        let cards: Vec<String> = self
+            .entries
+            .iter()
+            .map(|(entry, thumbnail)| {
+                let href = escape(&relative_path(&self.dir, &entry.image));
+                let preview = match thumbnail {
+                    Some(name) => format!("<img src=\"thumbs/{}\" alt=\"{}\">", escape(name), href),
+                    None => "<div class=\"missing\">no thumbnail</div>".to_owned(),
+                };
+                format!(
+                    "<figure>\n<a href=\"{}\">{}</a>\n<figcaption>seed <code>{}</code>, depth {}</figcaption>\n<details><summary>AST</summary><pre>{}</pre></details>\n</figure>",
+                    href,
+                    preview,
+                    escape(&entry.seed),
+                    entry.depth,
+                    escape(&entry.ast)
+                )
+            })
+            .collect();
+
+        format!(
+            "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>kroyer gallery</title>
+<style>
+body {{ font-family: sans-serif; background: #222; color: #ddd; }}
+main {{ display: grid; grid-template-columns: repeat(auto-fill, minmax({size}px, 1fr)); gap: 1em; }}
+figure {{ margin: 0; }}
+img, .missing {{ max-width: {size}px; max-height: {size}px; }}
+.missing {{ width: {size}px; height: {size}px; display: flex; align-items: center; justify-content: center; background: #333; }}
+code {{ word-break: break-all; }}
+pre {{ white-space: pre-wrap; word-break: break-all; font-size: 0.8em; }}
+a {{ color: #ddd; }}
+</style>
+</head>
+<body>
+<h1>kroyer gallery</h1>
+<main>
+{cards}
+</main>
+</body>
+</html>
+",
+            size = THUMBNAIL_SIZE,
+            cards = cards.join("\n")
+        )
+    }
+}
+
+/// Scales the image down so its longest side is at most `THUMBNAIL_SIZE`
+fn thumbnail(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let (width, height) = img.dimensions();
+    let longest = width.max(height);
+    if longest <= THUMBNAIL_SIZE {
+        return img.clone();
+    }
+    let scale = |x: u32| ((x as u64 * THUMBNAIL_SIZE as u64 / longest as u64) as u32).max(1);
+    imageops::thumbnail(img, scale(width), scale(height))
+}
+
+/// Gets the path of `path` relative to the directory `dir`, with forward slashes, so it can be
+/// linked from a page in it. Paths which can't be resolved are given as they are
+fn relative_path(dir: &Path, path: &Path) -> String {
+    let (Ok(dir), Ok(path)) = (dir.canonicalize(), path.canonicalize()) else {
+        return path.to_string_lossy().replace('\\', "/");
+    };
+    let dir: Vec<Component> = dir.components().collect();
+    let path: Vec<Component> = path.components().collect();
+    let common = dir.iter().zip(&path).take_while(|(a, b)| a == b).count();
+
+    let parts: Vec<String> = std::iter::repeat_n("..".to_owned(), dir.len() - common)
+        .chain(
+            path[common..]
+                .iter()
+                .map(|x| x.as_os_str().to_string_lossy().into_owned()),
+        )
+        .collect();
+    parts.join("/")
+}
+
+/// Escapes the text, so it can be put in HTML, both as text and in attributes
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
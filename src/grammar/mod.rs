@@ -1,28 +1,114 @@
 use std::{fmt::Display, fs::OpenOptions, io::Read, path::PathBuf};
 
-use rand::Rng;
+use rand::{Rng, seq::IndexedRandom};
 
-use crate::{node::NodeType, rng};
+use crate::{
+    error::{Error, Result},
+    log,
+    node::{self, Node, NodeType, Operator, ast::NodeAst, stats::NodeStats},
+    rng::{self, Stream, StreamRng},
+};
+
+/// The newest grammar file version this build of kroyer understands
+pub const GRAMMAR_VERSION: u32 = 3;
+
+/// The version of grammars without a `@kroyer-version` header. Newer versions change the images of
+/// existing seeds, so grammars only get them by declaring them
+const DEFAULT_VERSION: u32 = 2;
+
+/// The first version where rand nodes hash the point they are evaluated at, instead of drawing
+/// from the shared RNG. See `rng::set_legacy_rand()`
+const HASHED_RAND_VERSION: u32 = 2;
+
+/// The first version where the structure, literals and rand nodes draw from separate streams.
+/// See `rng::set_separate_streams()`
+const SEPARATE_STREAMS_VERSION: u32 = 3;
+
+/// The orders trees are drawn from a seed in. Each new order changes the images of existing
+/// seeds, so it gets a new grammar version, and the old orders are kept, so a seed always
+/// recreates its image with the version it was made with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GenerationVersion {
+    /// Every draw comes from the shared RNG, like with `Shared`, and rand nodes draw a new value
+    /// from it at every pixel, like with --legacy-rand. Used by grammars of version 1
+    LegacyRand,
+    /// Every draw comes from the shared RNG, in the order the tree is generated in. Used by
+    /// grammars of version 2
+    Shared,
+    /// The structure, literals and rand nodes draw from separate streams. Used from version 3
+    Streams,
+}
+
+impl GenerationVersion {
+    /// Gets the generation of a grammar version
+    pub fn from_version(version: u32) -> Self {
+        if version >= SEPARATE_STREAMS_VERSION {
+            GenerationVersion::Streams
+        } else if version >= HASHED_RAND_VERSION {
+            GenerationVersion::Shared
+        } else {
+            GenerationVersion::LegacyRand
+        }
+    }
+
+    /// Checks if rand nodes draw from the shared RNG at every pixel
+    pub fn legacy_rand(&self) -> bool {
+        *self == GenerationVersion::LegacyRand
+    }
+
+    /// Checks if the structure, literals and rand nodes draw from separate streams
+    pub fn separate_streams(&self) -> bool {
+        *self == GenerationVersion::Streams
+    }
+}
 
 /// Holds the node and the weigth of the node in the tree
 #[derive(Clone, Debug)]
 pub struct Grammar {
     pub rules: Vec<(NodeType, usize)>,
+    /// The version declared by the `@kroyer-version` header, if any
+    pub version: Option<u32>,
+    /// The weights of the operators used in if statements, set with the `@if-operators`
+    /// directive. If this is `None`, the operators will be picked uniformly
+    pub if_operators: Option<Vec<(Operator, usize)>>,
 }
 
 impl Grammar {
     pub fn new(rules: Vec<(NodeType, usize)>) -> Self {
-        Self { rules }
+        Self {
+            rules,
+            version: None,
+            if_operators: None,
+        }
+    }
+
+    /// Gets the version of the grammar, which is the one of its header, if it has one
+    pub fn version(&self) -> u32 {
+        self.version.unwrap_or(DEFAULT_VERSION)
     }
 
+    /// Gets the order trees are drawn from a seed in, which depends on the version
+    pub fn generation(&self) -> GenerationVersion {
+        GenerationVersion::from_version(self.version())
+    }
+
+    /// Picks a node type from the rules, by their weights
+    #[deprecated(note = "draws from the seeded RNG, use `Grammar::pick_with()` instead")]
+    #[allow(dead_code)]
     pub fn pick(&mut self) -> NodeType {
+        self.pick_with(&mut rng::Seeded)
+    }
+
+    /// Picks a node type from the rules, by their weights, drawing from the structure stream of
+    /// `rng`
+    pub fn pick_with(&mut self, rng: &mut impl StreamRng) -> NodeType {
         let total = self.rules.iter().fold(0, |a, x| a + x.1);
 
         if total == 0 {
             return NodeType::Literal;
         }
 
-        let choice = rng::get_rng().random_range(0..total);
+        let choice = rng.draw(Stream::Structure, |rng| rng.random_range(0..total));
 
         let mut acc = 0;
         for rule in &self.rules {
@@ -34,6 +120,35 @@ impl Grammar {
         panic!("CHOICE SHOULD ALWAYS BE UNDER TOTAL WEIGHTS");
     }
 
+    /// Picks an operator for an if statement, using the weights from `@if-operators` if they
+    /// are set. The operator is drawn from the literals stream of `rng`
+    pub fn pick_operator(&mut self, rng: &mut impl StreamRng) -> Operator {
+        let Some(operators) = &self.if_operators else {
+            return rng.draw(Stream::Literals, |rng| {
+                Operator::as_list().choose(rng).cloned().unwrap()
+            });
+        };
+
+        let total = operators.iter().fold(0, |a, x| a + x.1);
+
+        if total == 0 {
+            return rng.draw(Stream::Literals, |rng| {
+                Operator::as_list().choose(rng).cloned().unwrap()
+            });
+        }
+
+        let choice = rng.draw(Stream::Literals, |rng| rng.random_range(0..total));
+
+        let mut acc = 0;
+        for (operator, weight) in operators {
+            acc += weight;
+            if choice < acc {
+                return operator.clone();
+            }
+        }
+        panic!("CHOICE SHOULD ALWAYS BE UNDER TOTAL WEIGHTS");
+    }
+
     /// Parses a Grammar struct from a given string.
     /// The grammar of a grammar file is as such:
     /// `node: weight`
@@ -44,8 +159,14 @@ impl Grammar {
     /// sub: 2
     /// add: 3
     /// ```
-    pub fn parse_from_str(content: &str) -> Self {
+    /// Lines starting with `@` are directives, like the optional `@kroyer-version: 3` header.
+    /// Grammars without the header are version 2, which keeps the images of their seeds.
+    /// Grammars with rules have to include a terminable node, so trees can end
+    pub fn parse_from_str(content: &str) -> Result<Self> {
         let mut rules: Vec<(NodeType, usize)> = vec![];
+        let mut version: Option<u32> = None;
+        let mut if_operators: Option<Vec<(Operator, usize)>> = None;
+        let mut unknown_directives: Vec<String> = vec![];
 
         for (i, line) in content.trim().lines().enumerate() {
             let (rule, _) = line.split_once("#").unwrap_or((line, ""));
@@ -53,26 +174,69 @@ impl Grammar {
                 continue;
             }
 
+            if let Some(directive) = rule.trim().strip_prefix('@') {
+                let (name, value) = directive.split_once(":").unwrap_or((directive, ""));
+                let (name, value) = (name.trim(), value.trim());
+
+                match name {
+                    "kroyer-version" => {
+                        let Ok(file_version) = value.parse::<u32>() else {
+                            return Err(Error::Grammar(format!(
+                                "Given grammar has invalid version \"{}\" at line {}:\n\"{}\"",
+                                value, i, line
+                            )));
+                        };
+
+                        if file_version > GRAMMAR_VERSION {
+                            return Err(Error::Grammar(format!(
+                                "Given grammar requires kroyer grammar version {}, but this version of kroyer only supports up to version {}. Try updating kroyer",
+                                file_version, GRAMMAR_VERSION
+                            )));
+                        }
+
+                        version = Some(file_version);
+                    }
+                    "if-operators" => {
+                        if_operators = Some(Self::parse_if_operators(value, i, line));
+                    }
+                    _ => {
+                        if !unknown_directives.iter().any(|x| x == name) {
+                            unknown_directives.push(name.to_owned());
+                        }
+                    }
+                }
+                continue;
+            }
+
             let Some((lhs, rhs)) = rule.split_once(":") else {
-                eprintln!(
-                    "[WARNING]: Given grammar missing delimeter \":\" at line {}:\n\"{}\"\nIgnoring line.",
-                    i, line,
+                log::warning!(
+                    "Given grammar missing delimeter \":\" at line {}:\n\"{}\"\nIgnoring line.",
+                    i,
+                    line,
                 );
                 continue;
             };
 
             let Ok(node_type) = NodeType::try_from(lhs.trim()) else {
-                eprintln!(
-                    "[WARNING]: Given grammar includes not recognized label \"{}\" at line: {}:\n\"{}\"\nIgnoring line.",
-                    lhs, i, line
+                let suggestion = node::closest_name(lhs.trim(), NodeType::names())
+                    .map(|name| format!("\nDid you mean \"{}\"?", name))
+                    .unwrap_or_default();
+                log::warning!(
+                    "Given grammar includes not recognized label \"{}\" at line: {}:\n\"{}\"{}\nIgnoring line.",
+                    lhs,
+                    i,
+                    line,
+                    suggestion
                 );
                 continue;
             };
 
             let Ok(weight) = rhs.trim().parse::<usize>() else {
-                eprintln!(
-                    "[WARNING]: Given grammar includes invalid weight of \"{}\" at line: {}:\n\"{}\"\nIgnoring line.",
-                    rhs, i, line
+                log::warning!(
+                    "Given grammar includes invalid weight of \"{}\" at line: {}:\n\"{}\"\nIgnoring line.",
+                    rhs,
+                    i,
+                    line
                 );
                 continue;
             };
@@ -80,34 +244,155 @@ impl Grammar {
             rules.push((node_type, weight));
         }
 
-        Grammar::new(rules)
+        if !unknown_directives.is_empty() {
+            log::warning!(
+                "Given grammar includes unknown directives: {}\nIgnoring them.",
+                unknown_directives
+                    .iter()
+                    .map(|x| format!("\"@{}\"", x))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        // Trees end in terminable nodes, so rules without one could never make a tree. Empty
+        // grammars only make literals, see `Grammar::pick()`
+        if !rules.is_empty() && !rules.iter().any(|x| x.0.is_end()) {
+            return Err(Error::Grammar(
+                "Grammar needs to include at least one element that is terminable".to_owned(),
+            ));
+        }
+
+        Ok(Self {
+            rules,
+            version,
+            if_operators,
+        })
     }
 
-    /// Parses a Grammar struct from a given file, via `Grammar::parse_from_str()`
-    pub fn parse_from_file(path: PathBuf) -> Self {
-        let mut file = match OpenOptions::new().read(true).open(&path) {
-            Ok(f) => f,
-            Err(e) => {
-                eprintln!(
-                    "[ERROR]: Failed to open grammar file {:?}.\nDetails: {}",
-                    path, e
+    /// Parses the value of the `@if-operators` directive, which is a comma separated list of
+    /// `operator: weight` pairs, like `<: 4, >: 4, ==: 1, !=: 1`.
+    /// Operators that aren't listed get a weight of 0
+    fn parse_if_operators(value: &str, i: usize, line: &str) -> Vec<(Operator, usize)> {
+        let mut operators: Vec<(Operator, usize)> = vec![];
+
+        for pair in value.split(',') {
+            if pair.trim().is_empty() {
+                continue;
+            }
+
+            let Some((lhs, rhs)) = pair.split_once(":") else {
+                log::warning!(
+                    "Given grammar has @if-operators entry \"{}\" missing delimeter \":\" at line {}:\n\"{}\"\nIgnoring entry.",
+                    pair.trim(),
+                    i,
+                    line
+                );
+                continue;
+            };
+
+            let Ok(operator) = Operator::try_from(lhs.trim()) else {
+                log::warning!(
+                    "Given grammar has unknown operator \"{}\" in @if-operators at line {}:\n\"{}\"\nIgnoring entry.",
+                    lhs.trim(),
+                    i,
+                    line
+                );
+                continue;
+            };
+
+            let Ok(weight) = rhs.trim().parse::<usize>() else {
+                log::warning!(
+                    "Given grammar has invalid weight \"{}\" in @if-operators at line {}:\n\"{}\"\nIgnoring entry.",
+                    rhs.trim(),
+                    i,
+                    line
                 );
-                std::process::exit(1);
+                continue;
+            };
+
+            operators.push((operator, weight));
+        }
+
+        if operators.iter().all(|x| x.1 == 0) {
+            log::warning!(
+                "Given grammar has no operators with a weight above 0 in @if-operators at line {}:\n\"{}\"\nOperators will be picked uniformly.",
+                i,
+                line
+            );
+        }
+
+        operators
+    }
+
+    /// Creates a grammar with the weight of each node set to how often it appears in `ast`, so
+    /// generated trees resemble it. The weights of the if operators are counted the same way.
+    /// Terminal nodes from the default grammar, which the AST has none of, are added with their
+    /// default weight
+    pub fn from_ast(ast: &NodeAst) -> Self {
+        let channels: Vec<&Node> = ast
+            .tree_channels()
+            .into_iter()
+            .map(|x| ast.channel(x).as_ref())
+            .collect();
+
+        let mut rules: Vec<(NodeType, usize)> = NodeType::as_list().map(|x| (x, 0)).to_vec();
+        let mut operators: Vec<(Operator, usize)> = Operator::as_list().map(|x| (x, 0)).to_vec();
+
+        for node in channels {
+            let stats = NodeStats::from_node(node);
+            for (node_type, weight) in rules.iter_mut() {
+                *weight += stats.count(*node_type);
             }
-        };
+
+            node.visit(&mut |node| {
+                if let Node::If(if_node) = node
+                    && let Some(count) = operators.iter_mut().find(|x| x.0 == *if_node.operator())
+                {
+                    count.1 += 1;
+                }
+            });
+        }
+
+        for (node_type, weight) in Self::default().rules {
+            if node_type.arg_num() == 0
+                && let Some(rule) = rules.iter_mut().find(|x| x.0 == node_type)
+                && rule.1 == 0
+            {
+                rule.1 = weight;
+            }
+        }
+
+        rules.retain(|x| x.1 > 0);
+        operators.retain(|x| x.1 > 0);
+
+        Self {
+            rules,
+            version: Some(GRAMMAR_VERSION),
+            if_operators: (!operators.is_empty()).then_some(operators),
+        }
+    }
+
+    /// Parses a Grammar struct from a given file, via `Grammar::parse_from_str()`
+    pub fn parse_from_file(path: PathBuf) -> Result<Self> {
+        let mut file = OpenOptions::new().read(true).open(&path).map_err(|e| {
+            Error::Io(format!(
+                "Failed to open grammar file {:?}.\nDetails: {}",
+                path, e
+            ))
+        })?;
 
         let mut buf = String::new();
         if let Err(e) = file.read_to_string(&mut buf) {
-            eprintln!(
-                "[ERROR]: Failed to read grammar file {:?}.\nDetails: {}",
+            return Err(Error::Io(format!(
+                "Failed to read grammar file {:?}.\nDetails: {}",
                 path, e
-            );
-            std::process::exit(1);
+            )));
         };
 
         if buf.trim().is_empty() {
-            eprintln!(
-                "[WARNING]: Given grammar file is empty. Use --dump-default-grammar to get the default grammar file"
+            log::warning!(
+                "Given grammar file is empty. Use --dump-default-grammar to get the default grammar file"
             );
         }
 
@@ -137,6 +422,15 @@ impl Default for Grammar {
 
 impl Display for Grammar {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "@kroyer-version: {}", self.version())?;
+        if let Some(operators) = &self.if_operators {
+            let list = operators
+                .iter()
+                .map(|(operator, weight)| format!("{}: {}", operator, weight))
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(f, "@if-operators: {}", list)?;
+        }
         for (node, weight) in &self.rules {
             writeln!(f, "{}: {}", node, weight)?;
         }
@@ -0,0 +1,62 @@
+/// A transfer function, which encodes linear colors for display
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transfer {
+    /// A plain power curve, with the exponent `1 / gamma`
+    Gamma(f64),
+    /// The piecewise curve of the sRGB standard
+    Srgb,
+}
+
+impl Transfer {
+    /// Encodes a linear value in 0..1
+    pub fn encode(&self, value: f64) -> f64 {
+        match self {
+            Transfer::Gamma(gamma) => value.powf(1. / gamma),
+            Transfer::Srgb if value <= 0.0031308 => value * 12.92,
+            Transfer::Srgb => 1.055 * value.powf(1. / 2.4) - 0.055,
+        }
+    }
+}
+
+/// Moves values from the -1..1 range of the trees to 0..1
+pub fn to_unit(values: [f64; 3]) -> [f64; 3] {
+    values.map(|x| (x + 1.) / 2.)
+}
+
+/// Moves values from 0..1 back to the -1..1 range of the trees
+pub fn from_unit(values: [f64; 3]) -> [f64; 3] {
+    values.map(|x| x * 2. - 1.)
+}
+
+/// Converts a hue, saturation and value into red, green and blue, all in 0..1. The hue wraps
+/// around, so 1.25 is the same as 0.25, while the saturation and value are clamped
+pub fn hsv_to_rgb([hue, saturation, value]: [f64; 3]) -> [f64; 3] {
+    let saturation = saturation.clamp(0., 1.);
+    let value = value.clamp(0., 1.);
+    let chroma = value * saturation;
+    from_chroma(hue, chroma, value - chroma)
+}
+
+/// Converts a hue, saturation and lightness into red, green and blue, all in 0..1. The hue wraps
+/// around, so 1.25 is the same as 0.25, while the saturation and lightness are clamped
+pub fn hsl_to_rgb([hue, saturation, lightness]: [f64; 3]) -> [f64; 3] {
+    let saturation = saturation.clamp(0., 1.);
+    let lightness = lightness.clamp(0., 1.);
+    let chroma = (1. - (2. * lightness - 1.).abs()) * saturation;
+    from_chroma(hue, chroma, lightness - chroma / 2.)
+}
+
+/// Gets the color with the given hue and chroma, with `min` added to every channel
+fn from_chroma(hue: f64, chroma: f64, min: f64) -> [f64; 3] {
+    let sector = hue.rem_euclid(1.) * 6.;
+    let x = chroma * (1. - (sector % 2. - 1.).abs());
+    let [r, g, b] = match sector as u32 {
+        0 => [chroma, x, 0.],
+        1 => [x, chroma, 0.],
+        2 => [0., chroma, x],
+        3 => [0., x, chroma],
+        4 => [x, 0., chroma],
+        _ => [chroma, 0., x],
+    };
+    [r + min, g + min, b + min]
+}
@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+
+use crate::{cli::HexColor, error::Error};
+
+/// The colors of --palette, which the value of the red tree picks from, instead of the trees
+/// giving the red, green and blue
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorMap {
+    colors: Vec<[u8; 3]>,
+    /// If the colors are blended between the entries, instead of the nearest one being used
+    smooth: bool,
+}
+
+impl ColorMap {
+    /// Reads the colors from a file, which is either a list of hex colors, separated by
+    /// whitespace or commas, or a PNG strip, whose colors are read along its longest side
+    pub fn read(path: &PathBuf, smooth: bool) -> Result<Self, Error> {
+        let result = std::fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| match bytes.starts_with(b"\x89PNG") {
+                true => read_strip(&bytes),
+                false => parse_colors(&String::from_utf8_lossy(&bytes)),
+            });
+
+        match result {
+            Ok(colors) => Ok(Self { colors, smooth }),
+            Err(e) => Err(Error::Io(format!(
+                "Failed to read palette from {:?}.\nDetails: {}",
+                path, e
+            ))),
+        }
+    }
+
+    /// Gets the colors, in order
+    pub fn colors(&self) -> &[[u8; 3]] {
+        &self.colors
+    }
+
+    /// Checks if the colors are blended between the entries
+    pub fn is_smooth(&self) -> bool {
+        self.smooth
+    }
+
+    /// Gets the red, green and blue, in the range of the trees, for a value from -1 to 1. -1 is
+    /// the first color, and 1 the last. Values outside of the range get the nearest end
+    pub fn color_at(&self, value: f64) -> [f64; 3] {
+        let last = self.colors.len() - 1;
+        let position = ((value + 1.) / 2.).clamp(0., 1.);
+
+        let color = match self.smooth {
+            true => {
+                let position = position * last as f64;
+                let index = (position.floor() as usize).min(last);
+                let frac = position - index as f64;
+                let (from, to) = (self.colors[index], self.colors[(index + 1).min(last)]);
+                [0, 1, 2].map(|i| from[i] as f64 + (to[i] as f64 - from[i] as f64) * frac)
+            }
+            // Every color gets an equal part of the range
+            false => {
+                let index = ((position * self.colors.len() as f64) as usize).min(last);
+                self.colors[index].map(|x| x as f64)
+            }
+        };
+        // Half a step is added, so the colors are the same after being truncated into 8 bits
+        color.map(|x| (x + 0.5).min(255.) / 127.5 - 1.)
+    }
+}
+
+/// Parses a list of hex colors, like `ff0000, #00ff00 0000ff`
+fn parse_colors(text: &str) -> Result<Vec<[u8; 3]>, String> {
+    let colors = text
+        .split(|x: char| x.is_whitespace() || x == ',')
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            x.parse::<HexColor>()
+                .map(|x| x.0)
+                .map_err(|e| format!("'{}': {}", x, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match colors.is_empty() {
+        true => Err("the palette has no colors".to_owned()),
+        false => Ok(colors),
+    }
+}
+
+/// Reads the colors along the middle of the longest side of a PNG
+fn read_strip(bytes: &[u8]) -> Result<Vec<[u8; 3]>, String> {
+    let img = image::load_from_memory_with_format(bytes, image::ImageFormat::Png)
+        .map_err(|e| e.to_string())?
+        .to_rgb8();
+    let (width, height) = img.dimensions();
+
+    let colors = match width >= height {
+        true => (0..width).map(|x| img.get_pixel(x, height / 2).0).collect(),
+        false => (0..height).map(|y| img.get_pixel(width / 2, y).0).collect(),
+    };
+    Ok(colors)
+}
@@ -0,0 +1,106 @@
+use std::{fmt::Display, io::Write, path::PathBuf};
+
+use image::{
+    ExtendedColorType, ImageBuffer, ImageEncoder, ImageResult, Rgba, codecs::png::PngEncoder,
+};
+
+use crate::error::Result;
+
+use super::save_image;
+
+/// How much two images of the same size differ
+#[derive(Clone, Debug, PartialEq)]
+pub struct Summary {
+    /// The amount of pixels in each image
+    pub pixels: u64,
+    /// The amount of pixels where any channel differs
+    pub differing: u64,
+    /// The average absolute difference of every channel, from 0 to 255
+    pub mean: f64,
+    /// The largest absolute difference of any channel
+    pub max: u8,
+}
+
+impl Summary {
+    /// Gets the percent of the pixels which differ
+    pub fn differing_percent(&self) -> f64 {
+        match self.pixels {
+            0 => 0.,
+            pixels => self.differing as f64 / pixels as f64 * 100.,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"pixels\": {}, \"differing_pixels\": {}, \"differing_percent\": {}, \"mean_difference\": {}, \"max_difference\": {}}}",
+            self.pixels,
+            self.differing,
+            self.differing_percent(),
+            self.mean,
+            self.max
+        )
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} of {} pixels differ ({:.2}%)",
+            self.differing,
+            self.pixels,
+            self.differing_percent()
+        )?;
+        writeln!(f, "mean difference: {:.3}", self.mean)?;
+        writeln!(f, "max difference:  {}", self.max)
+    }
+}
+
+/// Gets the absolute difference of each channel of two images of the same size, and a summary of
+/// it. The difference image is opaque, with the differences of the red, green and blue as its
+/// colors, so identical pixels are black. Differences in the alpha only count in the summary
+pub fn diff(
+    a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> (ImageBuffer<Rgba<u8>, Vec<u8>>, Summary) {
+    let mut img = ImageBuffer::new(a.width(), a.height());
+    let mut differing = 0;
+    let mut sum = 0;
+    let mut max = 0;
+    for ((pixel, a), b) in img.pixels_mut().zip(a.pixels()).zip(b.pixels()) {
+        let difference = [0, 1, 2, 3].map(|i| a.0[i].abs_diff(b.0[i]));
+        if difference.iter().any(|x| *x > 0) {
+            differing += 1;
+        }
+        sum += difference.iter().map(|x| *x as u64).sum::<u64>();
+        max = difference.into_iter().fold(max, u8::max);
+        *pixel = Rgba([difference[0], difference[1], difference[2], 255]);
+    }
+
+    let pixels = a.width() as u64 * a.height() as u64;
+    let summary = Summary {
+        pixels,
+        differing,
+        mean: match pixels {
+            0 => 0.,
+            pixels => sum as f64 / (pixels * 4) as f64,
+        },
+        max,
+    };
+    (img, summary)
+}
+
+/// Saves the difference image, in the format given by the extension of `path`
+pub fn gen_diff(path: PathBuf, img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<()> {
+    save_image(img, &path, None)
+}
+
+/// Encodes the difference image as a PNG into `writer`
+pub fn encode_diff(writer: impl Write, img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> ImageResult<()> {
+    PngEncoder::new(writer).write_image(
+        img.as_raw(),
+        img.width(),
+        img.height(),
+        ExtendedColorType::Rgba8,
+    )
+}
@@ -0,0 +1,37 @@
+use std::{io::Cursor, path::PathBuf};
+
+use image::{ImageFormat, Rgba, Rgba32FImage};
+
+use crate::{
+    error::{Error, Result},
+    node::ast::NodeAst,
+};
+
+use super::{RenderSettings, get_values, render, save_image};
+
+/// Saves the still image as an OpenEXR image. See `get_float_img()`
+pub fn gen_exr(path: PathBuf, settings: &RenderSettings, ast: &NodeAst) -> Result<()> {
+    let img = get_float_img(settings, settings.still_t, ast);
+    save_image(&img, &path, Some(ImageFormat::OpenExr))
+}
+
+/// Encodes the still image as an OpenEXR image, and returns the bytes of the file
+pub fn encode_exr(settings: &RenderSettings, ast: &NodeAst) -> Result<Vec<u8>> {
+    let img = get_float_img(settings, settings.still_t, ast);
+    let mut bytes = Cursor::new(vec![]);
+    if let Err(e) = img.write_to(&mut bytes, ImageFormat::OpenExr) {
+        return Err(Error::Io(format!("Failed to encode exr.\nDetails: {}", e)));
+    }
+    Ok(bytes.into_inner())
+}
+
+/// Renders the image at the time `t` with float colors. The values of the trees are moved from
+/// -1..1 to 0..1, like the 0..255 of `get_img()`, but are neither clamped nor rounded, so values
+/// outside of that range, and NaN, are kept as they are
+pub fn get_float_img(settings: &RenderSettings, t: f64, ast: &NodeAst) -> Rgba32FImage {
+    let program = ast.compile();
+    render(settings, ast, |x, y, stats| {
+        let values = get_values(x, y, settings, t, &program, stats);
+        Rgba(values.map(|x| ((x + 1.) / 2.) as f32))
+    })
+}
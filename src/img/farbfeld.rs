@@ -0,0 +1,35 @@
+use std::{io::Write, path::PathBuf};
+
+use crate::{
+    error::{Error, Result},
+    node::ast::NodeAst,
+};
+
+use super::{RenderSettings, commit_file, create_file, get_img_16};
+
+/// Saves the still image as a farbfeld image. See `encode_farbfeld()`
+pub fn gen_farbfeld(path: PathBuf, settings: &RenderSettings, ast: &NodeAst) -> Result<()> {
+    let mut file = create_file(&path)?;
+    encode_farbfeld(&mut file, settings, ast)?;
+    commit_file(file, &path)
+}
+
+/// Writes the still image as a farbfeld image into `writer`. The colors have 16 bits per
+/// channel, so they keep more of the values of the trees than 8-bit images
+pub fn encode_farbfeld(
+    mut writer: impl Write,
+    settings: &RenderSettings,
+    ast: &NodeAst,
+) -> Result<()> {
+    let img = get_img_16(settings, settings.still_t, ast);
+    let mut encode = || -> std::io::Result<()> {
+        writer.write_all(b"farbfeld")?;
+        writer.write_all(&img.width().to_be_bytes())?;
+        writer.write_all(&img.height().to_be_bytes())?;
+        let samples: Vec<u8> = img.as_raw().iter().flat_map(|x| x.to_be_bytes()).collect();
+        writer.write_all(&samples)?;
+        writer.flush()
+    };
+
+    encode().map_err(|e| Error::Io(format!("Failed to write farbfeld image.\nDetails: {}", e)))
+}
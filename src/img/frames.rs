@@ -0,0 +1,114 @@
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    error::{Error, Result},
+    node::ast::NodeAst,
+};
+
+use super::{
+    Animation, RenderSettings, commit_file, create_file, get_img, meta::Metadata, uses_rand,
+    write_png,
+};
+
+/// The least amount of digits in the number of a frame
+const MIN_DIGITS: usize = 4;
+
+/// Saves the frames in `range` as numbered PNGs with the metadata, at the paths given by
+/// `template`. See `frame_path()`. Each frame is saved as soon as it is rendered, so only the
+/// frames being rendered are in memory
+pub fn gen_frames(
+    template: &Path,
+    range: Range<u32>,
+    settings: &RenderSettings,
+    animation: &Animation,
+    metadata: &Metadata,
+    ast: &NodeAst,
+) -> Result<()> {
+    // The padding depends on the whole animation, so the names are the same for every range
+    let digits = animation
+        .frames
+        .saturating_sub(1)
+        .to_string()
+        .len()
+        .max(MIN_DIGITS);
+    if let Some(dir) = frame_path(template, 0, digits).parent()
+        && !dir.as_os_str().is_empty()
+        && let Err(e) = std::fs::create_dir_all(dir)
+    {
+        return Err(Error::Io(format!(
+            "Failed to create directory {:?}.\nDetails: {}",
+            dir, e
+        )));
+    }
+
+    let save_frame = |i: u32| {
+        let img = get_img(settings, animation.t(i), ast);
+        let path = frame_path(template, i, digits);
+        let mut file = create_file(&path)?;
+        write_png(&mut file, &img, metadata)?;
+        commit_file(file, &path)
+    };
+
+    // Like in `get_frames()`, frames using legacy rand are rendered in order on one thread
+    if uses_rand(ast) {
+        range.into_iter().try_for_each(save_frame)
+    } else {
+        range.into_par_iter().try_for_each(save_frame)
+    }
+}
+
+/// Gets the path of a frame from the template. The first `%d` in the file name is replaced with
+/// the number of the frame, padded with zeros to the width it gives, like `%04d`, or to `digits`.
+/// Templates without one get the number added to the end of the file name, like `frame-0001.png`
+pub fn frame_path(template: &Path, frame: u32, digits: usize) -> PathBuf {
+    let name = template.file_name().unwrap_or_default().to_string_lossy();
+
+    let name = match find_placeholder(&name) {
+        Some((start, end, width)) => format!(
+            "{}{:0width$}{}",
+            &name[..start],
+            frame,
+            &name[end..],
+            width = width.unwrap_or(digits)
+        ),
+        None => {
+            let path = Path::new(name.as_ref());
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            match path.extension() {
+                Some(extension) => format!(
+                    "{}-{:0digits$}.{}",
+                    stem,
+                    frame,
+                    extension.to_string_lossy()
+                ),
+                None => format!("{}-{:0digits$}", stem, frame),
+            }
+        }
+    };
+    template.with_file_name(name)
+}
+
+/// Checks if the file name of the path has a placeholder for the number of a frame, like `%04d`
+pub fn is_template(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    find_placeholder(&name).is_some()
+}
+
+/// Finds the first placeholder like `%d` or `%04d` in a file name, and gets where it starts and
+/// ends, and the width it gives, if any
+fn find_placeholder(name: &str) -> Option<(usize, usize, Option<usize>)> {
+    name.match_indices('%').find_map(|(start, _)| {
+        let rest = &name[start + 1..];
+        let len = rest.find(|x: char| !x.is_ascii_digit())?;
+        if !rest[len..].starts_with('d') {
+            return None;
+        }
+        let width = rest[..len].parse().ok();
+        Some((start, start + len + 2, width))
+    })
+}
@@ -0,0 +1,423 @@
+use super::RenderSettings;
+use crate::{log, node::ast::NodeAst};
+
+/// Gets the values of the trees at the time `t`, evaluated on the GPU, for every pixel of the
+/// rendered image in rows from the top left. Gets `None`, after a warning, when the GPU can't
+/// render the image, which is then rendered on the CPU.
+///
+/// The GPU evaluates the trees exported to WGSL with 32-bit floats, where kroyer uses 64-bit
+/// ones, so values which are close to the edges of if statements or the range of a channel can
+/// land on the other side, and functions like sin and pow are less exact. Rand nodes hash the
+/// 32-bit coordinates, like the WGSL export does, so their noise is as stable as on the CPU, but
+/// not the same
+pub fn tree_values(settings: &RenderSettings, t: f64, ast: &NodeAst) -> Option<Vec<[f32; 4]>> {
+    if let Some(flag) = unsupported_flag(settings, ast) {
+        fall_back(&format!("The GPU can't render images with {}", flag));
+        return None;
+    }
+
+    #[cfg(feature = "gpu")]
+    let values = wgpu_render::tree_values(settings, t, ast);
+    #[cfg(not(feature = "gpu"))]
+    let values: Result<_, String> = {
+        let _ = t;
+        Err("kroyer was built without the `gpu` feature, so it can't render on the GPU".to_owned())
+    };
+    values.inspect_err(|e| fall_back(e)).ok()
+}
+
+/// Gets the flag of the settings the GPU can't render, if any. The GPU only evaluates the trees
+/// once at the middle of the top left corner of each pixel, and they can't be mirrored, measured
+/// or draw from the shared RNG
+fn unsupported_flag(settings: &RenderSettings, ast: &NodeAst) -> Option<&'static str> {
+    if settings.samples != 1 {
+        Some("--samples")
+    } else if settings.symmetry != crate::cli::Symmetry::None {
+        Some("--symmetry")
+    } else if settings.stats.is_some() {
+        Some("--stats")
+    } else if super::uses_rand(ast) {
+        Some("--legacy-rand")
+    } else {
+        None
+    }
+}
+
+/// Warns that the image is rendered on the CPU, because of `reason`. The warning is only given
+/// once, instead of for every frame
+fn fall_back(reason: &str) {
+    static WARNED: std::sync::Once = std::sync::Once::new();
+    WARNED.call_once(|| log::warning!("{}. Rendering on the CPU instead", reason));
+}
+
+#[cfg(feature = "gpu")]
+mod wgpu_render {
+    use std::sync::{LazyLock, Mutex};
+
+    use super::RenderSettings;
+    use crate::{
+        export::{
+            export_node,
+            wgsl::{FUNCTIONS, Wgsl},
+        },
+        log,
+        node::ast::NodeAst,
+        rng,
+    };
+
+    /// The size of the pixels of the storage texture, which has four 32-bit floats
+    const PIXEL_SIZE: u32 = 16;
+
+    /// The side of the square of pixels each workgroup evaluates
+    const WORKGROUP_SIZE: u32 = 8;
+
+    /// The uniform and output of the compute shader. The trees are evaluated once for each pixel
+    /// of the rendered image, at the coordinates the CPU would evaluate them at
+    const PARAMS: &str = "\
+struct Params {
+    left: u32,
+    top: u32,
+    width: u32,
+    height: u32,
+    full_width: f32,
+    full_height: f32,
+    x_start: f32,
+    x_end: f32,
+    y_start: f32,
+    y_end: f32,
+    t: f32,
+    seed: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+
+@group(0) @binding(1)
+var output: texture_storage_2d<rgba32float, write>;
+";
+
+    /// The device the images are rendered on, and the pipeline of the last AST rendered, with the
+    /// shader it was made from, so the frames of an animation reuse it
+    struct Gpu {
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        pipeline: Mutex<Option<(String, wgpu::ComputePipeline)>>,
+    }
+
+    /// The GPU, which is requested the first time it is used, or the reason it couldn't be
+    static GPU: LazyLock<Result<Gpu, String>> = LazyLock::new(Gpu::request);
+
+    impl Gpu {
+        /// Requests the default adapter, and a device on it
+        fn request() -> Result<Self, String> {
+            let instance = wgpu::Instance::new(
+                wgpu::InstanceDescriptor::new_without_display_handle_from_env(),
+            );
+            let adapter = pollster::block_on(
+                instance.request_adapter(&wgpu::RequestAdapterOptions::default()),
+            )
+            .map_err(|e| format!("No GPU was found.\nDetails: {}", e))?;
+            let info = adapter.get_info();
+            let (device, queue) =
+                pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default()))
+                    .map_err(|e| {
+                        format!("Failed to open the GPU {:?}.\nDetails: {}", info.name, e)
+                    })?;
+            log::info!("Rendering on the GPU {:?} ({:?})", info.name, info.backend);
+
+            Ok(Self {
+                device,
+                queue,
+                pipeline: Mutex::new(None),
+            })
+        }
+
+        /// Gets the pipeline which runs `shader`, and compiles it, unless it was the last one
+        fn pipeline(&self, shader: String) -> Result<wgpu::ComputePipeline, String> {
+            let mut last = self.pipeline.lock().unwrap();
+            if let Some((source, pipeline)) = &*last
+                && *source == shader
+            {
+                return Ok(pipeline.clone());
+            }
+
+            let scope = self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+            let module = self
+                .device
+                .create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("kroyer"),
+                    source: wgpu::ShaderSource::Wgsl(shader.as_str().into()),
+                });
+            let pipeline = self
+                .device
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("kroyer"),
+                    layout: None,
+                    module: &module,
+                    entry_point: Some("main"),
+                    compilation_options: Default::default(),
+                    cache: None,
+                });
+            if let Some(e) = pollster::block_on(scope.pop()) {
+                return Err(format!(
+                    "Failed to compile the AST for the GPU.\nDetails: {}",
+                    e
+                ));
+            }
+
+            *last = Some((shader, pipeline.clone()));
+            Ok(pipeline)
+        }
+    }
+
+    /// Evaluates the trees on the GPU. See `super::tree_values()`
+    pub fn tree_values(
+        settings: &RenderSettings,
+        t: f64,
+        ast: &NodeAst,
+    ) -> Result<Vec<[f32; 4]>, String> {
+        let gpu = GPU.as_ref().map_err(Clone::clone)?;
+        let (width, height) = settings.size();
+        if width == 0 || height == 0 {
+            return Ok(vec![]);
+        }
+
+        let limits = gpu.device.limits();
+        let bytes_per_row =
+            (width * PIXEL_SIZE).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer_size = bytes_per_row as u64 * height as u64;
+        if width.max(height) > limits.max_texture_dimension_2d
+            || buffer_size > limits.max_buffer_size
+        {
+            return Err(format!(
+                "The image is {}x{}, which is too large for the GPU",
+                width, height
+            ));
+        }
+
+        let pipeline = gpu.pipeline(shader(ast))?;
+        let scope = gpu.device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+        let params = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("kroyer params"),
+            size: 48,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        gpu.queue
+            .write_buffer(&params, 0, &params_bytes(settings, t));
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = gpu.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("kroyer output"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let readback = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("kroyer readback"),
+            size: buffer_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let view = texture.create_view(&Default::default());
+        let bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("kroyer"),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+            ],
+        });
+
+        let mut encoder = gpu.device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(
+                width.div_ceil(WORKGROUP_SIZE),
+                height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &readback,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+        gpu.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        readback.map_async(wgpu::MapMode::Read, .., move |result| {
+            let _ = sender.send(result);
+        });
+        let polled = gpu.device.poll(wgpu::PollType::wait_indefinitely());
+        if let Some(e) = pollster::block_on(scope.pop()) {
+            return Err(format!("Failed to render on the GPU.\nDetails: {}", e));
+        }
+        polled
+            .map_err(|e| e.to_string())
+            .and_then(|_| receiver.recv().map_err(|e| e.to_string()))
+            .and_then(|result| result.map_err(|e| e.to_string()))
+            .map_err(|e| format!("Failed to read the image from the GPU.\nDetails: {}", e))?;
+
+        let data = readback.get_mapped_range(..).map_err(|e| e.to_string())?;
+        let values = data
+            .chunks(bytes_per_row as usize)
+            .flat_map(|row| row[..(width * PIXEL_SIZE) as usize].chunks(PIXEL_SIZE as usize))
+            .map(|pixel| {
+                let channel =
+                    |i: usize| f32::from_ne_bytes(pixel[i * 4..i * 4 + 4].try_into().unwrap());
+                [channel(0), channel(1), channel(2), channel(3)]
+            })
+            .collect();
+        drop(data);
+        readback.unmap();
+        Ok(values)
+    }
+
+    /// Writes the compute shader which stores the values of the trees of `ast` in the output. The
+    /// trees are simplified first, since WGSL compilers evaluate branches of literals when they
+    /// compile the shader, and reject them if they give NaN, like `sqrt(-0.5)`. Simplifying
+    /// folds them into literals with the values kroyer gives them
+    fn shader(ast: &NodeAst) -> String {
+        let ast = ast.clone().simplify();
+        let r = export_node(&Wgsl, &ast.r);
+        let (g, b) = match ast.grayscale {
+            true => ("r".to_owned(), "r".to_owned()),
+            false => (export_node(&Wgsl, &ast.g), export_node(&Wgsl, &ast.b)),
+        };
+        let a = ast
+            .a
+            .as_deref()
+            .map_or_else(|| "1.0".to_owned(), |a| export_node(&Wgsl, a));
+
+        format!(
+            "{}{}
+@compute @workgroup_size({}, {})
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {{
+    if (id.x >= params.width || id.y >= params.height) {{
+        return;
+    }}
+    let x_frac = f32(params.left + id.x) / params.full_width;
+    let y_frac = f32(params.top + id.y) / params.full_height;
+    let x = params.x_start + (params.x_end - params.x_start) * x_frac;
+    let y = params.y_start + (params.y_end - params.y_start) * y_frac;
+    let t = params.t;
+
+    let r = {};
+    let g = {};
+    let b = {};
+    let a = {};
+    textureStore(output, id.xy, vec4<f32>(r, g, b, a));
+}}
+",
+            PARAMS, FUNCTIONS, WORKGROUP_SIZE, WORKGROUP_SIZE, r, g, b, a
+        )
+    }
+
+    /// Gets the bytes of the uniform of the compute shader, in the order of `PARAMS`
+    fn params_bytes(settings: &RenderSettings, t: f64) -> Vec<u8> {
+        let (width, height) = settings.size();
+        let (left, top) = settings.origin();
+        let integers = [left, top, width, height].map(u32::to_ne_bytes);
+        let floats = [
+            settings.width as f64,
+            settings.height as f64,
+            settings.x_range.start,
+            settings.x_range.end,
+            settings.y_range.start,
+            settings.y_range.end,
+            t,
+        ]
+        .map(|x| (x as f32).to_ne_bytes());
+        let seed = rng::get_seed().low_u32().to_ne_bytes();
+
+        integers
+            .into_iter()
+            .chain(floats)
+            .chain([seed])
+            .flatten()
+            .collect()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use rand::SeedableRng;
+        use rand_chacha::ChaCha20Rng;
+
+        use super::*;
+        use crate::{grammar::Grammar, node::NodeType};
+
+        fn settings(width: u32, height: u32) -> RenderSettings {
+            RenderSettings {
+                gpu: true,
+                ..RenderSettings::plain(width, height)
+            }
+        }
+
+        #[test]
+        fn renders_a_small_image() {
+            if let Err(e) = &*GPU {
+                eprintln!("Skipping the GPU smoke test. {}", e);
+                return;
+            }
+            let ast = NodeAst::parse_from_str("R: x\nG: y\nB: (x < 0 ? t : -0.5)\nA: 1").unwrap();
+            let settings = settings(8, 8);
+            let values = tree_values(&settings, 0.25, &ast).unwrap();
+
+            assert_eq!(values.len(), 64);
+            let cpu = ast.compile();
+            for (i, gpu) in values.into_iter().enumerate() {
+                let x = settings.x_range.at((i % 8) as f64 / 8.);
+                let y = settings.y_range.at((i / 8) as f64 / 8.);
+                let cpu = cpu.get_values(x, y, 0.25);
+                for (gpu, cpu) in gpu.into_iter().zip(cpu) {
+                    assert!((gpu as f64 - cpu).abs() < 1e-5, "{} != {}", gpu, cpu);
+                }
+            }
+        }
+
+        #[test]
+        fn exports_valid_shaders() {
+            // Every node type is used, so if statements with every operator are exported
+            let mut grammar = Grammar::new(NodeType::as_list().map(|x| (x, 1)).to_vec());
+            for seed in 0..200 {
+                let mut rng = ChaCha20Rng::seed_from_u64(seed);
+                let depth = 1 + seed as usize % 10;
+                let mut ast = NodeAst::from_grammar_with(&mut grammar, depth, None, &mut rng);
+                ast.salt_rand();
+
+                let shader = shader(&ast);
+                let module = wgpu::naga::front::wgsl::parse_str(&shader)
+                    .unwrap_or_else(|e| panic!("seed {}: {}", seed, e.emit_to_string(&shader)));
+                wgpu::naga::valid::Validator::new(Default::default(), Default::default())
+                    .validate(&module)
+                    .unwrap_or_else(|e| panic!("seed {}: {}", seed, e.emit_to_string(&shader)));
+            }
+        }
+    }
+}
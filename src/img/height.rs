@@ -0,0 +1,136 @@
+use std::{io::Write, path::PathBuf};
+
+use image::{ImageBuffer, Luma, Rgb};
+
+use crate::{
+    cli::Region,
+    error::{Error, Result},
+    node::ast::NodeAst,
+};
+
+use super::{RenderSettings, commit_file, create_file, get_values, meta::Metadata, render};
+
+/// The images of the surface the red tree describes, where -1 is the lowest and 1 the highest
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Relief {
+    /// The heights, as a 16-bit grayscale image
+    Heightmap,
+    /// A tangent-space normal map of the heights, with the given strength. See `get_normals()`
+    NormalMap(f64),
+}
+
+/// Saves the relief of the still image as a PNG with the metadata
+pub fn gen_relief(
+    path: PathBuf,
+    relief: Relief,
+    settings: &RenderSettings,
+    metadata: &Metadata,
+    ast: &NodeAst,
+) -> Result<()> {
+    let mut file = create_file(&path)?;
+    encode_relief(&mut file, relief, settings, metadata, ast)?;
+    commit_file(file, &path)
+}
+
+/// Encodes the relief of the still image as a PNG with the metadata into `writer`
+pub fn encode_relief(
+    writer: impl Write,
+    relief: Relief,
+    settings: &RenderSettings,
+    metadata: &Metadata,
+    ast: &NodeAst,
+) -> Result<()> {
+    let t = settings.still_t;
+    let (width, height) = settings.size();
+    let encode = || -> std::result::Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(writer, width, height);
+        let data = match relief {
+            Relief::Heightmap => {
+                encoder.set_color(png::ColorType::Grayscale);
+                encoder.set_depth(png::BitDepth::Sixteen);
+                let heights = get_heights(settings, t, ast);
+                // PNG stores 16-bit samples in big-endian
+                heights
+                    .as_raw()
+                    .iter()
+                    .flat_map(|x| ((x * u16::MAX as f32).round() as u16).to_be_bytes())
+                    .collect()
+            }
+            Relief::NormalMap(strength) => {
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                get_normals(settings, strength, t, ast).into_raw()
+            }
+        };
+        metadata.add_to_png(&mut encoder)?;
+
+        let mut png_writer = encoder.write_header()?;
+        png_writer.write_image_data(&data)?;
+        png_writer.finish()
+    };
+
+    encode().map_err(|e| Error::Io(format!("Failed to encode png.\nDetails: {}", e)))
+}
+
+/// Renders the heights of the red tree at the time `t`, from 0 to 1. Values outside of the range
+/// of the tree are clamped, and NaN is 0
+fn get_heights(
+    settings: &RenderSettings,
+    t: f64,
+    ast: &NodeAst,
+) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+    let program = ast.compile();
+    render(settings, ast, |x, y, stats| {
+        let [r, ..] = get_values(x, y, settings, t, &program, stats);
+        let height = match r.is_nan() {
+            true => 0.,
+            false => ((r + 1.) / 2.).clamp(0., 1.),
+        };
+        Luma([height as f32])
+    })
+}
+
+/// Renders the normals of the heights at the time `t`, encoded as RGB with green pointing up,
+/// like OpenGL expects. The slopes are the differences between the neighbouring pixels, where
+/// pixels at the edges of the image use themselves in place of the missing neighbour. At a
+/// `strength` of 1, going from the lowest to the highest height rises as much as the image is
+/// wide, or tall if it is taller
+fn get_normals(
+    settings: &RenderSettings,
+    strength: f64,
+    t: f64,
+    ast: &NodeAst,
+) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let (width, height) = settings.size();
+    let (left, top) = settings.origin();
+
+    // The pixels around a region are rendered too, so its edges match the rest of the image
+    let padded_left = left.saturating_sub(1);
+    let padded_top = top.saturating_sub(1);
+    let padded = RenderSettings {
+        region: Some(Region {
+            x: padded_left,
+            y: padded_top,
+            width: (left + width + 1).min(settings.width) - padded_left,
+            height: (top + height + 1).min(settings.height) - padded_top,
+        }),
+        ..settings.clone()
+    };
+    let heights = get_heights(&padded, t, ast);
+    let scale = strength * settings.width.max(settings.height) as f64;
+
+    let at = |x: u32, y: u32| heights.get_pixel(x, y).0[0] as f64;
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let x = left + x - padded_left;
+        let y = top + y - padded_top;
+        let (west, east) = (x.saturating_sub(1), (x + 1).min(heights.width() - 1));
+        let (north, south) = (y.saturating_sub(1), (y + 1).min(heights.height() - 1));
+        let dx = (at(east, y) - at(west, y)) / (east - west).max(1) as f64;
+        // The rows of the image go down, while the green of the normal points up
+        let dy = (at(x, south) - at(x, north)) / (south - north).max(1) as f64;
+
+        let normal = [-dx * scale, dy * scale, 1.];
+        let len = normal.iter().map(|x| x * x).sum::<f64>().sqrt();
+        Rgb(normal.map(|x| ((x / len + 1.) * 127.5).round() as u8))
+    })
+}
@@ -0,0 +1,220 @@
+use std::{f64::consts::TAU, fs::File, io::BufReader, path::PathBuf};
+
+use crate::error::Error;
+
+/// The prefix of the keys of the metadata kroyer writes
+pub(super) const KEY_PREFIX: &str = "kroyer:";
+/// The label of a gif comment extension
+const GIF_COMMENT: u8 = 0xFE;
+
+/// What an image was made from, written into the PNGs and gifs kroyer saves, so they can be
+/// re-rendered with --from-image
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata {
+    /// The seed, in hex, as printed by --dump-seed
+    pub seed: String,
+    /// The max depth of the AST
+    pub depth: usize,
+    /// The grammar, as printed by --dump-grammar
+    pub grammar: String,
+    /// The AST in the default format, if it was embedded with --embed-ast
+    pub ast: Option<String>,
+    /// The start of the range of t in animations
+    pub t_start: f64,
+    /// The end of the range of t in animations
+    pub t_end: f64,
+    /// The value of t in still images
+    pub still_t: f64,
+}
+
+impl Metadata {
+    /// Gets the fields as keys and values. Short fields are marked as such, since PNGs store them
+    /// uncompressed
+    fn fields(&self) -> Vec<(String, String, bool)> {
+        let mut fields = vec![
+            ("seed", self.seed.clone(), true),
+            ("depth", self.depth.to_string(), true),
+            ("t-start", self.t_start.to_string(), true),
+            ("t-end", self.t_end.to_string(), true),
+            ("still-t", self.still_t.to_string(), true),
+            ("grammar", self.grammar.clone(), false),
+        ];
+        if let Some(ast) = &self.ast {
+            fields.push(("ast", ast.clone(), false));
+        }
+        fields
+            .into_iter()
+            .map(|(key, value, short)| (format!("{}{}", KEY_PREFIX, key), value, short))
+            .collect()
+    }
+
+    /// Creates the metadata from the keys and values found in an image, ignoring keys which
+    /// aren't kroyer's. Images saved before t could be set have the defaults of t
+    pub(super) fn from_fields(
+        fields: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, String> {
+        let (mut seed, mut depth, mut grammar, mut ast) = (None, None, None, None);
+        let (mut t_start, mut t_end, mut still_t) = (0., TAU, 0.);
+        for (key, value) in fields {
+            match key.strip_prefix(KEY_PREFIX) {
+                Some("seed") => seed = Some(value),
+                Some("depth") => depth = Some(value),
+                Some("grammar") => grammar = Some(value),
+                Some("ast") => ast = Some(value),
+                Some("t-start") => t_start = parse_t(&value)?,
+                Some("t-end") => t_end = parse_t(&value)?,
+                Some("still-t") => still_t = parse_t(&value)?,
+                _ => {}
+            }
+        }
+
+        let (Some(seed), Some(depth), Some(grammar)) = (seed, depth, grammar) else {
+            return Err("the image has no kroyer metadata".to_owned());
+        };
+        let Ok(depth) = depth.trim().parse() else {
+            return Err(format!("the depth \"{}\" is not a whole number", depth));
+        };
+        Ok(Self {
+            seed,
+            depth,
+            grammar,
+            ast,
+            t_start,
+            t_end,
+            still_t,
+        })
+    }
+
+    /// Adds the metadata to a PNG, as tEXt chunks for short fields, and zTXt chunks for the rest
+    pub fn add_to_png<W: std::io::Write>(
+        &self,
+        encoder: &mut png::Encoder<W>,
+    ) -> Result<(), png::EncodingError> {
+        for (key, value, short) in self.fields() {
+            match short {
+                true => encoder.add_text_chunk(key, value)?,
+                false => encoder.add_ztxt_chunk(key, value)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the metadata into a gif, as a comment extension for each field, written like
+    /// `kroyer:seed=...`
+    pub fn write_to_gif<W: std::io::Write>(
+        &self,
+        encoder: &mut gif::Encoder<W>,
+    ) -> std::io::Result<()> {
+        for (key, value, _) in self.fields() {
+            let comment = format!("{}={}", key, value);
+            encoder.write_raw_extension(gif::AnyExtension(GIF_COMMENT), &[comment.as_bytes()])?;
+        }
+        Ok(())
+    }
+
+    /// Reads the metadata from the sidecar written by --metadata next to a file, or if it has
+    /// none, from a PNG or gif saved by kroyer
+    pub fn read(path: &PathBuf) -> Result<Self, Error> {
+        let result = super::sidecar::read(path).unwrap_or_else(|| {
+            std::fs::read(path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| match bytes {
+                    _ if bytes.starts_with(b"\x89PNG") => read_png(path),
+                    _ if bytes.starts_with(b"GIF8") => read_gif(&bytes),
+                    _ => Err(
+                        "only PNGs, gifs and files with a sidecar can have kroyer metadata"
+                            .to_owned(),
+                    ),
+                })
+        });
+
+        result.map_err(|e| {
+            Error::Io(format!(
+                "Failed to read metadata from {:?}.\nDetails: {}",
+                path, e
+            ))
+        })
+    }
+}
+
+/// Parses a value of t from the metadata
+fn parse_t(value: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|x| x.is_finite())
+        .ok_or(format!(
+            "the value of t \"{}\" is not a finite number",
+            value
+        ))
+}
+
+/// Reads the text chunks of a PNG
+fn read_png(path: &PathBuf) -> Result<Metadata, String> {
+    let file = BufReader::new(File::open(path).map_err(|e| e.to_string())?);
+    let reader = png::Decoder::new(file)
+        .read_info()
+        .map_err(|e| e.to_string())?;
+    let info = reader.info();
+
+    let mut fields: Vec<(String, String)> = info
+        .uncompressed_latin1_text
+        .iter()
+        .map(|x| (x.keyword.clone(), x.text.clone()))
+        .collect();
+    for chunk in &info.compressed_latin1_text {
+        let text = chunk.get_text().map_err(|e| e.to_string())?;
+        fields.push((chunk.keyword.clone(), text));
+    }
+    Metadata::from_fields(fields)
+}
+
+/// Reads the comment extensions of a gif. The blocks of the gif are walked by hand, since the
+/// gif crate skips comments when decoding
+fn read_gif(bytes: &[u8]) -> Result<Metadata, String> {
+    let truncated = || "the gif is truncated".to_owned();
+    let byte = |i: usize| bytes.get(i).copied().ok_or_else(truncated);
+    // The size of a color table, from the flags of the block it belongs to
+    let table_size = |flags: u8| match flags & 0x80 {
+        0 => 0,
+        _ => 3 << ((flags & 0x07) + 1),
+    };
+    // Reads the sub-blocks starting at `i`, and gets their data and the index after them
+    let sub_blocks = |mut i: usize| -> Result<(Vec<u8>, usize), String> {
+        let mut data = vec![];
+        loop {
+            let len = byte(i)? as usize;
+            i += 1;
+            if len == 0 {
+                return Ok((data, i));
+            }
+            data.extend(bytes.get(i..i + len).ok_or_else(truncated)?);
+            i += len;
+        }
+    };
+
+    let mut fields = vec![];
+    let mut i = 13 + table_size(byte(10)?);
+    loop {
+        match byte(i)? {
+            // An extension
+            0x21 => {
+                let (data, next) = sub_blocks(i + 2)?;
+                if byte(i + 1)? == GIF_COMMENT {
+                    let comment = String::from_utf8_lossy(&data);
+                    if let Some((key, value)) = comment.split_once('=') {
+                        fields.push((key.to_owned(), value.to_owned()));
+                    }
+                }
+                i = next;
+            }
+            // An image, with its descriptor, local color table and LZW code size
+            0x2C => i = sub_blocks(i + 10 + table_size(byte(i + 9)?) + 1)?.1,
+            // The trailer
+            0x3B => break,
+            block => return Err(format!("unknown gif block 0x{:02x}", block)),
+        }
+    }
+    Metadata::from_fields(fields)
+}
@@ -1,76 +1,921 @@
-use std::{f64::consts::TAU, fs::OpenOptions, path::PathBuf, time::Duration};
+pub mod color;
+pub mod colormap;
+pub mod diff;
+pub mod exr;
+pub mod farbfeld;
+pub mod frames;
+pub mod gpu;
+pub mod height;
+pub mod meta;
+pub mod normalize;
+pub mod npy;
+pub mod pnm;
+pub mod post;
+pub mod preview;
+pub mod quantize;
+pub mod sheet;
+pub mod sidecar;
+pub mod stats;
+pub mod stream;
+pub mod tiff;
+pub mod warp;
+pub mod y4m;
 
-use image::{ImageBuffer, Rgba, codecs::gif::Repeat};
+use std::{
+    borrow::Cow,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use crate::node::ast::{self, NodeAst};
+use color::Transfer;
+use colormap::ColorMap;
+use image::{EncodableLayout, ImageBuffer, ImageFormat, Pixel, PixelWithColorType, Rgba};
+use meta::Metadata;
+use normalize::Normalization;
+use quantize::Palette;
+use rayon::prelude::*;
+use stats::ValueStats;
 
-pub fn gen_img(path: PathBuf, width: u32, height: u32, tree: &NodeAst) {
-    let img = get_img(width, height, 0., tree);
-    if let Err(e) = img.save(&path) {
-        eprintln!(
-            "[ERROR]: Failed to save image to {:?}.\nDetails: {}",
-            path, e
-        );
-        std::process::exit(1);
+use crate::{
+    cli::{AxisRange, ColorSpace, Dither, HexColor, PostFilter, Region, Symmetry, TMode, ToneMap},
+    error::{Error, Result},
+    io::atomic::AtomicFile,
+    log,
+    node::{ast::NodeAst, bytecode::CompiledAst},
+    rng,
+};
+
+/// Saves the still image, in the format given by the extension of `path`, and gets the rendered
+/// image. PNGs get the metadata, which other formats can't hold
+pub fn gen_img(
+    path: PathBuf,
+    settings: &RenderSettings,
+    metadata: &Metadata,
+    tree: &NodeAst,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>> {
+    let img = get_img(settings, settings.still_t, tree);
+    if path
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("png"))
+    {
+        let mut file = create_file(&path)?;
+        write_png(&mut file, &img, metadata)?;
+        commit_file(file, &path)?;
+        return Ok(img);
     }
+
+    save_image(&img, &path, None)?;
+    Ok(img)
 }
 
-pub fn get_img(width: u32, height: u32, t: f64, ast: &NodeAst) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-    let mut img_buf = image::ImageBuffer::new(width, height);
+/// Encodes the still image as a PNG with the metadata into `writer`, and gets the rendered image
+pub fn encode_png(
+    writer: impl Write,
+    settings: &RenderSettings,
+    metadata: &Metadata,
+    tree: &NodeAst,
+) -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>> {
+    let img = get_img(settings, settings.still_t, tree);
+    write_png(writer, &img, metadata)?;
+    Ok(img)
+}
+
+/// Encodes a rendered image as a PNG with the metadata into `writer`
+pub fn write_png(
+    writer: impl Write,
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    metadata: &Metadata,
+) -> Result<()> {
+    let encode = || -> std::result::Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(writer, img.width(), img.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        metadata.add_to_png(&mut encoder)?;
 
-    for (x, y, pixel) in img_buf.enumerate_pixels_mut() {
-        let x_frac = x as f64 / width as f64;
-        let y_frac = y as f64 / height as f64;
-        let r = (ast.r.get_value(x_frac, y_frac, t) + 1.) * 127.5;
-        let g = (ast.g.get_value(x_frac, y_frac, t) + 1.) * 127.5;
-        let b = (ast.b.get_value(x_frac, y_frac, t) + 1.) * 127.5;
+        let mut png_writer = encoder.write_header()?;
+        png_writer.write_image_data(img.as_raw())?;
+        png_writer.finish()
+    };
+
+    encode().map_err(|e| Error::Io(format!("Failed to encode png.\nDetails: {}", e)))
+}
 
-        *pixel = image::Rgba([r as u8, g as u8, b as u8, 255])
+/// Renders the image at the time `t`, and applies the post filters. The rows are rendered in
+/// parallel, unless the AST uses `Rand` with --legacy-rand, since its values then come from the
+/// shared RNG, and have to be drawn in the same order every time for a seed to give the same image
+pub fn get_img(settings: &RenderSettings, t: f64, ast: &NodeAst) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    if settings.gpu
+        && let Some(values) = gpu::tree_values(settings, t, ast)
+    {
+        let (width, _) = settings.size();
+        let (left, top) = settings.origin();
+        let img = render(settings, ast, |x, y, _| {
+            let values = values[((y - top) * width + x - left) as usize].map(f64::from);
+            to_rgba8(encode(shade(values, settings), settings))
+        });
+        return post::apply(img, &settings.post);
     }
 
+    let program = ast.compile();
+    let img = render(settings, ast, |x, y, stats| {
+        get_pixel(x, y, settings, t, &program, stats)
+    });
+    post::apply(img, &settings.post)
+}
+
+/// Renders the image at the time `t` with 16 bits per channel, like `get_img()` without the post
+/// filters. The values are rounded to the nearest step, and clamped
+pub fn get_img_16(
+    settings: &RenderSettings,
+    t: f64,
+    ast: &NodeAst,
+) -> ImageBuffer<Rgba<u16>, Vec<u16>> {
+    let program = ast.compile();
+    render(settings, ast, |x, y, stats| {
+        let values = get_encoded_values(x, y, settings, t, &program, stats);
+        Rgba(values.map(|x| ((x + 1.) / 2. * u16::MAX as f64).round() as u16))
+    })
+}
+
+/// Renders an image of any pixel type, using `get_pixel` for the pixel at each `x` and `y` of
+/// the full image, even if only a region of it is rendered. See `get_img()` for when the rows
+/// are rendered in parallel. If the settings collect statistics, `get_pixel` gets the
+/// statistics of its row to add its values to, which are added to the settings once the row is
+/// done, so the threads rarely wait on each other
+fn render<P>(
+    settings: &RenderSettings,
+    ast: &NodeAst,
+    get_pixel: impl Fn(u32, u32, Option<&mut ValueStats>) -> P + Sync,
+) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel + Send + Sync,
+    P::Subpixel: Send + Sync,
+{
+    let (width, height) = settings.size();
+    let (left, top) = settings.origin();
+    let mut img_buf: ImageBuffer<P, Vec<P::Subpixel>> = image::ImageBuffer::new(width, height);
+
+    let new_stats = || settings.stats.as_ref().map(|_| ValueStats::default());
+    let add_stats = |stats: Option<ValueStats>| {
+        if let (Some(shared), Some(stats)) = (&settings.stats, stats) {
+            shared.lock().unwrap().merge(&stats);
+        }
+    };
+
+    if uses_rand(ast) {
+        let mut stats = new_stats();
+        for (x, y, pixel) in img_buf.enumerate_pixels_mut() {
+            *pixel = get_pixel(left + x, top + y, stats.as_mut());
+        }
+        add_stats(stats);
+        return img_buf;
+    }
+
+    let channels = P::CHANNEL_COUNT as usize;
+    let row_len = width as usize * channels;
+    img_buf
+        .par_chunks_mut(row_len.max(1))
+        .enumerate()
+        .for_each(|(y, row)| {
+            let mut stats = new_stats();
+            for (x, pixel) in row.chunks_mut(channels).enumerate() {
+                let value = get_pixel(left + x as u32, top + y as u32, stats.as_mut());
+                pixel.copy_from_slice(value.channels());
+            }
+            add_stats(stats);
+        });
+
     img_buf
 }
 
-pub fn gen_gif(path: PathBuf, width: u32, height: u32, frames: u32, ast: &ast::NodeAst) {
-    let file = match OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(&path)
+/// Checks if any channel of the AST uses `Rand`, drawing from the shared RNG
+pub fn uses_rand(ast: &NodeAst) -> bool {
+    rng::legacy_rand()
+        && ast
+            .tree_channels()
+            .into_iter()
+            .any(|x| ast.channel(x).contains_rand())
+}
+
+/// Gets the color of the pixel at `x` and `y`
+fn get_pixel(
+    x: u32,
+    y: u32,
+    settings: &RenderSettings,
+    t: f64,
+    ast: &CompiledAst,
+    stats: Option<&mut ValueStats>,
+) -> Rgba<u8> {
+    to_rgba8(get_encoded_values(x, y, settings, t, ast, stats))
+}
+
+/// Converts values in the range of the trees to an 8-bit color
+fn to_rgba8(values: [f64; 4]) -> Rgba<u8> {
+    image::Rgba(values.map(|x| ((x + 1.) * 127.5) as u8))
+}
+
+/// Gets the values of the pixel at `x` and `y`, like `get_values()`, with the red, green and
+/// blue encoded with the transfer function of `settings`, if set
+fn get_encoded_values(
+    x: u32,
+    y: u32,
+    settings: &RenderSettings,
+    t: f64,
+    ast: &CompiledAst,
+    stats: Option<&mut ValueStats>,
+) -> [f64; 4] {
+    encode(get_values(x, y, settings, t, ast, stats), settings)
+}
+
+/// Encodes the red, green and blue of `values` with the transfer function of `settings`, if set
+fn encode(values: [f64; 4], settings: &RenderSettings) -> [f64; 4] {
+    let [r, g, b, a] = values;
+    let [r, g, b] = match settings.transfer {
+        Some(transfer) => {
+            let rgb = color::to_unit([r, g, b]).map(|x| transfer.encode(x.clamp(0., 1.)));
+            color::from_unit(rgb)
+        }
+        None => [r, g, b],
+    };
+    [r, g, b, a]
+}
+
+/// Gets the red, green, blue and alpha values of the pixel at `x` and `y`, in the range of the
+/// trees. With more than one sample, the values are averaged over a regular grid of samples
+/// within the pixel, so every render of it is the same. Pixels mirrored by the symmetry get the
+/// values of the pixel they mirror. The raw values of every sample are added to `stats`, if set
+fn get_values(
+    x: u32,
+    y: u32,
+    settings: &RenderSettings,
+    t: f64,
+    ast: &CompiledAst,
+    mut stats: Option<&mut ValueStats>,
+) -> [f64; 4] {
+    let (x, y) = settings
+        .symmetry
+        .mirror(x, y, settings.width, settings.height);
+    let samples = settings.samples;
+    let pixel_x = x as f64 / settings.width as f64;
+    let pixel_y = y as f64 / settings.height as f64;
+    if samples == 1 {
+        return sample(
+            settings.x_range.at(pixel_x),
+            settings.y_range.at(pixel_y),
+            settings,
+            t,
+            ast,
+            stats,
+        );
+    }
+
+    let mut sum = [0.; 4];
+    for i in 0..samples {
+        for j in 0..samples {
+            let x_frac = pixel_x + j as f64 / (samples * settings.width) as f64;
+            let y_frac = pixel_y + i as f64 / (samples * settings.height) as f64;
+            let values = sample(
+                settings.x_range.at(x_frac),
+                settings.y_range.at(y_frac),
+                settings,
+                t,
+                ast,
+                stats.as_deref_mut(),
+            );
+            for (sum, value) in sum.iter_mut().zip(values) {
+                *sum += value;
+            }
+        }
+    }
+    sum.map(|x| x / (samples * samples) as f64)
+}
+
+/// Gets the red, green, blue and alpha values at the coordinates `x` and `y`, shaded like
+/// `shade()` does. The alpha is 1 if the AST has no alpha channel. The values of the trees are
+/// added to `stats`, if set
+fn sample(
+    x: f64,
+    y: f64,
+    settings: &RenderSettings,
+    t: f64,
+    ast: &CompiledAst,
+    stats: Option<&mut ValueStats>,
+) -> [f64; 4] {
+    let values = tree_values(x, y, settings, t, ast);
+    if let Some(stats) = stats {
+        stats.add(values);
+    }
+    shade(values, settings)
+}
+
+/// Gets the colors of the values of the trees, normalized, tone mapped and converted from the
+/// color space of `settings`, or picked from its palette
+fn shade(values: [f64; 4], settings: &RenderSettings) -> [f64; 4] {
+    let [r, g, b, a] = values;
+    let [r, g, b] = match settings.normalization {
+        Some(normalization) => normalization.apply([r, g, b]),
+        None => [r, g, b],
+    };
+
+    if let Some(nan_color) = settings.nan_color
+        && [r, g, b].iter().any(|x| x.is_nan())
     {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("[ERROR]: Failed to create file {:?}.\nDetails: {}", path, e);
-            std::process::exit(1);
+        let [r, g, b] = nan_color.0.map(|x| x as f64 / 127.5 - 1.);
+        return [r, g, b, a];
+    }
+
+    let [r, g, b] = match settings.tone_map {
+        Some(tone_map) => [r, g, b].map(|x| tone_map.apply(x)),
+        None => [r, g, b],
+    };
+
+    if let Some(palette) = &settings.palette {
+        let [r, g, b] = palette.color_at(r);
+        return [r, g, b, a];
+    }
+
+    let [r, g, b] = match settings.color_space {
+        ColorSpace::Rgb => [r, g, b],
+        ColorSpace::Hsv => color::from_unit(color::hsv_to_rgb(color::to_unit([r, g, b]))),
+        ColorSpace::Hsl => color::from_unit(color::hsl_to_rgb(color::to_unit([r, g, b]))),
+    };
+
+    [r, g, b, a]
+}
+
+/// Gets the values of the trees at the coordinates `x` and `y`, rotated by a radial symmetry
+fn tree_values(x: f64, y: f64, settings: &RenderSettings, t: f64, ast: &CompiledAst) -> [f64; 4] {
+    let center = (settings.x_range.at(0.5), settings.y_range.at(0.5));
+    let (x, y) = settings.symmetry.rotate(x, y, center);
+    ast.get_values(x, y, t)
+}
+
+/// The settings of how an image is rendered
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    /// The width of the image in pixels
+    pub width: u32,
+    /// The height of the image in pixels
+    pub height: u32,
+    /// How the values of the red, green and blue trees are interpreted
+    pub color_space: ColorSpace,
+    /// The amount of samples taken along each axis of a pixel, which are averaged into its color
+    pub samples: u32,
+    /// The range x goes over, from the left to the right edge
+    pub x_range: AxisRange,
+    /// The range y goes over, from the top to the bottom edge
+    pub y_range: AxisRange,
+    /// How values outside of the range of the trees are mapped into it. If unset, 8-bit images
+    /// clamp them, and float images keep them
+    pub tone_map: Option<ToneMap>,
+    /// The color of pixels where a tree gives NaN. If unset, NaN becomes 0 in 8-bit images, and
+    /// stays NaN in float images
+    pub nan_color: Option<HexColor>,
+    /// The transfer function 8-bit colors are encoded with. If unset, the values are written
+    /// linearly. Float images are always linear
+    pub transfer: Option<Transfer>,
+    /// The part of the image which is rendered. If unset, the whole image is rendered
+    pub region: Option<Region>,
+    /// The value of t in still images
+    pub still_t: f64,
+    /// The filters applied to 8-bit images after they are rendered
+    pub post: Vec<PostFilter>,
+    /// The colors the red tree picks from, if set, instead of the trees giving the colors
+    pub palette: Option<ColorMap>,
+    /// How the coordinates are mirrored or rotated before the trees are evaluated
+    pub symmetry: Symmetry,
+    /// How the values of the red, green and blue trees are stretched, if set
+    pub normalization: Option<Normalization>,
+    /// The statistics every render adds the raw values of the trees to, if set
+    pub stats: Option<Arc<Mutex<ValueStats>>>,
+    /// Whether the trees of 8-bit images are evaluated on the GPU, when it can render them. See
+    /// `gpu::tree_values()`
+    pub gpu: bool,
+}
+
+impl RenderSettings {
+    /// Gets the width and height of the rendered image, which is the size of the region, if set
+    pub fn size(&self) -> (u32, u32) {
+        match self.region {
+            Some(region) => (region.width, region.height),
+            None => (self.width, self.height),
+        }
+    }
+
+    /// Gets the pixel of the full image at the top left corner of the rendered image
+    fn origin(&self) -> (u32, u32) {
+        match self.region {
+            Some(region) => (region.x, region.y),
+            None => (0, 0),
         }
+    }
+}
+
+#[cfg(test)]
+impl RenderSettings {
+    /// Gets the settings of a plain render of the whole image, over the centered range of the
+    /// axes, with nothing applied to the colors
+    pub fn plain(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            color_space: ColorSpace::Rgb,
+            samples: 1,
+            x_range: AxisRange::CENTERED,
+            y_range: AxisRange::CENTERED,
+            tone_map: None,
+            nan_color: None,
+            transfer: None,
+            region: None,
+            still_t: 0.,
+            post: vec![],
+            palette: None,
+            symmetry: Symmetry::None,
+            normalization: None,
+            stats: None,
+            gpu: false,
+        }
+    }
+}
+
+/// The settings of an animated image
+#[derive(Clone, Debug)]
+pub struct Animation {
+    /// The amount of frames in a loop
+    pub frames: u32,
+    /// The frames shown per second
+    pub fps: f64,
+    /// How t moves from `t_start` to `t_end` over the frames
+    pub t_mode: TMode,
+    /// The start of the range of t
+    pub t_start: f64,
+    /// The end of the range of t
+    pub t_end: f64,
+}
+
+impl Animation {
+    /// Creates the settings for an animation played at `fps` frames per second, where t moves
+    /// over the range from `t_start` to `t_end`
+    pub fn new(frames: u32, fps: f64, t_mode: TMode, t_start: f64, t_end: f64) -> Result<Self> {
+        if !fps.is_finite() || fps <= 0. {
+            return Err(Error::Usage(format!(
+                "--fps has to be above 0, but was {}",
+                fps
+            )));
+        }
+
+        Ok(Self {
+            frames,
+            fps,
+            t_mode,
+            t_start,
+            t_end,
+        })
+    }
+
+    /// Gets the value of t in the given frame
+    pub fn t(&self, frame: u32) -> f64 {
+        let position = self.t_mode.position(frame as f64 / self.frames as f64);
+        self.t_start + (self.t_end - self.t_start) * position
+    }
+
+    /// Gets the delay between the frames of a gif. Gif delays are in hundredths of a second, so
+    /// the delay is rounded to that, with a warning if it changes
+    fn gif_delay(&self) -> Duration {
+        let centis = (100. / self.fps).round().max(1.);
+        if centis != 100. / self.fps {
+            log::warning!(
+                "gif frame delays are in hundredths of a second, so {} fps can't be represented exactly. Using a delay of {}ms ({:.2} fps) instead",
+                self.fps,
+                centis * 10.,
+                100. / centis
+            );
+        }
+        Duration::from_millis(centis as u64 * 10)
+    }
+
+    /// Gets the delay between the frames of an APNG, as a fraction of a second
+    fn apng_delay(&self) -> (u16, u16) {
+        if self.fps.fract() == 0. && self.fps <= u16::MAX as f64 {
+            return (1, self.fps as u16);
+        }
+        let millis = (1000. / self.fps).round().clamp(1., u16::MAX as f64);
+        (millis as u16, 1000)
+    }
+}
+
+/// Renders every frame of the animation, in order
+pub fn get_frames(
+    settings: &RenderSettings,
+    animation: &Animation,
+    ast: &NodeAst,
+) -> Vec<ImageBuffer<Rgba<u8>, Vec<u8>>> {
+    let render_frame = |i: u32| get_img(settings, animation.t(i), ast);
+
+    // Like the pixels in `get_img()`, frames using legacy rand are rendered in order on one thread
+    if uses_rand(ast) {
+        (0..animation.frames).map(render_frame).collect()
+    } else {
+        (0..animation.frames)
+            .into_par_iter()
+            .map(render_frame)
+            .collect()
+    }
+}
+
+/// Creates the file at `path`. It is written under a temporary name, until it is committed with
+/// `commit_file()`. See `AtomicFile`
+fn create_file(path: &PathBuf) -> Result<AtomicFile> {
+    AtomicFile::create(path)
+        .map_err(|e| Error::Io(format!("Failed to create file {:?}.\nDetails: {}", path, e)))
+}
+
+/// Moves a complete file to its path
+fn commit_file(file: AtomicFile, path: &PathBuf) -> Result<()> {
+    file.commit()
+        .map_err(|e| Error::Io(format!("Failed to write file {:?}.\nDetails: {}", path, e)))
+}
+
+/// Saves an image with the image crate, in `format`, or the format given by the extension of
+/// `path`. Like other images, it is only moved to `path` once it is complete
+fn save_image<P>(
+    img: &ImageBuffer<P, Vec<P::Subpixel>>,
+    path: &PathBuf,
+    format: Option<ImageFormat>,
+) -> Result<()>
+where
+    P: Pixel + PixelWithColorType,
+    [P::Subpixel]: EncodableLayout,
+{
+    let mut file = create_file(path)?;
+    let result = match format {
+        Some(format) => Ok(format),
+        None => ImageFormat::from_path(path),
+    }
+    .and_then(|format| img.write_to(&mut file, format));
+
+    if let Err(e) = result {
+        return Err(Error::Io(format!(
+            "Failed to save image to {:?}.\nDetails: {}",
+            path, e
+        )));
+    }
+    commit_file(file, path)
+}
+
+/// Saves the animation as a gif, and gets the first frame, if there are any
+pub fn gen_gif(
+    path: PathBuf,
+    settings: &RenderSettings,
+    animation: &Animation,
+    options: &GifOptions,
+    metadata: &Metadata,
+    ast: &NodeAst,
+) -> Result<Option<ImageBuffer<Rgba<u8>, Vec<u8>>>> {
+    let frames = get_frames(settings, animation, ast);
+    let first = frames.first().cloned();
+    let mut file = create_file(&path)?;
+    write_gif(&mut file, frames, animation, options, metadata)?;
+    commit_file(file, &path)?;
+    Ok(first)
+}
+
+/// The settings of how the colors of a gif are reduced to its palettes
+#[derive(Clone, Debug)]
+pub struct GifOptions {
+    /// The max amount of colors in a palette, from 2 to 256
+    pub colors: usize,
+    /// How the colors are dithered
+    pub dither: Dither,
+    /// If every frame shares a single palette, instead of each frame having its own
+    pub global_palette: bool,
+    /// The colors of a palette every frame shares, which is used instead of picking one
+    pub fixed_palette: Option<Vec<[u8; 3]>>,
+}
+
+/// Encodes the frames of a gif, and the metadata, into `writer`. Gifs only have binary
+/// transparency, so pixels with an alpha below half are fully transparent, and the rest are opaque
+pub fn encode_gif(
+    writer: impl Write,
+    settings: &RenderSettings,
+    animation: &Animation,
+    options: &GifOptions,
+    metadata: &Metadata,
+    ast: &NodeAst,
+) -> Result<()> {
+    write_gif(
+        writer,
+        get_frames(settings, animation, ast),
+        animation,
+        options,
+        metadata,
+    )
+}
+
+/// Encodes rendered frames as a gif with the metadata into `writer`. See `encode_gif()`
+pub fn write_gif(
+    writer: impl Write,
+    mut frames: Vec<ImageBuffer<Rgba<u8>, Vec<u8>>>,
+    animation: &Animation,
+    options: &GifOptions,
+    metadata: &Metadata,
+) -> Result<()> {
+    let (width, height) = frames.first().map_or((0, 0), |x| x.dimensions());
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(Error::Usage(format!(
+            "Gifs can at most be {} by {} pixels",
+            u16::MAX,
+            u16::MAX
+        )));
     };
 
-    let mut gif_enc = image::codecs::gif::GifEncoder::new(file);
-    if let Err(e) = gif_enc.set_repeat(Repeat::Infinite) {
-        eprintln!(
-            "[ERROR]: Failed to set gif repeat to infinite.\nDetails: {}",
-            e
+    // Gif pixels are either fully transparent or opaque, so the alpha is thresholded. Every
+    // transparent pixel gets the same color, so they only take up one color of the palette
+    for frame in frames.iter_mut() {
+        for pixel in frame.pixels_mut() {
+            pixel.0 = match pixel.0[3] < 128 {
+                true => [0; 4],
+                false => [pixel.0[0], pixel.0[1], pixel.0[2], 255],
+            };
+        }
+    }
+
+    let global_palette = match &options.fixed_palette {
+        Some(colors) => {
+            let transparent = frames
+                .iter()
+                .any(|x| quantize::has_transparency(x.as_raw()));
+            Some(Palette::fixed(colors, transparent))
+        }
+        None => options.global_palette.then(|| {
+            let pixels: Vec<&[u8]> = frames.iter().map(|x| x.as_raw().as_slice()).collect();
+            Palette::from_frames(&pixels, options.colors)
+        }),
+    };
+
+    let delay = (animation.gif_delay().as_millis() / 10) as u16;
+    let encode = |writer| -> std::result::Result<(), gif::EncodingError> {
+        let global_rgb = global_palette.as_ref().map(Palette::to_rgb);
+        let mut encoder =
+            gif::Encoder::new(writer, width, height, global_rgb.as_deref().unwrap_or(&[]))?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        metadata.write_to_gif(&mut encoder)?;
+
+        for frame in &frames {
+            let local_palette = match global_palette {
+                Some(_) => None,
+                None => Some(Palette::new(frame.as_raw(), options.colors)),
+            };
+            let palette = global_palette.as_ref().or(local_palette.as_ref()).unwrap();
+
+            let buffer =
+                quantize::map_pixels(frame.as_raw(), width as usize, palette, options.dither);
+            encoder.write_frame(&gif::Frame {
+                width,
+                height,
+                delay,
+                dispose: gif::DisposalMethod::Background,
+                transparent: quantize::has_transparency(frame.as_raw())
+                    .then(|| palette.transparent_index()),
+                palette: local_palette.as_ref().map(Palette::to_rgb),
+                buffer: Cow::Owned(buffer),
+                ..gif::Frame::default()
+            })?;
+        }
+        Ok(())
+    };
+
+    encode(writer).map_err(|e| Error::Io(format!("Failed to encode gif.\nDetails: {}", e)))
+}
+
+/// Saves the animation as an animated PNG, which keeps every color, unlike a gif, and gets the
+/// first frame, if there are any
+pub fn gen_apng(
+    path: PathBuf,
+    settings: &RenderSettings,
+    animation: &Animation,
+    metadata: &Metadata,
+    ast: &NodeAst,
+) -> Result<Option<ImageBuffer<Rgba<u8>, Vec<u8>>>> {
+    let frames = get_frames(settings, animation, ast);
+    let mut file = create_file(&path)?;
+    write_apng(&mut file, &frames, animation, metadata)?;
+    commit_file(file, &path)?;
+    Ok(frames.into_iter().next())
+}
+
+/// Encodes the frames of an animated PNG, and the metadata, into `writer`
+pub fn encode_apng(
+    writer: impl Write,
+    settings: &RenderSettings,
+    animation: &Animation,
+    metadata: &Metadata,
+    ast: &NodeAst,
+) -> Result<()> {
+    let frames = get_frames(settings, animation, ast);
+    write_apng(writer, &frames, animation, metadata)
+}
+
+/// Encodes rendered frames as an animated PNG with the metadata into `writer`
+pub fn write_apng(
+    writer: impl Write,
+    frames: &[ImageBuffer<Rgba<u8>, Vec<u8>>],
+    animation: &Animation,
+    metadata: &Metadata,
+) -> Result<()> {
+    let encode = || -> std::result::Result<(), png::EncodingError> {
+        let (width, height) = frames.first().map_or((0, 0), |x| x.dimensions());
+        let mut encoder = png::Encoder::new(writer, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(frames.len() as u32, 0)?;
+        let (numerator, denominator) = animation.apng_delay();
+        encoder.set_frame_delay(numerator, denominator)?;
+        metadata.add_to_png(&mut encoder)?;
+
+        let mut png_writer = encoder.write_header()?;
+        for frame in frames {
+            png_writer.write_image_data(frame.as_raw())?;
+        }
+        png_writer.finish()
+    };
+
+    encode().map_err(|e| Error::Io(format!("Failed to encode apng.\nDetails: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{PI, TAU};
+
+    use super::*;
+
+    #[test]
+    fn t_steps_linearly_without_reaching_the_end() {
+        let animation = Animation::new(8, 10., TMode::Linear, 0., TAU).unwrap();
+        let ts: Vec<f64> = (0..8).map(|x| animation.t(x)).collect();
+        assert_eq!(ts[0], 0.);
+        assert_eq!(ts[7], TAU * 7. / 8.);
+        for pair in ts.windows(2) {
+            assert!((pair[1] - pair[0] - TAU / 8.).abs() < 1e-12, "{:?}", ts);
+        }
+        // The frame after the last one is the first one again, so the loop is seamless
+        assert_eq!(animation.t(8), TAU);
+
+        let reversed = Animation::new(4, 10., TMode::Linear, 1., -1.).unwrap();
+        assert_eq!(
+            (0..4).map(|x| reversed.t(x)).collect::<Vec<_>>(),
+            [1., 0.5, 0., -0.5]
         );
     }
 
-    let mut frame_vec = vec![];
-    for i in 0..frames {
-        // Gets the current frame as a percentage of the frame count, then converts it into a
-        // percentage of TAU (2pi), which goes from -1 to 1.
-        let t = ((i as f64 / frames as f64) * TAU).sin();
-        let img_buf = get_img(width, height, t, ast);
+    #[test]
+    fn t_swings_back_in_pingpong_and_sine_modes() {
+        let pingpong = Animation::new(8, 10., TMode::Pingpong, 0., PI).unwrap();
+        let ts: Vec<f64> = (0..8).map(|x| pingpong.t(x)).collect();
+        assert_eq!(ts[0], 0.);
+        assert_eq!(ts[4], PI);
+        for i in 1..4 {
+            assert_eq!(ts[i], ts[8 - i]);
+        }
 
-        let frame = image::Frame::from_parts(
-            img_buf,
-            0,
-            0,
-            image::Delay::from_saturating_duration(Duration::from_secs(0)),
+        let sine = Animation::new(4, 10., TMode::Sine, 0., 2.).unwrap();
+        let ts: Vec<f64> = (0..4).map(|x| sine.t(x)).collect();
+        assert_eq!(ts[0], 1.);
+        assert_eq!(ts[1], 2.);
+        assert!(
+            (ts[2] - 1.).abs() < 1e-12 && ts[3].abs() < 1e-12,
+            "{:?}",
+            ts
         );
+    }
+
+    #[test]
+    fn supersampling_averages_a_grid_of_samples() {
+        // Pixel 1 covers x from -0.5 to 0, so half of its samples are on each side of the edge
+        let ast = NodeAst::parse_from_str("RGB: (x < -0.25 ? 1 : -1)").unwrap();
+        let red = |samples| {
+            let settings = RenderSettings {
+                samples,
+                ..RenderSettings::plain(4, 1)
+            };
+            let img = get_img(&settings, 0., &ast);
+            img.pixels().map(|x| x.0[0]).collect::<Vec<_>>()
+        };
+        assert_eq!(red(1), [255, 255, 0, 0]);
+        assert_eq!(red(4), [255, 127, 0, 0]);
+
+        // Every pixel is the mean of the samples at the offsets of a regular grid
+        let ast = NodeAst::parse_from_str("R: sin(mult(x, 3))\nG: mult(x, y)\nB: t").unwrap();
+        let compiled = ast.compile();
+        let settings = RenderSettings {
+            samples: 3,
+            ..RenderSettings::plain(5, 4)
+        };
+        for (x, y) in [(0, 0), (2, 1), (4, 3)] {
+            let mut expected = [0.; 4];
+            for (i, j) in (0..3).flat_map(|i| (0..3).map(move |j| (i, j))) {
+                let x_frac = (x as f64 + j as f64 / 3.) / 5.;
+                let y_frac = (y as f64 + i as f64 / 3.) / 4.;
+                let values = compiled.get_values(x_frac * 2. - 1., y_frac * 2. - 1., 0.5);
+                for (sum, value) in expected.iter_mut().zip(values) {
+                    *sum += value / 9.;
+                }
+            }
+            let values = get_values(x, y, &settings, 0.5, &compiled, None);
+            for (value, expected) in values.into_iter().zip(expected) {
+                assert!(
+                    (value - expected).abs() < 1e-12,
+                    "{:?} at ({}, {})",
+                    values,
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tone_maps_keep_detail_outside_of_the_range() {
+        assert_eq!(ToneMap::Clamp.apply(1.5), 1.);
+        assert_eq!(ToneMap::Wrap.apply(1.5), -0.5);
+        assert_eq!(ToneMap::Wrap.apply(-1.5), 0.5);
+        assert_eq!(ToneMap::Tanh.apply(f64::INFINITY), 1.);
+        assert!(ToneMap::Tanh.apply(f64::NAN).is_nan());
 
-        frame_vec.push(frame);
+        // x goes from -1 to 0.875 in steps of 0.125, so mult(x, 2) reaches the edges of the range
+        // in 9 of the pixels
+        let ast = NodeAst::parse_from_str("RGB: mult(x, 2)").unwrap();
+        let red = |tone_map| {
+            let settings = RenderSettings {
+                tone_map,
+                ..RenderSettings::plain(16, 1)
+            };
+            let img = get_img(&settings, 0., &ast);
+            img.pixels().map(|x| x.0[0]).collect::<Vec<_>>()
+        };
+        let saturated = |reds: &[u8]| reds.iter().filter(|x| **x == 0 || **x == 255).count();
+        assert_eq!(saturated(&red(Some(ToneMap::Clamp))), 9);
+        assert_eq!(saturated(&red(None)), 9);
+        let tanh = red(Some(ToneMap::Tanh));
+        assert_eq!(saturated(&tanh), 0, "{:?}", tanh);
+        assert!(tanh.windows(2).all(|x| x[0] < x[1]), "{:?}", tanh);
     }
-    if let Err(e) = gif_enc.encode_frames(frame_vec) {
-        eprintln!("[ERROR]: Failed to encode gif.\nDetails: {}", e);
-        std::process::exit(1);
+
+    #[test]
+    fn nan_pixels_get_the_nan_color() {
+        // sqrt(x) is NaN in the left half of the image
+        let ast = NodeAst::parse_from_str("R: sqrt(x)\nG: 1\nB: -1").unwrap();
+        let pixels = |nan_color| {
+            let settings = RenderSettings {
+                nan_color,
+                ..RenderSettings::plain(4, 1)
+            };
+            let img = get_img(&settings, 0., &ast);
+            img.pixels().map(|x| x.0).collect::<Vec<_>>()
+        };
+
+        let nan_color = Some(HexColor([0xff, 0x00, 0xcc]));
+        let with_color = pixels(nan_color);
+        assert_eq!(with_color[..2], [[0xff, 0x00, 0xcc, 255]; 2]);
+        assert_eq!(with_color[2..], pixels(None)[2..]);
+        assert_eq!(pixels(None)[..2], [[0, 255, 0, 255]; 2]);
+    }
+
+    #[test]
+    fn regions_are_crops_of_the_full_image() {
+        let ast = NodeAst::parse_from_str("R: sin(mult(x, 7))\nG: (x < y ? t : y)\nB: mult(x, y)")
+            .unwrap();
+        let settings = RenderSettings {
+            samples: 2,
+            ..RenderSettings::plain(7, 5)
+        };
+        let full = get_img(&settings, 0.5, &ast);
+
+        // The tiles cover the whole image, with uneven sizes at the right and bottom edges
+        let mut stitched = ImageBuffer::new(7, 5);
+        for (x, width) in [(0, 3), (3, 3), (6, 1)] {
+            for (y, height) in [(0, 2), (2, 3)] {
+                let region = Region {
+                    x,
+                    y,
+                    width,
+                    height,
+                };
+                let tile = get_img(
+                    &RenderSettings {
+                        region: Some(region),
+                        ..settings.clone()
+                    },
+                    0.5,
+                    &ast,
+                );
+                let crop = image::imageops::crop_imm(&full, x, y, width, height).to_image();
+                assert_eq!(tile, crop, "{:?}", region);
+                image::imageops::replace(&mut stitched, &tile, x as i64, y as i64);
+            }
+        }
+        assert_eq!(stitched, full);
     }
 }
@@ -0,0 +1,106 @@
+use std::fmt::Display;
+
+use rayon::prelude::*;
+
+use crate::node::ast::NodeAst;
+
+use super::{RenderSettings, tree_values, uses_rand};
+
+/// The most samples taken over all the values of t together. Animations take fewer samples of
+/// each frame, so normalizing them takes about as long as a still
+const MAX_SAMPLES: u32 = 1 << 18;
+
+/// The least amount of samples along each axis of a frame
+const MIN_GRID: u32 = 8;
+
+/// How the values of the red, green and blue trees are stretched over the range of the trees
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Normalization {
+    pub scale: [f64; 3],
+    pub offset: [f64; 3],
+}
+
+impl Normalization {
+    /// Finds the scale and offset which map the lowest and highest values of each tree to -1
+    /// and 1, ignoring `percentile` percent of the values at each end. The values are sampled on
+    /// a grid over the full image at every value of `ts`, even if only a region is rendered, so
+    /// the regions of an image are normalized the same
+    pub fn compute(settings: &RenderSettings, ts: &[f64], ast: &NodeAst, percentile: f64) -> Self {
+        let program = ast.compile();
+        let per_frame = MAX_SAMPLES / ts.len().max(1) as u32;
+        let side = (per_frame as f64).sqrt() as u32;
+        let grid_width = side.max(MIN_GRID).min(settings.width);
+        let grid_height = side.max(MIN_GRID).min(settings.height);
+
+        let points: Vec<(f64, u32, u32)> = ts
+            .iter()
+            .flat_map(|&t| {
+                (0..grid_height).flat_map(move |j| (0..grid_width).map(move |i| (t, i, j)))
+            })
+            .collect();
+        let sample = |&(t, i, j): &(f64, u32, u32)| {
+            let x = settings.x_range.at(i as f64 / grid_width as f64);
+            let y = settings.y_range.at(j as f64 / grid_height as f64);
+            let [r, g, b, _] = tree_values(x, y, settings, t, &program);
+            [r, g, b]
+        };
+
+        // Like the pixels of an image, samples using legacy rand are taken in order
+        let values: Vec<[f64; 3]> = match uses_rand(ast) {
+            true => points.iter().map(sample).collect(),
+            false => points.par_iter().map(sample).collect(),
+        };
+
+        let mut scale = [1.; 3];
+        let mut offset = [0.; 3];
+        for channel in 0..3 {
+            let mut channel_values: Vec<f64> = values
+                .iter()
+                .map(|x| x[channel])
+                .filter(|x| x.is_finite())
+                .collect();
+            let Some((low, high)) = bounds(&mut channel_values, percentile) else {
+                continue;
+            };
+            // A constant tree has nothing to stretch, so it is kept as it is
+            if high > low {
+                scale[channel] = 2. / (high - low);
+                offset[channel] = -1. - low * scale[channel];
+            }
+        }
+        Self { scale, offset }
+    }
+
+    /// Maps the values of the red, green and blue trees
+    pub fn apply(&self, values: [f64; 3]) -> [f64; 3] {
+        [0, 1, 2].map(|i| values[i] * self.scale[i] + self.offset[i])
+    }
+}
+
+impl Display for Normalization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let channels = ["R", "G", "B"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                format!(
+                    "{}: scale {}, offset {}",
+                    name, self.scale[i], self.offset[i]
+                )
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", channels.join("; "))
+    }
+}
+
+/// Gets the values at `percentile` percent from the lowest and the highest, or `None` if there
+/// are no values
+fn bounds(values: &mut [f64], percentile: f64) -> Option<(f64, f64)> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable_by(f64::total_cmp);
+    let last = values.len() - 1;
+    let skip = ((percentile / 100. * last as f64).round() as usize).min(last / 2);
+    Some((values[skip], values[last - skip]))
+}
@@ -0,0 +1,108 @@
+use std::{io::Write, path::PathBuf};
+
+use image::{Rgb, Rgb32FImage};
+
+use crate::{
+    error::{Error, Result},
+    node::ast::NodeAst,
+};
+
+use super::{Animation, RenderSettings, commit_file, create_file, get_values, render};
+
+/// The formats of raw float values kroyer can write
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FloatKind {
+    /// A NumPy array, with a header giving its shape
+    Npy,
+    /// The values alone, with no header
+    F32Raw,
+}
+
+/// The size the header of a NumPy array, with its magic string and length, is padded to
+const NPY_ALIGN: usize = 64;
+
+/// Saves the values of the trees as raw floats. See `encode_floats()`
+pub fn gen_floats(
+    path: PathBuf,
+    kind: FloatKind,
+    settings: &RenderSettings,
+    animation: Option<&Animation>,
+    ast: &NodeAst,
+) -> Result<()> {
+    let mut file = create_file(&path)?;
+    encode_floats(&mut file, kind, settings, animation, ast)?;
+    commit_file(file, &path)
+}
+
+/// Writes the red, green and blue values of the trees into `writer` as little-endian 32-bit
+/// floats, row by row, in the range of the trees. The values are neither clamped nor rounded.
+/// The array has the shape (height, width, 3), or (frames, height, width, 3) if `animation` is
+/// set, in which case the frames are written one after another
+pub fn encode_floats(
+    mut writer: impl Write,
+    kind: FloatKind,
+    settings: &RenderSettings,
+    animation: Option<&Animation>,
+    ast: &NodeAst,
+) -> Result<()> {
+    let (width, height) = settings.size();
+    let mut encode = || -> std::io::Result<()> {
+        if kind == FloatKind::Npy {
+            let shape = match animation {
+                Some(animation) => vec![animation.frames, height, width, 3],
+                None => vec![height, width, 3],
+            };
+            writer.write_all(&npy_header(&shape))?;
+        }
+
+        let Some(animation) = animation else {
+            write_values(
+                &mut writer,
+                &get_values_img(settings, settings.still_t, ast),
+            )?;
+            return writer.flush();
+        };
+
+        for i in 0..animation.frames {
+            write_values(&mut writer, &get_values_img(settings, animation.t(i), ast))?;
+        }
+        writer.flush()
+    };
+
+    encode().map_err(|e| Error::Io(format!("Failed to write float values.\nDetails: {}", e)))
+}
+
+/// Renders the red, green and blue values of the trees at the time `t`
+fn get_values_img(settings: &RenderSettings, t: f64, ast: &NodeAst) -> Rgb32FImage {
+    let program = ast.compile();
+    render(settings, ast, |x, y, stats| {
+        let [r, g, b, _] = get_values(x, y, settings, t, &program, stats);
+        Rgb([r, g, b].map(|x| x as f32))
+    })
+}
+
+fn write_values(writer: &mut impl Write, img: &Rgb32FImage) -> std::io::Result<()> {
+    let bytes: Vec<u8> = img.as_raw().iter().flat_map(|x| x.to_le_bytes()).collect();
+    writer.write_all(&bytes)
+}
+
+/// Creates the header of a version 1.0 NumPy array of little-endian 32-bit floats, in C order,
+/// with the given shape. The header is padded with spaces, and ends with a newline, so the
+/// values start at a multiple of `NPY_ALIGN` bytes
+fn npy_header(shape: &[u32]) -> Vec<u8> {
+    let shape: Vec<String> = shape.iter().map(|x| x.to_string()).collect();
+    let mut dict = format!(
+        "{{'descr': '<f4', 'fortran_order': False, 'shape': ({}), }}",
+        shape.join(", ")
+    );
+    // The magic string, the version and the length of the header come before it
+    let prefix_len = 6 + 2 + 2;
+    let padding = NPY_ALIGN - (prefix_len + dict.len() + 1) % NPY_ALIGN;
+    dict.push_str(&" ".repeat(padding % NPY_ALIGN));
+    dict.push('\n');
+
+    let mut header = b"\x93NUMPY\x01\x00".to_vec();
+    header.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+    header.extend_from_slice(dict.as_bytes());
+    header
+}
@@ -0,0 +1,128 @@
+use std::{io::Write, path::PathBuf};
+
+use image::{ImageBuffer, Rgba};
+
+use crate::{
+    error::{Error, Result},
+    node::ast::NodeAst,
+};
+
+use super::{Animation, RenderSettings, commit_file, create_file, get_img};
+
+/// The netpbm formats kroyer can write
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PnmKind {
+    /// A binary PPM (P6), with the red, green and blue of each pixel
+    Ppm,
+    /// A PAM (P7), with the red, green, blue and alpha of each pixel
+    Pam,
+}
+
+/// Saves the image as a netpbm image. See `encode_pnm()`
+pub fn gen_pnm(
+    path: PathBuf,
+    kind: PnmKind,
+    settings: &RenderSettings,
+    animation: Option<&Animation>,
+    ast: &NodeAst,
+) -> Result<()> {
+    let mut file = create_file(&path)?;
+    encode_pnm(&mut file, kind, settings, animation, ast)?;
+    commit_file(file, &path)
+}
+
+/// Writes the image as a netpbm image into `writer`. If `animation` is set, every frame is
+/// written as its own image, one after another, which netpbm tools read as a stream of images
+pub fn encode_pnm(
+    mut writer: impl Write,
+    kind: PnmKind,
+    settings: &RenderSettings,
+    animation: Option<&Animation>,
+    ast: &NodeAst,
+) -> Result<()> {
+    let mut encode = || -> std::io::Result<()> {
+        let Some(animation) = animation else {
+            write_image(&mut writer, kind, &get_img(settings, settings.still_t, ast))?;
+            return writer.flush();
+        };
+
+        for i in 0..animation.frames {
+            write_image(&mut writer, kind, &get_img(settings, animation.t(i), ast))?;
+        }
+        writer.flush()
+    };
+
+    encode().map_err(|e| Error::Io(format!("Failed to write netpbm image.\nDetails: {}", e)))
+}
+
+fn write_image(
+    writer: &mut impl Write,
+    kind: PnmKind,
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> std::io::Result<()> {
+    match kind {
+        PnmKind::Ppm => {
+            write!(writer, "P6\n{} {}\n255\n", img.width(), img.height())?;
+            let rgb: Vec<u8> = img
+                .pixels()
+                .flat_map(|x| [x.0[0], x.0[1], x.0[2]])
+                .collect();
+            writer.write_all(&rgb)
+        }
+        PnmKind::Pam => {
+            write!(
+                writer,
+                "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+                img.width(),
+                img.height()
+            )?;
+            writer.write_all(img.as_raw())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::TMode;
+
+    /// Encodes a 3 by 2 image, which is red left of the middle, so its first two columns are red
+    fn encode(kind: PnmKind, animation: Option<&Animation>) -> Vec<u8> {
+        let settings = RenderSettings::plain(3, 2);
+        let ast = NodeAst::parse_from_str("R: (x < 0 ? 1 : -1)\nG: -1\nB: -1\nA: 1").unwrap();
+        let mut image = vec![];
+        encode_pnm(&mut image, kind, &settings, animation, &ast).unwrap();
+        image
+    }
+
+    #[test]
+    fn ppms_have_a_p6_header_and_rgb_pixels() {
+        let image = encode(PnmKind::Ppm, None);
+        let header = b"P6\n3 2\n255\n";
+        assert!(image.starts_with(header));
+        let pixels = &image[header.len()..];
+        assert_eq!(pixels.len(), 3 * 2 * 3);
+        assert_eq!(&pixels[..9], [255, 0, 0, 255, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn pams_have_a_p7_header_and_rgba_pixels() {
+        let image = encode(PnmKind::Pam, None);
+        let header = b"P7\nWIDTH 3\nHEIGHT 2\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n";
+        assert!(image.starts_with(header));
+        let pixels = &image[header.len()..];
+        assert_eq!(pixels.len(), 3 * 2 * 4);
+        assert_eq!(
+            &pixels[..12],
+            [255, 0, 0, 255, 255, 0, 0, 255, 0, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn animations_are_a_stream_of_whole_images() {
+        let animation = Animation::new(4, 10., TMode::Linear, 0., 1.).unwrap();
+        let image = encode(PnmKind::Ppm, Some(&animation));
+        let still = encode(PnmKind::Ppm, None);
+        assert_eq!(image, still.repeat(4));
+    }
+}
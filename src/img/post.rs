@@ -0,0 +1,51 @@
+use image::{ImageBuffer, Rgba, imageops};
+
+use crate::cli::PostFilter;
+
+/// The standard deviation of the blur the sharpen filter compares the image with
+const SHARPEN_SIGMA: f32 = 1.;
+
+/// Applies the filters to the image, in order
+pub fn apply(
+    mut img: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    filters: &[PostFilter],
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    for filter in filters {
+        img = match *filter {
+            PostFilter::Blur(sigma) if sigma > 0. => imageops::blur(&img, sigma),
+            PostFilter::Blur(_) => img,
+            PostFilter::Sharpen(amount) => sharpen(img, amount),
+            PostFilter::Contrast(factor) => contrast(img, factor),
+        };
+    }
+    img
+}
+
+/// Adds `amount` of the difference between each pixel and the same pixel in a blurred copy. The
+/// alpha is kept as it is
+fn sharpen(mut img: ImageBuffer<Rgba<u8>, Vec<u8>>, amount: f32) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let blurred = imageops::blur(&img, SHARPEN_SIGMA);
+    for (pixel, blurred) in img.pixels_mut().zip(blurred.pixels()) {
+        for i in 0..3 {
+            let value = pixel.0[i] as f32;
+            let sharpened = value + (value - blurred.0[i] as f32) * amount;
+            pixel.0[i] = sharpened.round().clamp(0., 255.) as u8;
+        }
+    }
+    img
+}
+
+/// Scales the distance of each color from the middle gray by `factor`. The alpha is kept as it is
+fn contrast(
+    mut img: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    factor: f32,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    const MIDDLE: f32 = 127.5;
+    for pixel in img.pixels_mut() {
+        for i in 0..3 {
+            let value = (pixel.0[i] as f32 - MIDDLE) * factor + MIDDLE;
+            pixel.0[i] = value.round().clamp(0., 255.) as u8;
+        }
+    }
+    img
+}
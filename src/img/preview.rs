@@ -0,0 +1,221 @@
+use std::io::Write;
+
+use image::{ImageBuffer, Rgba, imageops};
+
+use crate::{
+    cli::Dither,
+    io::term::{self, Graphics, Terminal},
+    log,
+};
+
+use super::quantize::{self, Palette};
+
+/// The size of a character cell in pixels, for terminals which don't tell their size in pixels
+const CELL_SIZE: (u32, u32) = (8, 16);
+
+/// The most bytes of base64 in each chunk of a kitty image
+const KITTY_CHUNK: usize = 4096;
+
+/// Shows the image in the terminal, scaled down to fit in it, with the best graphics the
+/// terminal has. See `term::graphics()`. A line is left below the image for the prompt. If
+/// kroyer isn't run in a terminal which can show images, a warning is given instead
+pub fn show(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) {
+    let (Some(graphics), Some(mut terminal)) = (term::graphics(), Terminal::open()) else {
+        log::warning!("The terminal can't show images, so there is no preview");
+        return;
+    };
+    let size = terminal.size();
+    let rows = size.rows.saturating_sub(1).max(1);
+
+    let result = match graphics {
+        Graphics::Kitty | Graphics::Sixel => {
+            let (width, height) = match size.pixels {
+                Some((width, height)) => (width, height / size.rows * rows),
+                None => (size.cols * CELL_SIZE.0, rows * CELL_SIZE.1),
+            };
+            let img = fit(img, width, height);
+            match graphics {
+                Graphics::Kitty => write_kitty(&mut terminal, &img),
+                _ => write_sixel(&mut terminal, &img),
+            }
+        }
+        Graphics::TrueColor | Graphics::Color256 => {
+            let img = fit(img, size.cols, rows * 2);
+            write_half_blocks(&mut terminal, &img, graphics == Graphics::TrueColor)
+        }
+    };
+
+    if let Err(e) = result.and_then(|_| terminal.flush()) {
+        log::warning!("Failed to show the preview.\nDetails: {}", e);
+    }
+}
+
+/// Scales the image down to fit in `width` by `height`, keeping its aspect, and blends it onto
+/// black, so every pixel is opaque. Images which already fit aren't scaled
+fn fit(
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    width: u32,
+    height: u32,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let scale = (width as f64 / img.width() as f64)
+        .min(height as f64 / img.height() as f64)
+        .min(1.);
+    let mut img = match scale < 1. {
+        true => imageops::thumbnail(
+            img,
+            ((img.width() as f64 * scale) as u32).max(1),
+            ((img.height() as f64 * scale) as u32).max(1),
+        ),
+        false => img.clone(),
+    };
+    for pixel in img.pixels_mut() {
+        let alpha = pixel.0[3] as u32;
+        for i in 0..3 {
+            pixel.0[i] = (pixel.0[i] as u32 * alpha / 255) as u8;
+        }
+        pixel.0[3] = 255;
+    }
+    img
+}
+
+/// Writes the image with the kitty graphics protocol, as base64 RGBA sent in chunks
+fn write_kitty(
+    writer: &mut impl Write,
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> std::io::Result<()> {
+    let data = base64(img.as_raw());
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        match i {
+            0 => write!(
+                writer,
+                "\x1b_Gf=32,s={},v={},a=T,m={};",
+                img.width(),
+                img.height(),
+                more
+            )?,
+            _ => write!(writer, "\x1b_Gm={};", more)?,
+        }
+        writer.write_all(chunk)?;
+        writer.write_all(b"\x1b\\")?;
+    }
+    writeln!(writer)
+}
+
+/// Writes the image as sixels, with a palette of at most 256 colors
+fn write_sixel(
+    writer: &mut impl Write,
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> std::io::Result<()> {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let palette = Palette::new(img.as_raw(), 256);
+    let indices = quantize::map_pixels(img.as_raw(), width, &palette, Dither::None);
+    let colors = palette.to_rgb();
+
+    write!(writer, "\x1bPq\"1;1;{};{}", width, height)?;
+    for (i, color) in colors.chunks_exact(3).enumerate() {
+        let [r, g, b] = [0, 1, 2].map(|x| color[x] as u32 * 100 / 255);
+        write!(writer, "#{};2;{};{};{}", i, r, g, b)?;
+    }
+
+    // Each sixel is a column of 6 pixels, drawn once for every color in the band
+    for top in (0..height).step_by(6) {
+        let rows = top..(top + 6).min(height);
+        let mut used: Vec<u8> = rows
+            .clone()
+            .flat_map(|y| indices[y * width..(y + 1) * width].iter().copied())
+            .collect();
+        used.sort_unstable();
+        used.dedup();
+
+        for (n, color) in used.iter().enumerate() {
+            let sixels: Vec<u8> = (0..width)
+                .map(|x| {
+                    let bits = rows
+                        .clone()
+                        .enumerate()
+                        .filter(|(_, y)| indices[y * width + x] == *color)
+                        .fold(0, |acc, (bit, _)| acc | 1 << bit);
+                    63 + bits
+                })
+                .collect();
+            if n > 0 {
+                // Goes back to the start of the band, to draw the next color over it
+                writer.write_all(b"$")?;
+            }
+            write!(writer, "#{}", color)?;
+            write_sixel_runs(writer, &sixels)?;
+        }
+        writer.write_all(b"-")?;
+    }
+    writer.write_all(b"\x1b\\\n")
+}
+
+/// Writes the sixels, with runs of the same sixel shortened to a repeat
+fn write_sixel_runs(writer: &mut impl Write, sixels: &[u8]) -> std::io::Result<()> {
+    let mut rest = sixels;
+    while let Some(&sixel) = rest.first() {
+        let len = rest.iter().take_while(|x| **x == sixel).count();
+        match len {
+            1..=3 => writer.write_all(&rest[..len])?,
+            _ => write!(writer, "!{}{}", len, sixel as char)?,
+        }
+        rest = &rest[len..];
+    }
+    Ok(())
+}
+
+/// Writes the image as upper half blocks, where the foreground is the upper pixel, and the
+/// background the lower one
+fn write_half_blocks(
+    writer: &mut impl Write,
+    img: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    true_color: bool,
+) -> std::io::Result<()> {
+    let color = |layer: u8, pixel: &Rgba<u8>| {
+        let [r, g, b, _] = pixel.0;
+        match true_color {
+            true => format!("\x1b[{}8;2;{};{};{}m", layer, r, g, b),
+            false => format!("\x1b[{}8;5;{}m", layer, xterm_color(r, g, b)),
+        }
+    };
+
+    for top in (0..img.height()).step_by(2) {
+        for x in 0..img.width() {
+            let upper = color(3, img.get_pixel(x, top));
+            let lower = match top + 1 < img.height() {
+                true => color(4, img.get_pixel(x, top + 1)),
+                false => "\x1b[49m".to_owned(),
+            };
+            write!(writer, "{}{}\u{2580}", upper, lower)?;
+        }
+        writeln!(writer, "\x1b[0m")?;
+    }
+    Ok(())
+}
+
+/// Gets the closest color of the 6x6x6 color cube of xterm's 256 colors
+fn xterm_color(r: u8, g: u8, b: u8) -> u8 {
+    let level = |x: u8| ((x as u32 * 5 + 127) / 255) as u8;
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+/// Encodes the bytes as base64, with padding
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, x)| acc | (*x as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use color_quant::NeuQuant;
+
+use crate::cli::Dither;
+
+/// The sampling factor of NeuQuant, where 1 is the slowest, and gives the best palettes
+const NEUQUANT_SAMPLING: i32 = 1;
+
+/// The amount of frames sampled for a global palette
+const GLOBAL_PALETTE_FRAMES: usize = 16;
+
+/// The 4x4 Bayer matrix used for ordered dithering
+const BAYER: [[f64; 4]; 4] = [
+    [0., 8., 2., 10.],
+    [12., 4., 14., 6.],
+    [3., 11., 1., 9.],
+    [15., 7., 13., 5.],
+];
+
+/// A palette of at most 256 RGBA colors, which pixels can be mapped onto
+pub enum Palette {
+    /// Every color of the image, if there are few enough, with the index of each
+    Exact(Vec<[u8; 4]>, HashMap<[u8; 4], u8>),
+    /// Colors picked by NeuQuant
+    Quantized(NeuQuant),
+}
+
+impl Palette {
+    /// Creates a palette of at most `colors` colors for the RGBA `pixels`. If they have no more
+    /// colors than that, every color is kept exactly
+    pub fn new(pixels: &[u8], colors: usize) -> Self {
+        let mut exact = vec![];
+        let mut indices = HashMap::new();
+        for pixel in pixels.chunks_exact(4) {
+            let pixel = [pixel[0], pixel[1], pixel[2], pixel[3]];
+            if indices.contains_key(&pixel) {
+                continue;
+            }
+            if exact.len() == colors {
+                return Palette::Quantized(NeuQuant::new(NEUQUANT_SAMPLING, colors, pixels));
+            }
+            indices.insert(pixel, exact.len() as u8);
+            exact.push(pixel);
+        }
+        Palette::Exact(exact, indices)
+    }
+
+    /// Creates a palette shared by all `frames`, from an evenly spaced sample of them. If any
+    /// frame has transparent pixels, the palette has a transparent color, even if the sampled
+    /// frames have none
+    pub fn from_frames(frames: &[&[u8]], colors: usize) -> Self {
+        let step = frames.len().div_ceil(GLOBAL_PALETTE_FRAMES).max(1);
+        let mut pixels: Vec<u8> = frames
+            .iter()
+            .step_by(step)
+            .flat_map(|x| x.iter())
+            .copied()
+            .collect();
+        if frames.iter().any(|x| has_transparency(x)) {
+            pixels.extend([0; 4]);
+        }
+        Self::new(&pixels, colors)
+    }
+
+    /// Creates a palette of exactly the given opaque colors, and a transparent color if
+    /// `transparent` is set. The caller makes sure there are at most 256 colors
+    pub fn fixed(colors: &[[u8; 3]], transparent: bool) -> Self {
+        let pixels: Vec<u8> = colors
+            .iter()
+            .flat_map(|x| [x[0], x[1], x[2], 255])
+            .chain(transparent.then_some([0; 4]).into_iter().flatten())
+            .collect();
+        Self::new(&pixels, 256)
+    }
+
+    /// Gets the index of the color closest to `pixel`
+    fn index_of(&self, pixel: [u8; 4]) -> u8 {
+        match self {
+            Palette::Exact(colors, indices) => match indices.get(&pixel) {
+                Some(index) => *index,
+                None => nearest(colors, pixel),
+            },
+            Palette::Quantized(nq) => nq.index_of(&pixel) as u8,
+        }
+    }
+
+    /// Gets the color at `index`
+    fn color(&self, index: u8) -> [u8; 4] {
+        match self {
+            Palette::Exact(colors, _) => colors[index as usize],
+            Palette::Quantized(nq) => nq.lookup(index as usize).unwrap_or_default(),
+        }
+    }
+
+    /// Gets the colors as RGB bytes, as used for gif palettes
+    pub fn to_rgb(&self) -> Vec<u8> {
+        match self {
+            Palette::Exact(colors, _) => colors.iter().flat_map(|x| [x[0], x[1], x[2]]).collect(),
+            Palette::Quantized(nq) => nq.color_map_rgb(),
+        }
+    }
+
+    /// Gets the index of the transparent color, which transparent pixels are mapped to. They are
+    /// expected to be `[0, 0, 0, 0]`
+    pub fn transparent_index(&self) -> u8 {
+        self.index_of([0; 4])
+    }
+}
+
+/// Checks if any of the RGBA `pixels` are fully transparent
+pub fn has_transparency(pixels: &[u8]) -> bool {
+    pixels.chunks_exact(4).any(|x| x[3] == 0)
+}
+
+/// Finds the index of the color in `colors` closest to `pixel`
+fn nearest(colors: &[[u8; 4]], pixel: [u8; 4]) -> u8 {
+    let distance = |color: &[u8; 4]| -> i32 {
+        color
+            .iter()
+            .zip(pixel)
+            .map(|(a, b)| (*a as i32 - b as i32).pow(2))
+            .sum()
+    };
+    (0..colors.len())
+        .min_by_key(|x| distance(&colors[*x]))
+        .unwrap_or(0) as u8
+}
+
+/// Maps the RGBA `pixels` of an image `width` pixels wide onto the palette, and gets the index
+/// of each pixel. Fully transparent pixels are never dithered
+pub fn map_pixels(pixels: &[u8], width: usize, palette: &Palette, dither: Dither) -> Vec<u8> {
+    let pixels: Vec<[u8; 4]> = pixels
+        .chunks_exact(4)
+        .map(|x| [x[0], x[1], x[2], x[3]])
+        .collect();
+
+    match dither {
+        Dither::None => pixels.into_iter().map(|x| palette.index_of(x)).collect(),
+        Dither::Ordered => {
+            let spread = 255. / (palette.to_rgb().len() as f64 / 3.).cbrt();
+            pixels
+                .into_iter()
+                .enumerate()
+                .map(|(i, pixel)| {
+                    if pixel[3] == 0 {
+                        return palette.index_of(pixel);
+                    }
+                    let threshold = (BAYER[i / width % 4][i % width % 4] + 0.5) / 16. - 0.5;
+                    let [r, g, b] =
+                        [0, 1, 2].map(|x| (pixel[x] as f64 + threshold * spread).clamp(0., 255.));
+                    palette.index_of([r as u8, g as u8, b as u8, pixel[3]])
+                })
+                .collect()
+        }
+        Dither::FloydSteinberg => floyd_steinberg(&pixels, width, palette),
+    }
+}
+
+/// Maps the pixels onto the palette, spreading the error of each pixel onto its neighbours
+fn floyd_steinberg(pixels: &[[u8; 4]], width: usize, palette: &Palette) -> Vec<u8> {
+    let mut errors = vec![[0.; 3]; pixels.len()];
+    let mut indices = Vec::with_capacity(pixels.len());
+
+    for (i, pixel) in pixels.iter().enumerate() {
+        if pixel[3] == 0 {
+            indices.push(palette.index_of(*pixel));
+            continue;
+        }
+
+        let target = [0, 1, 2].map(|x| (pixel[x] as f64 + errors[i][x]).clamp(0., 255.));
+        let index = palette.index_of([
+            target[0].round() as u8,
+            target[1].round() as u8,
+            target[2].round() as u8,
+            pixel[3],
+        ]);
+        indices.push(index);
+
+        let color = palette.color(index);
+        let error = [0, 1, 2].map(|x| target[x] - color[x] as f64);
+        let (x, y) = (i % width, i / width);
+        let mut spread = |dx: isize, dy: usize, weight: f64| {
+            let Some(nx) = x.checked_add_signed(dx).filter(|nx| *nx < width) else {
+                return;
+            };
+            let j = (y + dy) * width + nx;
+            if let Some(neighbour) = errors.get_mut(j) {
+                for c in 0..3 {
+                    neighbour[c] += error[c] * weight;
+                }
+            }
+        };
+        spread(1, 0, 7. / 16.);
+        spread(-1, 1, 3. / 16.);
+        spread(0, 1, 5. / 16.);
+        spread(1, 1, 1. / 16.);
+    }
+
+    indices
+}
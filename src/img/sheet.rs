@@ -0,0 +1,167 @@
+use std::{io::Write, path::PathBuf};
+
+use image::{
+    ExtendedColorType, ImageBuffer, ImageEncoder, ImageResult, Rgba, codecs::png::PngEncoder,
+};
+
+use crate::{cli::Grid, error::Result};
+
+use super::save_image;
+
+/// The width of a glyph of the label font, in pixels
+const GLYPH_WIDTH: u32 = 3;
+/// The height of a glyph of the label font, in pixels
+const GLYPH_HEIGHT: u32 = 5;
+/// The hex digits of the label font, as 3x5 bitmaps. Each row is 3 bits, from the top row in the
+/// highest bits, with the leftmost pixel of a row in the highest bit
+const HEX_GLYPHS: [u16; 16] = [
+    0b111_101_101_101_111, // 0
+    0b010_110_010_010_111, // 1
+    0b111_001_111_100_111, // 2
+    0b111_001_111_001_111, // 3
+    0b101_101_111_001_001, // 4
+    0b111_100_111_001_111, // 5
+    0b111_100_111_101_111, // 6
+    0b111_001_010_010_010, // 7
+    0b111_101_111_101_111, // 8
+    0b111_101_111_001_111, // 9
+    0b010_101_111_101_101, // a
+    0b110_101_110_101_110, // b
+    0b011_100_100_100_011, // c
+    0b110_101_101_101_110, // d
+    0b111_100_110_100_111, // e
+    0b111_100_110_100_100, // f
+];
+/// The x of the 0x prefix of seeds, which is lower than the digits, so it isn't read as one
+const X_GLYPH: u16 = 0b000_000_101_010_101;
+
+/// A cell of a contact sheet, with the label drawn in its corner, if any
+pub type Cell = (ImageBuffer<Rgba<u8>, Vec<u8>>, Option<String>);
+
+/// Saves a contact sheet from `compose()` as a PNG
+pub fn gen_contact_sheet(path: PathBuf, sheet: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Result<()> {
+    save_image(sheet, &path, None)
+}
+
+/// Encodes a contact sheet from `compose()` as a PNG into `writer`
+pub fn encode_contact_sheet(
+    writer: impl Write,
+    sheet: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+) -> ImageResult<()> {
+    PngEncoder::new(writer).write_image(
+        sheet.as_raw(),
+        sheet.width(),
+        sheet.height(),
+        ExtendedColorType::Rgba8,
+    )
+}
+
+/// Lays the cells out in a grid, row by row from the top left, in the order they are given.
+/// Every cell is expected to have the size of the first one
+pub fn compose(grid: Grid, cells: &[Cell]) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let (cell_width, cell_height) = cells.first().map_or((0, 0), |x| x.0.dimensions());
+    let mut sheet = ImageBuffer::new(cell_width * grid.cols, cell_height * grid.rows);
+
+    for (i, (img, label)) in cells.iter().enumerate() {
+        let left = i as u32 % grid.cols * cell_width;
+        let top = i as u32 / grid.cols * cell_height;
+        for (x, y, pixel) in img.enumerate_pixels() {
+            sheet.put_pixel(left + x, top + y, *pixel);
+        }
+        if let Some(label) = label {
+            draw_label(&mut sheet, label, left, top, cell_width, cell_height);
+        }
+    }
+
+    sheet
+}
+
+/// Draws the hex digits of `label` in white on black, in the top left corner of the cell at
+/// `left` and `top`. The label is wrapped onto as many lines as needed to fit the width of the
+/// cell, and cut off at its bottom. Characters that aren't hex digits, or the x of a 0x prefix,
+/// are skipped
+fn draw_label(
+    sheet: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    label: &str,
+    left: u32,
+    top: u32,
+    cell_width: u32,
+    cell_height: u32,
+) {
+    let glyphs: Vec<u16> = label
+        .chars()
+        .filter_map(|x| match x {
+            'x' => Some(X_GLYPH),
+            _ => x.to_digit(16).map(|x| HEX_GLYPHS[x as usize]),
+        })
+        .collect();
+    // Every glyph has a pixel of padding to its left and top
+    let per_line = ((cell_width.saturating_sub(1)) / (GLYPH_WIDTH + 1)) as usize;
+    let max_lines = (cell_height.saturating_sub(1) / (GLYPH_HEIGHT + 1)) as usize;
+    if per_line == 0 || max_lines == 0 || glyphs.is_empty() {
+        return;
+    }
+
+    let lines: Vec<&[u16]> = glyphs.chunks(per_line).take(max_lines).collect();
+    let width = lines[0].len() as u32 * (GLYPH_WIDTH + 1) + 1;
+    let height = lines.len() as u32 * (GLYPH_HEIGHT + 1) + 1;
+    for y in 0..height {
+        for x in 0..width {
+            sheet.put_pixel(left + x, top + y, Rgba([0, 0, 0, 255]));
+        }
+    }
+
+    for (row, line) in lines.iter().enumerate() {
+        for (col, glyph) in line.iter().enumerate() {
+            let glyph_left = left + 1 + col as u32 * (GLYPH_WIDTH + 1);
+            let glyph_top = top + 1 + row as u32 * (GLYPH_HEIGHT + 1);
+            for y in 0..GLYPH_HEIGHT {
+                for x in 0..GLYPH_WIDTH {
+                    let bit = (GLYPH_HEIGHT - 1 - y) * GLYPH_WIDTH + (GLYPH_WIDTH - 1 - x);
+                    if glyph >> bit & 1 == 1 {
+                        sheet.put_pixel(glyph_left + x, glyph_top + y, Rgba([255; 4]));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_drawn_in_the_corner_of_their_cell() {
+        let cell = ImageBuffer::from_pixel(10, 8, Rgba([128, 128, 128, 255]));
+        let grid = Grid { rows: 1, cols: 2 };
+        let sheet = compose(
+            grid,
+            &[(cell.clone(), None), (cell, Some("0xf".to_owned()))],
+        );
+
+        // Two glyphs fit on a line of the cell, and only one line fits, so the f is cut off
+        let expected = [
+            "#########.",
+            "#   #####.",
+            "# # #####.",
+            "# # # # #.",
+            "# # ## ##.",
+            "#   # # #.",
+            "#########.",
+            "..........",
+        ];
+        for (y, row) in expected.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let color = match c {
+                    ' ' => [255; 4],
+                    '#' => [0, 0, 0, 255],
+                    _ => [128, 128, 128, 255],
+                };
+                let (x, y) = (x as u32, y as u32);
+                assert_eq!(sheet.get_pixel(x + 10, y).0, color, "({}, {})", x, y);
+                assert_eq!(sheet.get_pixel(x, y).0, [128, 128, 128, 255]);
+            }
+        }
+    }
+}
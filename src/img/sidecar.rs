@@ -0,0 +1,206 @@
+use std::{
+    iter::Peekable,
+    path::{Path, PathBuf},
+    str::Chars,
+    time::Duration,
+};
+
+use crate::error::Error;
+
+use super::meta::Metadata;
+
+/// The version of the fields of a sidecar, which changes if they are renamed, removed, or change
+/// meaning. New fields may be added without changing it
+const SCHEMA_VERSION: u32 = 1;
+
+/// What a file was rendered from, and how, written as JSON next to it by --metadata
+#[derive(Clone, Debug)]
+pub struct Sidecar {
+    /// The metadata, as embedded in PNGs and gifs
+    pub metadata: Metadata,
+    /// The width of the image in pixels
+    pub width: u32,
+    /// The height of the image in pixels
+    pub height: u32,
+    /// The amount of frames in the file
+    pub frames: u32,
+    /// The structural hash of the AST, as printed by --dump-hash
+    pub ast_hash: String,
+    /// How long rendering and saving the file took
+    pub render_time: Duration,
+}
+
+impl Sidecar {
+    /// Gets the path of the sidecar of the file at `path`, which is its path with `.json` added
+    pub fn path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".json");
+        PathBuf::from(name)
+    }
+
+    pub fn to_json(&self) -> String {
+        let metadata = &self.metadata;
+        let ast = match &metadata.ast {
+            Some(ast) => json_string(ast),
+            None => "null".to_owned(),
+        };
+        format!(
+            "{{\n  \"schema_version\": {},\n  \"kroyer_version\": {},\n  \"seed\": {},\n  \"depth\": {},\n  \"width\": {},\n  \"height\": {},\n  \"frames\": {},\n  \"t_start\": {},\n  \"t_end\": {},\n  \"still_t\": {},\n  \"ast_hash\": {},\n  \"render_time_ms\": {:.3},\n  \"grammar\": {},\n  \"ast\": {}\n}}\n",
+            SCHEMA_VERSION,
+            json_string(env!("CARGO_PKG_VERSION")),
+            json_string(&metadata.seed),
+            metadata.depth,
+            self.width,
+            self.height,
+            self.frames,
+            json_number(metadata.t_start),
+            json_number(metadata.t_end),
+            json_number(metadata.still_t),
+            json_string(&self.ast_hash),
+            self.render_time.as_secs_f64() * 1000.,
+            json_string(&metadata.grammar),
+            ast
+        )
+    }
+}
+
+/// Writes the sidecar of the file at `path`
+pub fn gen_sidecar(path: &Path, sidecar: &Sidecar) -> Result<(), Error> {
+    let sidecar_path = Sidecar::path(path);
+    crate::io::write_file(&sidecar_path, &sidecar.to_json(), true).map_err(|e| {
+        Error::Io(format!(
+            "Failed to write metadata to {:?}.\nDetails: {}",
+            sidecar_path, e
+        ))
+    })
+}
+
+/// Reads the metadata from the sidecar of the file at `path`, or gets `None` if it has none
+pub fn read(path: &Path) -> Option<Result<Metadata, String>> {
+    let text = std::fs::read_to_string(Sidecar::path(path)).ok()?;
+    Some(parse(&text))
+}
+
+/// Parses the metadata from the JSON of a sidecar. Only the flat objects kroyer writes are
+/// understood, with strings, numbers and null as values
+fn parse(text: &str) -> Result<Metadata, String> {
+    let mut fields = vec![];
+    let mut chars = text.trim().chars().peekable();
+    if chars.next() != Some('{') {
+        return Err("the sidecar isn't a JSON object".to_owned());
+    }
+
+    loop {
+        skip_whitespace(&mut chars);
+        match chars.peek() {
+            Some('}') => break,
+            Some('"') => {}
+            _ => return Err("expected a key in the sidecar".to_owned()),
+        }
+        let key = parse_string(&mut chars)?;
+        skip_whitespace(&mut chars);
+        if chars.next() != Some(':') {
+            return Err(format!("expected a ':' after \"{}\" in the sidecar", key));
+        }
+        skip_whitespace(&mut chars);
+        let value = match chars.peek() {
+            Some('"') => Some(parse_string(&mut chars)?),
+            _ => {
+                let mut value = String::new();
+                while let Some(x) = chars.next_if(|x| !matches!(x, ',' | '}') && !x.is_whitespace())
+                {
+                    value.push(x);
+                }
+                (value != "null").then_some(value)
+            }
+        };
+        if let Some(value) = value {
+            fields.push((key, value));
+        }
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            Some(',') => {}
+            Some('}') => break,
+            _ => return Err("expected a ',' or '}' in the sidecar".to_owned()),
+        }
+    }
+
+    if let Some((_, version)) = fields.iter().find(|x| x.0 == "schema_version")
+        && version.parse::<u32>().is_ok_and(|x| x > SCHEMA_VERSION)
+    {
+        return Err(format!(
+            "the sidecar has schema version {}, but only {} is supported",
+            version, SCHEMA_VERSION
+        ));
+    }
+
+    // The keys are given as the keys of the embedded metadata
+    let fields = fields.into_iter().map(|(key, value)| {
+        let key = match key.as_str() {
+            "t_start" => "t-start",
+            "t_end" => "t-end",
+            "still_t" => "still-t",
+            key => key,
+        };
+        (format!("{}{}", super::meta::KEY_PREFIX, key), value)
+    });
+    Metadata::from_fields(fields)
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|x| x.is_whitespace()).is_some() {}
+}
+
+/// Parses a JSON string, starting at its opening quote
+fn parse_string(chars: &mut impl Iterator<Item = char>) -> Result<String, String> {
+    let unterminated = || "unterminated string in the sidecar".to_owned();
+    chars.next();
+    let mut string = String::new();
+    loop {
+        match chars.next().ok_or_else(unterminated)? {
+            '"' => return Ok(string),
+            '\\' => match chars.next().ok_or_else(unterminated)? {
+                'n' => string.push('\n'),
+                't' => string.push('\t'),
+                'r' => string.push('\r'),
+                'b' => string.push('\u{8}'),
+                'f' => string.push('\u{c}'),
+                'u' => {
+                    let hex: String = chars.take(4).collect();
+                    let code = u32::from_str_radix(&hex, 16)
+                        .map_err(|_| format!("invalid escape \\u{} in the sidecar", hex))?;
+                    string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                x => string.push(x),
+            },
+            x => string.push(x),
+        }
+    }
+}
+
+/// Writes the text as a JSON string, with quotes
+fn json_string(text: &str) -> String {
+    let mut string = String::with_capacity(text.len() + 2);
+    string.push('"');
+    for x in text.chars() {
+        match x {
+            '"' => string.push_str("\\\""),
+            '\\' => string.push_str("\\\\"),
+            '\n' => string.push_str("\\n"),
+            '\t' => string.push_str("\\t"),
+            '\r' => string.push_str("\\r"),
+            x if (x as u32) < 0x20 => string.push_str(&format!("\\u{:04x}", x as u32)),
+            x => string.push(x),
+        }
+    }
+    string.push('"');
+    string
+}
+
+/// Writes a number as JSON, which has no infinity or NaN, so they are written as null
+fn json_number(number: f64) -> String {
+    match number.is_finite() {
+        true => number.to_string(),
+        false => "null".to_owned(),
+    }
+}
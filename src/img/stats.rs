@@ -0,0 +1,210 @@
+use std::fmt::Display;
+
+/// The amount of bars in the histogram of the range of the trees, from -1 to 1
+const BINS: usize = 10;
+
+/// The width in characters of the longest bar of a histogram
+const BAR_WIDTH: u64 = 40;
+
+/// Statistics of the raw values one tree gave while rendering, before they are normalized, tone
+/// mapped or clamped
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChannelStats {
+    /// The amount of values, including NaN
+    pub count: u64,
+    pub nan: u64,
+    /// The amount of values below -1, including negative infinity
+    pub below: u64,
+    /// The amount of values above 1, including infinity
+    pub above: u64,
+    min: f64,
+    max: f64,
+    /// The sum of the finite values
+    sum: f64,
+    /// The sum of the squares of the finite values
+    sum_sq: f64,
+    /// The amount of finite values
+    finite: u64,
+    /// The amount of values in each equal part of the range from -1 to 1
+    pub bins: [u64; BINS],
+}
+
+impl ChannelStats {
+    /// Adds a value
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        if value.is_nan() {
+            self.nan += 1;
+            return;
+        }
+
+        if self.count == self.nan + 1 {
+            (self.min, self.max) = (value, value);
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        if value.is_finite() {
+            self.finite += 1;
+            self.sum += value;
+            self.sum_sq += value * value;
+        }
+
+        match value {
+            _ if value < -1. => self.below += 1,
+            _ if value > 1. => self.above += 1,
+            _ => {
+                let bin = ((value + 1.) / 2. * BINS as f64) as usize;
+                self.bins[bin.min(BINS - 1)] += 1;
+            }
+        }
+    }
+
+    /// Adds every value of `other`
+    fn merge(&mut self, other: &Self) {
+        if other.count == other.nan {
+            // `other` has no min or max
+        } else if self.count == self.nan {
+            (self.min, self.max) = (other.min, other.max);
+        } else {
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+        }
+        self.count += other.count;
+        self.nan += other.nan;
+        self.below += other.below;
+        self.above += other.above;
+        self.sum += other.sum;
+        self.sum_sq += other.sum_sq;
+        self.finite += other.finite;
+        for (bin, other) in self.bins.iter_mut().zip(other.bins) {
+            *bin += other;
+        }
+    }
+
+    /// Gets the smallest value which isn't NaN, if there are any
+    pub fn min(&self) -> Option<f64> {
+        (self.count > self.nan).then_some(self.min)
+    }
+
+    /// Gets the largest value which isn't NaN, if there are any
+    pub fn max(&self) -> Option<f64> {
+        (self.count > self.nan).then_some(self.max)
+    }
+
+    /// Gets the average of the finite values, if there are any
+    pub fn mean(&self) -> Option<f64> {
+        (self.finite > 0).then(|| self.sum / self.finite as f64)
+    }
+
+    /// Gets the standard deviation of the finite values, if there are any
+    pub fn std_dev(&self) -> Option<f64> {
+        let mean = self.mean()?;
+        let variance = self.sum_sq / self.finite as f64 - mean * mean;
+        Some(variance.max(0.).sqrt())
+    }
+
+    /// Gets the fraction of the values which are `amount`, from 0 to 1
+    fn fraction(&self, amount: u64) -> f64 {
+        match self.count {
+            0 => 0.,
+            count => amount as f64 / count as f64,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let number = |x: Option<f64>| match x {
+            Some(x) if x.is_finite() => x.to_string(),
+            _ => "null".to_owned(),
+        };
+        let bins: Vec<String> = self.bins.iter().map(|x| x.to_string()).collect();
+        format!(
+            "{{\"count\": {}, \"min\": {}, \"max\": {}, \"mean\": {}, \"std_dev\": {}, \"nan\": {}, \"below\": {}, \"above\": {}, \"histogram\": [{}]}}",
+            self.count,
+            number(self.min()),
+            number(self.max()),
+            number(self.mean()),
+            number(self.std_dev()),
+            self.fraction(self.nan),
+            self.fraction(self.below),
+            self.fraction(self.above),
+            bins.join(", ")
+        )
+    }
+}
+
+impl Display for ChannelStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let number = |x: Option<f64>| x.map_or("-".to_owned(), |x| format!("{:.4}", x));
+        writeln!(f, "  min: {}", number(self.min()))?;
+        writeln!(f, "  max: {}", number(self.max()))?;
+        writeln!(f, "  mean: {}", number(self.mean()))?;
+        writeln!(f, "  std dev: {}", number(self.std_dev()))?;
+        writeln!(f, "  NaN: {:.2}%", self.fraction(self.nan) * 100.)?;
+        writeln!(
+            f,
+            "  outside of -1..1: {:.2}%",
+            self.fraction(self.below + self.above) * 100.
+        )?;
+
+        // The values outside of the range get a bar at each end
+        let below = ("      < -1".to_owned(), self.below);
+        let above = ("       > 1".to_owned(), self.above);
+        let bins = self.bins.iter().enumerate().map(|(i, count)| {
+            let start = i as f64 / BINS as f64 * 2. - 1.;
+            (
+                format!("{:+.1}..{:+.1}", start, start + 2. / BINS as f64),
+                *count,
+            )
+        });
+        let rows: Vec<(String, u64)> = std::iter::once(below)
+            .chain(bins)
+            .chain(std::iter::once(above))
+            .collect();
+        let most = rows.iter().map(|x| x.1).max().unwrap_or(0).max(1);
+        for (label, count) in rows {
+            let bar = "#".repeat((count * BAR_WIDTH).div_ceil(most) as usize);
+            writeln!(f, "  {} |{}", label, bar)?;
+        }
+        Ok(())
+    }
+}
+
+/// Statistics of the raw values of the red, green and blue trees, over every sample rendered
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ValueStats {
+    pub r: ChannelStats,
+    pub g: ChannelStats,
+    pub b: ChannelStats,
+}
+
+impl ValueStats {
+    /// Adds the values of a sample. The alpha is ignored
+    pub fn add(&mut self, [r, g, b, _]: [f64; 4]) {
+        self.r.add(r);
+        self.g.add(g);
+        self.b.add(b);
+    }
+
+    /// Adds every value of `other`
+    pub fn merge(&mut self, other: &Self) {
+        self.r.merge(&other.r);
+        self.g.merge(&other.g);
+        self.b.merge(&other.b);
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"r\": {}, \"g\": {}, \"b\": {}}}",
+            self.r.to_json(),
+            self.g.to_json(),
+            self.b.to_json()
+        )
+    }
+}
+
+impl Display for ValueStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "R:\n{}G:\n{}B:\n{}", self.r, self.g, self.b)
+    }
+}
@@ -0,0 +1,148 @@
+use std::{io::Write, path::PathBuf};
+
+use crate::{
+    cli::Region,
+    error::{Error, Result},
+    node::ast::NodeAst,
+};
+
+use super::{RenderSettings, commit_file, create_file, get_img, meta::Metadata};
+
+/// Still images with more pixels than this are streamed, instead of rendered whole. A whole
+/// image this size takes 64 MiB
+const STREAM_THRESHOLD: u64 = 4096 * 4096;
+
+/// The amount of pixels rendered at once when streaming, rounded to whole rows. A band this size
+/// takes 4 MiB
+const BAND_PIXELS: u64 = 1 << 20;
+
+/// Checks if the image is big enough to be streamed, even without --stream-encode
+pub fn should_stream(settings: &RenderSettings) -> bool {
+    let (width, height) = settings.size();
+    width as u64 * height as u64 > STREAM_THRESHOLD
+}
+
+/// Saves the still image as a PNG, without ever having all of it in memory. See
+/// `encode_png_streamed()`
+pub fn gen_png_streamed(
+    path: PathBuf,
+    settings: &RenderSettings,
+    metadata: &Metadata,
+    ast: &NodeAst,
+) -> Result<()> {
+    let mut file = create_file(&path)?;
+    encode_png_streamed(&mut file, settings, metadata, ast)?;
+    commit_file(file, &path)
+}
+
+/// Encodes the still image as a PNG with the metadata into `writer`, by rendering it in bands
+/// of rows, which are compressed as soon as they are rendered. The bands are rendered from the
+/// top, so rand nodes are picked in the same order as when rendering the whole image, and the
+/// pixels are the same
+pub fn encode_png_streamed(
+    writer: impl Write,
+    settings: &RenderSettings,
+    metadata: &Metadata,
+    ast: &NodeAst,
+) -> Result<()> {
+    encode_png_in_bands(writer, settings, metadata, ast, BAND_PIXELS)
+}
+
+/// Encodes the still image like `encode_png_streamed()`, in bands of about `band_pixels` pixels
+fn encode_png_in_bands(
+    writer: impl Write,
+    settings: &RenderSettings,
+    metadata: &Metadata,
+    ast: &NodeAst,
+    band_pixels: u64,
+) -> Result<()> {
+    let (width, height) = settings.size();
+    let (left, top) = settings.origin();
+
+    let encode = || -> std::result::Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(writer, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        metadata.add_to_png(&mut encoder)?;
+        let mut writer = encoder.write_header()?;
+        let mut stream = writer.stream_writer()?;
+
+        let band_height = (band_pixels / width as u64).clamp(1, height as u64) as u32;
+        let mut y = 0;
+        while y < height {
+            let rows = band_height.min(height - y);
+            let band = RenderSettings {
+                region: Some(Region {
+                    x: left,
+                    y: top + y,
+                    width,
+                    height: rows,
+                }),
+                ..settings.clone()
+            };
+            stream.write_all(get_img(&band, settings.still_t, ast).as_raw())?;
+            y += rows;
+        }
+
+        stream.finish()?;
+        Ok(())
+    };
+
+    encode().map_err(|e| Error::Io(format!("Failed to encode streamed png.\nDetails: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::img::encode_png;
+
+    use super::*;
+
+    /// Gets the types and data of the chunks of a PNG, other than the compressed pixels
+    fn chunks(png: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut chunks = vec![];
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+            if kind != b"IDAT" {
+                chunks.push((kind, data));
+            }
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn banded_pngs_are_the_same_as_whole_ones() {
+        let ast = NodeAst::parse_from_str("R: sin(mult(x, 7))\nG: (x < y ? 1 : y)\nB: mult(x, y)")
+            .unwrap();
+        let metadata = Metadata {
+            seed: "0x2a".to_owned(),
+            depth: 10,
+            grammar: "x: 1\ny: 1\n".to_owned(),
+            ast: Some(ast.to_string()),
+            t_start: 0.,
+            t_end: 1.,
+            still_t: 0.,
+        };
+        let settings = RenderSettings::plain(37, 23);
+
+        let mut whole = vec![];
+        let img = encode_png(&mut whole, &settings, &metadata, &ast).unwrap();
+        // The compressed pixels are split into chunks differently when streamed, so only the
+        // other chunks and the decoded pixels are the same. 116 pixels are bands of 3 rows, with
+        // a last band of 2 rows
+        for band_pixels in [116, 1, BAND_PIXELS] {
+            let mut banded = vec![];
+            encode_png_in_bands(&mut banded, &settings, &metadata, &ast, band_pixels).unwrap();
+            assert_eq!(
+                chunks(&banded),
+                chunks(&whole),
+                "bands of {} pixels",
+                band_pixels
+            );
+            let decoded = image::load_from_memory(&banded).unwrap().into_rgba8();
+            assert_eq!(decoded, img, "bands of {} pixels", band_pixels);
+        }
+    }
+}
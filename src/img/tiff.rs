@@ -0,0 +1,76 @@
+use std::{
+    io::{Cursor, Seek, Write},
+    path::PathBuf,
+};
+
+use ::tiff::{
+    TiffResult,
+    encoder::{
+        TiffEncoder,
+        colortype::{RGBA8, RGBA16, RGBA32Float},
+    },
+};
+
+use crate::{
+    cli::BitDepth,
+    error::{Error, Result},
+    node::ast::NodeAst,
+};
+
+use super::{RenderSettings, commit_file, create_file, exr::get_float_img, get_img, get_img_16};
+
+/// Saves the still image as a TIFF image. See `write_tiff()`
+pub fn gen_tiff(
+    path: PathBuf,
+    depth: BitDepth,
+    settings: &RenderSettings,
+    ast: &NodeAst,
+) -> Result<()> {
+    let mut file = create_file(&path)?;
+    write_tiff(&mut file, depth, settings, ast)?;
+    commit_file(file, &path)
+}
+
+/// Encodes the still image as a TIFF image, and returns the bytes of the file
+pub fn encode_tiff(depth: BitDepth, settings: &RenderSettings, ast: &NodeAst) -> Result<Vec<u8>> {
+    let mut bytes = Cursor::new(vec![]);
+    write_tiff(&mut bytes, depth, settings, ast)?;
+    Ok(bytes.into_inner())
+}
+
+/// Writes the still image as an RGBA TIFF image with samples of `depth` into `writer`. 8-bit
+/// images are rendered like PNGs, with the post filters, and 16-bit images like farbfeld. Float
+/// images keep the values of the trees like EXR images, without the tone mapping, so they are
+/// moved from -1..1 to 0..1, but neither clamped nor rounded
+pub fn write_tiff(
+    writer: impl Write + Seek,
+    depth: BitDepth,
+    settings: &RenderSettings,
+    ast: &NodeAst,
+) -> Result<()> {
+    let t = settings.still_t;
+    let (width, height) = settings.size();
+    let encode = || -> TiffResult<()> {
+        let mut encoder = TiffEncoder::new(writer)?;
+        match depth {
+            BitDepth::Eight => {
+                let img = get_img(settings, t, ast);
+                encoder.write_image::<RGBA8>(width, height, img.as_raw())
+            }
+            BitDepth::Sixteen => {
+                let img = get_img_16(settings, t, ast);
+                encoder.write_image::<RGBA16>(width, height, img.as_raw())
+            }
+            BitDepth::Float => {
+                let settings = RenderSettings {
+                    tone_map: None,
+                    ..settings.clone()
+                };
+                let img = get_float_img(&settings, t, ast);
+                encoder.write_image::<RGBA32Float>(width, height, img.as_raw())
+            }
+        }
+    };
+
+    encode().map_err(|e| Error::Io(format!("Failed to encode tiff.\nDetails: {}", e)))
+}
@@ -0,0 +1,164 @@
+use std::path::Path;
+
+use image::{ImageBuffer, ImageFormat, Rgba};
+use rayon::prelude::*;
+
+use crate::{
+    cli::{WarpEdge, WarpFilter},
+    error::Result,
+    node::{ast::NodeAst, bytecode::CompiledAst},
+};
+
+use super::{
+    Animation, GifOptions, RenderSettings, commit_file, create_file, meta::Metadata, post, render,
+    save_image, stats::ValueStats, tree_values, uses_rand, write_gif, write_png,
+};
+
+/// An image, and the settings of how it is displaced by the trees
+#[derive(Clone, Debug)]
+pub struct Warp {
+    /// The image which is displaced
+    pub image: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    /// How pixels displaced outside of the image are picked
+    pub edge: WarpEdge,
+    /// How the image is sampled between its pixels
+    pub filter: WarpFilter,
+    /// The displacement of a tree value of 1, as a fraction of the width or height
+    pub strength: f64,
+    /// If the blue tree scales the brightness, from black at -1 to double at 1
+    pub brightness: bool,
+}
+
+/// Saves the image warped by the trees at `path`. Gifs animate the warp over t, and other formats
+/// are warped at --still-t. PNGs and gifs get the metadata
+pub fn gen_warp(
+    path: &Path,
+    warp: &Warp,
+    settings: &RenderSettings,
+    animation: &Animation,
+    options: &GifOptions,
+    metadata: &Metadata,
+    ast: &NodeAst,
+) -> Result<()> {
+    let path = path.to_path_buf();
+    let format = ImageFormat::from_path(&path).ok();
+
+    if format == Some(ImageFormat::Gif) {
+        let warp_frame = |i: u32| get_warped(warp, settings, animation.t(i), ast);
+        // Like in `get_frames()`, frames using legacy rand are warped in order on one thread
+        let frames = match uses_rand(ast) {
+            true => (0..animation.frames).map(warp_frame).collect(),
+            false => (0..animation.frames)
+                .into_par_iter()
+                .map(warp_frame)
+                .collect(),
+        };
+        let mut file = create_file(&path)?;
+        write_gif(&mut file, frames, animation, options, metadata)?;
+        return commit_file(file, &path);
+    }
+
+    let img = get_warped(warp, settings, settings.still_t, ast);
+    if format == Some(ImageFormat::Png) {
+        let mut file = create_file(&path)?;
+        write_png(&mut file, &img, metadata)?;
+        return commit_file(file, &path);
+    }
+    save_image(&img, &path, None)
+}
+
+/// Warps the image at the time `t`, and applies the post filters. Every pixel of the output
+/// evaluates the trees at its coordinates, like a rendered image the size of the image, and
+/// samples the image moved by the red tree along x and the green tree along y
+pub fn get_warped(
+    warp: &Warp,
+    settings: &RenderSettings,
+    t: f64,
+    ast: &NodeAst,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let (width, height) = warp.image.dimensions();
+    let settings = RenderSettings {
+        width,
+        height,
+        region: None,
+        ..settings.clone()
+    };
+    let program = ast.compile();
+    let img = render(&settings, ast, |x, y, stats| {
+        warp_pixel(x, y, warp, &settings, t, &program, stats)
+    });
+    post::apply(img, &settings.post)
+}
+
+/// Gets the color of the warped pixel at `x` and `y`. NaN displaces nothing, and keeps the
+/// brightness as it is
+fn warp_pixel(
+    x: u32,
+    y: u32,
+    warp: &Warp,
+    settings: &RenderSettings,
+    t: f64,
+    ast: &CompiledAst,
+    stats: Option<&mut ValueStats>,
+) -> Rgba<u8> {
+    let (width, height) = warp.image.dimensions();
+    let x_frac = x as f64 / width as f64;
+    let y_frac = y as f64 / height as f64;
+    let values = tree_values(
+        settings.x_range.at(x_frac),
+        settings.y_range.at(y_frac),
+        settings,
+        t,
+        ast,
+    );
+    if let Some(stats) = stats {
+        stats.add(values);
+    }
+    let [r, g, b, _] = values.map(|x| if x.is_nan() { 0. } else { x });
+
+    let source_x = x as f64 + r * warp.strength * width as f64;
+    let source_y = y as f64 + g * warp.strength * height as f64;
+    let color = match warp.filter {
+        WarpFilter::Bilinear => bilinear(&warp.image, source_x, source_y, warp.edge),
+        WarpFilter::Nearest => {
+            pixel_at(&warp.image, source_x.round(), source_y.round(), warp.edge).map(|x| x as f64)
+        }
+    };
+
+    let scale = match warp.brightness {
+        true => (b + 1.).max(0.),
+        false => 1.,
+    };
+    let [r, g, b, a] = color;
+    Rgba([r * scale, g * scale, b * scale, a].map(|x| x.round().clamp(0., 255.) as u8))
+}
+
+/// Blends the four pixels around `x` and `y`, by how close they are
+fn bilinear(input: &ImageBuffer<Rgba<u8>, Vec<u8>>, x: f64, y: f64, edge: WarpEdge) -> [f64; 4] {
+    let (left, top) = (x.floor(), y.floor());
+    let (x_frac, y_frac) = (x - left, y - top);
+
+    let mut color = [0.; 4];
+    for (dx, dy, weight) in [
+        (0., 0., (1. - x_frac) * (1. - y_frac)),
+        (1., 0., x_frac * (1. - y_frac)),
+        (0., 1., (1. - x_frac) * y_frac),
+        (1., 1., x_frac * y_frac),
+    ] {
+        let pixel = pixel_at(input, left + dx, top + dy, edge);
+        for (color, value) in color.iter_mut().zip(pixel) {
+            *color += value as f64 * weight;
+        }
+    }
+    color
+}
+
+/// Gets the pixel at the whole coordinates `x` and `y`, which may be outside of the image
+fn pixel_at(input: &ImageBuffer<Rgba<u8>, Vec<u8>>, x: f64, y: f64, edge: WarpEdge) -> [u8; 4] {
+    let (width, height) = input.dimensions();
+    let fit = |value: f64, len: u32| match edge {
+        WarpEdge::Clamp => value.clamp(0., len as f64 - 1.) as u32,
+        WarpEdge::Wrap => value.rem_euclid(len as f64) as u32 % len,
+    };
+    input.get_pixel(fit(x, width), fit(y, height)).0
+}
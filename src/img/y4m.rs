@@ -0,0 +1,113 @@
+use std::{io::Write, path::PathBuf};
+
+use image::{ImageBuffer, Rgba};
+
+use crate::{
+    error::{Error, Result},
+    node::ast::NodeAst,
+};
+
+use super::{Animation, RenderSettings, commit_file, create_file, get_img};
+
+/// Saves the animation as a YUV4MPEG2 video, which can also be a named pipe
+pub fn gen_y4m(
+    path: PathBuf,
+    settings: &RenderSettings,
+    animation: &Animation,
+    ast: &NodeAst,
+) -> Result<()> {
+    let mut file = create_file(&path)?;
+    encode_y4m(&mut file, settings, animation, ast)?;
+    commit_file(file, &path)
+}
+
+/// Streams the animation as a YUV4MPEG2 video into `writer`, one frame at a time, so it can be
+/// piped into a video encoder like ffmpeg. The frames use full resolution chroma (4:4:4), and
+/// the limited range BT.601 colors which y4m readers expect
+pub fn encode_y4m(
+    mut writer: impl Write,
+    settings: &RenderSettings,
+    animation: &Animation,
+    ast: &NodeAst,
+) -> Result<()> {
+    let mut encode = || -> std::io::Result<()> {
+        let (numerator, denominator) = frame_rate(animation.fps);
+        let (width, height) = settings.size();
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+            width, height, numerator, denominator
+        )?;
+
+        for i in 0..animation.frames {
+            let img = get_img(settings, animation.t(i), ast);
+            writer.write_all(b"FRAME\n")?;
+            writer.write_all(&to_yuv_planes(&img))?;
+        }
+        writer.flush()
+    };
+
+    encode().map_err(|e| Error::Io(format!("Failed to write y4m stream.\nDetails: {}", e)))
+}
+
+/// Gets the frame rate as a fraction, as written in the header
+fn frame_rate(fps: f64) -> (u64, u64) {
+    if fps.fract() == 0. {
+        return (fps as u64, 1);
+    }
+    ((fps * 1000.).round() as u64, 1000)
+}
+
+/// Converts an image into its Y, U and V planes, one after another
+fn to_yuv_planes(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Vec<u8> {
+    let pixels = img.pixels().len();
+    let mut planes = vec![0; pixels * 3];
+
+    for (i, pixel) in img.pixels().enumerate() {
+        let [r, g, b, _] = pixel.0.map(|x| x as f64);
+        let y = 16. + 0.256788 * r + 0.504129 * g + 0.097906 * b;
+        let u = 128. - 0.148223 * r - 0.290993 * g + 0.439216 * b;
+        let v = 128. + 0.439216 * r - 0.367788 * g - 0.071427 * b;
+
+        planes[i] = y.round() as u8;
+        planes[pixels + i] = u.round() as u8;
+        planes[pixels * 2 + i] = v.round() as u8;
+    }
+
+    planes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::TMode;
+
+    #[test]
+    fn streams_a_header_and_a_marked_frame_per_frame() {
+        let settings = RenderSettings::plain(4, 2);
+        let animation = Animation::new(3, 12.5, TMode::Linear, 0., 1.).unwrap();
+        let ast = NodeAst::parse_from_str("RGB: 1").unwrap();
+        let mut stream = vec![];
+        encode_y4m(&mut stream, &settings, &animation, &ast).unwrap();
+
+        let header = b"YUV4MPEG2 W4 H2 F12500:1000 Ip A1:1 C444\n";
+        assert!(stream.starts_with(header));
+        let frames = &stream[header.len()..];
+        let frame_len = b"FRAME\n".len() + 4 * 2 * 3;
+        assert_eq!(frames.len(), frame_len * 3);
+        for frame in frames.chunks(frame_len) {
+            let (marker, planes) = frame.split_at(6);
+            assert_eq!(marker, b"FRAME\n");
+            // White is the top of the limited range, with no chroma
+            let (y, uv) = planes.split_at(8);
+            assert!(y.iter().all(|x| *x == 235), "{:?}", y);
+            assert!(uv.iter().all(|x| *x == 128), "{:?}", uv);
+        }
+    }
+
+    #[test]
+    fn whole_frame_rates_have_a_denominator_of_1() {
+        assert_eq!(frame_rate(30.), (30, 1));
+        assert_eq!(frame_rate(29.97), (29970, 1000));
+    }
+}
@@ -0,0 +1,222 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// A file which is written under a temporary name in the same directory as its path, and only
+/// moved to the path by `commit()`, once it is complete. This way an error or Ctrl-C while
+/// writing never leaves a truncated file at the path. The temporary file is removed if the file
+/// is dropped without being committed, if kroyer exits, or if it is stopped by a signal.
+/// Paths which already exist, but aren't regular files, like named pipes, are written directly
+pub struct AtomicFile {
+    /// The open file, which is only taken when it is committed or dropped
+    file: Option<BufWriter<File>>,
+    path: PathBuf,
+    /// The temporary path the file is written to, unless it is written directly
+    temp: Option<PathBuf>,
+    /// The slot the temporary path is kept in, so it can be removed on a signal
+    slot: Option<usize>,
+}
+
+impl AtomicFile {
+    /// Creates a temporary file next to `path`
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        let direct = std::fs::metadata(path).is_ok_and(|x| !x.is_file());
+        if direct {
+            return Ok(Self {
+                file: Some(BufWriter::new(File::create(path)?)),
+                path: path.to_path_buf(),
+                temp: None,
+                slot: None,
+            });
+        }
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+        let file = File::create(&temp)?;
+        let slot = pending::add(&temp);
+        Ok(Self {
+            file: Some(BufWriter::new(file)),
+            path: path.to_path_buf(),
+            temp: Some(temp),
+            slot,
+        })
+    }
+
+    /// Writes what is left in the buffer, and moves the file to its path
+    pub fn commit(mut self) -> std::io::Result<()> {
+        self.file().flush()?;
+        // The file is closed first, since open files can't be moved on every platform
+        drop(self.file.take());
+        if let Some(temp) = &self.temp {
+            std::fs::rename(temp, &self.path)?;
+        }
+        // The file is at its path now, so there is nothing left to remove
+        self.temp = None;
+        Ok(())
+    }
+
+    fn file(&mut self) -> &mut BufWriter<File> {
+        self.file
+            .as_mut()
+            .expect("the file is only taken when it is committed or dropped")
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file().flush()
+    }
+}
+
+impl Seek for AtomicFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.file().seek(pos)
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        drop(self.file.take());
+        if let Some(temp) = &self.temp {
+            let _ = std::fs::remove_file(temp);
+        }
+        if let Some(slot) = self.slot {
+            pending::remove(slot);
+        }
+    }
+}
+
+/// The temporary files being written, which are removed if kroyer exits before they are
+/// committed. `std::process::exit()` doesn't drop them, and signals stop kroyer without
+/// unwinding, so they are kept where an `atexit` and a signal handler can find them
+#[cfg(unix)]
+mod pending {
+    use std::{
+        ffi::{CString, c_char, c_int},
+        os::unix::ffi::OsStrExt,
+        path::Path,
+        ptr,
+        sync::{
+            Once,
+            atomic::{AtomicPtr, Ordering},
+        },
+    };
+
+    /// The most temporary files kept at once. Files written while every slot is taken are still
+    /// removed when dropped, but not on exit or a signal
+    const SLOTS: usize = 64;
+
+    /// The paths of the temporary files, as C strings, which signal handlers can read without
+    /// allocating or locking
+    static PATHS: [AtomicPtr<c_char>; SLOTS] = [const { AtomicPtr::new(ptr::null_mut()) }; SLOTS];
+
+    static HANDLERS: Once = Once::new();
+
+    /// Keeps the path of a temporary file, and gets the slot it was put in
+    pub fn add(path: &Path) -> Option<usize> {
+        HANDLERS.call_once(install_handlers);
+
+        let path = CString::new(path.as_os_str().as_bytes()).ok()?.into_raw();
+        let slot = PATHS.iter().position(|x| {
+            x.compare_exchange(ptr::null_mut(), path, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        });
+        if slot.is_none() {
+            // SAFETY: The pointer came from `CString::into_raw()`, and wasn't stored
+            drop(unsafe { CString::from_raw(path) });
+        }
+        slot
+    }
+
+    /// Forgets the path in the slot
+    pub fn remove(slot: usize) {
+        let path = PATHS[slot].swap(ptr::null_mut(), Ordering::SeqCst);
+        if !path.is_null() {
+            // SAFETY: The pointer came from `CString::into_raw()` in `add()`
+            drop(unsafe { CString::from_raw(path) });
+        }
+    }
+
+    /// Removes every temporary file. Only async-signal-safe functions are used
+    extern "C" fn remove_files() {
+        for path in &PATHS {
+            let path = path.load(Ordering::SeqCst);
+            if !path.is_null() {
+                // SAFETY: The pointer is a C string from `add()`
+                unsafe { libc::unlink(path) };
+            }
+        }
+    }
+
+    /// Removes every temporary file, and stops kroyer with the signal like it would without the
+    /// handler
+    extern "C" fn on_signal(signal: c_int) {
+        remove_files();
+        // SAFETY: Resetting and raising a signal is async-signal-safe
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    fn install_handlers() {
+        let handler = on_signal as extern "C" fn(c_int);
+        // SAFETY: The handlers only touch the atomic slots, and call async-signal-safe functions
+        unsafe {
+            libc::atexit(remove_files);
+            for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+                libc::signal(signal, handler as libc::sighandler_t);
+            }
+        }
+    }
+}
+
+/// Other platforms only remove temporary files when they are dropped
+#[cfg(not(unix))]
+mod pending {
+    use std::path::Path;
+
+    pub fn add(_path: &Path) -> Option<usize> {
+        None
+    }
+
+    pub fn remove(_slot: usize) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_committed_files_reach_their_path() {
+        let dir = std::env::temp_dir().join(format!("kroyer-atomic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.gif");
+
+        // An encoder which fails after writing part of the image returns before the commit
+        let encode = |fail: bool| -> std::io::Result<()> {
+            let mut file = AtomicFile::create(&path)?;
+            file.write_all(b"GIF89a")?;
+            if fail {
+                return Err(std::io::Error::other("the encoder failed"));
+            }
+            file.write_all(b";")?;
+            file.commit()
+        };
+
+        assert!(encode(true).is_err());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        encode(false).unwrap();
+        assert!(encode(true).is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"GIF89a;");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
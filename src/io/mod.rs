@@ -1,4 +1,12 @@
-use std::io::{BufRead as _, IsTerminal};
+pub mod atomic;
+pub mod name;
+pub mod term;
+
+use std::{
+    fs::OpenOptions,
+    io::{BufRead as _, IsTerminal, Write},
+    path::Path,
+};
 
 pub fn read_stdin() -> Option<String> {
     if !std::io::stdin().is_terminal() {
@@ -13,3 +21,46 @@ pub fn read_stdin() -> Option<String> {
         None
     }
 }
+
+/// Writes `content` to the file at `path`, creating its parent directories if they don't exist.
+/// If the file already exists, this fails with `ErrorKind::AlreadyExists`, unless `overwrite` is
+/// set
+pub fn write_file(path: &Path, content: &str, overwrite: bool) -> std::io::Result<()> {
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if overwrite {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+
+    options.open(path)?.write_all(content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_only_overwritten_when_allowed() {
+        let dir = std::env::temp_dir().join(format!("kroyer-write-{}", std::process::id()));
+        let path = dir.join("dumps").join("ast.txt");
+
+        write_file(&path, "RGB: x\n", false).unwrap();
+        let error = write_file(&path, "RGB: y\n", false).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "RGB: x\n");
+
+        // The old content is truncated, so nothing of it is left after a shorter dump
+        write_file(&path, "L: t\n", true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "L: t\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A value which can be put into the name of an image by --name-template
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Placeholder {
+    /// The seed of the image, in hex with a 0x prefix, as printed by --dump-seed
+    Seed,
+    /// The structural hash of the AST
+    Hash,
+    /// The date the image was saved, in UTC, like `2025-03-14`
+    Date,
+    /// The number of the image in a batch, from 1, padded like the numbered files of --count
+    N,
+    /// The width of the image
+    W,
+    /// The height of the image
+    H,
+}
+
+impl Placeholder {
+    const ALL: [Placeholder; 6] = [
+        Placeholder::Seed,
+        Placeholder::Hash,
+        Placeholder::Date,
+        Placeholder::N,
+        Placeholder::W,
+        Placeholder::H,
+    ];
+
+    /// Gets the name of the placeholder, as it is written between the braces
+    fn name(&self) -> &'static str {
+        match self {
+            Placeholder::Seed => "seed",
+            Placeholder::Hash => "hash",
+            Placeholder::Date => "date",
+            Placeholder::N => "n",
+            Placeholder::W => "w",
+            Placeholder::H => "h",
+        }
+    }
+}
+
+/// A part of a name template, which is either kept as it is, or replaced by a value
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+/// The path of --name-template, where placeholders like `{seed}` are replaced with the values of
+/// each image
+#[derive(Clone, Debug, PartialEq)]
+pub struct NameTemplate {
+    text: String,
+    parts: Vec<Part>,
+}
+
+impl NameTemplate {
+    /// Gets the template as a path, with the placeholders as they are written. The format of the
+    /// image is decided by its extension, like with --out
+    pub fn as_path(&self) -> &Path {
+        Path::new(&self.text)
+    }
+
+    /// Checks if any of the placeholders are in the template
+    pub fn uses_any(&self, placeholders: &[Placeholder]) -> bool {
+        self.parts
+            .iter()
+            .any(|x| matches!(x, Part::Placeholder(p) if placeholders.contains(p)))
+    }
+
+    /// Gets the path with every placeholder replaced by the value `value` gets for it. Values are
+    /// only asked for if the template uses them
+    pub fn expand(&self, mut value: impl FnMut(Placeholder) -> String) -> PathBuf {
+        let name: String = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Placeholder(placeholder) => value(*placeholder),
+            })
+            .collect();
+        PathBuf::from(name)
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = || {
+            let names: Vec<String> = Placeholder::ALL
+                .iter()
+                .map(|x| format!("{{{}}}", x.name()))
+                .collect();
+            names.join(", ")
+        };
+
+        let mut parts = vec![];
+        let mut rest = s;
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return Err(format!(
+                    "'}}' has no matching '{{'. The placeholders are {}",
+                    valid()
+                ));
+            }
+            let Some(len) = rest[start..].find('}') else {
+                return Err(format!(
+                    "'{{' is never closed. The placeholders are {}",
+                    valid()
+                ));
+            };
+
+            let name = &rest[start + 1..start + len];
+            let Some(placeholder) = Placeholder::ALL.into_iter().find(|x| x.name() == name) else {
+                return Err(format!(
+                    "unknown placeholder '{{{}}}'. The placeholders are {}",
+                    name,
+                    valid()
+                ));
+            };
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_owned()));
+            }
+            parts.push(Part::Placeholder(placeholder));
+            rest = &rest[start + len + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_owned()));
+        }
+
+        if parts.is_empty() {
+            return Err("the template can't be empty".to_owned());
+        }
+        Ok(Self {
+            text: s.to_owned(),
+            parts,
+        })
+    }
+}
+
+/// The paths given to the images of a run by --name-template, so two images are never saved
+/// under the same name
+#[derive(Clone, Debug, Default)]
+pub struct Names {
+    taken: HashSet<PathBuf>,
+}
+
+impl Names {
+    /// Takes the path for an image, or fails if an earlier image of the run already has it
+    pub fn take(&mut self, path: PathBuf) -> Result<PathBuf, PathBuf> {
+        match self.taken.insert(path.clone()) {
+            true => Ok(path),
+            false => Err(path),
+        }
+    }
+}
+
+/// Gets the current date in UTC, like `2025-03-14`
+pub fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+    let (year, month, day) = civil_date((secs / 86400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Gets the year, month and day of the day `days` after 1970-01-01, in the proleptic Gregorian
+/// calendar. See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gets the value of each placeholder, written out so it is clear which was put where
+    fn value(placeholder: Placeholder) -> String {
+        format!("<{}>", placeholder.name())
+    }
+
+    fn expand(template: &str) -> PathBuf {
+        template.parse::<NameTemplate>().unwrap().expand(value)
+    }
+
+    #[test]
+    fn placeholders_are_replaced_by_their_values() {
+        assert_eq!(
+            expand("{seed}-{hash}-{date}-{n}-{w}x{h}.png"),
+            PathBuf::from("<seed>-<hash>-<date>-<n>-<w>x<h>.png")
+        );
+        assert_eq!(
+            expand("dir/{n}{n}/{w}{h}.gif"),
+            PathBuf::from("dir/<n><n>/<w><h>.gif")
+        );
+        assert_eq!(expand("out.png"), PathBuf::from("out.png"));
+        assert_eq!(expand("{seed}"), PathBuf::from("<seed>"));
+    }
+
+    #[test]
+    fn only_used_placeholders_are_asked_for() {
+        let template: NameTemplate = "{w}x{h}-{w}.png".parse().unwrap();
+        let mut asked = vec![];
+        template.expand(|x| {
+            asked.push(x);
+            value(x)
+        });
+        assert_eq!(asked, [Placeholder::W, Placeholder::H, Placeholder::W]);
+
+        assert!(template.uses_any(&[Placeholder::Seed, Placeholder::H]));
+        assert!(!template.uses_any(&[Placeholder::Seed, Placeholder::N]));
+        assert_eq!(template.as_path(), Path::new("{w}x{h}-{w}.png"));
+    }
+
+    #[test]
+    fn bad_templates_list_the_placeholders() {
+        let valid = "The placeholders are {seed}, {hash}, {date}, {n}, {w}, {h}";
+        for (template, error) in [
+            ("{size}.png", "unknown placeholder '{size}'"),
+            ("{Seed}.png", "unknown placeholder '{Seed}'"),
+            ("{}.png", "unknown placeholder '{}'"),
+            ("{seed.png", "'{' is never closed"),
+            ("seed}.png", "'}' has no matching '{'"),
+            ("{n}}.png", "'}' has no matching '{'"),
+        ] {
+            assert_eq!(
+                template.parse::<NameTemplate>(),
+                Err(format!("{}. {}", error, valid)),
+                "{}",
+                template
+            );
+        }
+        assert_eq!(
+            "".parse::<NameTemplate>(),
+            Err("the template can't be empty".to_owned())
+        );
+    }
+
+    #[test]
+    fn names_can_only_be_taken_once() {
+        let mut names = Names::default();
+        assert_eq!(names.take("a.png".into()), Ok("a.png".into()));
+        assert_eq!(names.take("b.png".into()), Ok("b.png".into()));
+        assert_eq!(names.take("a.png".into()), Err("a.png".into()));
+    }
+
+    #[test]
+    fn dates_count_days_from_1970() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+        assert_eq!(civil_date(11016), (2000, 2, 29));
+        assert_eq!(civil_date(19782), (2024, 2, 29));
+        assert_eq!(civil_date(47541), (2100, 3, 1));
+    }
+}
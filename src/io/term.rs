@@ -0,0 +1,142 @@
+use std::{
+    fs::File,
+    io::{IsTerminal, Stderr, Write},
+};
+
+/// The ways a terminal can show an image, from the best to the worst
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Graphics {
+    /// The kitty graphics protocol, which shows the pixels as they are
+    Kitty,
+    /// Sixel graphics, which show the pixels with a palette of 256 colors
+    Sixel,
+    /// Half-block characters, with 24-bit colors, showing two pixels per character
+    TrueColor,
+    /// Half-block characters, with the 256 colors of xterm
+    Color256,
+}
+
+/// Guesses the best way the terminal can show an image from the environment, or `None` if it is
+/// a dumb terminal, which can't show one at all
+pub fn graphics() -> Option<Graphics> {
+    let var = |name: &str| std::env::var(name).unwrap_or_default();
+    let term = var("TERM");
+    let program = var("TERM_PROGRAM");
+
+    if term.is_empty() || term == "dumb" {
+        return None;
+    }
+    if term.contains("kitty")
+        || !var("KITTY_WINDOW_ID").is_empty()
+        || ["WezTerm", "ghostty"].contains(&program.as_str())
+    {
+        return Some(Graphics::Kitty);
+    }
+    if term.contains("sixel")
+        || ["foot", "mlterm", "contour"]
+            .iter()
+            .any(|x| term.starts_with(x))
+    {
+        return Some(Graphics::Sixel);
+    }
+    match var("COLORTERM").as_str() {
+        "truecolor" | "24bit" => Some(Graphics::TrueColor),
+        _ => Some(Graphics::Color256),
+    }
+}
+
+/// The size of a terminal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TermSize {
+    pub cols: u32,
+    pub rows: u32,
+    /// The width and height of the window in pixels, if the terminal tells it
+    pub pixels: Option<(u32, u32)>,
+}
+
+/// A handle to the terminal, which is STDERR if it is a terminal, or else the terminal kroyer
+/// was started from. STDOUT is never used, so it can still be piped
+pub enum Terminal {
+    Stderr(Stderr),
+    Tty(File),
+}
+
+impl Terminal {
+    /// Opens the terminal, or gets `None` if kroyer isn't run in one
+    pub fn open() -> Option<Self> {
+        let stderr = std::io::stderr();
+        if stderr.is_terminal() {
+            return Some(Terminal::Stderr(stderr));
+        }
+        #[cfg(unix)]
+        if let Ok(tty) = std::fs::OpenOptions::new().write(true).open("/dev/tty") {
+            return Some(Terminal::Tty(tty));
+        }
+        None
+    }
+
+    /// Gets the size of the terminal, falling back to $COLUMNS and $LINES, or 80 by 24
+    pub fn size(&self) -> TermSize {
+        #[cfg(unix)]
+        if let Some(size) = self.window_size() {
+            return size;
+        }
+
+        let var = |name: &str, default: u32| {
+            std::env::var(name)
+                .ok()
+                .and_then(|x| x.parse().ok())
+                .filter(|x| *x > 0)
+                .unwrap_or(default)
+        };
+        TermSize {
+            cols: var("COLUMNS", 80),
+            rows: var("LINES", 24),
+            pixels: None,
+        }
+    }
+
+    /// Asks the terminal for its size
+    #[cfg(unix)]
+    fn window_size(&self) -> Option<TermSize> {
+        use std::os::fd::AsRawFd;
+
+        let fd = match self {
+            Terminal::Stderr(stderr) => stderr.as_raw_fd(),
+            Terminal::Tty(tty) => tty.as_raw_fd(),
+        };
+        let mut size = libc::winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: TIOCGWINSZ only writes a `winsize` into the pointer
+        if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } != 0 || size.ws_col == 0 {
+            return None;
+        }
+        let pixels = (size.ws_xpixel > 0 && size.ws_ypixel > 0)
+            .then_some((size.ws_xpixel as u32, size.ws_ypixel as u32));
+        Some(TermSize {
+            cols: size.ws_col as u32,
+            rows: size.ws_row.max(1) as u32,
+            pixels,
+        })
+    }
+}
+
+impl Write for Terminal {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Terminal::Stderr(stderr) => stderr.write(buf),
+            Terminal::Tty(tty) => tty.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Terminal::Stderr(stderr) => stderr.flush(),
+            Terminal::Tty(tty) => tty.flush(),
+        }
+    }
+}
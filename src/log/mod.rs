@@ -0,0 +1,89 @@
+use std::{
+    fmt::Arguments,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// How much kroyer logs to STDERR. Each level also logs everything of the levels before it
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    /// Failures which stop kroyer. These are always logged
+    Error,
+    /// Problems kroyer works around, like unknown grammar directives. Hidden by --quiet
+    Warning,
+    /// What kroyer does, like the seed and the paths written. Logged with -v
+    Info,
+    /// Details for finding problems, like the format of each output. Logged with -vv
+    Debug,
+}
+
+impl Level {
+    /// Gets the level given by the amount of -v flags, and --quiet
+    pub fn from_args(verbose: u8, quiet: bool) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Level::Error,
+            (false, 0) => Level::Warning,
+            (false, 1) => Level::Info,
+            (false, _) => Level::Debug,
+        }
+    }
+
+    fn tag(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warning => "WARNING",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+}
+
+/// The most detailed level which is logged, as the index of the `Level`
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Warning as u8);
+
+/// Sets the most detailed level which is logged
+pub fn set_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Checks if messages of `level` are logged
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Writes a message to STDERR, like `[WARNING]: message`, if its level is logged. Use the
+/// `error!()`, `warning!()`, `info!()` and `debug!()` macros instead of calling this directly
+pub fn write(level: Level, message: Arguments) {
+    if enabled(level) {
+        eprintln!("[{}]: {}", level.tag(), message);
+    }
+}
+
+/// Logs an error, which is always written
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Error, format_args!($($arg)*))
+    };
+}
+
+/// Logs a warning, unless --quiet is set
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Warning, format_args!($($arg)*))
+    };
+}
+
+/// Logs what kroyer does, with -v
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Info, format_args!($($arg)*))
+    };
+}
+
+/// Logs details for finding problems, with -vv
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Debug, format_args!($($arg)*))
+    };
+}
+
+pub(crate) use {debug, error, info, warning};
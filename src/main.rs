@@ -1,122 +1,1843 @@
-use std::{fs::OpenOptions, io::Read, path::PathBuf, str::FromStr};
+use std::{
+    fs::OpenOptions,
+    io::{BufWriter, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, ValueEnum};
+use error::{Error, Result};
 use grammar::Grammar;
+use io::name::Placeholder;
 use node::{NodeType, ast};
 use primitive_types::U256;
 
+mod audio;
+mod bench;
 mod cli;
+mod config;
+pub mod error;
+pub mod export;
+mod gallery;
 pub mod grammar;
+pub mod hash;
 mod img;
 pub mod io;
+pub mod log;
 pub mod node;
 pub mod rng;
 
 fn main() {
-    let args = cli::Args::parse();
+    if let Err(e) = run() {
+        e.exit();
+    }
+}
+
+/// Runs kroyer with the arguments. Errors are returned to `main()`, which exits with the code of
+/// the error, after everything in here has been dropped
+fn run() -> Result<()> {
+    let matches = cli::Args::command().get_matches();
+    let mut args = cli::Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    log::set_level(log::Level::from_args(args.verbose, args.quiet));
+
+    if let Some(cli::Command::Completions { shell }) = args.command {
+        return completions(shell);
+    }
+
+    // Every setting is resolved here, from the built-in defaults, then the config file, then
+    // the flags
+    let config = config::Config::load(args.config.as_deref())?;
+    let sources = config.apply(&mut args, &matches);
+    if let Some(cli::Command::Config {
+        action: cli::ConfigAction::Dump,
+    }) = args.command
+    {
+        print!("{}", config::dump(&args, &config, &sources));
+        return Ok(());
+    }
 
     // Handle flags that cancel all other operations
     if args.dump_default_grammar {
         print!("# DEFAULT GRAMMAR\n\n{}", Grammar::default());
-        std::process::exit(0);
+        return Ok(());
     }
 
-    let stdin_stolen = matches!(args.seed, Some(None)) || matches!(args.ast, Some(None));
+    let parse_options = ast::parse::ParseOptions {
+        max_depth: args.max_ast_depth,
+        max_nodes: args.max_ast_nodes,
+        lenient: args.lenient,
+    };
 
-    let mut grammar = match args.file {
-        Some(path) => Grammar::parse_from_file(path),
-        None => {
-            if !stdin_stolen {
-                match io::read_stdin() {
-                    Some(str) => Grammar::parse_from_str(&str),
-                    None => Grammar::default(),
-                }
-            } else {
+    if let Some(ast_opt) = &args.validate_ast {
+        let ast_str = match ast_opt {
+            Some(path) => read_ast_file(path)?,
+            None => io::read_stdin().unwrap_or("".to_owned()),
+        };
+        return validate_ast(&ast_str, args.ast_format, parse_options);
+    }
+
+    if let Some(ast_opt) = &args.ast_to_grammar {
+        let ast_str = match ast_opt {
+            Some(path) => read_ast_file(path)?,
+            None => io::read_stdin().unwrap_or("".to_owned()),
+        };
+        let ast = parse_ast(
+            &ast_str,
+            args.ast_format,
+            parse_options,
+            args.all_errors,
+            None,
+        )?;
+        print!("{}", Grammar::from_ast(&ast));
+        return Ok(());
+    }
+
+    let stdin_use = resolve_stdin_use(&args)?;
+
+    let metadata = args
+        .from_image
+        .as_ref()
+        .map(img::meta::Metadata::read)
+        .transpose()?;
+    if let Some(metadata) = &metadata {
+        args.depth = metadata.depth;
+        args.t_start = metadata.t_start;
+        args.t_end = metadata.t_end;
+        args.still_t = metadata.still_t;
+    }
+
+    let mut grammar = match (&metadata, &args.file) {
+        (Some(metadata), _) => {
+            log::info!(
+                "Using the grammar in the metadata of {:?}",
+                args.from_image.as_ref().unwrap()
+            );
+            Grammar::parse_from_str(&metadata.grammar)?
+        }
+        (None, Some(path)) => {
+            log::info!("Using the grammar in {:?}", path);
+            Grammar::parse_from_file(path.clone())?
+        }
+        (None, None) if stdin_use == Some(cli::StdinAs::Grammar) => match io::read_stdin() {
+            Some(str) => {
+                log::info!("Using the grammar from STDIN");
+                Grammar::parse_from_str(&str)?
+            }
+            None => {
+                log::info!("Using the default grammar, since STDIN is empty");
                 Grammar::default()
             }
+        },
+        (None, None) => {
+            log::info!("Using the default grammar");
+            Grammar::default()
+        }
+    };
+    // The version decides the order of the draws, so it is also written into every dumped
+    // grammar and the metadata
+    if let Some(version) = args.compat {
+        if !(1..=grammar::GRAMMAR_VERSION).contains(&version) {
+            return Err(Error::Usage(format!(
+                "--compat has to be from 1 to {}, but was {}",
+                grammar::GRAMMAR_VERSION,
+                version
+            )));
+        }
+        grammar.version = Some(version);
+    }
+    rng::set_separate_streams(grammar.generation().separate_streams());
+    rng::set_legacy_rand(args.legacy_rand || grammar.generation().legacy_rand());
+    log::debug!(
+        "The grammar is version {}, which generates with {:?}",
+        grammar.version(),
+        grammar.generation()
+    );
+
+    if args.max_nodes == Some(0) {
+        return Err(Error::Usage("--max-nodes has to be at least 1".to_owned()));
+    }
+
+    if args.count == 0 {
+        return Err(Error::Usage("--count has to be at least 1".to_owned()));
+    }
+
+    // Only the seed and the number tell images of a batch apart for sure. Images with the same
+    // AST have the same hash, which is only found once they are named
+    if let Some(template) = &args.name_template
+        && args.count > 1
+        && !template.uses_any(&[Placeholder::N, Placeholder::Seed, Placeholder::Hash])
+    {
+        return Err(Error::Usage(
+            "Every image of a batch would get the same name from --name-template. Use {n} or {seed} in it".to_owned(),
+        ));
+    }
+
+    if args.bench_iterations == 0 {
+        return Err(Error::Usage(
+            "--bench-iterations has to be at least 1".to_owned(),
+        ));
+    }
+
+    if let Some(range) = args.frame_range
+        && (range.start >= range.end || range.end > args.frames)
+    {
+        return Err(Error::Usage(format!(
+            "--frame-range {}..{} has to start before it ends, and end by frame {}",
+            range.start, range.end, args.frames
+        )));
+    }
+
+    if let Some(t) = args.t {
+        args.still_t = t;
+    }
+
+    if !args.still_t.is_finite() {
+        return Err(Error::Usage(format!(
+            "The t of still images has to be a finite number, but was {}",
+            args.still_t
+        )));
+    }
+
+    if !args.t_start.is_finite() || !args.t_end.is_finite() {
+        return Err(Error::Usage(format!(
+            "The range of t has to be finite, but was {} to {}",
+            args.t_start, args.t_end
+        )));
+    }
+
+    if !(0. ..50.).contains(&args.normalize_percentile) {
+        return Err(Error::Usage(format!(
+            "--normalize-percentile has to be at least 0 and below 50, but was {}",
+            args.normalize_percentile
+        )));
+    }
+
+    if !args.normal_strength.is_finite() || args.normal_strength < 0. {
+        return Err(Error::Usage(format!(
+            "--normal-strength has to be a finite number of at least 0, but was {}",
+            args.normal_strength
+        )));
+    }
+
+    if !args.duration.is_finite() || args.duration <= 0. {
+        return Err(Error::Usage(format!(
+            "--duration has to be above 0, but was {}",
+            args.duration
+        )));
+    }
+
+    if args.sample_rate == 0 {
+        return Err(Error::Usage("--sample-rate has to be above 0".to_owned()));
+    }
+
+    if !(0. ..=1.).contains(&args.amplitude) {
+        return Err(Error::Usage(format!(
+            "--amplitude has to be from 0 to 1, but was {}",
+            args.amplitude
+        )));
+    }
+
+    if !args.warp_strength.is_finite() {
+        return Err(Error::Usage(format!(
+            "--warp-strength has to be a finite number, but was {}",
+            args.warp_strength
+        )));
+    }
+
+    if args.samples == 0 {
+        return Err(Error::Usage("--samples has to be at least 1".to_owned()));
+    }
+
+    if let Some(region) = args.region {
+        let right = region.x as u64 + region.width as u64;
+        let bottom = region.y as u64 + region.height as u64;
+        if right > args.width as u64 || bottom > args.height as u64 {
+            return Err(Error::Usage(format!(
+                "The region {},{},{},{} reaches outside of the {} by {} image",
+                region.x, region.y, region.width, region.height, args.width, args.height
+            )));
         }
+    }
+
+    if !(2..=256).contains(&args.gif_colors) {
+        return Err(Error::Usage(format!(
+            "--gif-colors has to be from 2 to 256, but was {}",
+            args.gif_colors
+        )));
+    }
+
+    if let Some(gamma) = args.gamma
+        && (!gamma.is_finite() || gamma <= 0.)
+    {
+        return Err(Error::Usage(format!(
+            "--gamma has to be above 0, but was {}",
+            gamma
+        )));
+    }
+
+    // Seeds in the metadata are always hex, but older images wrote them without the 0x prefix
+    let seed_str = match (&args.seed, stdin_use, &metadata) {
+        (_, _, Some(metadata)) => Some(hex_seed(&metadata.seed)),
+        (_, Some(cli::StdinAs::Seed), _) => Some(io::read_stdin().unwrap_or("".to_owned())),
+        (Some(seed_opt), _, _) => seed_opt.clone(),
+        _ => None,
+    };
+
+    // Without a seed, one is picked from the OS up front, so the seed dumped is the one the RNG
+    // started from
+    let seeded = match (&seed_str, &args.seed_from_file) {
+        (Some(seed_str), _) => rng::set_seed_str(seed_str),
+        (None, Some(path)) => rng::seed_from_file(path).map(rng::set_seed),
+        (None, None) => rng::os_seed().map(rng::set_seed),
+    };
+    if let Err(e) = seeded {
+        return Err(match (seed_str, &args.seed_from_file) {
+            (Some(seed_str), _) => Error::Usage(format!(
+                "Invalid seed supplied: \"{}\"\nDetails: {}",
+                seed_str, e
+            )),
+            (None, Some(path)) => Error::Io(format!(
+                "Failed to read seed file {:?}.\nDetails: {}",
+                path, e
+            )),
+            (None, None) => Error::Io(format!("Failed to get a seed from the OS.\nDetails: {}", e)),
+        });
+    }
+
+    match rng::seed_phrase() {
+        Some(phrase) => log::info!(
+            "Using the seed 0x{:x}, from the phrase {:?}",
+            rng::get_seed(),
+            phrase
+        ),
+        None => log::info!("Using the seed 0x{:x}", rng::get_seed()),
+    }
+
+    if args.dump_derived_seeds {
+        dump_derived_seeds(&args);
+        return Ok(());
+    }
+
+    let ast_str = match (&args.ast, stdin_use) {
+        (_, Some(cli::StdinAs::Ast)) => Some(io::read_stdin().unwrap_or("".to_owned())),
+        (Some(Some(path)), _) => Some(read_ast_file(path)?),
+        // Embedded ASTs are in the default format, so they are converted to the one of
+        // --ast-format, which they are parsed with
+        _ => metadata
+            .and_then(|x| x.ast)
+            .map(|ast_str| match args.ast_format {
+                cli::AstFormat::Default => Ok(ast_str),
+                cli::AstFormat::Sexpr => {
+                    let format = cli::AstFormat::Default;
+                    parse_ast(&ast_str, format, parse_options, args.all_errors, None)
+                        .map(|x| x.to_sexpr())
+                }
+            })
+            .transpose()?,
     };
 
-    if matches!(args.seed, Some(None)) && matches!(args.ast, Some(None)) {
-        eprintln!(
-            "[ERROR]: Both --seed and --ast are trying to read from STDIN. Only one is allowed at a time"
+    if let Err(e) = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build_global()
+    {
+        return Err(Error::Io(format!(
+            "Failed to start {} render threads.\nDetails: {}",
+            args.threads, e
+        )));
+    }
+    log::debug!("Rendering on {} threads", rayon::current_num_threads());
+
+    if args.bench {
+        return bench(&args, &mut grammar, ast_str.as_deref(), parse_options);
+    }
+
+    if args.compare_seed.is_some() || args.diff_with.is_some() {
+        return compare(&args, &mut grammar, ast_str.as_deref(), parse_options);
+    }
+
+    if let Some(paths) = &args.warp_image {
+        return warp_image(
+            &args,
+            &mut grammar,
+            ast_str.as_deref(),
+            parse_options,
+            paths,
         );
-        std::process::exit(1)
     }
 
-    if let Some(seed_opt) = args.seed {
-        let seed_str = match seed_opt {
-            Some(str) => str,
-            None => io::read_stdin().unwrap_or("".to_owned()),
+    if let Some(grid) = args.contact_sheet {
+        return contact_sheet(&args, &mut grammar, ast_str.as_deref(), parse_options, grid);
+    }
+
+    let mut gallery = args
+        .gallery
+        .as_ref()
+        .map(|x| gallery::Gallery::new(x))
+        .transpose()?;
+    let mut names = io::name::Names::default();
+
+    if args.count == 1 {
+        let ast_str = ast_str.as_deref();
+        return render(
+            &args,
+            &mut grammar,
+            ast_str,
+            parse_options,
+            None,
+            gallery.as_mut(),
+            &mut names,
+        );
+    }
+
+    // Every image of a batch gets its own seed, derived from the base seed
+    let base_seed = rng::get_seed();
+    for index in 0..args.count {
+        rng::set_seed(rng::derive_seed(base_seed, rng::BATCH_DOMAIN, index as u64));
+        log::info!(
+            "Image {} of the batch uses the seed 0x{:x}",
+            index + 1,
+            rng::get_seed()
+        );
+        let batch = Batch {
+            index,
+            count: args.count,
         };
-        let seed = match U256::from_str(seed_str.trim()) {
-            Ok(num) => num,
-            Err(e) => {
-                eprintln!(
-                    "[ERROR]: Invalid seed supplied: \"{}\"\nDetails: {}",
-                    seed_str, e
-                );
-                std::process::exit(1);
+        render(
+            &args,
+            &mut grammar,
+            ast_str.as_deref(),
+            parse_options,
+            Some(batch),
+            gallery.as_mut(),
+            &mut names,
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints the completion script of `shell` to STDOUT. The script is generated into a buffer
+/// first, since the generator panics if it can't write
+fn completions(shell: clap_complete::Shell) -> Result<()> {
+    let mut script = vec![];
+    clap_complete::generate(shell, &mut cli::Args::command(), "kroyer", &mut script);
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(&script)
+        .and_then(|_| stdout.flush())
+        .map_err(|e| Error::Io(format!("Failed to write completions.\nDetails: {}", e)))
+}
+
+/// Prints the seed of every image of --count, or cell of --contact-sheet, numbered like the files
+/// of a batch. A single image has the seed of the run
+fn dump_derived_seeds(args: &cli::Args) {
+    let base_seed = rng::get_seed();
+    let count = args.contact_sheet.map_or(args.count, |grid| grid.cells());
+    if count == 1 && args.contact_sheet.is_none() {
+        println!("1 0x{:x}", base_seed);
+        return;
+    }
+    for index in 0..count {
+        let seed = rng::derive_seed(base_seed, rng::BATCH_DOMAIN, index as u64);
+        println!("{} 0x{:x}", index + 1, seed);
+    }
+}
+
+/// The position of an image in a batch made with --count
+#[derive(Clone, Copy, Debug)]
+struct Batch {
+    index: usize,
+    count: usize,
+}
+
+impl Batch {
+    /// Gets the number of the image, from 1, padded to at least 3 digits, like `001`
+    fn number(&self) -> String {
+        let digits = self.count.to_string().len().max(3);
+        format!("{:0digits$}", self.index + 1)
+    }
+}
+
+/// Creates the AST, and renders it. See `create_ast()`. The image is added to the gallery, if
+/// there is one, and `names` keeps the name it gets from --name-template
+fn render(
+    args: &cli::Args,
+    grammar: &mut Grammar,
+    ast_str: Option<&str>,
+    parse_options: ast::parse::ParseOptions,
+    batch: Option<Batch>,
+    mut gallery: Option<&mut gallery::Gallery>,
+    names: &mut io::name::Names,
+) -> Result<()> {
+    let start = Instant::now();
+    let ast = create_ast(args, grammar, ast_str, parse_options, batch)?;
+    log::info!("Created the AST in {:.2?}", start.elapsed());
+
+    let has_t = grammar.rules.iter().any(|x| x.0 == NodeType::T);
+
+    let format = output_format(args, has_t);
+    if args.frame_range.is_some() && format != cli::OutputFormat::Frames {
+        return Err(Error::Usage(
+            "--frame-range can only be used with --format frames".to_owned(),
+        ));
+    }
+    if args.bit_depth != cli::BitDepth::Eight && format != cli::OutputFormat::Tiff {
+        return Err(Error::Usage(
+            "--bit-depth can only be used with --format tiff".to_owned(),
+        ));
+    }
+    let relief = match (args.heightmap, args.normal_map) {
+        (true, _) => Some(img::height::Relief::Heightmap),
+        (_, true) => Some(img::height::Relief::NormalMap(args.normal_strength)),
+        (false, false) => None,
+    };
+    if relief.is_some() && format != cli::OutputFormat::Still {
+        return Err(Error::Usage(
+            "--heightmap and --normal-map can only be saved with --format still".to_owned(),
+        ));
+    }
+    let animation = animation(args)?;
+    let mut settings = render_settings(args)?;
+    // Animations are normalized over every frame, so the frames don't flicker
+    let ts = match has_t && args.t.is_none() {
+        true => (0..animation.frames).map(|i| animation.t(i)).collect(),
+        false => vec![settings.still_t],
+    };
+    normalize(args, &mut settings, &ts, &ast);
+    let gif_options = gif_options(args, &settings);
+    let metadata = metadata(args, grammar, &ast);
+
+    // Netpbm images and raw floats hold every frame when the grammar uses t, like a gif, unless
+    // --t asks for a still
+    let pnm_animation = (has_t && args.t.is_none()).then_some(&animation);
+    // Filters need the whole image, so filtered images aren't streamed
+    let stream =
+        settings.post.is_empty() && (args.stream_encode || img::stream::should_stream(&settings));
+    // Warnings about the settings are only given once per batch
+    let warn = batch.is_none_or(|x| x.index == 0);
+    if warn && args.stream_encode && !settings.post.is_empty() {
+        log::warning!("Filtered images can't be streamed, so the image is rendered whole");
+    }
+    if warn && format == cli::OutputFormat::Exr && !settings.post.is_empty() {
+        log::warning!("EXR images keep the exact values of the trees, so --post is ignored");
+    }
+    if warn && relief.is_some() && !settings.post.is_empty() {
+        log::warning!("Heightmaps and normal maps aren't filtered, so --post is ignored");
+    }
+    let tiff_post = format == cli::OutputFormat::Tiff && args.bit_depth != cli::BitDepth::Eight;
+    if warn && tiff_post && !settings.post.is_empty() {
+        log::warning!("Only 8-bit TIFF images are filtered, so --post is ignored");
+    }
+    if warn && format == cli::OutputFormat::Farbfeld && !settings.post.is_empty() {
+        log::warning!("Farbfeld images are rendered with 16-bit colors, so --post is ignored");
+    }
+    if warn && format.float_kind().is_some() && !settings.post.is_empty() {
+        log::warning!("Raw floats are the exact values of the trees, so --post is ignored");
+    }
+
+    // The name is taken before anything is saved, so an image never replaces an earlier one
+    let named = args
+        .name_template
+        .as_ref()
+        .map(|template| name_image(template, batch, &settings, &ast, names))
+        .transpose()?;
+
+    // The still image of the first output which keeps it, so --preview-term shows it without
+    // rendering it again
+    let mut preview = None;
+    for output in output_plan(args, format, has_t, batch, named.as_deref())? {
+        log::debug!("Writing {:?} as {:?}", output.target, output.format);
+        let path = match output.target {
+            Target::File(path) => path,
+            Target::Stdout => {
+                let start = Instant::now();
+                write_to_stdout(batch, |stdout| match output.format {
+                    cli::OutputFormat::Still if let Some(relief) = relief => {
+                        img::height::encode_relief(stdout, relief, &settings, &metadata, &ast)
+                    }
+                    cli::OutputFormat::Still if stream => {
+                        img::stream::encode_png_streamed(stdout, &settings, &metadata, &ast)
+                    }
+                    cli::OutputFormat::Still => {
+                        let img = img::encode_png(stdout, &settings, &metadata, &ast)?;
+                        preview.get_or_insert(img);
+                        Ok(())
+                    }
+                    cli::OutputFormat::Gif => {
+                        let options = &gif_options;
+                        img::encode_gif(stdout, &settings, &animation, options, &metadata, &ast)
+                    }
+                    cli::OutputFormat::Apng => {
+                        img::encode_apng(stdout, &settings, &animation, &metadata, &ast)
+                    }
+                    cli::OutputFormat::Y4m => {
+                        img::y4m::encode_y4m(stdout, &settings, &animation, &ast)
+                    }
+                    cli::OutputFormat::Exr => {
+                        let bytes = img::exr::encode_exr(&settings, &ast)?;
+                        stdout.write_all(&bytes).map_err(stdout_error)
+                    }
+                    cli::OutputFormat::Ppm | cli::OutputFormat::Pam => {
+                        let kind = output.format.pnm_kind().unwrap();
+                        img::pnm::encode_pnm(stdout, kind, &settings, pnm_animation, &ast)
+                    }
+                    cli::OutputFormat::Npy | cli::OutputFormat::F32raw => {
+                        let kind = output.format.float_kind().unwrap();
+                        img::npy::encode_floats(stdout, kind, &settings, pnm_animation, &ast)
+                    }
+                    cli::OutputFormat::Farbfeld => {
+                        img::farbfeld::encode_farbfeld(stdout, &settings, &ast)
+                    }
+                    cli::OutputFormat::Tiff => {
+                        let bytes = img::tiff::encode_tiff(args.bit_depth, &settings, &ast)?;
+                        stdout.write_all(&bytes).map_err(stdout_error)
+                    }
+                    cli::OutputFormat::Frames => Err(Error::Usage(
+                        "--format frames saves files, so it can't be written to STDOUT".to_owned(),
+                    )),
+                })?;
+                log::info!("Rendered the image to STDOUT in {:.2?}", start.elapsed());
+                if let Some(stats) = settings.stats.take() {
+                    print_stats(args, &stats.lock().unwrap(), true);
+                }
+                continue;
+            }
+        };
+
+        let is_png = path
+            .extension()
+            .is_some_and(|x| x.eq_ignore_ascii_case("png"));
+        if args.stream_encode && output.format == cli::OutputFormat::Still && !is_png {
+            log::warning!("Only PNG images can be streamed, so the image is rendered whole");
+        }
+        let start = Instant::now();
+        // The rendered image, if the encoder keeps it, so the gallery can use it as a thumbnail
+        let rendered = match output.format {
+            cli::OutputFormat::Still if let Some(relief) = relief => {
+                img::height::gen_relief(path.clone(), relief, &settings, &metadata, &ast)?;
+                None
+            }
+            cli::OutputFormat::Still if stream && is_png => {
+                img::stream::gen_png_streamed(path.clone(), &settings, &metadata, &ast)?;
+                None
+            }
+            cli::OutputFormat::Still => {
+                Some(img::gen_img(path.clone(), &settings, &metadata, &ast)?)
+            }
+            cli::OutputFormat::Gif => img::gen_gif(
+                path.clone(),
+                &settings,
+                &animation,
+                &gif_options,
+                &metadata,
+                &ast,
+            )?,
+            cli::OutputFormat::Apng => {
+                img::gen_apng(path.clone(), &settings, &animation, &metadata, &ast)?
+            }
+            cli::OutputFormat::Y4m => {
+                img::y4m::gen_y4m(path.clone(), &settings, &animation, &ast)?;
+                None
+            }
+            cli::OutputFormat::Exr => {
+                img::exr::gen_exr(path.clone(), &settings, &ast)?;
+                None
+            }
+            cli::OutputFormat::Ppm | cli::OutputFormat::Pam => {
+                let kind = output.format.pnm_kind().unwrap();
+                img::pnm::gen_pnm(path.clone(), kind, &settings, pnm_animation, &ast)?;
+                None
+            }
+            cli::OutputFormat::Npy | cli::OutputFormat::F32raw => {
+                let kind = output.format.float_kind().unwrap();
+                img::npy::gen_floats(path.clone(), kind, &settings, pnm_animation, &ast)?;
+                None
+            }
+            cli::OutputFormat::Farbfeld => {
+                img::farbfeld::gen_farbfeld(path.clone(), &settings, &ast)?;
+                None
+            }
+            cli::OutputFormat::Tiff => {
+                img::tiff::gen_tiff(path.clone(), args.bit_depth, &settings, &ast)?;
+                None
+            }
+            cli::OutputFormat::Frames => {
+                let range = match args.frame_range {
+                    Some(range) => range.start..range.end,
+                    None => 0..animation.frames,
+                };
+                img::frames::gen_frames(&path, range, &settings, &animation, &metadata, &ast)?;
+                None
             }
         };
 
+        log::info!(
+            "Rendered the image to {:?} in {:.2?}",
+            path,
+            start.elapsed()
+        );
+        if args.preview_term && output.format == cli::OutputFormat::Still && preview.is_none() {
+            preview = rendered.clone();
+        }
+
+        if args.metadata {
+            let (width, height) = settings.size();
+            let frames = match output.format {
+                cli::OutputFormat::Gif | cli::OutputFormat::Apng | cli::OutputFormat::Y4m => {
+                    animation.frames
+                }
+                cli::OutputFormat::Ppm
+                | cli::OutputFormat::Pam
+                | cli::OutputFormat::Npy
+                | cli::OutputFormat::F32raw => pnm_animation.map_or(1, |x| x.frames),
+                cli::OutputFormat::Frames => match args.frame_range {
+                    Some(range) => range.end - range.start,
+                    None => animation.frames,
+                },
+                _ => 1,
+            };
+            let sidecar = img::sidecar::Sidecar {
+                metadata: metadata.clone(),
+                width,
+                height,
+                frames,
+                ast_hash: ast.structural_hash(),
+                render_time: start.elapsed(),
+            };
+            img::sidecar::gen_sidecar(&path, &sidecar)?;
+        }
+
+        // Only the main output, which is the first, is in the statistics and the gallery
+        if let Some(stats) = settings.stats.take() {
+            print_stats(args, &stats.lock().unwrap(), false);
+        }
+        if let Some(gallery) = gallery.take() {
+            gallery.add(
+                gallery::Entry {
+                    image: path,
+                    seed: format!("0x{:x}", rng::get_seed()),
+                    depth: args.depth,
+                    ast: ast.to_string(),
+                },
+                rendered.as_ref(),
+            )?;
+        }
+    }
+
+    if args.preview_term {
+        let img = preview.unwrap_or_else(|| img::get_img(&settings, settings.still_t, &ast));
+        img::preview::show(&img);
+    }
+    Ok(())
+}
+
+/// Gets the path of the image from --name-template, and creates its directory. Fails if an earlier
+/// image of the run got the same path
+fn name_image(
+    template: &io::name::NameTemplate,
+    batch: Option<Batch>,
+    settings: &img::RenderSettings,
+    ast: &ast::NodeAst,
+    names: &mut io::name::Names,
+) -> Result<PathBuf> {
+    let path = template.expand(|placeholder| match placeholder {
+        Placeholder::Seed => format!("0x{:x}", rng::get_seed()),
+        Placeholder::Hash => ast.structural_hash(),
+        Placeholder::Date => io::name::today(),
+        // A single image is numbered like the first image of a batch
+        Placeholder::N => batch.unwrap_or(Batch { index: 0, count: 1 }).number(),
+        Placeholder::W => settings.size().0.to_string(),
+        Placeholder::H => settings.size().1.to_string(),
+    });
+    let path = names.take(path).map_err(|path| {
+        Error::Usage(format!(
+            "--name-template gives more than one image the name {:?}. Use {{n}} or {{seed}} in it, so every image gets its own name",
+            path
+        ))
+    })?;
+
+    if let Some(dir) = path.parent()
+        && !dir.as_os_str().is_empty()
+        && let Err(e) = std::fs::create_dir_all(dir)
+    {
+        return Err(Error::Io(format!(
+            "Failed to create directory {:?}.\nDetails: {}",
+            dir, e
+        )));
+    }
+    Ok(path)
+}
+
+/// Prints the statistics of --stats, as JSON with --json. They are printed to STDERR if the image
+/// was written to STDOUT, so they aren't mixed into it
+fn print_stats(args: &cli::Args, stats: &img::stats::ValueStats, stderr: bool) {
+    let text = match args.json {
+        true => format!("{}\n", stats.to_json()),
+        false => stats.to_string(),
+    };
+    match stderr {
+        true => eprint!("{}", text),
+        false => print!("{}", text),
+    }
+}
+
+/// Where an image of a run is written
+#[derive(Clone, Debug)]
+enum Target {
+    Stdout,
+    File(PathBuf),
+}
+
+/// An image written by a run
+#[derive(Clone, Debug)]
+struct Output {
+    format: cli::OutputFormat,
+    target: Target,
+}
+
+/// Gets the images a run writes. This is the image of --out, or `named`, the path given by
+/// --name-template, in the format `format`, followed by the ones of --also-still and --also-gif,
+/// which are all made from the same AST
+fn output_plan(
+    args: &cli::Args,
+    format: cli::OutputFormat,
+    has_t: bool,
+    batch: Option<Batch>,
+    named: Option<&Path>,
+) -> Result<Vec<Output>> {
+    // Video streams and netpbm images go to STDOUT unless --out or --name-template is set
+    let to_stdout = args.dump_raw || (args.out.is_none() && named.is_none() && format.is_piped());
+    let target = |path: &Path| match is_stdout(path) {
+        true => Target::Stdout,
+        false => Target::File(numbered_path(path, batch)),
+    };
+
+    let main_target = match (to_stdout, named) {
+        (true, _) => Target::Stdout,
+        // Named images are already told apart, so they aren't numbered
+        (false, Some(path)) if is_stdout(path) => Target::Stdout,
+        (false, Some(path)) => Target::File(path.to_path_buf()),
+        (false, None) => match (&args.out, &args.out_dir) {
+            (Some(path), _) => target(path),
+            (None, Some(dir)) => {
+                std::fs::create_dir_all(dir).map_err(|e| {
+                    Error::Io(format!(
+                        "Failed to create directory {:?}.\nDetails: {}",
+                        dir, e
+                    ))
+                })?;
+                target(&dir.join(format.default_path()))
+            }
+            (None, None) => target(Path::new(format.default_path())),
+        },
+    };
+
+    let mut plan = vec![Output {
+        format,
+        target: main_target,
+    }];
+    if let Some(path) = &args.also_still {
+        plan.push(Output {
+            format: cli::OutputFormat::Still,
+            target: target(path),
+        });
+    }
+    if let Some(path) = &args.also_gif {
+        // The warning is only given once per batch
+        if !has_t && batch.is_none_or(|x| x.index == 0) {
+            log::warning!(
+                "The grammar doesn't use t, so every frame of the --also-gif gif is the same"
+            );
+        }
+        plan.push(Output {
+            format: cli::OutputFormat::Gif,
+            target: target(path),
+        });
+    }
+
+    let stdout_outputs = plan
+        .iter()
+        .filter(|x| matches!(x.target, Target::Stdout))
+        .count();
+    if stdout_outputs > 1 {
+        return Err(Error::Usage(format!(
+            "Only one image can be written to STDOUT, but {} were",
+            stdout_outputs
+        )));
+    }
+    Ok(plan)
+}
+
+/// Checks if a run writes an image to STDOUT, like `output_plan()` decides, so the text of dumps
+/// is printed to STDERR instead of being mixed into the image
+fn image_to_stdout(args: &cli::Args, has_t: bool) -> bool {
+    let main = match (&args.out, &args.name_template) {
+        (Some(path), _) => is_stdout(path),
+        (None, Some(template)) => is_stdout(template.as_path()),
+        (None, None) => output_format(args, has_t).is_piped(),
+    };
+    let also = [&args.also_still, &args.also_gif]
+        .iter()
+        .any(|x| x.as_deref().is_some_and(is_stdout));
+    args.dump_raw || main || also
+}
+
+/// Prints the text of a dump, to STDERR if the image is written to STDOUT
+fn print_dump(text: &str, stderr: bool) {
+    match stderr {
+        true => eprint!("{}", text),
+        false => print!("{}", text),
+    }
+}
+
+/// Checks if the path is `-`, which means STDOUT
+fn is_stdout(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Writes an image to STDOUT with `encode`, unless STDOUT is a terminal, or the image is part of
+/// a batch
+fn write_to_stdout(
+    batch: Option<Batch>,
+    encode: impl FnOnce(&mut BufWriter<std::io::StdoutLock>) -> Result<()>,
+) -> Result<()> {
+    if batch.is_some() {
+        return Err(Error::Usage(
+            "--count can't write to STDOUT. Set --out to get numbered files".to_owned(),
+        ));
+    }
+    if std::io::stdout().is_terminal() {
+        return Err(Error::Usage("Refusing to write binary image data to a terminal. Pipe the output into a file or another program, or set --out".to_owned()));
+    }
+
+    let mut stdout = BufWriter::new(std::io::stdout().lock());
+    encode(&mut stdout)?;
+    stdout.flush().map_err(stdout_error)
+}
+
+/// Gets the error of an image which couldn't be written to STDOUT
+fn stdout_error(e: impl std::fmt::Display) -> Error {
+    Error::Io(format!("Failed to write image to STDOUT.\nDetails: {}", e))
+}
+
+/// Renders a cell for every seed of a batch the size of `grid`, and saves them as a single image
+fn contact_sheet(
+    args: &cli::Args,
+    grammar: &mut Grammar,
+    ast_str: Option<&str>,
+    parse_options: ast::parse::ParseOptions,
+    grid: cli::Grid,
+) -> Result<()> {
+    let mut settings = render_settings(args)?;
+    let base_seed = rng::get_seed();
+    let count = grid.cells();
+
+    // Each cell is rendered right after its AST is created, so rand nodes draw from the RNG in
+    // the same state as with --count
+    let mut cells = Vec::with_capacity(count);
+    for index in 0..count {
+        let seed = rng::derive_seed(base_seed, rng::BATCH_DOMAIN, index as u64);
+        rng::set_seed(seed);
+        let batch = Batch { index, count };
+        let ast = create_ast(args, grammar, ast_str, parse_options, Some(batch))?;
+        let label = args.label_cells.then(|| format!("0x{:x}", seed));
+        normalize(args, &mut settings, &[args.still_t], &ast);
+        cells.push((img::get_img(&settings, settings.still_t, &ast), label));
+    }
+
+    let sheet = img::sheet::compose(grid, &cells);
+    let path = args.out.clone().unwrap_or_else(|| PathBuf::from("out.png"));
+    if is_stdout(&path) {
+        write_to_stdout(None, |stdout| {
+            img::sheet::encode_contact_sheet(stdout, &sheet).map_err(stdout_error)
+        })?;
+    } else {
+        img::sheet::gen_contact_sheet(path, &sheet)?;
+    }
+
+    if args.preview_term {
+        img::preview::show(&sheet);
+    }
+    Ok(())
+}
+
+/// Renders the still image, and compares it with the one of --compare-seed, or the image of
+/// --diff-with. The difference is saved, and its summary printed
+fn compare(
+    args: &cli::Args,
+    grammar: &mut Grammar,
+    ast_str: Option<&str>,
+    parse_options: ast::parse::ParseOptions,
+) -> Result<()> {
+    // The seed is checked before anything is rendered
+    let other_seed = args
+        .compare_seed
+        .as_ref()
+        .map(|seed_str| {
+            rng::parse_seed(seed_str).map(|x| x.0).map_err(|e| {
+                Error::Usage(format!(
+                    "Invalid seed supplied to --compare-seed: \"{}\"\nDetails: {}",
+                    seed_str, e
+                ))
+            })
+        })
+        .transpose()?;
+
+    let mut settings = render_settings(args)?;
+    let mut render_seed = |seed: U256| -> Result<_> {
         rng::set_seed(seed);
+        let ast = create_ast(args, grammar, ast_str, parse_options, None)?;
+        normalize(args, &mut settings, &[args.still_t], &ast);
+        Ok(img::get_img(&settings, settings.still_t, &ast))
+    };
+
+    let img = render_seed(rng::get_seed())?;
+    let other = match (other_seed, &args.diff_with) {
+        (Some(seed), _) => render_seed(seed)?,
+        (None, Some(path)) => match image::open(path) {
+            Ok(other) => other.to_rgba8(),
+            Err(e) => {
+                return Err(Error::Io(format!(
+                    "Failed to read image {:?}.\nDetails: {}",
+                    path, e
+                )));
+            }
+        },
+        (None, None) => unreachable!("compare() is only called with one of them"),
+    };
+
+    if img.dimensions() != other.dimensions() {
+        return Err(Error::Usage(format!(
+            "The images have different sizes, {} by {} and {} by {}",
+            img.width(),
+            img.height(),
+            other.width(),
+            other.height()
+        )));
+    }
+
+    let (diff, summary) = img::diff::diff(&img, &other);
+    let path = args
+        .out
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("diff.png"));
+    if is_stdout(&path) {
+        write_to_stdout(None, |stdout| {
+            img::diff::encode_diff(stdout, &diff).map_err(stdout_error)
+        })?;
+        // The summary goes to STDERR, so it isn't mixed into the image
+        match args.json {
+            true => eprintln!("{}", summary.to_json()),
+            false => eprint!("{}", summary),
+        }
+        return Ok(());
+    }
+    img::diff::gen_diff(path, &diff)?;
+
+    match args.json {
+        true => println!("{}", summary.to_json()),
+        false => print!("{}", summary),
+    }
+    Ok(())
+}
+
+/// Warps the image of --warp-image by the AST, and saves it. See `img::warp::gen_warp()`
+fn warp_image(
+    args: &cli::Args,
+    grammar: &mut Grammar,
+    ast_str: Option<&str>,
+    parse_options: ast::parse::ParseOptions,
+    paths: &[PathBuf],
+) -> Result<()> {
+    let [input_path, output_path] = paths else {
+        unreachable!("--warp-image takes two paths");
+    };
+
+    // The image is read before anything is generated
+    let input = match image::open(input_path) {
+        Ok(input) => input.to_rgba8(),
+        Err(e) => {
+            return Err(Error::Io(format!(
+                "Failed to read image {:?}.\nDetails: {}",
+                input_path, e
+            )));
+        }
+    };
+    if input.width() == 0 || input.height() == 0 {
+        return Err(Error::Usage(format!(
+            "The image {:?} has no pixels",
+            input_path
+        )));
+    }
+
+    let ast = create_ast(args, grammar, ast_str, parse_options, None)?;
+    let warp = img::warp::Warp {
+        image: input,
+        edge: args.warp_edge,
+        filter: args.warp_filter,
+        strength: args.warp_strength,
+        brightness: args.warp_brightness,
+    };
+    let settings = render_settings(args)?;
+    img::warp::gen_warp(
+        output_path,
+        &warp,
+        &settings,
+        &animation(args)?,
+        &gif_options(args, &settings),
+        &metadata(args, grammar, &ast),
+        &ast,
+    )?;
+    if let Some(stats) = &settings.stats {
+        print_stats(args, &stats.lock().unwrap(), false);
     }
+    Ok(())
+}
+
+/// Creates and renders the AST --bench-iterations times, timing each phase, and prints the
+/// report. The image is encoded like it would be saved, but thrown away
+fn bench(
+    args: &cli::Args,
+    grammar: &mut Grammar,
+    ast_str: Option<&str>,
+    parse_options: ast::parse::ParseOptions,
+) -> Result<()> {
+    // --t times a still, even if the grammar uses t
+    let has_t = grammar.rules.iter().any(|x| x.0 == NodeType::T) && args.t.is_none();
+    let format = match output_format(args, has_t) {
+        cli::OutputFormat::Apng => cli::OutputFormat::Apng,
+        cli::OutputFormat::Frames => cli::OutputFormat::Frames,
+        _ if has_t => cli::OutputFormat::Gif,
+        _ => cli::OutputFormat::Still,
+    };
+    let animation = animation(args)?;
+    let mut settings = render_settings(args)?;
+    let gif_options = gif_options(args, &settings);
+    let (width, height) = settings.size();
+
+    let mut report = bench::Report {
+        pixels: width as u64 * height as u64,
+        frames: if has_t { args.frames } else { 1 },
+        generation: bench::Phase::default(),
+        evaluation: bench::Phase::default(),
+        encoding: bench::Phase::default(),
+    };
 
-    let ast = {
-        if let Some(ast_opt) = args.ast {
-            let ast_str = match ast_opt {
-                Some(path) => {
-                    let Ok(mut file) = OpenOptions::new().read(true).open(path.clone()) else {
-                        eprintln!("[ERROR]: Failed to open AST file {:?}", path);
-                        std::process::exit(1)
-                    };
+    // The seed is reset every iteration, so each one renders the same image
+    let seed = rng::get_seed();
+    for _ in 0..args.bench_iterations {
+        rng::set_seed(seed);
+
+        let start = Instant::now();
+        let ast = create_ast(args, grammar, ast_str, parse_options, None)?;
+        report.generation.times.push(start.elapsed());
+        let metadata = metadata(args, grammar, &ast);
+
+        let start = Instant::now();
+        let ts = match has_t {
+            true => (0..animation.frames).map(|i| animation.t(i)).collect(),
+            false => vec![settings.still_t],
+        };
+        normalize(args, &mut settings, &ts, &ast);
+        let frames = match has_t {
+            true => img::get_frames(&settings, &animation, &ast),
+            false => vec![img::get_img(&settings, settings.still_t, &ast)],
+        };
+        report.evaluation.times.push(start.elapsed());
 
-                    let mut buf = String::new();
-                    _ = file.read_to_string(&mut buf);
-                    buf
+        let start = Instant::now();
+        let sink = std::io::sink();
+        match format {
+            cli::OutputFormat::Gif => {
+                img::write_gif(sink, frames, &animation, &gif_options, &metadata)?
+            }
+            cli::OutputFormat::Apng => img::write_apng(sink, &frames, &animation, &metadata)?,
+            cli::OutputFormat::Frames => {
+                for frame in &frames {
+                    img::write_png(std::io::sink(), frame, &metadata)?;
                 }
-                None => io::read_stdin().unwrap_or("".to_owned()),
+            }
+            _ => img::write_png(sink, &frames[0], &metadata)?,
+        }
+        report.encoding.times.push(start.elapsed());
+    }
+
+    match args.json {
+        true => println!("{}", report.to_json()),
+        false => print!("{}", report),
+    }
+    Ok(())
+}
+
+/// Creates the AST, from the grammar or from `ast_str`, and applies and dumps what was asked for.
+/// `batch` is set if the image is part of a batch, which numbers the dumped files
+fn create_ast(
+    args: &cli::Args,
+    grammar: &mut Grammar,
+    ast_str: Option<&str>,
+    parse_options: ast::parse::ParseOptions,
+    batch: Option<Batch>,
+) -> Result<ast::NodeAst> {
+    // Everything is drawn from the RNGs of the seed, which legacy rand nodes keep drawing from
+    // while rendering
+    let separate_streams = grammar.generation().separate_streams();
+    let mut rng = rng::Streams::new(rng::get_seed(), separate_streams);
+    let mut ast = {
+        if let Some(ast_str) = ast_str {
+            let mut fill = || {
+                let mut budget = args.max_nodes.map(|x| x.saturating_sub(1));
+                node::Node::gen_rand_with(grammar, args.depth, &mut budget, &mut rng)
+            };
+            let fill: Option<&mut dyn FnMut() -> node::NodePtr> = match args.fill_missing {
+                true => Some(&mut fill),
+                false => None,
             };
-            ast::NodeAst::parse_from_str(&ast_str)
+            parse_ast(
+                ast_str,
+                args.ast_format,
+                parse_options,
+                args.all_errors,
+                fill,
+            )?
         } else {
-            ast::NodeAst::from_grammar(&mut grammar, args.depth)
+            let mut ast = match args.grayscale {
+                true => ast::NodeAst::grayscale_from_grammar(
+                    grammar,
+                    args.depth,
+                    args.max_nodes,
+                    &mut rng,
+                ),
+                false => {
+                    ast::NodeAst::from_grammar_with(grammar, args.depth, args.max_nodes, &mut rng)
+                }
+            };
+            if args.alpha {
+                ast.gen_alpha(grammar, args.depth, args.max_nodes, &mut rng);
+            }
+            ast
         }
     };
 
-    if args.dump_seed {
-        println!("SEED: {:x}", rng::get_seed())
+    if let Some(other_path) = &args.breed {
+        let other = parse_ast(
+            &read_ast_file(other_path)?,
+            args.ast_format,
+            parse_options,
+            args.all_errors,
+            None,
+        )?;
+        ast = ast.breed(&other, args.depth, grammar, &mut rng);
+    }
+
+    if let Some(count) = args.mutate {
+        let mutated = ast.mutate(
+            grammar,
+            count,
+            args.mutate_depth,
+            args.mutate_root,
+            &mut rng,
+        );
+        if mutated < count {
+            log::warning!(
+                "Only {} of the {} requested mutations could be made, since the AST doesn't have enough branches",
+                mutated,
+                count
+            );
+        }
+    }
+
+    if args.freeze_rand {
+        ast.freeze_rand(&mut rng);
+    }
+    rng::resume_runtime(rng);
+
+    if args.simplify {
+        ast = ast.simplify();
+    }
+
+    if args.canonical {
+        ast.canonicalize();
+    }
+
+    ast.salt_rand();
+
+    if log::enabled(log::Level::Info) {
+        let alpha = match &ast.a {
+            Some(a) => format!(", A: {}", a.node_count()),
+            None => String::new(),
+        };
+        log::info!(
+            "The AST has {} nodes (R: {}, G: {}, B: {}{})",
+            ast.node_count(),
+            ast.r.node_count(),
+            ast.g.node_count(),
+            ast.b.node_count(),
+            alpha
+        );
+    }
+
+    let has_t = grammar.rules.iter().any(|x| x.0 == NodeType::T);
+    let dump_to_stderr = image_to_stdout(args, has_t);
+    let dump = |text: String| print_dump(&text, dump_to_stderr);
+
+    // The seed is dumped on its own, so it can be piped into --seed, unless --verbose labels it
+    if args.dump_seed && args.verbose > 0 {
+        if let Some(phrase) = rng::seed_phrase() {
+            dump(format!("SEED PHRASE: {}\n", phrase));
+        }
+        dump(format!("SEED: 0x{:x}\n", rng::get_seed()));
+        dump(format!("VERSION: {}\n", grammar.version()));
+    } else if args.dump_seed {
+        dump(format!("0x{:x}\n", rng::get_seed()));
+    }
+
+    if args.dump_hash {
+        dump(format!("HASH: {}\n", ast.structural_hash()));
     }
 
     if args.dump_grammar {
-        println!("# CURRENT GRAMMAR\n{}", grammar);
+        dump(format!("# CURRENT GRAMMAR\n{}\n", grammar));
     }
 
+    let ast_dump = match (args.ast_format, args.pretty) {
+        _ if args.infix => ast.to_infix(),
+        (cli::AstFormat::Default, false) => ast.to_string(),
+        (cli::AstFormat::Default, true) => ast.to_pretty(args.pretty_width),
+        (cli::AstFormat::Sexpr, false) => ast.to_sexpr(),
+        (cli::AstFormat::Sexpr, true) => ast.to_pretty_sexpr(args.pretty_width),
+    };
+
     if args.dump_ast {
-        println!("R:\n{}\nG:\n{}\nB:\n{}", ast.r, ast.g, ast.b);
+        dump(format!("{}\n", ast_dump));
     }
 
-    let has_t = grammar.rules.iter().any(|x| x.0 == NodeType::T);
+    if let Some(path) = &args.dump_seed_file {
+        dump_to_file(
+            &numbered_path(path, batch),
+            &format!("0x{:x}\n", rng::get_seed()),
+            args.force,
+        )?;
+    }
+
+    // The grammar is the same for every image of a batch, so it is only dumped once
+    if let Some(path) = &args.dump_grammar_file
+        && batch.is_none_or(|x| x.index == 0)
+    {
+        dump_to_file(path, &grammar.to_string(), args.force)?;
+    }
+
+    if let Some(path) = &args.dump_ast_file {
+        dump_to_file(
+            &numbered_path(path, batch),
+            &format!("{}\n", ast_dump),
+            args.force,
+        )?;
+    }
+
+    if let Some(export) = &args.export {
+        let [language, path] = export.as_slice() else {
+            unreachable!("clap makes --export take two values")
+        };
+        let Ok(language) = cli::ExportLanguage::from_str(language, true) else {
+            let supported: Vec<String> = cli::ExportLanguage::value_variants()
+                .iter()
+                .filter_map(|x| x.to_possible_value())
+                .map(|x| x.get_name().to_owned())
+                .collect();
+            return Err(Error::Usage(format!(
+                "Unknown export language \"{}\". Supported languages are: {}",
+                language,
+                supported.join(", ")
+            )));
+        };
+        let code = match language {
+            cli::ExportLanguage::Wgsl => Some(export::export_ast(&export::wgsl::Wgsl, &ast)),
+            cli::ExportLanguage::Rust => Some(export::export_ast(
+                &export::rust::RustSource::new(rng::get_seed().low_u64()),
+                &ast,
+            )),
+            cli::ExportLanguage::Python => Some(export::export_ast(
+                &export::python::Python::new(rng::get_seed().low_u64()),
+                &ast,
+            )),
+            cli::ExportLanguage::Wav => {
+                let path = Path::new(path);
+                if path.exists() && !args.force {
+                    return Err(Error::Io(format!(
+                        "Refusing to overwrite existing file {:?}. Use --force to overwrite it",
+                        path
+                    )));
+                }
+                audio::gen_wav(path, &wav_settings(args)?, &ast)?;
+                None
+            }
+        };
+        if let Some(code) = code {
+            dump_to_file(&PathBuf::from(path), &code, args.force)?;
+        }
+    }
+
+    if args.lint_ast {
+        let dead_branches = ast.dead_branches();
+        if args.json {
+            let branches: Vec<String> = dead_branches.iter().map(|x| x.to_json()).collect();
+            dump(format!(
+                "{{\"dead_branches\": [{}]}}\n",
+                branches.join(", ")
+            ));
+        } else if dead_branches.is_empty() {
+            dump("No dead branches found\n".to_owned());
+        } else {
+            for branch in &dead_branches {
+                dump(format!("{}\n", branch));
+            }
+        }
+    }
 
-    let is_gif_ext = match &args.out {
-        Some(path) => path.to_str().unwrap().to_lowercase().ends_with(".gif"),
-        None => false,
+    if args.ast_stats {
+        let stats = node::stats::AstStats::from_ast(&ast);
+        match args.json {
+            true => dump(format!("{}\n", stats.to_json())),
+            false => dump(stats.to_string()),
+        }
+    }
+
+    // A palette only needs the red tree, or the tree of the channel
+    Ok(match (args.channel, &args.palette) {
+        (Some(channel), _) => ast::NodeAst::grayscale(ast.channel(channel.index()).clone()),
+        (None, Some(_)) => ast::NodeAst {
+            a: ast.a.clone(),
+            ..ast::NodeAst::grayscale(ast.r.clone())
+        },
+        (None, None) => ast,
+    })
+}
+
+/// Gets the settings images are rendered with, from the arguments
+fn render_settings(args: &cli::Args) -> Result<img::RenderSettings> {
+    Ok(img::RenderSettings {
+        width: args.width,
+        height: args.height,
+        color_space: args.color_space,
+        samples: args.samples,
+        x_range: match args.centered {
+            true => cli::AxisRange::CENTERED,
+            false => args.x_range,
+        },
+        y_range: match args.centered {
+            true => cli::AxisRange::CENTERED,
+            false => args.y_range,
+        },
+        tone_map: args.tone_map,
+        nan_color: args.nan_color,
+        transfer: match (args.gamma, args.srgb) {
+            (Some(gamma), _) => Some(img::color::Transfer::Gamma(gamma)),
+            (None, true) => Some(img::color::Transfer::Srgb),
+            (None, false) => None,
+        },
+        region: args.region,
+        still_t: args.still_t,
+        post: args.post.clone(),
+        palette: args
+            .palette
+            .as_ref()
+            .map(|x| img::colormap::ColorMap::read(x, args.palette_smooth))
+            .transpose()?,
+        symmetry: args.symmetry,
+        normalization: None,
+        stats: args.stats.then(Default::default),
+        gpu: args.gpu,
+    })
+}
+
+/// Sets the normalization of --normalize for the AST, found over the values of t in `ts`
+fn normalize(args: &cli::Args, settings: &mut img::RenderSettings, ts: &[f64], ast: &ast::NodeAst) {
+    if !args.normalize {
+        return;
+    }
+
+    let start = Instant::now();
+    let normalization =
+        img::normalize::Normalization::compute(settings, ts, ast, args.normalize_percentile);
+    log::info!(
+        "Normalized the trees in {:.2?} with {}",
+        start.elapsed(),
+        normalization
+    );
+    settings.normalization = Some(normalization);
+}
+
+/// Gets the settings of `--export wav` from the args
+fn wav_settings(args: &cli::Args) -> Result<audio::WavSettings> {
+    let settings = render_settings(args)?;
+    Ok(audio::WavSettings {
+        duration: args.duration,
+        sample_rate: args.sample_rate,
+        amplitude: args.amplitude,
+        x_range: settings.x_range,
+        y: settings.y_range.at(0.5),
+        t: args.still_t,
+    })
+}
+
+/// Gets the settings of the animation from the args
+fn animation(args: &cli::Args) -> Result<img::Animation> {
+    img::Animation::new(args.frames, args.fps, args.t_mode, args.t_start, args.t_end)
+}
+
+/// Gets the options of gif encoding from the args. A --palette which fits in a gif is used as
+/// its palette, unless its colors are blended
+fn gif_options(args: &cli::Args, settings: &img::RenderSettings) -> img::GifOptions {
+    let fixed_palette = settings
+        .palette
+        .as_ref()
+        .filter(|x| !x.is_smooth() && x.colors().len() + args.alpha as usize <= args.gif_colors)
+        .map(|x| x.colors().to_vec());
+    img::GifOptions {
+        colors: args.gif_colors,
+        dither: args.dither,
+        global_palette: args.gif_global_palette,
+        fixed_palette,
+    }
+}
+
+/// Gets the metadata written into the image made from `ast`
+fn metadata(args: &cli::Args, grammar: &Grammar, ast: &ast::NodeAst) -> img::meta::Metadata {
+    img::meta::Metadata {
+        seed: format!("0x{:x}", rng::get_seed()),
+        depth: args.depth,
+        grammar: grammar.to_string(),
+        ast: args.embed_ast.then(|| ast.to_string()),
+        t_start: args.t_start,
+        t_end: args.t_end,
+        still_t: args.still_t,
+    }
+}
+
+/// Adds the number of the image in the batch to a path, like `out-001.png`. The number has at
+/// least three digits, and more if the batch needs them. Paths outside of a batch are unchanged
+fn numbered_path(path: &Path, batch: Option<Batch>) -> PathBuf {
+    let Some(batch) = batch else {
+        return path.to_path_buf();
     };
 
-    if (args.out.is_none() && has_t) || is_gif_ext {
-        img::gen_gif(
-            args.out.unwrap_or(PathBuf::from_str("out.gif").unwrap()),
-            args.width,
-            args.height,
-            args.frames,
-            &ast,
-        );
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!(
+            "{}-{}.{}",
+            stem,
+            batch.number(),
+            extension.to_string_lossy()
+        ),
+        None => format!("{}-{}", stem, batch.number()),
+    };
+    path.with_file_name(name)
+}
+
+/// Decides the format of the image. --format picks it explicitly. Otherwise, an --out path or
+/// --name-template ending in .gif, .apng, .y4m, .exr, .ppm or .pam picks it, and without either,
+/// a gif is made if the grammar uses t, unless --t asks for a still
+fn output_format(args: &cli::Args, has_t: bool) -> cli::OutputFormat {
+    if let Some(format) = args.format {
+        return format;
+    }
+
+    // Heightmaps and normal maps are only saved as still images
+    if args.heightmap || args.normal_map {
+        return cli::OutputFormat::Still;
+    }
+
+    let out = args
+        .out
+        .as_deref()
+        .or(args.name_template.as_ref().map(|x| x.as_path()));
+    let Some(out) = out.filter(|x| !is_stdout(x)) else {
+        return match has_t && args.t.is_none() {
+            true => cli::OutputFormat::Gif,
+            false => cli::OutputFormat::Still,
+        };
+    };
+
+    if img::frames::is_template(out) {
+        return cli::OutputFormat::Frames;
+    }
+
+    let extension = out
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(str::to_lowercase);
+    match extension.as_deref() {
+        Some("gif") => cli::OutputFormat::Gif,
+        Some("apng") => cli::OutputFormat::Apng,
+        Some("y4m") => cli::OutputFormat::Y4m,
+        Some("exr") => cli::OutputFormat::Exr,
+        Some("ppm") => cli::OutputFormat::Ppm,
+        Some("pam") => cli::OutputFormat::Pam,
+        Some("npy") => cli::OutputFormat::Npy,
+        Some("f32") => cli::OutputFormat::F32raw,
+        Some("ff") => cli::OutputFormat::Farbfeld,
+        Some("tif" | "tiff") => cli::OutputFormat::Tiff,
+        _ => cli::OutputFormat::Still,
+    }
+}
+
+/// Writes a dump into a file
+fn dump_to_file(path: &PathBuf, content: &str, force: bool) -> Result<()> {
+    io::write_file(path, content, force).map_err(|e| {
+        Error::Io(match e.kind() {
+            std::io::ErrorKind::AlreadyExists => format!(
+                "Refusing to overwrite existing file {:?}. Use --force to overwrite it",
+                path
+            ),
+            _ => format!("Failed to write to file {:?}.\nDetails: {}", path, e),
+        })
+    })
+}
+
+/// Reads an AST file into a string
+fn read_ast_file(path: &PathBuf) -> Result<String> {
+    let mut file = OpenOptions::new().read(true).open(path).map_err(|e| {
+        Error::Io(format!(
+            "Failed to open AST file {:?}.\nDetails: {}",
+            path, e
+        ))
+    })?;
+
+    let mut buf = String::new();
+    if let Err(e) = file.read_to_string(&mut buf) {
+        return Err(Error::Io(format!(
+            "Failed to read AST file {:?}.\nDetails: {}",
+            path, e
+        )));
+    };
+    Ok(buf)
+}
+
+/// Gets the seed of image metadata in the format of --seed. Seeds are written with a 0x prefix,
+/// but older images have them without it, and --seed would read those as seed phrases
+fn hex_seed(seed: &str) -> String {
+    let seed = seed.trim();
+    match seed.starts_with("0x") || seed.starts_with("0X") {
+        true => seed.to_owned(),
+        false => format!("0x{}", seed),
+    }
+}
+
+/// Decides what STDIN is read as. `--stdin-as` picks it explicitly. Otherwise, it is read by
+/// `--seed` or `--ast` if one of them is given without a value, and else as the grammar, unless a
+/// grammar file is given. Fails if the flags contradict each other, or if data is piped into
+/// STDIN which nothing would read
+fn resolve_stdin_use(args: &cli::Args) -> Result<Option<cli::StdinAs>> {
+    let seed_from_stdin = matches!(args.seed, Some(None));
+    let ast_from_stdin = matches!(args.ast, Some(None));
+
+    if seed_from_stdin && ast_from_stdin {
+        return Err(Error::Usage(
+            "Both --seed and --ast are trying to read from STDIN. Only one is allowed at a time"
+                .to_owned(),
+        ));
+    }
+
+    let implicit = if seed_from_stdin {
+        Some(cli::StdinAs::Seed)
+    } else if ast_from_stdin {
+        Some(cli::StdinAs::Ast)
+    } else if args.file.is_none() {
+        Some(cli::StdinAs::Grammar)
     } else {
-        img::gen_img(
-            args.out.unwrap_or(PathBuf::from_str("out.png").unwrap()),
-            args.width,
-            args.height,
-            &ast,
+        None
+    };
+
+    let Some(stdin_as) = args.stdin_as else {
+        if implicit.is_none()
+            && let Some(str) = io::read_stdin()
+            && !str.trim().is_empty()
+        {
+            return Err(Error::Usage(format!(
+                "Data was piped into STDIN, but nothing reads it, since the grammar is read from {:?}. Use --stdin-as seed or --stdin-as ast to use it",
+                args.file.as_ref().unwrap()
+            )));
+        }
+        return Ok(implicit);
+    };
+
+    let conflict = match stdin_as {
+        cli::StdinAs::Grammar if args.file.is_some() => Some("a grammar file is given"),
+        cli::StdinAs::Seed if matches!(args.seed, Some(Some(_))) => Some("--seed has a value"),
+        cli::StdinAs::Seed if args.seed_from_file.is_some() => {
+            Some("the seed is read from --seed-from-file")
+        }
+        cli::StdinAs::Ast if matches!(args.ast, Some(Some(_))) => Some("--ast has a value"),
+        _ if seed_from_stdin && stdin_as != cli::StdinAs::Seed => {
+            Some("--seed without a value reads STDIN")
+        }
+        _ if ast_from_stdin && stdin_as != cli::StdinAs::Ast => {
+            Some("--ast without a value reads STDIN")
+        }
+        _ => None,
+    };
+
+    if let Some(reason) = conflict {
+        let name = stdin_as.to_possible_value().unwrap();
+        return Err(Error::Usage(format!(
+            "--stdin-as {} can't be used, since {}",
+            name.get_name(),
+            reason
+        )));
+    }
+
+    Ok(Some(stdin_as))
+}
+
+/// Parses an AST in the given format. If `all_errors` is set, every error found is returned,
+/// otherwise only the first one is. If `fill` is set, it creates the trees of missing channels
+fn try_parse_ast(
+    ast_str: &str,
+    format: cli::AstFormat,
+    options: ast::parse::ParseOptions,
+    all_errors: bool,
+    fill: Option<&mut dyn FnMut() -> node::NodePtr>,
+) -> std::result::Result<ast::NodeAst, Vec<ast::parse::AstParseError>> {
+    if let Some(fill) = fill {
+        return match format {
+            cli::AstFormat::Default => {
+                ast::NodeAst::parse_with_fill(ast_str, options, all_errors, fill)
+            }
+            cli::AstFormat::Sexpr => {
+                ast::NodeAst::parse_from_sexpr_with_fill(ast_str, options, fill)
+                    .map_err(|e| vec![e])
+            }
+        };
+    }
+
+    match format {
+        cli::AstFormat::Default if all_errors => ast::NodeAst::parse_all_errors(ast_str, options),
+        cli::AstFormat::Default => {
+            ast::NodeAst::parse_from_str_with_options(ast_str, options).map_err(|e| vec![e])
+        }
+        cli::AstFormat::Sexpr => {
+            ast::NodeAst::parse_from_sexpr_with_options(ast_str, options).map_err(|e| vec![e])
+        }
+    }
+}
+
+/// Prints parse errors, and gets the error which ends them, which is a summary if there is more
+/// than one, or else the only error
+fn report_parse_errors(errors: &[ast::parse::AstParseError]) -> Error {
+    if errors.len() > 1 {
+        for e in errors {
+            log::error!("{}", e);
+        }
+        return Error::Ast(format!("Found {} problems in the AST", errors.len()));
+    }
+    Error::Ast(errors.iter().map(|e| e.to_string()).collect())
+}
+
+/// Parses an AST in the given format, and reports if it is valid, or all the errors found in it
+fn validate_ast(
+    ast_str: &str,
+    format: cli::AstFormat,
+    options: ast::parse::ParseOptions,
+) -> Result<()> {
+    let ast = try_parse_ast(ast_str, format, options, true, None)
+        .map_err(|errors| report_parse_errors(&errors))?;
+    println!("AST is valid ({} nodes)", ast.node_count());
+    Ok(())
+}
+
+/// Parses an AST in the given format
+fn parse_ast(
+    ast_str: &str,
+    format: cli::AstFormat,
+    options: ast::parse::ParseOptions,
+    all_errors: bool,
+    fill: Option<&mut dyn FnMut() -> node::NodePtr>,
+) -> Result<ast::NodeAst> {
+    try_parse_ast(ast_str, format, options, all_errors, fill)
+        .map_err(|errors| report_parse_errors(&errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    /// Resolves what STDIN is read as, with the arguments given after the name of the program
+    fn stdin_use(args: &[&str]) -> Result<Option<cli::StdinAs>> {
+        let args = cli::Args::try_parse_from([&["kroyer"], args].concat()).unwrap();
+        resolve_stdin_use(&args)
+    }
+
+    /// Gets the message of the usage error of resolving the arguments
+    fn usage_error(args: &[&str]) -> String {
+        match stdin_use(args) {
+            Err(Error::Usage(message)) => message,
+            other => panic!("{:?} gave {:?} instead of a usage error", args, other),
+        }
+    }
+
+    #[test]
+    fn stdin_is_read_by_whatever_is_missing_a_value() {
+        use cli::StdinAs;
+
+        assert_eq!(stdin_use(&[]), Ok(Some(StdinAs::Grammar)));
+        assert_eq!(stdin_use(&["g.kroyer", "--seed"]), Ok(Some(StdinAs::Seed)));
+        assert_eq!(stdin_use(&["g.kroyer", "--ast"]), Ok(Some(StdinAs::Ast)));
+        assert_eq!(
+            stdin_use(&["--seed", "--stdin-as", "seed"]),
+            Ok(Some(StdinAs::Seed))
+        );
+        assert_eq!(
+            stdin_use(&["g.kroyer", "--stdin-as", "ast"]),
+            Ok(Some(StdinAs::Ast))
         );
+        assert_eq!(
+            stdin_use(&["--stdin-as", "grammar"]),
+            Ok(Some(StdinAs::Grammar))
+        );
+    }
+
+    #[test]
+    fn contradicting_stdin_uses_are_usage_errors() {
+        assert!(usage_error(&["--seed", "--ast"]).contains("Only one is allowed"));
+        let cases: [(&[&str], &str); 6] = [
+            (
+                &["g.kroyer", "--stdin-as", "grammar"],
+                "a grammar file is given",
+            ),
+            (
+                &["--seed", "0x2a", "--stdin-as", "seed"],
+                "--seed has a value",
+            ),
+            (
+                &["--seed-from-file", "seed.txt", "--stdin-as", "seed"],
+                "the seed is read from --seed-from-file",
+            ),
+            (
+                &["--ast", "a.ast", "--stdin-as", "ast"],
+                "--ast has a value",
+            ),
+            (
+                &["--seed", "--stdin-as", "ast"],
+                "--seed without a value reads STDIN",
+            ),
+            (
+                &["--ast", "--stdin-as", "grammar"],
+                "--ast without a value reads STDIN",
+            ),
+        ];
+        for (args, reason) in cases {
+            let message = usage_error(args);
+            assert!(message.ends_with(reason), "{:?} gave {:?}", args, message);
+            assert!(message.starts_with("--stdin-as "), "{}", message);
+        }
+    }
+
+    #[test]
+    fn seeds_of_metadata_get_one_0x_prefix() {
+        assert_eq!(hex_seed("face"), "0xface");
+        assert_eq!(hex_seed("0xface"), "0xface");
+        assert_eq!(hex_seed("0XFACE"), "0XFACE");
+        assert_eq!(hex_seed(" 0xface\n"), "0xface");
     }
 }
@@ -0,0 +1,150 @@
+use crate::{
+    hash,
+    node::{Node, Operator},
+};
+
+use super::NodeAst;
+
+impl NodeAst {
+    /// Gets a SHA-256 hash of the structure of the AST, as a hex string. The hash only depends on
+    /// the nodes of the trees, not on how the AST was formatted, or if it was written as a
+    /// grayscale AST or with three identical channels. The alpha channel is only part of the
+    /// serialization if the AST has one, so the hashes of ASTs without one are unchanged by it.
+    ///
+    /// The hash is part of kroyer's interface, and has to stay the same across releases. The
+    /// serialization written by `write_canonical()` may therefore never change. If it has to, the
+    /// version prefix has to change with it
+    pub fn structural_hash(&self) -> String {
+        let mut canonical = String::from("kroyer-ast-v1\n");
+        for node in [&self.r, &self.g, &self.b] {
+            write_canonical(node, &mut canonical);
+            canonical.push('\n');
+        }
+        if let Some(a) = &self.a {
+            write_canonical(a, &mut canonical);
+            canonical.push('\n');
+        }
+        hash::to_hex(&hash::sha256(canonical.as_bytes()))
+    }
+}
+
+impl Node {
+    /// Gets the serialization of this branch which the structural hash is based on
+    pub fn to_canonical_string(&self) -> String {
+        let mut out = String::new();
+        write_canonical(self, &mut out);
+        out
+    }
+}
+
+/// Writes a node as a prefix expression, with explicit names and the exact bits of literals. The
+/// tree is walked with an explicit stack, so trees of any depth can be hashed
+fn write_canonical(node: &Node, out: &mut String) {
+    let mut stack = vec![Item::Node(node)];
+    while let Some(item) = stack.pop() {
+        let node = match item {
+            Item::Node(node) => node,
+            Item::Child(node) => {
+                out.push(' ');
+                node
+            }
+            Item::Close => {
+                out.push(')');
+                continue;
+            }
+        };
+
+        let name = match node {
+            Node::X => "x",
+            Node::Y => "y",
+            Node::T => "t",
+            Node::Rand(_) => "rand",
+            Node::Literal(value) => {
+                out.push_str(&format!("literal:{:016x}", value.to_bits()));
+                continue;
+            }
+            Node::Mult(..) => "mult",
+            Node::Add(..) => "add",
+            Node::Sub(..) => "sub",
+            Node::Div(..) => "div",
+            Node::Pow(..) => "pow",
+            Node::Sqrt(..) => "sqrt",
+            Node::Mod(..) => "mod",
+            Node::Max(..) => "max",
+            Node::Min(..) => "min",
+            Node::Sin(..) => "sin",
+            Node::Cos(..) => "cos",
+            Node::Tan(..) => "tan",
+            Node::Abs(..) => "abs",
+            Node::If(if_node) => match if_node.operator() {
+                Operator::LessThan => "if:lt",
+                Operator::GreaterThan => "if:gt",
+                Operator::Equals => "if:eq",
+                Operator::NotEquals => "if:ne",
+            },
+        };
+
+        let children = node.children();
+        if children.is_empty() {
+            out.push_str(name);
+            continue;
+        }
+
+        out.push('(');
+        out.push_str(name);
+        stack.push(Item::Close);
+        stack.extend(children.into_iter().rev().map(|x| Item::Child(x)));
+    }
+}
+
+/// An item of the stack of `write_canonical()`
+enum Item<'a> {
+    /// The root of the branch
+    Node(&'a Node),
+    /// A child, which is written after a space
+    Child(&'a Node),
+    /// The end of a node with children
+    Close,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AST: &str = "R: add(x, mult(y, 0.5))\nG: (x < y ? sin(t) : rand)\nB: sqrt(abs(-2))";
+
+    #[test]
+    fn hashes_never_change() {
+        let ast = NodeAst::parse_from_str(AST).unwrap();
+        assert_eq!(
+            ast.r.to_canonical_string(),
+            "(add x (mult y literal:3fe0000000000000))"
+        );
+        assert_eq!(ast.g.to_canonical_string(), "(if:lt x y (sin t) rand)");
+        // The SHA-256 of "kroyer-ast-v1\n", followed by the serialization of each channel on its
+        // own line
+        assert_eq!(
+            ast.structural_hash(),
+            "1ea7b0de272fce156cd12b83bbb93e21a73942dc40df3229a0f7593096039cf0"
+        );
+
+        let with_alpha = NodeAst::parse_from_str(&format!("{}\nA: x", AST)).unwrap();
+        assert_eq!(
+            with_alpha.structural_hash(),
+            "c9cb32c01862b2e52a433d6bf189c3932f6370a64f029f46788003bf7999e2a8"
+        );
+    }
+
+    #[test]
+    fn hashes_only_depend_on_the_nodes() {
+        let hash = |source: &str| NodeAst::parse_from_str(source).unwrap().structural_hash();
+        let grayscale = hash("L: add(x, 0.5)");
+        assert_eq!(grayscale, hash("RGB: add(x, 0.5)"));
+        assert_eq!(
+            grayscale,
+            hash("R: add(x, .5)\nG: add( x , 5e-1 )\n# comment\nB: add(x,0.5)")
+        );
+        assert_ne!(grayscale, hash("RGB: add(0.5, x)"));
+        assert_ne!(grayscale, hash("RGB: add(x, 0.5000001)"));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::node::Node;
+
+use super::NodeAst;
+
+/// The precedence of conditional expressions, like `x < y ? 1 : t`
+const TERNARY: u8 = 0;
+/// The precedence of the comparison in a conditional expression
+const COMPARISON: u8 = 1;
+/// The precedence of `+` and `-`
+const SUM: u8 = 2;
+/// The precedence of `*`, `/` and `%`, and of negative literals
+const PRODUCT: u8 = 3;
+/// The precedence of leaves and function calls, which never need brackets
+const PRIMARY: u8 = 4;
+
+impl NodeAst {
+    /// Formats the AST with infix operators, like `x * 0.5 + sin(y)`, using only the brackets
+    /// required by precedence. This is meant for reading, and can't be parsed back into an AST
+    pub fn to_infix(&self) -> String {
+        self.format_channels(|node| node.to_infix())
+    }
+}
+
+impl Node {
+    /// Formats the tree with infix operators. See `NodeAst::to_infix()`
+    pub fn to_infix(&self) -> String {
+        infix(self).0
+    }
+}
+
+/// Formats `node` with infix operators, and gets the precedence of the outermost operator
+fn infix(node: &Node) -> (String, u8) {
+    // Operators of the same precedence are left associative, so only the right operand needs
+    // brackets, to keep the order the tree evaluates them in
+    let binary = |lhs: &Node, operator: &str, rhs: &Node, precedence: u8| {
+        (
+            format!(
+                "{} {} {}",
+                operand(lhs, precedence),
+                operator,
+                operand(rhs, precedence + 1)
+            ),
+            precedence,
+        )
+    };
+    let call = |name: &str, args: &[&Node]| {
+        let args: Vec<String> = args.iter().map(|x| infix(x).0).collect();
+        (format!("{}({})", name, args.join(", ")), PRIMARY)
+    };
+
+    match node {
+        Node::X => ("x".to_owned(), PRIMARY),
+        Node::Y => ("y".to_owned(), PRIMARY),
+        Node::T => ("t".to_owned(), PRIMARY),
+        Node::Rand(_) => ("rand".to_owned(), PRIMARY),
+        Node::Literal(value) if value.is_sign_negative() => (value.to_string(), PRODUCT),
+        Node::Literal(value) => (value.to_string(), PRIMARY),
+        Node::Mult(lhs, rhs) => binary(lhs, "*", rhs, PRODUCT),
+        Node::Add(lhs, rhs) => binary(lhs, "+", rhs, SUM),
+        // kroyer subtracts the first operand from the second
+        Node::Sub(lhs, rhs) => binary(rhs, "-", lhs, SUM),
+        Node::Div(lhs, rhs) => binary(lhs, "/", rhs, PRODUCT),
+        Node::Mod(lhs, rhs) => binary(lhs, "%", rhs, PRODUCT),
+        Node::Pow(lhs, rhs) => call("pow", &[lhs, rhs]),
+        Node::Max(lhs, rhs) => call("max", &[lhs, rhs]),
+        Node::Min(lhs, rhs) => call("min", &[lhs, rhs]),
+        Node::Sqrt(val) => call("sqrt", &[val]),
+        Node::Sin(val) => call("sin", &[val]),
+        Node::Cos(val) => call("cos", &[val]),
+        Node::Tan(val) => call("tan", &[val]),
+        Node::Abs(val) => call("abs", &[val]),
+        Node::If(if_node) => {
+            let [lhs, rhs, on_true, on_false] = node.children()[..] else {
+                unreachable!("an if node has four children")
+            };
+            // Nested conditionals are always bracketed, since they are hard to read otherwise
+            (
+                format!(
+                    "{} {} {} ? {} : {}",
+                    operand(lhs, COMPARISON + 1),
+                    if_node.operator(),
+                    operand(rhs, COMPARISON + 1),
+                    operand(on_true, TERNARY + 1),
+                    operand(on_false, TERNARY + 1)
+                ),
+                TERNARY,
+            )
+        }
+    }
+}
+
+/// Formats an operand, which needs brackets if it binds looser than `precedence`
+fn operand(node: &Node, precedence: u8) -> String {
+    let (str, node_precedence) = infix(node);
+    if node_precedence < precedence {
+        format!("({})", str)
+    } else {
+        str
+    }
+}
@@ -1,21 +1,208 @@
+pub mod hash;
+pub mod infix;
 pub mod parse;
+pub mod pretty;
+pub mod sexpr;
 
-use crate::grammar::Grammar;
+use std::fmt::Display;
+
+use crate::{
+    grammar::Grammar,
+    rng::{self, StreamRng},
+};
 
 use super::{Node, NodePtr};
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct NodeAst {
     pub r: NodePtr,
     pub g: NodePtr,
     pub b: NodePtr,
+    /// If a single tree is used for all three channels. When this is set, `r`, `g` and `b` are
+    /// identical, and only `r` needs to be evaluated
+    pub grayscale: bool,
+    /// The tree of the alpha channel, if the AST has one. Without it, every pixel is opaque
+    pub a: Option<NodePtr>,
 }
 
 impl NodeAst {
+    /// Generates a random AST from the grammar. Each channel has a max depth of `depth`
+    #[deprecated(note = "draws from the seeded RNG, use `NodeAst::from_grammar_with()` instead")]
+    #[allow(dead_code)]
     pub fn from_grammar(grammar: &mut Grammar, depth: usize) -> Self {
+        Self::from_grammar_with(grammar, depth, None, &mut rng::Seeded)
+    }
+
+    /// Generates a random AST from the grammar, drawing from `rng`. Each channel has a max depth
+    /// of `depth`, and if `max_nodes` is set, each channel will have at most that many nodes
+    pub fn from_grammar_with(
+        grammar: &mut Grammar,
+        depth: usize,
+        max_nodes: Option<usize>,
+        rng: &mut impl StreamRng,
+    ) -> Self {
+        // The root node is always created, so it is taken out of the budget beforehand
+        let budget = max_nodes.map(|x| x.saturating_sub(1));
+        Self {
+            r: Node::gen_rand_with(grammar, depth, &mut budget.clone(), rng),
+            g: Node::gen_rand_with(grammar, depth, &mut budget.clone(), rng),
+            b: Node::gen_rand_with(grammar, depth, &mut budget.clone(), rng),
+            grayscale: false,
+            a: None,
+        }
+    }
+
+    /// Generates the tree of the alpha channel from the grammar, like the trees of the other
+    /// channels. See `NodeAst::from_grammar_with()`
+    pub fn gen_alpha(
+        &mut self,
+        grammar: &mut Grammar,
+        depth: usize,
+        max_nodes: Option<usize>,
+        rng: &mut impl StreamRng,
+    ) {
+        let mut budget = max_nodes.map(|x| x.saturating_sub(1));
+        self.a = Some(Node::gen_rand_with(grammar, depth, &mut budget, rng));
+    }
+
+    /// Generates a grayscale AST from the grammar, where a single tree is used for all three
+    /// channels. See `NodeAst::from_grammar_with()`
+    pub fn grayscale_from_grammar(
+        grammar: &mut Grammar,
+        depth: usize,
+        max_nodes: Option<usize>,
+        rng: &mut impl StreamRng,
+    ) -> Self {
+        let mut budget = max_nodes.map(|x| x.saturating_sub(1));
+        Self::grayscale(Node::gen_rand_with(grammar, depth, &mut budget, rng))
+    }
+
+    /// Creates a grayscale AST, which uses `node` for all three channels
+    pub fn grayscale(node: NodePtr) -> Self {
         Self {
-            r: Node::gen_rand(grammar, depth),
-            g: Node::gen_rand(grammar, depth),
-            b: Node::gen_rand(grammar, depth),
+            r: node.clone(),
+            g: node.clone(),
+            b: node,
+            grayscale: true,
+            a: None,
         }
     }
+
+    /// Gets the tree of a channel, where 0 is red, 1 is green, 2 is blue and 3 is alpha.
+    /// Panics if the alpha channel is requested, and the AST doesn't have one
+    pub fn channel(&self, channel: usize) -> &NodePtr {
+        match channel {
+            0 => &self.r,
+            1 => &self.g,
+            2 => &self.b,
+            _ => self.a.as_ref().expect("the AST has no alpha channel"),
+        }
+    }
+
+    /// Gets the channels with their own tree, as indices for `NodeAst::channel()`. Grayscale ASTs
+    /// only have the red channel, and the alpha channel is only included if the AST has one
+    pub fn tree_channels(&self) -> Vec<usize> {
+        let mut channels = match self.grayscale {
+            true => vec![0],
+            false => vec![0, 1, 2],
+        };
+        if self.a.is_some() {
+            channels.push(3);
+        }
+        channels
+    }
+
+    /// Makes the `g` and `b` channels copies of the `r` channel again, if this is a grayscale AST.
+    /// This should be called after modifying the `r` channel of a grayscale AST
+    pub fn sync_grayscale(&mut self) {
+        if self.grayscale {
+            self.g = self.r.clone();
+            self.b = self.r.clone();
+        }
+    }
+
+    /// Replaces every `Rand` node with a literal, via `Node::freeze_rand()`
+    pub fn freeze_rand(&mut self, rng: &mut impl StreamRng) {
+        self.r.freeze_rand(rng);
+        if self.grayscale {
+            self.sync_grayscale();
+        } else {
+            self.g.freeze_rand(rng);
+            self.b.freeze_rand(rng);
+        }
+        if let Some(a) = &mut self.a {
+            a.freeze_rand(rng);
+        }
+    }
+
+    /// Gives every `Rand` node a salt, via `Node::salt_rand()`. The salts are numbered across the
+    /// channels, so no two rand nodes give the same values, except in the identical channels of
+    /// a grayscale AST. The salts only depend on the shape of the trees, so a parsed dump gets the
+    /// same salts as the AST it was dumped from
+    pub fn salt_rand(&mut self) {
+        let mut next = 0;
+        self.r.salt_rand(&mut next);
+        if self.grayscale {
+            self.sync_grayscale();
+        } else {
+            self.g.salt_rand(&mut next);
+            self.b.salt_rand(&mut next);
+        }
+        if let Some(a) = &mut self.a {
+            a.salt_rand(&mut next);
+        }
+    }
+
+    /// Gets the amount of nodes in all channels
+    pub fn node_count(&self) -> usize {
+        let alpha = self.a.as_ref().map_or(0, |x| x.node_count());
+        self.r.node_count() + self.g.node_count() + self.b.node_count() + alpha
+    }
+}
+
+impl Display for NodeAst {
+    /// Displays the AST in the format parsed by `NodeAst::parse_from_str()`. Grayscale ASTs use a
+    /// single 'L:' header, and the alpha channel, if any, is written last, under an 'A:' header
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format_channels(|node| node.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    use super::*;
+
+    #[test]
+    fn frozen_rand_nodes_are_literals_drawn_in_pre_order() {
+        let mut ast =
+            NodeAst::parse_from_str("R: add(rand, x)\nG: rand\nB: mult(rand, rand)").unwrap();
+        ast.freeze_rand(&mut ChaCha20Rng::seed_from_u64(7));
+
+        let mut rng = ChaCha20Rng::seed_from_u64(7);
+        let mut literal = || Box::new(Node::Literal(rng.random_range(-1.0..=1.0)));
+        assert_eq!(ast.r, Box::new(Node::Add(literal(), Box::new(Node::X))));
+        assert_eq!(ast.g, literal());
+        assert_eq!(ast.b, Box::new(Node::Mult(literal(), literal())));
+
+        // The literals are dumped, so the dump renders the same values everywhere
+        let parsed = NodeAst::parse_from_str(&ast.to_string()).unwrap();
+        for (x, y) in [(-1., 0.5), (0., 0.), (0.25, -0.75)] {
+            for channel in ast.tree_channels() {
+                let expected = ast.channel(channel).get_value(x, y, 0.);
+                assert_eq!(parsed.channel(channel).get_value(x, y, 0.), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn frozen_grayscale_asts_keep_identical_channels() {
+        let mut ast = NodeAst::parse_from_str("L: add(rand, rand)").unwrap();
+        ast.freeze_rand(&mut ChaCha20Rng::seed_from_u64(7));
+        assert!(!ast.r.contains_rand());
+        assert_eq!(ast.r, ast.g);
+        assert_eq!(ast.r, ast.b);
+    }
 }
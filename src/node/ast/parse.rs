@@ -1,6 +1,9 @@
-use std::io::Write;
+use std::{collections::HashMap, fmt::Display};
 
-use crate::node::{self, IfNode, Node, NodePtr, NodeType, Operator};
+use crate::{
+    log,
+    node::{self, IfNode, Node, NodePtr, NodeType, Operator},
+};
 
 use super::NodeAst;
 
@@ -22,7 +25,7 @@ enum AstToken {
     /// B:
     /// <ast>
     /// ```
-    SectionHeader(char),
+    SectionHeader(String),
     /// A open bracket: `(`
     BracketOpen,
     /// A closed bracket: `)`
@@ -39,255 +42,645 @@ enum AstToken {
     Eof,
 }
 
+impl Display for AstToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AstToken::Ident(ident) => write!(f, "identifier \"{}\"", ident),
+            AstToken::Literal(literal) => write!(f, "literal \"{}\"", literal),
+            AstToken::SectionHeader(header) => write!(f, "section header '{}'", header),
+            AstToken::BracketOpen => write!(f, "\"(\""),
+            AstToken::BracketEnd => write!(f, "\")\""),
+            AstToken::Operator(op) => write!(f, "operator \"{}\"", op),
+            AstToken::IfThen => write!(f, "\"?\""),
+            AstToken::Else => write!(f, "\":\""),
+            AstToken::Unknown(str) => write!(f, "unknown expression \"{}\"", str),
+            AstToken::Eof => write!(f, "EOF"),
+        }
+    }
+}
+
+/// An error that occured whilst parsing an AST
+#[derive(Clone, Debug, PartialEq)]
+pub struct AstParseError {
+    /// A description of what went wrong
+    pub message: String,
+    /// The token that caused the error
+    pub found: String,
+    /// A hint about what the parser expected instead of the found token
+    pub expected: Option<String>,
+    /// The byte offset of the found token in the source
+    pub offset: usize,
+    /// The line of the found token, starting from 1
+    pub line: usize,
+    /// The column of the found token, starting from 1
+    pub column: usize,
+}
+
+impl Display for AstParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Whilst parsing AST, {} at line {}, column {}",
+            self.message, self.line, self.column
+        )?;
+        if let Some(expected) = &self.expected {
+            write!(f, "\nExpected {}, found {}", expected, self.found)?;
+        } else {
+            write!(f, "\nFound {}", self.found)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AstParseError {}
+
+impl AstParseError {
+    /// Creates an error at the byte `offset` in `source`, calculating the line and column
+    pub fn at(
+        source: &str,
+        offset: usize,
+        message: &str,
+        found: &str,
+        expected: Option<&str>,
+    ) -> Self {
+        let before = &source[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|ch| *ch != '\n').count() + 1;
+
+        Self {
+            message: message.to_owned(),
+            found: found.to_owned(),
+            expected: expected.map(str::to_owned),
+            offset,
+            line,
+            column,
+        }
+    }
+}
+
+type ParseResult<T> = Result<T, AstParseError>;
+
+/// The default max nesting of a parsed AST. Deeper ASTs risk overflowing the stack
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+/// The default max number of nodes in a parsed AST
+pub const DEFAULT_MAX_NODES: usize = 1_000_000;
+
+/// Options for parsing an AST. The limits make the parser fail, instead of running out of stack
+/// or memory on a huge or malicious file
+#[derive(Debug, Clone, Copy)]
+pub struct ParseOptions {
+    /// The max nesting of the expressions
+    pub max_depth: usize,
+    /// The max number of nodes parsed, counting each use of a `def` name as all of its nodes
+    pub max_nodes: usize,
+    /// If content after the last channel should be ignored with a warning, instead of being an
+    /// error
+    pub lenient: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_nodes: DEFAULT_MAX_NODES,
+            lenient: false,
+        }
+    }
+}
+
+/// Handles content found after every channel of an AST has been parsed, which `error` points at.
+/// In lenient mode, a warning is printed, and the content is ignored
+pub fn trailing_content(error: AstParseError, options: ParseOptions) -> ParseResult<()> {
+    if options.lenient {
+        log::warning!("{}\nIgnoring it.", error);
+        return Ok(());
+    }
+    Err(error)
+}
+
 impl AstToken {
-    pub fn to_node(&self, parser: &mut AstParser) -> Option<NodePtr> {
+    /// Checks if this token starts a new top level statement, or ends the file
+    fn is_statement_start(&self) -> bool {
+        match self {
+            AstToken::SectionHeader(_) | AstToken::Eof => true,
+            AstToken::Ident(ident) => ident.eq_ignore_ascii_case("def"),
+            _ => false,
+        }
+    }
+
+    /// Parses the expression starting with this token, including a trailing ternary if statement
+    pub fn to_node(&self, parser: &mut AstParser) -> ParseResult<NodePtr> {
+        let node = self.to_operand(parser)?;
+        parse_if_statement(node, parser)
+    }
+
+    /// Parses the expression starting with this token, without checking if it is the left hand
+    /// side of a ternary if statement
+    pub fn to_operand(&self, parser: &mut AstParser) -> ParseResult<NodePtr> {
+        // The bracket depth the expression started at, before any of its brackets
+        let start_brackets = match self {
+            AstToken::BracketOpen => parser.brackets - 1,
+            _ => parser.brackets,
+        };
+
+        parser.enter()?;
+        let node = self.to_operand_inner(parser);
+        parser.depth -= 1;
+
+        match node {
+            Err(e) if parser.recover => parser.recover_from(e, start_brackets),
+            node => node,
+        }
+    }
+
+    fn to_operand_inner(&self, parser: &mut AstParser) -> ParseResult<NodePtr> {
         match self {
             AstToken::Ident(ident) => {
                 let Ok(parent) = NodeType::try_from(ident.as_str()) else {
-                    eprintln!(
-                        "[ERROR]: Whilst parsing AST, got invalid identifier \"{}\"",
-                        ident
-                    );
-                    std::process::exit(1);
+                    return parser.use_definition(ident);
                 };
 
-                let node = node_from_token_stream(parent, parser);
-                Some(Box::new(node))
+                parser.add_nodes(1)?;
+                let node = node_without_ternary(parent, parser)?;
+                Ok(Box::new(node))
             }
-            AstToken::Literal(literal) => Some(Box::new(Node::Literal(*literal))),
-            _ => None,
+            AstToken::Literal(literal) => {
+                parser.add_nodes(1)?;
+                Ok(Box::new(Node::Literal(*literal)))
+            }
+            AstToken::BracketOpen => parse_bracketed_if(parser),
+            _ => Err(parser.error(
+                "expected expression",
+                Some("an identifier, a literal or \"(\""),
+            )),
         }
     }
 }
 
-fn parse_if_statement(lhs: NodePtr, parser: &mut AstParser) -> Option<NodePtr> {
+/// Consumes the next token, and errors if it isn't `token`
+fn expect(parser: &mut AstParser, token: AstToken, message: &str) -> ParseResult<()> {
+    if parser.next_token() != token {
+        return Err(parser.error(message, Some(&token.to_string())));
+    }
+    Ok(())
+}
+
+/// Parses a ternary if statement wrapped in brackets, like `(lhs op rhs ? on_true : on_false)`.
+/// This is the form used when displaying an if statement. Expects the opening bracket to have
+/// been consumed already
+fn parse_bracketed_if(parser: &mut AstParser) -> ParseResult<NodePtr> {
+    let lhs = parser.next_token().to_operand(parser)?;
+
+    let AstToken::Operator(operator) = parser.next_token() else {
+        return Err(parser.error("expected operator in if statement", Some("an operator")));
+    };
+
+    let node = parse_if_rest(lhs, operator, parser)?;
+
+    expect(parser, AstToken::BracketEnd, "expected end of if statement")?;
+
+    Ok(node)
+}
+
+/// Parses a ternary if statement, if the next token is an operator. Otherwise `lhs` is
+/// returned as is
+fn parse_if_statement(lhs: NodePtr, parser: &mut AstParser) -> ParseResult<NodePtr> {
     let AstToken::Operator(operator) = parser.peek() else {
-        return None;
+        return Ok(lhs);
     };
     _ = parser.next_token();
 
-    let Some(rhs) = parser.next_token().to_node(parser) else {
-        eprintln!(
-            "[ERROR]: Whilst parsing AST, expected right hand side argument for operator {}, got \"{:?}\"",
-            operator,
-            parser.get_current_token()
-        );
-        std::process::exit(1)
-    };
+    parse_if_rest(lhs, operator, parser)
+}
 
-    if parser.next_token() != AstToken::IfThen {
-        eprintln!(
-            "[ERROR]: Whilst parsing AST, expected \"?\" in if statement, got \"{:?}\"",
-            parser.get_current_token()
-        );
-        std::process::exit(1)
-    }
+/// Parses the rest of a ternary if statement, after the operator has been consumed
+fn parse_if_rest(lhs: NodePtr, operator: Operator, parser: &mut AstParser) -> ParseResult<NodePtr> {
+    // Chained if statements nest without going through `to_operand()`, so they are counted here
+    parser.enter()?;
+    parser.add_nodes(1)?;
+    let node = parse_if_rest_inner(lhs, operator, parser);
+    parser.depth -= 1;
+    node
+}
 
-    let Some(on_true) = parser.next_token().to_node(parser) else {
-        eprintln!(
-            "[ERROR]: Whilst parsing AST, expected literal or identifier in if statement, got \"{:?}\"",
-            parser.get_current_token()
-        );
-        std::process::exit(1)
-    };
+fn parse_if_rest_inner(
+    lhs: NodePtr,
+    operator: Operator,
+    parser: &mut AstParser,
+) -> ParseResult<NodePtr> {
+    let rhs = parser.next_token().to_node(parser)?;
 
-    if parser.next_token() != AstToken::Else {
-        eprintln!(
-            "[ERROR]: Whilst parsing AST, expected \":\" in if statement, got \"{:?}\"",
-            parser.get_current_token()
-        );
-        std::process::exit(1)
-    }
+    expect(parser, AstToken::IfThen, "expected \"?\" in if statement")?;
 
-    let Some(on_false) = parser.next_token().to_node(parser) else {
-        eprintln!(
-            "[ERROR]: Whilst parsing AST, expected literal or identifier as else statement, got \"{:?}\"",
-            parser.get_current_token()
-        );
-        std::process::exit(1)
-    };
+    let on_true = parser.next_token().to_node(parser)?;
+
+    expect(parser, AstToken::Else, "expected \":\" in if statement")?;
+
+    let on_false = parser.next_token().to_node(parser)?;
 
     let if_node = IfNode {
         lhs,
         rhs,
-        operator: operator.clone(),
+        operator,
         on_true,
         on_false,
     };
 
-    Some(Box::new(Node::If(if_node)))
+    Ok(Box::new(Node::If(if_node)))
+}
+
+/// Parses a node, without checking if it is the left hand side of a ternary if statement
+fn node_without_ternary(parent: NodeType, parser: &mut AstParser) -> ParseResult<Node> {
+    match parent {
+        NodeType::If => parse_if_call(parser),
+        NodeType::Literal => Err(parser.error(
+            "\"literal\" can't be used as an identifier",
+            Some("a number"),
+        )),
+        _ => parse_call(parent, parser),
+    }
+}
+
+/// Parses the function call form of an if statement, like:
+/// `if(lhs, op, rhs, on_true, on_false)`
+fn parse_if_call(parser: &mut AstParser) -> ParseResult<Node> {
+    expect(parser, AstToken::BracketOpen, "expected param list for if")?;
+
+    // The left hand side can't be parsed as a ternary, since it is followed by the operator
+    let lhs = parser.next_token().to_operand(parser)?;
+
+    let AstToken::Operator(operator) = parser.next_token() else {
+        return Err(parser.error(
+            "expected operator as second argument for if",
+            Some("an operator"),
+        ));
+    };
+
+    let rhs = parser.next_token().to_node(parser)?;
+    let on_true = parser.next_token().to_node(parser)?;
+    let on_false = parser.next_token().to_node(parser)?;
+
+    expect(parser, AstToken::BracketEnd, "if expected 5 paramaters")?;
+
+    Ok(Node::If(IfNode {
+        lhs,
+        rhs,
+        operator,
+        on_true,
+        on_false,
+    }))
 }
 
-fn node_from_token_stream(parent: NodeType, parser: &mut AstParser) -> Node {
+/// Parses a node which uses the function call form, like `add(x, y)`
+fn parse_call(parent: NodeType, parser: &mut AstParser) -> ParseResult<Node> {
     let num_args = parent.arg_num();
 
     let mut args: Vec<NodePtr> = Vec::with_capacity(num_args);
 
     if num_args >= 1 {
-        let first_tok = parser.next_token();
+        expect(
+            parser,
+            AstToken::BracketOpen,
+            &format!("expected param list for {}", parent),
+        )?;
 
-        if first_tok == AstToken::Eof {
-            eprintln!(
-                "[ERROR]: Whilst parsing AST, expected parameter list for \"{}\", got EOF",
-                parent
-            );
-        }
-
-        if !matches!(first_tok, AstToken::BracketOpen) {
-            eprintln!(
-                "[ERROR]: Whilst parsing AST, expected param list for {}, got {:?}",
-                parent, first_tok
-            );
-            std::process::exit(1);
-        }
-
-        while parser.peek() != AstToken::Eof {
+        loop {
             let tok = parser.next_token();
             match &tok {
-                AstToken::Ident(ident) => {
-                    let Ok(node_type) = NodeType::try_from(ident.as_str()) else {
-                        eprintln!(
-                            "[ERROR]: Whilst parsing AST, invalid identifier \"{}\"",
-                            ident
-                        );
-                        std::process::exit(1);
-                    };
-
-                    args.push(Box::new(node_from_token_stream(node_type, parser)));
-                }
-                AstToken::Literal(literal) => {
-                    let node = Node::Literal(*literal);
-                    args.push(Box::new(node));
-                }
-                AstToken::SectionHeader(header) => {
-                    eprintln!(
-                        "[ERROR]: Whilst parsing AST, expected parameter for {}, found section header '{}'",
-                        parent, header
-                    );
-                    std::process::exit(1);
+                AstToken::Ident(_) | AstToken::Literal(_) | AstToken::BracketOpen => {
+                    if args.len() == num_args {
+                        return Err(parser.error(
+                            &format!("{} expected {} paramaters, got more", parent, num_args),
+                            Some("\")\""),
+                        ));
+                    }
+                    args.push(tok.to_node(parser)?);
                 }
-                AstToken::BracketOpen => continue,
                 AstToken::BracketEnd => {
+                    if args.len() != num_args {
+                        return Err(parser.error(
+                            &format!(
+                                "{} expected {} paramaters, got {}",
+                                parent,
+                                num_args,
+                                args.len()
+                            ),
+                            Some("a parameter"),
+                        ));
+                    }
                     break;
                 }
-                AstToken::Operator(op) => {
-                    eprintln!(
-                        "[ERROR]: Whilst parsing AST, expected parameter for {}, found out of place operator \"{}\"",
-                        parent, op
-                    )
-                }
-                AstToken::IfThen => {
-                    eprintln!(
-                        "[ERROR]: Whilst parsing AST, expected parameter for {}, found out of place '?'",
-                        parent,
-                    );
-                    std::process::exit(1);
-                }
-                AstToken::Else => {
-                    eprintln!(
-                        "[ERROR]: Whilst parsing AST, expected parameter for {}, found out of place ':'",
-                        parent,
-                    );
-                    std::process::exit(1);
-                }
-                AstToken::Unknown(str) => {
-                    eprintln!(
-                        "[ERROR]: Whilst parsing AST, expected parameter for {}, found unknown expression \"{}\"",
-                        parent, str
-                    );
-                    std::process::exit(1);
-                }
                 AstToken::Eof => {
-                    eprintln!(
-                        "[ERROR]: Whilst parsing AST, expected parameter for {}, found EOF",
-                        parent
-                    );
-                    std::process::exit(1)
+                    return Err(parser.error(
+                        &format!("missing closing bracket for {}", parent),
+                        Some("\")\""),
+                    ));
+                }
+                _ => {
+                    return Err(parser.error(
+                        &format!("expected parameter for {}", parent),
+                        Some("an identifier, a literal or \")\""),
+                    ));
                 }
             }
         }
-        _ = std::io::stdout().flush();
-        if args.len() != num_args {
-            eprintln!(
-                "[ERROR]: Whilst parsing AST, {} expected {} paramaters, got {}: {:?}",
-                parent,
-                num_args,
-                args.len(),
-                args
-            );
-            std::process::exit(1);
-        }
-    }
-
-    let node = match parent {
-        NodeType::X => Node::X,
-        NodeType::Y => Node::Y,
-        NodeType::T => Node::T,
-        NodeType::Rand => Node::Rand,
-        // Literals should be handled in while loop, and should never be the parent
-        NodeType::Literal => unreachable!(),
-        NodeType::Mult => Node::Mult(args[0].clone(), args[1].clone()),
-        NodeType::Add => Node::Add(args[0].clone(), args[1].clone()),
-        NodeType::Sub => Node::Sub(args[0].clone(), args[1].clone()),
-        NodeType::Div => Node::Div(args[0].clone(), args[1].clone()),
-        NodeType::Pow => Node::Pow(args[0].clone(), args[1].clone()),
-        NodeType::Sqrt => Node::Sqrt(args[0].clone()),
-        NodeType::Mod => Node::Mod(args[0].clone(), args[1].clone()),
-        NodeType::Max => Node::Max(args[0].clone(), args[1].clone()),
-        NodeType::Min => Node::Min(args[0].clone(), args[1].clone()),
-        NodeType::Sin => Node::Sin(args[0].clone()),
-        NodeType::Cos => Node::Cos(args[0].clone()),
-        NodeType::Tan => Node::Tan(args[0].clone()),
-        NodeType::Abs => Node::Abs(args[0].clone()),
-        NodeType::If => todo!(),
-    };
-
-    if let Some(if_node) = parse_if_statement(Box::new(node.clone()), parser) {
-        return *if_node;
     }
 
-    node
+    // The children are moved into the node, so no subtrees are cloned. The arity has already
+    // been checked, and literals and if statements are handled by `node_without_ternary()`
+    Ok(Node::from_children(parent, args).expect("node should have the right amount of children"))
 }
 
 struct AstParser {
+    /// The index of the next charachter to lex
     index: usize,
     source: String,
+    /// The charachters of the source. These are indexed directly, since `str::chars().nth()`
+    /// would make lexing quadratic
+    chars: Vec<char>,
     current_token: Option<AstToken>,
+    /// The index of the first charachter of the current token
+    token_start: usize,
+    /// The next token, its start and the index after it, if it has already been lexed by `peek()`
+    peeked: Option<(AstToken, usize, usize)>,
+    /// How deeply nested the expression currently being parsed is
+    depth: usize,
+    /// The max nesting allowed, before parsing fails. This keeps the recursive parser from
+    /// overflowing the stack
+    max_depth: usize,
+    /// The max number of nodes allowed, before parsing fails
+    max_nodes: usize,
+    /// How many nodes have been parsed so far
+    nodes: usize,
+    /// The expressions named with `def`
+    definitions: HashMap<String, NodePtr>,
+    /// How many brackets are currently open
+    brackets: usize,
+    /// If the parser should try to continue after an error in an expression
+    recover: bool,
+    /// The errors which the parser has recovered from
+    errors: Vec<AstParseError>,
 }
 
 impl AstParser {
-    pub fn new(source: &str) -> Self {
+    pub fn new(source: &str, options: ParseOptions) -> Self {
         Self {
             index: 0,
             source: source.to_owned(),
+            chars: source.chars().collect(),
             current_token: None,
+            token_start: 0,
+            peeked: None,
+            depth: 0,
+            max_depth: options.max_depth,
+            max_nodes: options.max_nodes,
+            nodes: 0,
+            definitions: HashMap::new(),
+            brackets: 0,
+            recover: false,
+            errors: vec![],
         }
     }
 
+    /// Records an error in an expression which started at a bracket depth of `start_brackets`,
+    /// and skips to the end of the expression, returning a placeholder for it.
+    /// If the error was caused by the start of a new statement, the error is returned instead,
+    /// since the expression can't continue past it
+    fn recover_from(
+        &mut self,
+        error: AstParseError,
+        start_brackets: usize,
+    ) -> ParseResult<NodePtr> {
+        if self
+            .current_token
+            .as_ref()
+            .is_some_and(AstToken::is_statement_start)
+        {
+            return Err(error);
+        }
+        self.errors.push(error);
+
+        // An invalid identifier may still be followed by its parameters
+        if matches!(self.current_token, Some(AstToken::Ident(_)))
+            && self.brackets == start_brackets
+            && self.peek() == AstToken::BracketOpen
+        {
+            _ = self.next_token();
+        }
+
+        while self.brackets > start_brackets && !self.peek().is_statement_start() {
+            _ = self.next_token();
+        }
+
+        Ok(Box::new(Node::Literal(0.0)))
+    }
+
+    /// Parses a definition like `def name = <expr>`, after `def` has been consumed
+    fn parse_definition(&mut self) -> ParseResult<()> {
+        let AstToken::Ident(name) = self.next_token() else {
+            return Err(self.error("expected name after \"def\"", Some("a name")));
+        };
+
+        // Header names are reserved, since a defined name followed by ':' isn't a header
+        if NodeType::try_from(name.as_str()).is_ok()
+            || name.eq_ignore_ascii_case("def")
+            || header_channels(&name).is_some()
+        {
+            return Err(self.error(
+                &format!("\"{}\" is reserved, and can't be used as a name", name),
+                Some("a name"),
+            ));
+        }
+
+        if self.definitions.contains_key(&name) {
+            return Err(self.error(&format!("\"{}\" is already defined", name), None));
+        }
+
+        if self.next_token() != AstToken::Unknown("=".to_owned()) {
+            return Err(self.error(
+                &format!("expected \"=\" after \"def {}\"", name),
+                Some("\"=\""),
+            ));
+        }
+
+        let node = self.next_token().to_node(self)?;
+        self.definitions.insert(name, node);
+        Ok(())
+    }
+
+    /// Gets a copy of the expression defined as `name`, after `name` has been consumed
+    fn use_definition(&mut self, name: &str) -> ParseResult<NodePtr> {
+        let Some(node) = self.definitions.get(name) else {
+            let names = NodeType::names().chain(self.definitions.keys().cloned());
+            return Err(self.error(
+                &invalid_identifier_message(name, names),
+                Some("a node, or a name defined with \"def\""),
+            ));
+        };
+
+        // The copy counts towards the nesting of the expression it is used in
+        if self.depth + node.depth() > self.max_depth + 1 {
+            return Err(self.error(
+                &depth_limit_message(self.depth + node.depth() - 1, self.max_depth),
+                None,
+            ));
+        }
+
+        let node = node.clone();
+        self.add_nodes(node.node_count())?;
+        Ok(node)
+    }
+
+    /// Counts `count` more parsed nodes, failing if there are more than `max_nodes` in total
+    fn add_nodes(&mut self, count: usize) -> ParseResult<()> {
+        self.nodes += count;
+        if self.nodes > self.max_nodes {
+            // Every node after this one would fail as well, so there is nothing to recover to
+            self.recover = false;
+            return Err(self.error(&node_limit_message(self.nodes, self.max_nodes), None));
+        }
+        Ok(())
+    }
+
+    /// Enters a nested expression, failing if it is nested deeper than `max_depth`
+    fn enter(&mut self) -> ParseResult<()> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            return Err(self.error(&depth_limit_message(self.depth, self.max_depth), None));
+        }
+        Ok(())
+    }
+
     pub fn get_current_token(&self) -> Option<AstToken> {
         self.current_token.clone()
     }
 
+    /// Creates an error at the position of the current token
+    pub fn error(&self, message: &str, expected: Option<&str>) -> AstParseError {
+        let offset = self.chars[..self.token_start.min(self.chars.len())]
+            .iter()
+            .map(|ch| ch.len_utf8())
+            .sum();
+
+        let found = self
+            .get_current_token()
+            .map(|tok| tok.to_string())
+            .unwrap_or("nothing".to_owned());
+
+        AstParseError::at(&self.source, offset, message, &found, expected)
+    }
+
+    /// Skips tokens until the next token is a section header, a `def` statement, or the end of
+    /// the file
+    pub fn skip_to_statement(&mut self) {
+        while !self.peek().is_statement_start() {
+            _ = self.next_token();
+        }
+    }
+
     pub fn peek(&mut self) -> AstToken {
+        if let Some((token, _, _)) = &self.peeked {
+            return token.clone();
+        }
+
         let idx = self.index;
+        let token_start = self.token_start;
         let token = self.next_token_inner();
+        self.peeked = Some((token.clone(), self.token_start, self.index));
         self.index = idx;
+        self.token_start = token_start;
         token
     }
 
     pub fn next_token(&mut self) -> AstToken {
-        let token = self.next_token_inner();
+        let token = match self.peeked.take() {
+            Some((token, token_start, index)) => {
+                self.token_start = token_start;
+                self.index = index;
+                token
+            }
+            None => self.next_token_inner(),
+        };
+        match token {
+            AstToken::BracketOpen => self.brackets += 1,
+            AstToken::BracketEnd => self.brackets = self.brackets.saturating_sub(1),
+            _ => {}
+        }
         self.current_token = Some(token.clone());
         token
     }
 
+    /// Gets the charachter at the current index
+    fn curr_char(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    /// Lexes a number literal starting at the current index, like `3`, `-0.5`, `.25` or `1.2e-7`.
+    /// Nothing is consumed if there is no number, or if it isn't followed by the end of a token
+    fn lex_number(&mut self) -> Option<f64> {
+        let is_digit = |i: usize| self.chars.get(i).is_some_and(char::is_ascii_digit);
+        let is_any =
+            |i: usize, chars: &str| self.chars.get(i).is_some_and(|ch| chars.contains(*ch));
+
+        let mut end = self.index;
+        if is_any(end, "+-") {
+            end += 1;
+        }
+
+        let mantissa_start = end;
+        while is_digit(end) {
+            end += 1;
+        }
+        let has_integer = end > mantissa_start;
+        if is_any(end, ".") {
+            end += 1;
+        }
+        let fraction_start = end;
+        while is_digit(end) {
+            end += 1;
+        }
+        if !has_integer && end == fraction_start {
+            return None;
+        }
+
+        if is_any(end, "eE") {
+            let mut exponent_end = end + 1;
+            if is_any(exponent_end, "+-") {
+                exponent_end += 1;
+            }
+            if is_digit(exponent_end) {
+                while is_digit(exponent_end) {
+                    exponent_end += 1;
+                }
+                end = exponent_end;
+            }
+        }
+
+        if self
+            .chars
+            .get(end)
+            .is_some_and(|ch| !ends_token(*ch) && !is_operator_char(*ch))
+        {
+            return None;
+        }
+
+        let num = self.chars[self.index..end]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()?;
+        self.index = end;
+        Some(num)
+    }
+
     fn next_token_inner(&mut self) -> AstToken {
-        while let Some(ch) = self.source.chars().nth(self.index) {
+        while let Some(ch) = self.curr_char() {
             if ch.is_whitespace() || ",".contains(ch) {
                 self.index += 1;
             } else if ch == '#' {
                 // Comment
-                while let Some(ch) = self.source.chars().nth(self.index) {
+                while let Some(ch) = self.curr_char() {
                     if ch == '\n' {
                         break;
                     }
@@ -298,24 +691,44 @@ impl AstParser {
             }
         }
 
-        if let Some(ch) = self.source.chars().nth(self.index) {
-            if ch == '(' {
-                self.index += 1;
-                return AstToken::BracketOpen;
-            } else if ch == ')' {
-                self.index += 1;
-                return AstToken::BracketEnd;
+        self.token_start = self.index;
+
+        if let Some(ch) = self.curr_char() {
+            match ch {
+                '(' => {
+                    self.index += 1;
+                    return AstToken::BracketOpen;
+                }
+                ')' => {
+                    self.index += 1;
+                    return AstToken::BracketEnd;
+                }
+                '?' => {
+                    self.index += 1;
+                    return AstToken::IfThen;
+                }
+                ':' => {
+                    self.index += 1;
+                    return AstToken::Else;
+                }
+                _ => {}
             }
         } else {
             // EOF has been reached
             return AstToken::Eof;
         }
 
+        if let Some(num) = self.lex_number() {
+            return AstToken::Literal(num);
+        }
+
         let mut buf = String::new();
 
-        while let Some(ch) = self.source.chars().nth(self.index) {
-            // Token terminating charachters
-            if "(),".contains(ch) || ch.is_whitespace() {
+        // Operators are lexed on their own, so they don't need to be surrounded by whitespace
+        let lexing_operator = self.curr_char().is_some_and(is_operator_char);
+
+        while let Some(ch) = self.curr_char() {
+            if ends_token(ch) || is_operator_char(ch) != lexing_operator {
                 break;
             }
 
@@ -323,10 +736,19 @@ impl AstParser {
             self.index += 1;
         }
 
-        if buf.len() > 1 && buf.ends_with(':') {
-            return AstToken::SectionHeader(buf.chars().next().unwrap());
+        // A section header is a name directly followed by ':', which isn't a node or a defined
+        // name, since those can also be directly followed by the ':' of an if statement
+        if self.curr_char() == Some(':')
+            && !buf.is_empty()
+            && buf.chars().all(char::is_alphabetic)
+            && NodeType::try_from(buf.as_str()).is_err()
+            && !self.definitions.contains_key(&buf)
+        {
+            self.index += 1;
+            return AstToken::SectionHeader(buf);
         }
 
+        // Non finite literals, like `inf` and `NaN`, are words, so they aren't lexed as numbers
         if let Ok(num) = buf.parse::<f64>() {
             return AstToken::Literal(num);
         }
@@ -335,117 +757,499 @@ impl AstParser {
             return AstToken::Operator(op);
         }
 
-        if buf == "?" {
-            return AstToken::IfThen;
+        if buf.chars().all(|ch| ch.is_alphanumeric() || ch == '_') {
+            return AstToken::Ident(buf);
         }
 
-        if buf == ":" {
-            return AstToken::Else;
+        AstToken::Unknown(buf)
+    }
+}
+
+/// Creates the message for an unknown identifier, suggesting the closest of `names`
+pub fn invalid_identifier_message(ident: &str, names: impl IntoIterator<Item = String>) -> String {
+    match node::closest_name(ident, names) {
+        Some(name) => format!("got invalid identifier, did you mean \"{}\"?", name),
+        None => "got invalid identifier".to_owned(),
+    }
+}
+
+/// Creates the message for an AST nested deeper than `max_depth`, where `depth` is how deep it
+/// was nested when parsing stopped
+pub fn depth_limit_message(depth: usize, max_depth: usize) -> String {
+    format!(
+        "the AST is nested at least {} deep, which is more than the limit of {} (see --max-ast-depth)",
+        depth, max_depth
+    )
+}
+
+/// Creates the message for an AST with more than `max_nodes` nodes, where `nodes` is how many
+/// had been parsed when parsing stopped
+pub fn node_limit_message(nodes: usize, max_nodes: usize) -> String {
+    format!(
+        "the AST has at least {} nodes, which is more than the limit of {} (see --max-ast-nodes)",
+        nodes, max_nodes
+    )
+}
+
+/// Checks if `ch` is one of the charachters used in comparison operators
+fn is_operator_char(ch: char) -> bool {
+    "<>=!".contains(ch)
+}
+
+/// Checks if `ch` ends the token before it
+fn ends_token(ch: char) -> bool {
+    "(),?:".contains(ch) || ch.is_whitespace()
+}
+
+/// Gets the indices of the channels a section header refers to
+fn header_channels(header: &str) -> Option<Vec<usize>> {
+    match header.to_lowercase().as_str() {
+        "r" => Some(vec![0]),
+        "g" => Some(vec![1]),
+        "b" => Some(vec![2]),
+        "rgb" | "l" => Some(vec![0, 1, 2]),
+        "a" => Some(vec![3]),
+        _ => None,
+    }
+}
+
+const CHANNEL_NAMES: [char; 3] = ['r', 'g', 'b'];
+
+/// The state of the top level of an AST file, which consists of section headers and the
+/// expressions under them
+#[derive(Default)]
+struct Sections {
+    /// The trees of the red, green, blue and alpha channels
+    channels: [Option<NodePtr>; 4],
+    /// The channels the next expression will be used for
+    curr_channels: Vec<usize>,
+    curr_header: Option<String>,
+    grayscale: bool,
+    /// The channels whose expressions failed to parse. These aren't reported as missing
+    failed_channels: Vec<usize>,
+}
+
+impl Sections {
+    /// Checks if every channel has been set, so `tok` would be content after the end of the AST.
+    /// The alpha channel is optional, so if it isn't set, its section may still follow
+    fn is_complete(&self, tok: &AstToken) -> bool {
+        let is_alpha_header =
+            matches!(tok, AstToken::SectionHeader(header) if header.eq_ignore_ascii_case("a"));
+        self.curr_channels.is_empty()
+            && self.channels[..3].iter().all(Option::is_some)
+            && (self.channels[3].is_some() || !is_alpha_header)
+    }
+
+    /// Uses `node` for the current channels, only cloning it if there is more than one
+    fn set_current_channels(&mut self, node: NodePtr) {
+        let Some(last) = self.curr_channels.pop() else {
+            return;
+        };
+        for channel in self.curr_channels.drain(..) {
+            self.channels[channel] = Some(node.clone());
         }
+        self.channels[last] = Some(node);
+    }
 
-        if buf.chars().all(char::is_alphanumeric) {
-            return AstToken::Ident(buf);
+    /// Parses a single top level token, and the expression it starts, if any
+    fn parse_token(&mut self, tok: AstToken, parser: &mut AstParser) -> ParseResult<()> {
+        match tok {
+            AstToken::Ident(ident) if ident.eq_ignore_ascii_case("def") => {
+                parser.parse_definition()?;
+            }
+            AstToken::Ident(_) | AstToken::Literal(_) | AstToken::BracketOpen => {
+                // These tokens always start an expression
+                if self.curr_channels.is_empty() {
+                    let Some(header) = &self.curr_header else {
+                        return Err(parser.error(
+                            "got expression outside header segment",
+                            Some("a section header, like 'R:'"),
+                        ));
+                    };
+                    return Err(parser.error(
+                        &format!("got more than one expression for the {} value", header),
+                        Some("a section header"),
+                    ));
+                }
+
+                let node = tok.to_node(parser)?;
+                self.set_current_channels(node);
+            }
+            AstToken::SectionHeader(header) => {
+                let Some(header_channels) = header_channels(&header) else {
+                    return Err(parser.error(
+                        "invalid header. Headers can only be 'r', 'g', 'b', 'rgb', 'l' or 'a'",
+                        None,
+                    ));
+                };
+
+                if header_channels
+                    .iter()
+                    .any(|x| self.channels[*x].is_some() || self.failed_channels.contains(x))
+                {
+                    return Err(parser.error("encountered duplicate header", None));
+                }
+
+                self.curr_channels = header_channels;
+                self.curr_header = Some(header.to_lowercase());
+                self.grayscale |= header.eq_ignore_ascii_case("l");
+
+                if parser.peek() == AstToken::Unknown("=".to_owned()) {
+                    _ = parser.next_token();
+                    self.parse_channel_alias(parser)?;
+                }
+            }
+            AstToken::Unknown(_) => {
+                return Err(parser.error("found unknown identifier", None));
+            }
+            _ => {
+                return Err(parser.error(
+                    "found out of place token",
+                    Some("a section header or an expression"),
+                ));
+            }
         }
+        Ok(())
+    }
 
-        AstToken::Unknown(buf)
+    /// Parses the channel name after the `=` of an alias like `G: = R`, and copies the tree of
+    /// that channel into the current channels
+    fn parse_channel_alias(&mut self, parser: &mut AstParser) -> ParseResult<()> {
+        let AstToken::Ident(name) = parser.next_token() else {
+            return Err(parser.error("expected channel name after \"=\"", Some("'r', 'g' or 'b'")));
+        };
+
+        let Some(source) = CHANNEL_NAMES
+            .iter()
+            .position(|x| name.eq_ignore_ascii_case(&x.to_string()))
+        else {
+            return Err(parser.error("invalid channel name after \"=\"", Some("'r', 'g' or 'b'")));
+        };
+
+        let Some(node) = self.channels[source].clone() else {
+            return Err(parser.error(
+                &format!(
+                    "the {} value is used before it has been defined",
+                    CHANNEL_NAMES[source]
+                ),
+                Some("a channel which is defined above this one"),
+            ));
+        };
+
+        self.set_current_channels(node);
+
+        Ok(())
     }
 }
 
 impl NodeAst {
-    pub fn parse_from_str(str: &str) -> Self {
-        let mut parser = AstParser::new(str);
+    /// Parses an AST from a string, in the format outputted by `--dump-ast`.
+    /// Besides the 'R:', 'G:' and 'B:' headers, an 'RGB:' header can be used to give all three
+    /// channels the same expression, and a channel can be an exact copy of an earlier channel by
+    /// writing the header like `G: = R`.
+    /// A grayscale AST uses a single 'L:' header instead.
+    /// An optional 'A:' section gives the tree of the alpha channel.
+    /// Outside of an expression, a statement like `def radius = sqrt(add(mult(x, x), mult(y, y)))`
+    /// names an expression, and `radius` can then be used as a node in the expressions below it
+    pub fn parse_from_str(str: &str) -> Result<Self, AstParseError> {
+        Self::parse_from_str_with_options(str, ParseOptions::default())
+    }
 
-        let mut curr_header = ' ';
+    /// Parses an AST like `NodeAst::parse_from_str()`, but fails if it is nested deeper, or has
+    /// more nodes, than `options` allow
+    pub fn parse_from_str_with_options(
+        str: &str,
+        options: ParseOptions,
+    ) -> Result<Self, AstParseError> {
+        Self::parse_inner(str, options, false, None).map_err(|mut errors| errors.remove(0))
+    }
 
-        let mut r_ast: Option<NodePtr> = None;
-        let mut g_ast: Option<NodePtr> = None;
-        let mut b_ast: Option<NodePtr> = None;
+    /// Parses an AST like `NodeAst::parse_from_str_with_options()`, but instead of stopping at
+    /// the first error, it skips to the end of the invalid call, or the next section header or
+    /// `def` statement, and continues, so every error that can be found is returned
+    pub fn parse_all_errors(str: &str, options: ParseOptions) -> Result<Self, Vec<AstParseError>> {
+        Self::parse_inner(str, options, true, None)
+    }
 
-        let mut set_ast = |node: NodePtr, header: char| {
-            match header {
-                'r' => r_ast = Some(node),
-                'g' => g_ast = Some(node),
-                'b' => b_ast = Some(node),
-                _ => {
-                    eprintln!("[ERROR]: Whilst parsing AST, got expression outside header segment");
-                    std::process::exit(1)
-                }
-            };
-        };
+    /// Parses an AST like `NodeAst::parse_from_str_with_options()`, or like
+    /// `NodeAst::parse_all_errors()` if `all_errors` is set, but channels without a section are
+    /// created by `fill`, instead of being an error
+    pub fn parse_with_fill(
+        str: &str,
+        options: ParseOptions,
+        all_errors: bool,
+        fill: &mut dyn FnMut() -> NodePtr,
+    ) -> Result<Self, Vec<AstParseError>> {
+        Self::parse_inner(str, options, all_errors, Some(fill)).map_err(|mut errors| {
+            if !all_errors {
+                errors.truncate(1);
+            }
+            errors
+        })
+    }
 
-        while parser.peek() != AstToken::Eof {
-            let tok = parser.next_token();
-            match tok {
-                AstToken::Ident(ident) => {
-                    let Ok(parent) = NodeType::try_from(ident.as_str()) else {
-                        eprintln!(
-                            "[ERROR]: Whilst parsing AST, got invalid identifier \"{}\"",
-                            ident
-                        );
-                        std::process::exit(1);
-                    };
+    fn parse_inner(
+        str: &str,
+        options: ParseOptions,
+        recover: bool,
+        mut fill: Option<&mut dyn FnMut() -> NodePtr>,
+    ) -> Result<Self, Vec<AstParseError>> {
+        let mut parser = AstParser::new(str, options);
+        parser.recover = recover;
+        let mut sections = Sections::default();
+        let mut errors = vec![];
+        // The start of a statement which ended an invalid expression, and still has to be parsed
+        let mut pending_statement: Option<AstToken> = None;
 
-                    let node = Box::new(node_from_token_stream(parent, &mut parser));
+        loop {
+            let tok = pending_statement
+                .take()
+                .unwrap_or_else(|| parser.next_token());
+            if tok == AstToken::Eof {
+                break;
+            }
 
-                    set_ast(node, curr_header);
+            if sections.is_complete(&tok) {
+                let error = parser.error(
+                    "found content after the end of the AST",
+                    Some("the end of the file"),
+                );
+                if let Err(e) = trailing_content(error, options) {
+                    errors.push(e);
                 }
-                AstToken::Literal(literal) => {
-                    let node = Box::new(Node::Literal(literal));
-                    set_ast(node, curr_header)
+                break;
+            }
+
+            let is_statement = tok.is_statement_start();
+            let result = sections.parse_token(tok, &mut parser);
+            errors.append(&mut parser.errors);
+
+            if let Err(e) = result {
+                errors.push(e);
+                if !parser.recover {
+                    return Err(errors);
                 }
-                AstToken::SectionHeader(header) => {
-                    let lower_header = header.to_lowercase().next().unwrap();
-
-                    if curr_header == lower_header {
-                        eprintln!(
-                            "[ERROR]: Whilst parsing AST, encountered duplicate header '{}'",
-                            header
-                        );
-                        std::process::exit(1);
-                    }
 
-                    if !"rgb".contains(lower_header) {
-                        eprintln!(
-                            "[ERROR]: Whilst parsing AST, invalid header '{}'. Headers can only be 'r', 'g', or 'b'",
-                            header
-                        )
+                let failed = std::mem::take(&mut sections.curr_channels);
+                sections.failed_channels.extend(failed);
+
+                match parser.get_current_token() {
+                    Some(tok) if tok.is_statement_start() && !is_statement => {
+                        pending_statement = Some(tok)
                     }
+                    _ => parser.skip_to_statement(),
+                }
+            }
+        }
 
-                    curr_header = lower_header;
+        for (i, name) in CHANNEL_NAMES.iter().enumerate() {
+            if sections.channels[i].is_none() && !sections.failed_channels.contains(&i) {
+                if let Some(fill) = fill.as_mut() {
+                    sections.channels[i] = Some(fill());
+                    continue;
                 }
-                AstToken::BracketOpen => todo!(),
-                AstToken::BracketEnd => todo!(),
-                AstToken::Operator(_) => todo!(),
-                AstToken::IfThen => todo!(),
-                AstToken::Else => todo!(),
-                AstToken::Unknown(ident) => {
-                    eprintln!(
-                        "[ERROR]: Whilst parsing AST, found unknown identifier \"{}\"",
-                        ident
-                    );
-                    std::process::exit(1)
+                errors.push(parser.error(
+                    &format!("no AST for the {} value was supplied", name),
+                    Some(&format!("the '{}:' section", name.to_ascii_uppercase())),
+                ));
+                if !recover {
+                    return Err(errors);
                 }
-                AstToken::Eof => todo!(),
             }
         }
 
-        if r_ast.is_none() {
-            eprintln!("[ERROR]: Whilst parsing AST, no AST for the r value was supplied");
-            std::process::exit(1)
+        if !errors.is_empty() {
+            return Err(errors);
         }
-        if g_ast.is_none() {
-            eprintln!("[ERROR]: Whilst parsing AST, no AST for the g value was supplied");
-            std::process::exit(1)
+
+        let [r, g, b, a] = sections.channels;
+        Ok(Self {
+            r: r.unwrap(),
+            g: g.unwrap(),
+            b: b.unwrap(),
+            grayscale: sections.grayscale,
+            a,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    use crate::{grammar::Grammar, node::NodeType};
+
+    use super::*;
+
+    /// Checks if two values are the same, where NaN equals NaN
+    fn same_value(lhs: f64, rhs: f64) -> bool {
+        lhs.to_bits() == rhs.to_bits() || (lhs.is_nan() && rhs.is_nan())
+    }
+
+    #[test]
+    fn dumped_asts_render_the_same_after_parsing() {
+        // Every node type is used, so if statements with every operator are dumped
+        let mut grammar = Grammar::new(NodeType::as_list().map(|x| (x, 1)).to_vec());
+        for seed in 0..200 {
+            let mut rng = ChaCha20Rng::seed_from_u64(seed);
+            let mut ast = NodeAst::from_grammar_with(&mut grammar, 6, None, &mut rng);
+            ast.salt_rand();
+
+            let dump = ast.to_string();
+            let mut parsed = NodeAst::parse_from_str(&dump)
+                .unwrap_or_else(|e| panic!("seed {} failed to parse: {}\n{}", seed, e, dump));
+            parsed.salt_rand();
+
+            for (x, y) in (0..16).flat_map(|x| (0..16).map(move |y| (x, y))) {
+                let (x, y) = (x as f64 / 7.5 - 1., y as f64 / 7.5 - 1.);
+                for channel in ast.tree_channels() {
+                    let expected = ast.channel(channel).get_value(x, y, 0.5);
+                    let value = parsed.channel(channel).get_value(x, y, 0.5);
+                    assert!(
+                        same_value(expected, value),
+                        "seed {} gave {} instead of {} at ({}, {})\n{}",
+                        seed,
+                        value,
+                        expected,
+                        x,
+                        y,
+                        dump
+                    );
+                }
+            }
         }
-        if b_ast.is_none() {
-            eprintln!("[ERROR]: Whilst parsing AST, no AST for the b value was supplied");
-            std::process::exit(1)
+    }
+
+    #[test]
+    fn errors_point_at_the_line_and_column_of_the_token() {
+        let error = NodeAst::parse_from_str("R: x\nG: y\nB:   sim(x)").unwrap_err();
+        assert_eq!((error.line, error.column, error.offset), (3, 6, 15));
+        assert!(
+            error.to_string().contains("at line 3, column 6"),
+            "{}",
+            error
+        );
+
+        // Columns count charachters, so they aren't thrown off by multi-byte ones
+        let error = NodeAst::parse_from_str("# ø\nRGB: (ø)").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 7));
+
+        let error = NodeAst::parse_from_str("RGB: sin(").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 10));
+    }
+
+    /// Lexes every token of `source`, until the end of it
+    fn lex(source: &str) -> Vec<AstToken> {
+        let mut parser = AstParser::new(source, ParseOptions::default());
+        std::iter::from_fn(|| Some(parser.next_token()))
+            .take_while(|x| *x != AstToken::Eof)
+            .collect()
+    }
+
+    #[test]
+    fn signed_fractional_and_exponent_literals_are_lexed() {
+        let cases = [
+            ("3", 3.),
+            ("-0.5", -0.5),
+            ("+2", 2.),
+            (".25", 0.25),
+            ("-.25", -0.25),
+            ("5.", 5.),
+            ("1e3", 1000.),
+            ("1.2e-7", 1.2e-7),
+            ("-3E+2", -300.),
+        ];
+        for (source, value) in cases {
+            assert_eq!(lex(source), [AstToken::Literal(value)], "{}", source);
         }
 
-        Self {
-            r: r_ast.unwrap(),
-            g: g_ast.unwrap(),
-            b: b_ast.unwrap(),
+        // Signs directly after an operator belong to the literal, and a dangling exponent isn't
+        // part of a number
+        assert!(matches!(
+            lex("x<-1")[..],
+            [
+                AstToken::Ident(_),
+                AstToken::Operator(_),
+                AstToken::Literal(-1.)
+            ]
+        ));
+        assert!(!matches!(lex("1e")[..], [AstToken::Literal(_)]));
+    }
+
+    #[test]
+    fn displayed_literals_are_lexed_into_the_same_value() {
+        let mut rng = ChaCha20Rng::seed_from_u64(606);
+        for _ in 0..10_000 {
+            let value = f64::from_bits(rng.random());
+            let source = Node::Literal(value).to_string();
+            match &lex(&source)[..] {
+                [AstToken::Literal(lexed)] => assert!(
+                    same_value(*lexed, value),
+                    "{} was lexed into {}",
+                    source,
+                    lexed
+                ),
+                tokens => panic!("{} was lexed into {:?}", source, tokens),
+            }
+        }
+    }
+
+    #[test]
+    fn ternaries_parse_without_spaces() {
+        let spaced = NodeAst::parse_from_str("RGB: ( x < y ? 1 : -1 )").unwrap();
+        let packed = NodeAst::parse_from_str("RGB: (x<y?1:-1)").unwrap();
+        assert_eq!(spaced, packed);
+        let nested = NodeAst::parse_from_str("RGB: sin((x==y?t:(x>0?1:2)))").unwrap();
+        assert_eq!(nested.r.get_value(0.5, 0.5, 0.25), 0.25f64.sin());
+        assert_eq!(nested.r.get_value(0.5, 0., 0.25), 1f64.sin());
+    }
+
+    #[test]
+    fn defined_names_can_be_the_true_branch_of_a_ternary() {
+        let ast = NodeAst::parse_from_str("def radius = 0.5\nRGB: (x<y?radius:y)").unwrap();
+        assert_eq!(ast.r.get_value(0., 1., 0.), 0.5);
+        assert_eq!(ast.r.get_value(1., -0.25, 0.), -0.25);
+
+        let error = NodeAst::parse_from_str("def rgb = x\nRGB: rgb").unwrap_err();
+        assert!(error.to_string().contains("reserved"), "{}", error);
+    }
+
+    #[test]
+    fn large_asts_are_lexed_in_linear_time() {
+        // A balanced tree of 2^16 literals, whose dump is about 1.5 MB. Lexing that in quadratic
+        // time takes hours, so this only passes if it is linear
+        let mut source = "-0.123456789e-3".to_owned();
+        for _ in 0..16 {
+            source = format!("add({0}, {0})", source);
         }
+        let source = format!("RGB: {}", source);
+        assert!(source.len() > 1_000_000);
+
+        let start = std::time::Instant::now();
+        let ast = NodeAst::parse_from_str(&source).unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(ast.r.node_count(), (1 << 17) - 1);
+        assert!(elapsed.as_secs() < 5, "parsing took {:?}", elapsed);
+    }
+
+    #[test]
+    fn very_deep_trees_fail_cleanly() {
+        // ASTs are parsed on the main thread, whose stack is 8 MB, instead of the 2 MB of test
+        // threads. The nesting limit stops the parser long before the chain could overflow it
+        let parse = std::thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(|| {
+                let depth = 100_000;
+                let source = format!("RGB: {}x{}", "sin(".repeat(depth), ")".repeat(depth));
+                NodeAst::parse_from_str(&source).map(|_| ())
+            })
+            .unwrap();
+        let error = parse.join().unwrap().unwrap_err();
+        assert!(
+            error.to_string().contains(&DEFAULT_MAX_DEPTH.to_string()),
+            "{}",
+            error
+        );
     }
 }
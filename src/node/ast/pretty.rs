@@ -0,0 +1,130 @@
+use crate::node::Node;
+
+use super::{NodeAst, sexpr::SExpr};
+
+/// The default max width of the lines in a pretty printed AST
+pub const DEFAULT_PRETTY_WIDTH: usize = 80;
+
+/// The amount of spaces each nesting level is indented by
+const INDENT: usize = 4;
+
+impl NodeAst {
+    /// Formats the AST like `Display`, but breaks up expressions which don't fit within `width`
+    /// charachters, with one argument per line
+    pub fn to_pretty(&self, width: usize) -> String {
+        self.format_channels(|node| {
+            let mut out = String::new();
+            write_pretty(node, 0, 0, width, &mut out);
+            out
+        })
+    }
+
+    /// Formats the AST like `NodeAst::to_sexpr()`, but breaks up expressions which don't fit
+    /// within `width` charachters, with one argument per line
+    pub fn to_pretty_sexpr(&self, width: usize) -> String {
+        self.format_channels(|node| {
+            let mut out = String::new();
+            write_pretty_sexpr(node, 0, width, &mut out);
+            out
+        })
+    }
+
+    pub(super) fn format_channels(&self, format: impl Fn(&Node) -> String) -> String {
+        let mut out = if self.grayscale {
+            format!("L:\n{}", format(&self.r))
+        } else {
+            format!(
+                "R:\n{}\nG:\n{}\nB:\n{}",
+                format(&self.r),
+                format(&self.g),
+                format(&self.b)
+            )
+        };
+        if let Some(a) = &self.a {
+            out.push_str(&format!("\nA:\n{}", format(a)));
+        }
+        out
+    }
+}
+
+/// Writes `node` in the default format, starting at `column` of a line indented by `indent`
+/// spaces
+fn write_pretty(node: &Node, indent: usize, column: usize, width: usize, out: &mut String) {
+    let flat = node.to_string();
+    if column + flat.len() <= width {
+        out.push_str(&flat);
+        return;
+    }
+
+    let inner = indent + INDENT;
+    let new_line = |out: &mut String, indent: usize| {
+        out.push('\n');
+        out.push_str(&" ".repeat(indent));
+    };
+
+    match node {
+        Node::If(if_node) => {
+            out.push('(');
+            new_line(out, inner);
+            write_pretty(&if_node.lhs, inner, inner, width, out);
+            for (prefix, child) in [
+                (if_node.operator.to_string(), &if_node.rhs),
+                ("?".to_owned(), &if_node.on_true),
+                (":".to_owned(), &if_node.on_false),
+            ] {
+                new_line(out, inner);
+                out.push_str(&prefix);
+                out.push(' ');
+                write_pretty(child, inner, inner + prefix.len() + 1, width, out);
+            }
+            new_line(out, indent);
+            out.push(')');
+        }
+        _ => {
+            out.push_str(&format!("{}(", node.node_type()));
+            let children = node.children();
+            for (i, child) in children.iter().enumerate() {
+                new_line(out, inner);
+                write_pretty(child, inner, inner, width, out);
+                if i + 1 < children.len() {
+                    out.push(',');
+                }
+            }
+            new_line(out, indent);
+            out.push(')');
+        }
+    }
+}
+
+/// Writes `node` as an s-expression, starting at a line indented by `indent` spaces
+fn write_pretty_sexpr(node: &Node, indent: usize, width: usize, out: &mut String) {
+    let flat = SExpr(node).to_string();
+    if indent + flat.len() <= width {
+        out.push_str(&flat);
+        return;
+    }
+
+    let inner = indent + INDENT;
+    let new_line = |out: &mut String| {
+        out.push('\n');
+        out.push_str(&" ".repeat(inner));
+    };
+
+    out.push_str(&format!("({}", node.node_type()));
+    if let Node::If(if_node) = node {
+        new_line(out);
+        write_pretty_sexpr(&if_node.lhs, inner, width, out);
+        new_line(out);
+        out.push_str(&if_node.operator.to_string());
+        for child in [&if_node.rhs, &if_node.on_true, &if_node.on_false] {
+            new_line(out);
+            write_pretty_sexpr(child, inner, width, out);
+        }
+    } else {
+        for child in node.children() {
+            new_line(out);
+            write_pretty_sexpr(child, inner, width, out);
+        }
+    }
+    out.push(')');
+}
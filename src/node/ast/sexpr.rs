@@ -0,0 +1,420 @@
+use std::fmt::Display;
+
+use crate::node::{IfNode, Node, NodePtr, NodeType, Operator};
+
+use super::{
+    NodeAst,
+    parse::{
+        AstParseError, ParseOptions, depth_limit_message, invalid_identifier_message,
+        node_limit_message, trailing_content,
+    },
+};
+
+/// Wraps a node, so it is displayed as an s-expression, like `(add (sin x) (mult y 0.3))`
+pub struct SExpr<'a>(pub &'a Node);
+
+impl Display for SExpr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Node::X | Node::Y | Node::T | Node::Rand(_) => write!(f, "{}", self.0.node_type()),
+            Node::Literal(float) => write!(f, "{}", float),
+            Node::If(if_node) => write!(
+                f,
+                "(if {} {} {} {} {})",
+                SExpr(&if_node.lhs),
+                if_node.operator,
+                SExpr(&if_node.rhs),
+                SExpr(&if_node.on_true),
+                SExpr(&if_node.on_false)
+            ),
+            node => {
+                write!(f, "({}", node.node_type())?;
+                for child in node.children() {
+                    write!(f, " {}", SExpr(child))?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl NodeAst {
+    /// Formats the AST with s-expressions, using the same section headers as the default format
+    pub fn to_sexpr(&self) -> String {
+        self.format_channels(|node| SExpr(node).to_string())
+    }
+
+    /// Parses an AST written with s-expressions, like:
+    /// ```
+    /// R: (add (sin x) (mult y 0.3))
+    /// G: (if x < y 1 (abs t))
+    /// B: 0.5
+    /// ```
+    /// The same headers as the default format can be used, so an 'RGB:' section gives all three
+    /// channels the same expression, and a grayscale AST uses a single 'L:' section.
+    /// An optional 'A:' section gives the tree of the alpha channel
+    pub fn parse_from_sexpr(str: &str) -> Result<Self, AstParseError> {
+        Self::parse_from_sexpr_with_options(str, ParseOptions::default())
+    }
+
+    /// Parses an AST like `NodeAst::parse_from_sexpr()`, but fails if it is nested deeper, or has
+    /// more nodes, than `options` allow
+    pub fn parse_from_sexpr_with_options(
+        str: &str,
+        options: ParseOptions,
+    ) -> Result<Self, AstParseError> {
+        Self::parse_sexpr_inner(str, options, None)
+    }
+
+    /// Parses an AST like `NodeAst::parse_from_sexpr_with_options()`, but channels without a
+    /// section are created by `fill`, instead of being an error
+    pub fn parse_from_sexpr_with_fill(
+        str: &str,
+        options: ParseOptions,
+        fill: &mut dyn FnMut() -> NodePtr,
+    ) -> Result<Self, AstParseError> {
+        Self::parse_sexpr_inner(str, options, Some(fill))
+    }
+
+    fn parse_sexpr_inner(
+        str: &str,
+        options: ParseOptions,
+        mut fill: Option<&mut dyn FnMut() -> NodePtr>,
+    ) -> Result<Self, AstParseError> {
+        let mut reader = SExprReader::new(str, options);
+
+        let mut curr_header = String::new();
+
+        let mut r_ast: Option<NodePtr> = None;
+        let mut g_ast: Option<NodePtr> = None;
+        let mut b_ast: Option<NodePtr> = None;
+        let mut a_ast: Option<NodePtr> = None;
+        let mut grayscale = false;
+
+        while let Some(tok) = reader.peek() {
+            // The alpha channel is optional, so its section may still follow the other channels
+            let in_alpha_section = tok.eq_ignore_ascii_case("a:") || curr_header == "a";
+            if r_ast.is_some()
+                && (grayscale || (g_ast.is_some() && b_ast.is_some()))
+                && (a_ast.is_some() || !in_alpha_section)
+            {
+                reader.next();
+                trailing_content(reader.trailing_error(), options)?;
+                break;
+            }
+
+            if let Some(header) = tok.strip_suffix(':') {
+                reader.next();
+
+                let lower_header = header.to_lowercase();
+                let already_set = match lower_header.as_str() {
+                    "r" => r_ast.is_some(),
+                    "g" => g_ast.is_some() || grayscale,
+                    "b" => b_ast.is_some() || grayscale,
+                    "rgb" | "l" => r_ast.is_some() || g_ast.is_some() || b_ast.is_some(),
+                    "a" => a_ast.is_some(),
+                    _ => {
+                        return Err(reader.error(
+                            "invalid header. Headers can only be 'r', 'g', 'b', 'rgb', 'l' or 'a'",
+                            None,
+                        ));
+                    }
+                };
+
+                if already_set {
+                    return Err(reader.error("encountered duplicate header", None));
+                }
+
+                grayscale |= lower_header == "l";
+                curr_header = lower_header;
+                continue;
+            }
+
+            // An 'RGB:' section gives all three channels the same expression
+            let slots = match curr_header.as_str() {
+                "r" | "l" => vec![&mut r_ast],
+                "g" => vec![&mut g_ast],
+                "b" => vec![&mut b_ast],
+                "rgb" => vec![&mut r_ast, &mut g_ast, &mut b_ast],
+                "a" => vec![&mut a_ast],
+                _ => {
+                    reader.next();
+                    return Err(reader.error(
+                        "got expression outside header segment",
+                        Some("a section header, like 'R:'"),
+                    ));
+                }
+            };
+
+            if slots[0].is_some() {
+                reader.next();
+                return Err(reader.error(
+                    &format!("got more than one expression for the {} value", curr_header),
+                    Some("a section header"),
+                ));
+            }
+
+            let node = reader.read_expr()?;
+            for slot in slots {
+                *slot = Some(node.clone());
+            }
+        }
+
+        if grayscale && let Some(r) = r_ast {
+            return Ok(Self {
+                a: a_ast,
+                ..Self::grayscale(r)
+            });
+        }
+
+        for (name, ast) in [('r', &mut r_ast), ('g', &mut g_ast), ('b', &mut b_ast)] {
+            if ast.is_none() {
+                if let Some(fill) = fill.as_mut() {
+                    *ast = Some(fill());
+                    continue;
+                }
+                reader.next();
+                return Err(reader.error(
+                    &format!("no AST for the {} value was supplied", name),
+                    Some(&format!("the '{}:' section", name.to_ascii_uppercase())),
+                ));
+            }
+        }
+
+        Ok(Self {
+            r: r_ast.unwrap(),
+            g: g_ast.unwrap(),
+            b: b_ast.unwrap(),
+            grayscale: false,
+            a: a_ast,
+        })
+    }
+}
+
+/// A small reader for s-expressions. Its tokens are brackets, and atoms separated by whitespace
+struct SExprReader<'a> {
+    source: &'a str,
+    /// The tokens, and their byte offsets in the source
+    tokens: Vec<(usize, &'a str)>,
+    index: usize,
+    /// How deeply nested the expression currently being read is
+    depth: usize,
+    /// The max nesting allowed, before reading fails
+    max_depth: usize,
+    /// How many nodes have been read so far
+    nodes: usize,
+    /// The max number of nodes allowed, before reading fails
+    max_nodes: usize,
+}
+
+impl<'a> SExprReader<'a> {
+    fn new(source: &'a str, options: ParseOptions) -> Self {
+        let mut tokens = vec![];
+        let mut atom_start: Option<usize> = None;
+        let mut in_comment = false;
+
+        for (i, ch) in source.char_indices() {
+            let is_delimiter = in_comment || ch.is_whitespace() || "()#".contains(ch);
+            if is_delimiter {
+                if let Some(start) = atom_start.take() {
+                    tokens.push((start, &source[start..i]));
+                }
+            } else if atom_start.is_none() {
+                atom_start = Some(i);
+            }
+
+            if in_comment {
+                in_comment = ch != '\n';
+            } else if ch == '#' {
+                in_comment = true;
+            } else if ch == '(' || ch == ')' {
+                tokens.push((i, &source[i..i + 1]));
+            }
+        }
+        if let Some(start) = atom_start {
+            tokens.push((start, &source[start..]));
+        }
+
+        Self {
+            source,
+            tokens,
+            index: 0,
+            depth: 0,
+            max_depth: options.max_depth,
+            nodes: 0,
+            max_nodes: options.max_nodes,
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.index).map(|x| x.1)
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let tok = self.peek();
+        self.index += 1;
+        tok
+    }
+
+    /// Creates an error at the position of the last read token
+    fn error(&self, message: &str, expected: Option<&str>) -> AstParseError {
+        let (offset, found) = match self.tokens.get(self.index.saturating_sub(1)) {
+            Some((offset, tok)) => (*offset, format!("\"{}\"", tok)),
+            _ => (self.source.len(), "EOF".to_owned()),
+        };
+        AstParseError::at(self.source, offset, message, &found, expected)
+    }
+
+    /// Creates an error for content after the end of the AST, at the last read token
+    fn trailing_error(&self) -> AstParseError {
+        self.error(
+            "found content after the end of the AST",
+            Some("the end of the file"),
+        )
+    }
+
+    fn read_expr(&mut self) -> Result<NodePtr, AstParseError> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            self.next();
+            return Err(self.error(&depth_limit_message(self.depth, self.max_depth), None));
+        }
+        self.nodes += 1;
+        if self.nodes > self.max_nodes {
+            self.next();
+            return Err(self.error(&node_limit_message(self.nodes, self.max_nodes), None));
+        }
+        let node = self.read_expr_inner();
+        self.depth -= 1;
+        node
+    }
+
+    fn read_expr_inner(&mut self) -> Result<NodePtr, AstParseError> {
+        let Some(tok) = self.next() else {
+            return Err(self.error("expected expression", Some("an atom or \"(\"")));
+        };
+
+        if tok == "(" {
+            return self.read_list();
+        }
+
+        if let Ok(num) = tok.parse::<f64>() {
+            return Ok(Box::new(Node::Literal(num)));
+        }
+
+        let Ok(node_type) = NodeType::try_from(tok) else {
+            return Err(self.error(&invalid_identifier_message(tok, NodeType::names()), None));
+        };
+
+        if node_type.arg_num() != 0 || node_type == NodeType::Literal {
+            return Err(self.error(
+                &format!("{} has to be called with arguments", node_type),
+                Some(&format!("\"({} ...)\"", node_type)),
+            ));
+        }
+
+        Ok(Box::new(Node::from_children(node_type, vec![]).unwrap()))
+    }
+
+    /// Reads a list, after the opening bracket has been read
+    fn read_list(&mut self) -> Result<NodePtr, AstParseError> {
+        let Some(head) = self.next() else {
+            return Err(self.error("expected identifier after \"(\"", Some("an identifier")));
+        };
+
+        let Ok(node_type) = NodeType::try_from(head) else {
+            return Err(self.error(&invalid_identifier_message(head, NodeType::names()), None));
+        };
+
+        let node = if node_type == NodeType::If {
+            let lhs = self.read_expr()?;
+            let Some(Ok(operator)) = self.next().map(Operator::try_from) else {
+                return Err(self.error(
+                    "expected operator as second argument for if",
+                    Some("an operator"),
+                ));
+            };
+            Node::If(IfNode {
+                lhs,
+                operator,
+                rhs: self.read_expr()?,
+                on_true: self.read_expr()?,
+                on_false: self.read_expr()?,
+            })
+        } else {
+            let mut args = vec![];
+            while self.peek().is_some_and(|tok| tok != ")") {
+                args.push(self.read_expr()?);
+            }
+
+            let arg_count = args.len();
+            let Some(node) = Node::from_children(node_type, args) else {
+                self.next();
+                return Err(self.error(
+                    &format!(
+                        "{} expected {} paramaters, got {}",
+                        node_type,
+                        node_type.arg_num(),
+                        arg_count
+                    ),
+                    None,
+                ));
+            };
+            node
+        };
+
+        if self.next() != Some(")") {
+            return Err(self.error(
+                &format!("missing closing bracket for {}", node_type),
+                Some("\")\""),
+            ));
+        }
+
+        Ok(Box::new(node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    use super::*;
+    use crate::grammar::Grammar;
+
+    #[test]
+    fn rgb_sections_give_every_channel_the_expression() {
+        let ast = NodeAst::parse_from_sexpr("RGB: (add x (sin y))\nA: 0.5").unwrap();
+        assert_eq!(
+            ast,
+            NodeAst::parse_from_str("RGB: add(x, sin(y))\nA: 0.5").unwrap()
+        );
+        assert!(!ast.grayscale);
+        assert_eq!(NodeAst::parse_from_sexpr(&ast.to_sexpr()).unwrap(), ast);
+
+        for source in ["RGB: x\nG: y", "R: x\nRGB: y", "L: x\nRGB: y", "RGB: x y"] {
+            assert!(NodeAst::parse_from_sexpr(source).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn written_asts_are_read_into_the_same_ast() {
+        let mut grammar = Grammar::new(NodeType::as_list().map(|x| (x, 1)).to_vec());
+        for seed in 0..20 {
+            let mut rng = ChaCha20Rng::seed_from_u64(seed);
+            let mut ast = match seed % 3 {
+                0 => NodeAst::grayscale_from_grammar(&mut grammar, 6, None, &mut rng),
+                _ => NodeAst::from_grammar_with(&mut grammar, 6, None, &mut rng),
+            };
+            if seed % 2 == 0 {
+                ast.gen_alpha(&mut grammar, 4, None, &mut rng);
+            }
+
+            let written = ast.to_sexpr();
+            let read = NodeAst::parse_from_sexpr(&written).unwrap();
+            assert_eq!(read, ast, "{}", written);
+            assert_eq!(read.grayscale, ast.grayscale, "{}", written);
+            assert_eq!(read.to_sexpr(), written);
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use std::cell::RefCell;
+
+use rand::Rng;
+
+use crate::rng;
+
+use super::{Node, Operator, ast::NodeAst, operands};
+
+/// An instruction of a `Program`. Instructions pop their operands from the value stack, and push
+/// their result onto it
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op {
+    X,
+    Y,
+    T,
+    /// A rand node with the given salt
+    Rand(u32),
+    /// The literal at the given index of the literal table
+    Literal(usize),
+    Mult,
+    Add,
+    Sub,
+    /// Divides the second operand by the first, since the divisor is evaluated first
+    Div,
+    Pow,
+    Sqrt,
+    Mod,
+    Max,
+    Min,
+    Sin,
+    Cos,
+    Tan,
+    Abs,
+    /// Pops the two operands of a condition, and jumps to the instruction at the index if it is
+    /// false
+    JumpUnless(Operator, usize),
+    /// Jumps to the instruction at the index
+    Jump(usize),
+}
+
+thread_local! {
+    /// The value stack programs are evaluated with. It is kept between evaluations, so
+    /// evaluating a pixel doesn't allocate
+    static STACK: RefCell<Vec<f64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A tree flattened into a list of instructions, which is evaluated with a value stack. This is
+/// faster than `Node::get_value()` for big trees, since the tree is only walked once.
+/// `Node::get_value()` is kept as the reference its results have to match
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    ops: Vec<Op>,
+    literals: Vec<f64>,
+    /// The most values the stack holds at once
+    stack_size: usize,
+}
+
+impl Program {
+    /// Compiles the tree into a program
+    pub fn compile(node: &Node) -> Self {
+        let mut program = Self {
+            ops: vec![],
+            literals: vec![],
+            stack_size: 0,
+        };
+        program.push(node);
+        program.stack_size = program.max_stack_size();
+        program
+    }
+
+    /// Appends the instructions of a branch. The operands are pushed in the order
+    /// `Node::get_value()` evaluates them in, so legacy rand nodes draw from the RNG in the same
+    /// order. The branch is walked with an explicit stack, so trees of any depth can be compiled
+    fn push(&mut self, node: &Node) {
+        let mut tasks = vec![Task::Visit(node)];
+        // The indices of the jumps of the if statements being compiled, whose targets are set
+        // once they are known
+        let mut jumps: Vec<usize> = vec![];
+
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(node) => self.visit(node, &mut tasks),
+                Task::Emit(op) => self.ops.push(op),
+                Task::Condition(operator) => {
+                    jumps.push(self.ops.len());
+                    self.ops.push(Op::JumpUnless(operator, 0));
+                }
+                Task::Else => {
+                    let condition = jumps.pop().unwrap();
+                    jumps.push(self.ops.len());
+                    self.ops.push(Op::Jump(0));
+                    let len = self.ops.len();
+                    if let Op::JumpUnless(_, target) = &mut self.ops[condition] {
+                        *target = len;
+                    }
+                }
+                Task::EndIf => {
+                    let jump = jumps.pop().unwrap();
+                    self.ops[jump] = Op::Jump(self.ops.len());
+                }
+            }
+        }
+    }
+
+    /// Appends the instruction of a node without operands, or schedules the tasks of a node
+    /// with them. Tasks are popped from the end, so they are pushed in reverse
+    fn visit<'a>(&mut self, node: &'a Node, tasks: &mut Vec<Task<'a>>) {
+        let op = match node {
+            Node::X => Op::X,
+            Node::Y => Op::Y,
+            Node::T => Op::T,
+            Node::Rand(salt) => Op::Rand(*salt),
+            Node::Literal(value) => {
+                self.literals.push(*value);
+                Op::Literal(self.literals.len() - 1)
+            }
+            Node::If(if_node) => {
+                tasks.extend([
+                    Task::EndIf,
+                    Task::Visit(&if_node.on_false),
+                    Task::Else,
+                    Task::Visit(&if_node.on_true),
+                    Task::Condition(if_node.operator.clone()),
+                    Task::Visit(&if_node.rhs),
+                    Task::Visit(&if_node.lhs),
+                ]);
+                return;
+            }
+            node => {
+                tasks.push(Task::Emit(operator_op(node)));
+                tasks.extend(operands(node).into_iter().rev().map(Task::Visit));
+                return;
+            }
+        };
+        self.ops.push(op);
+    }
+
+    /// Gets the most values the stack holds at once. Both branches of a condition are counted
+    /// as if they were run, which can only overestimate it
+    fn max_stack_size(&self) -> usize {
+        let (mut size, mut max) = (0usize, 0);
+        for op in &self.ops {
+            size = match op {
+                Op::X | Op::Y | Op::T | Op::Rand(_) | Op::Literal(_) => size + 1,
+                Op::Sqrt | Op::Sin | Op::Cos | Op::Tan | Op::Abs | Op::Jump(_) => size,
+                Op::JumpUnless(..) => size - 2,
+                _ => size - 1,
+            };
+            max = max.max(size);
+        }
+        max
+    }
+
+    /// Evaluates the program at the given coordinates and time, like `Node::get_value()`
+    pub fn eval(&self, x: f64, y: f64, t: f64) -> f64 {
+        STACK.with_borrow_mut(|stack| self.eval_on(stack, x, y, t))
+    }
+
+    /// Evaluates the program like `Program::eval()`, on the given value stack
+    fn eval_on(&self, stack: &mut Vec<f64>, x: f64, y: f64, t: f64) -> f64 {
+        stack.clear();
+        stack.reserve(self.stack_size);
+        let mut pc = 0;
+        while let Some(op) = self.ops.get(pc) {
+            pc += 1;
+            let value = match op {
+                Op::X => x,
+                Op::Y => y,
+                Op::T => t,
+                // This locks the shared RNG for every draw, but ASTs drawing from it are never rendered in
+                // parallel, since the image would depend on the order of the pixels. See `img::uses_rand()`
+                Op::Rand(_) if rng::legacy_rand() => {
+                    rng::get_rng(rng::Stream::Runtime).random_range(-1.0..=1.0)
+                }
+                Op::Rand(salt) => rng::noise(x, y, *salt),
+                Op::Literal(index) => self.literals[*index],
+                Op::Sqrt => stack.pop().unwrap().sqrt(),
+                Op::Sin => stack.pop().unwrap().sin(),
+                Op::Cos => stack.pop().unwrap().cos(),
+                Op::Tan => stack.pop().unwrap().tan(),
+                Op::Abs => stack.pop().unwrap().abs(),
+                Op::JumpUnless(operator, target) => {
+                    let [lhs, rhs] = pop_two(stack);
+                    if !operator.eval(lhs, rhs) {
+                        pc = *target;
+                    }
+                    continue;
+                }
+                Op::Jump(target) => {
+                    pc = *target;
+                    continue;
+                }
+                Op::Mult => {
+                    let [first, second] = pop_two(stack);
+                    first * second
+                }
+                Op::Add => {
+                    let [first, second] = pop_two(stack);
+                    first + second
+                }
+                Op::Sub => {
+                    let [first, second] = pop_two(stack);
+                    first - second
+                }
+                Op::Div => {
+                    let [first, second] = pop_two(stack);
+                    second / if first != 0. { first } else { f64::EPSILON }
+                }
+                Op::Pow => {
+                    let [first, second] = pop_two(stack);
+                    first.powf(second)
+                }
+                Op::Mod => {
+                    let [first, second] = pop_two(stack);
+                    first % second
+                }
+                Op::Max => {
+                    let [first, second] = pop_two(stack);
+                    first.max(second)
+                }
+                Op::Min => {
+                    let [first, second] = pop_two(stack);
+                    first.min(second)
+                }
+            };
+            stack.push(value);
+        }
+        stack.pop().unwrap()
+    }
+}
+
+/// A step of compiling a tree in `Program::push()`
+enum Task<'a> {
+    /// Appends the instructions of the node
+    Visit(&'a Node),
+    /// Appends the instruction
+    Emit(Op),
+    /// Appends the jump past the branch of an if statement which is true, once its condition is
+    /// evaluated
+    Condition(Operator),
+    /// Appends the jump past the branch which is false, after the branch which is true
+    Else,
+    /// Sets the target of the jump past the branch which is false
+    EndIf,
+}
+
+/// Gets the instruction of a node with operands
+fn operator_op(node: &Node) -> Op {
+    match node {
+        Node::Mult(..) => Op::Mult,
+        Node::Add(..) => Op::Add,
+        Node::Sub(..) => Op::Sub,
+        Node::Div(..) => Op::Div,
+        Node::Pow(..) => Op::Pow,
+        Node::Sqrt(_) => Op::Sqrt,
+        Node::Mod(..) => Op::Mod,
+        Node::Max(..) => Op::Max,
+        Node::Min(..) => Op::Min,
+        Node::Sin(_) => Op::Sin,
+        Node::Cos(_) => Op::Cos,
+        Node::Tan(_) => Op::Tan,
+        Node::Abs(_) => Op::Abs,
+        _ => unreachable!("only nodes with operands have an operator"),
+    }
+}
+
+/// Pops the two operands of a binary instruction, in the order they were pushed
+fn pop_two(stack: &mut Vec<f64>) -> [f64; 2] {
+    let second = stack.pop().unwrap();
+    let first = stack.pop().unwrap();
+    [first, second]
+}
+
+/// The channels of an AST, compiled into programs
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompiledAst {
+    r: Program,
+    /// The green and blue channels, unless the AST is grayscale
+    gb: Option<(Program, Program)>,
+    a: Option<Program>,
+}
+
+impl CompiledAst {
+    /// Gets the red, green, blue and alpha values at the given coordinates and time. The alpha
+    /// is 1 if the AST has no alpha channel
+    pub fn get_values(&self, x: f64, y: f64, t: f64) -> [f64; 4] {
+        STACK.with_borrow_mut(|stack| {
+            let r = self.r.eval_on(stack, x, y, t);
+            let (g, b) = match &self.gb {
+                Some((g, b)) => (g.eval_on(stack, x, y, t), b.eval_on(stack, x, y, t)),
+                None => (r, r),
+            };
+            let a = self.a.as_ref().map_or(1., |a| a.eval_on(stack, x, y, t));
+            [r, g, b, a]
+        })
+    }
+}
+
+impl NodeAst {
+    /// Compiles the channels of the AST into programs. See `Program`
+    pub fn compile(&self) -> CompiledAst {
+        CompiledAst {
+            r: Program::compile(&self.r),
+            gb: (!self.grayscale).then(|| (Program::compile(&self.g), Program::compile(&self.b))),
+            a: self.a.as_deref().map(Program::compile),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    use crate::{grammar::Grammar, node::NodeType};
+
+    use super::*;
+
+    /// Checks if two values are the same, where NaN equals NaN
+    fn same_value(lhs: f64, rhs: f64) -> bool {
+        lhs.to_bits() == rhs.to_bits() || (lhs.is_nan() && rhs.is_nan())
+    }
+
+    #[test]
+    fn programs_match_the_interpreter() {
+        // Every node type is used, so if statements with every operator are compiled
+        let mut grammar = Grammar::new(NodeType::as_list().map(|x| (x, 1)).to_vec());
+        let mut coordinates = ChaCha20Rng::seed_from_u64(0);
+        for seed in 0..500 {
+            let mut rng = ChaCha20Rng::seed_from_u64(seed);
+            let depth = 1 + seed as usize % 10;
+            let mut ast = NodeAst::from_grammar_with(&mut grammar, depth, None, &mut rng);
+            ast.salt_rand();
+            let compiled = ast.compile();
+
+            let edges = [(0., 0., 0.), (-1., 1., 0.), (1., -1., 1.)];
+            let random = (0..64).map(|_| {
+                (
+                    coordinates.random_range(-1.0..=1.0),
+                    coordinates.random_range(-1.0..=1.0),
+                    coordinates.random_range(0.0..=6.3),
+                )
+            });
+            for (x, y, t) in edges.into_iter().chain(random) {
+                let values = compiled.get_values(x, y, t);
+                for (channel, value) in values.into_iter().take(3).enumerate() {
+                    let expected = ast.channel(channel).get_value(x, y, t);
+                    assert!(
+                        same_value(value, expected),
+                        "seed {} gave {} instead of {} at ({}, {}, {}) in\n{}",
+                        seed,
+                        value,
+                        expected,
+                        x,
+                        y,
+                        t,
+                        ast
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn operands_are_used_in_the_right_order() {
+        let eval = |source: &str| {
+            let ast = NodeAst::parse_from_str(&format!("RGB: {}", source)).unwrap();
+            Program::compile(&ast.r).eval(0.25, 0.75, 2.)
+        };
+        // The operands of add and sub are evaluated in reverse, which makes sub(a, b) be b - a
+        assert_eq!(eval("sub(x, y)"), 0.75 - 0.25);
+        assert_eq!(eval("add(x, t)"), 2.25);
+        assert_eq!(eval("div(x, y)"), 0.25 / 0.75);
+        assert_eq!(eval("div(x, 0)"), 0.25 / f64::EPSILON);
+        assert_eq!(eval("pow(y, t)"), 0.75f64.powf(2.));
+        assert_eq!(eval("mod(t, y)"), 2. % 0.75);
+        assert_eq!(eval("(x < y ? sub(t, x) : y)"), 0.25 - 2.);
+        assert_eq!(eval("(x > y ? x : (x == x ? t : y))"), 2.);
+    }
+
+    #[test]
+    fn very_deep_trees_are_used_without_recursion() {
+        // Deep enough to overflow the stack of a test thread, if any step recursed per level. The
+        // grammar only picks sin, until the depth runs out and a rand node ends the chain
+        let depth = 100_000;
+        let mut grammar = Grammar::new(vec![(NodeType::Sin, 1), (NodeType::Rand, 0)]);
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        let mut ast = NodeAst::from_grammar_with(&mut grammar, depth, None, &mut rng);
+        ast.salt_rand();
+        assert_eq!(ast.r.depth(), depth + 1);
+        assert_eq!(ast.node_count(), 3 * (depth + 1));
+        let text = format!("{}RAND{}", "sin(".repeat(depth), ")".repeat(depth));
+        assert!(ast.r.to_string() == text);
+        assert_eq!(ast.clone(), ast);
+
+        let expected = (0..depth).fold(rng::noise(0.5, 0., 0), |value, _| value.sin());
+        assert_eq!(ast.r.get_value(0.5, 0., 0.), expected);
+        assert_eq!(Program::compile(&ast.r).eval(0.5, 0., 0.), expected);
+        let img = crate::img::get_img(&crate::img::RenderSettings::plain(2, 2), 0., &ast);
+        assert_eq!(img.dimensions(), (2, 2));
+
+        let mut frozen = ast.clone();
+        frozen.freeze_rand(&mut rng);
+        assert_ne!(frozen, ast);
+        // Both trees are dropped here, which also mustn't recurse
+    }
+}
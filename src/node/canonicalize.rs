@@ -0,0 +1,106 @@
+use std::cmp::Ordering;
+
+use super::{Node, ast::NodeAst};
+
+impl Node {
+    /// Orders the operands of commutative nodes (`add`, `mult`, `max` and `min`) by their
+    /// canonical serialization, so equivalent trees are written the same way.
+    /// Branches containing `Rand` are left as they are, since reordering them would change the
+    /// order the random values are picked in, and with that the image
+    pub fn canonicalize(&mut self) {
+        for child in self.children_mut() {
+            child.canonicalize();
+        }
+
+        let (lhs, rhs) = match self {
+            Node::Add(lhs, rhs)
+            | Node::Mult(lhs, rhs)
+            | Node::Max(lhs, rhs)
+            | Node::Min(lhs, rhs) => (lhs, rhs),
+            _ => return,
+        };
+
+        if lhs.contains_rand() || rhs.contains_rand() {
+            return;
+        }
+
+        if lhs.to_canonical_string().cmp(&rhs.to_canonical_string()) == Ordering::Greater {
+            std::mem::swap(lhs, rhs);
+        }
+    }
+
+    /// Checks if this branch contains a `Rand` node
+    pub fn contains_rand(&self) -> bool {
+        let mut found = false;
+        self.visit(&mut |node| found |= matches!(node, Node::Rand(_)));
+        found
+    }
+}
+
+impl NodeAst {
+    /// Canonicalizes the trees of all channels, via `Node::canonicalize()`
+    pub fn canonicalize(&mut self) {
+        self.r.canonicalize();
+        self.g.canonicalize();
+        self.b.canonicalize();
+        if let Some(a) = &mut self.a {
+            a.canonicalize();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    use crate::{grammar::Grammar, node::NodeType};
+
+    use super::*;
+
+    #[test]
+    fn canonicalizing_is_idempotent_and_keeps_the_values() {
+        let mut grammar = Grammar::new(NodeType::as_list().map(|x| (x, 1)).to_vec());
+        for seed in 0..200 {
+            let mut rng = ChaCha20Rng::seed_from_u64(seed);
+            let ast = NodeAst::from_grammar_with(&mut grammar, 6, None, &mut rng);
+            let mut once = ast.clone();
+            once.canonicalize();
+            let mut twice = once.clone();
+            twice.canonicalize();
+            assert_eq!(once.to_string(), twice.to_string(), "seed {}", seed);
+
+            for (x, y) in [(-1., 0.5), (0., 0.), (0.25, -0.75), (0.9, 0.3)] {
+                for channel in ast.tree_channels() {
+                    let expected = ast.channel(channel).get_value(x, y, 0.5);
+                    let value = once.channel(channel).get_value(x, y, 0.5);
+                    assert!(
+                        value.to_bits() == expected.to_bits()
+                            || value.is_nan() && expected.is_nan(),
+                        "seed {} gave {} instead of {}",
+                        seed,
+                        value,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn only_commutative_operands_are_ordered() {
+        let canonical = |source: &str| {
+            let mut ast = NodeAst::parse_from_str(&format!("RGB: {}", source)).unwrap();
+            ast.canonicalize();
+            ast.r.to_string()
+        };
+        assert_eq!(canonical("add(y, x)"), canonical("add(x, y)"));
+        assert_eq!(
+            canonical("max(mult(y, x), t)"),
+            canonical("max(t, mult(x, y))")
+        );
+        assert_eq!(canonical("sub(y, x)"), "sub(y, x)");
+        assert_eq!(canonical("pow(y, x)"), "pow(y, x)");
+        assert_eq!(canonical("add(x, rand)"), "add(x, RAND)");
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt::Display;
+
+use super::{Node, Operator, ast::NodeAst};
+
+/// An if statement whose condition always has the same result, so one of its branches can never
+/// be used
+#[derive(Clone, Debug)]
+pub struct DeadBranch {
+    /// The channel of the if statement, as an index for `NodeAst::channel()`
+    pub channel: usize,
+    /// The indices of the children to follow from the root of the channel to the if statement,
+    /// as given by `Node::children()`
+    pub path: Vec<usize>,
+    /// The result the condition always has
+    pub condition: bool,
+    /// The amount of nodes in the branch which is never used
+    pub dead_nodes: usize,
+}
+
+impl Node {
+    /// Gets the result the condition of this node always has, if it is an if statement with a
+    /// static condition. This is the case when both operands fold into constants, or when they
+    /// are identical and don't use `Rand`. Identical operands are never less or greater than each
+    /// other, but are only equal if they can't be NaN, as given by `Node::can_be_nan()`
+    pub fn static_condition(&self) -> Option<bool> {
+        let Node::If(if_node) = self else {
+            return None;
+        };
+
+        let lhs = if_node.lhs.clone().simplify();
+        let rhs = if_node.rhs.clone().simplify();
+        if let (Node::Literal(lhs), Node::Literal(rhs)) = (&lhs, &rhs) {
+            return Some(if_node.operator.eval(*lhs, *rhs));
+        }
+
+        if !lhs.contains_rand() && lhs.to_canonical_string() == rhs.to_canonical_string() {
+            return match if_node.operator {
+                Operator::LessThan | Operator::GreaterThan => Some(false),
+                // `!=` compares with `==` as well, see `Operator::eval()`
+                Operator::Equals | Operator::NotEquals => (!lhs.can_be_nan()).then_some(true),
+            };
+        }
+
+        None
+    }
+
+    /// Checks if this branch can give NaN. Only the nodes which give NaN for some finite values,
+    /// and literals which aren't finite, are looked for. Values which overflow into infinities
+    /// are ignored
+    pub fn can_be_nan(&self) -> bool {
+        let mut found = false;
+        self.visit(&mut |node| {
+            found |= match node {
+                Node::Literal(value) => !value.is_finite(),
+                Node::Sqrt(_)
+                | Node::Mod(..)
+                | Node::Pow(..)
+                | Node::Div(..)
+                | Node::Tan(_)
+                | Node::Rand(_) => true,
+                _ => false,
+            }
+        });
+        found
+    }
+
+    fn find_dead_branches(&self, channel: usize, path: &mut Vec<usize>, out: &mut Vec<DeadBranch>) {
+        if let (Some(condition), Node::If(if_node)) = (self.static_condition(), self) {
+            let dead = if condition {
+                &if_node.on_false
+            } else {
+                &if_node.on_true
+            };
+            out.push(DeadBranch {
+                channel,
+                path: path.clone(),
+                condition,
+                dead_nodes: dead.node_count(),
+            });
+        }
+
+        for (i, child) in self.children().iter().enumerate() {
+            path.push(i);
+            child.find_dead_branches(channel, path, out);
+            path.pop();
+        }
+    }
+}
+
+impl NodeAst {
+    /// Finds every if statement with a static condition, as given by `Node::static_condition()`
+    pub fn dead_branches(&self) -> Vec<DeadBranch> {
+        let mut out = vec![];
+        for channel in self.tree_channels() {
+            self.channel(channel)
+                .find_dead_branches(channel, &mut vec![], &mut out);
+        }
+        out
+    }
+}
+
+impl DeadBranch {
+    /// The name of the branch which is always used
+    pub fn taken(&self) -> &'static str {
+        if self.condition {
+            "on_true"
+        } else {
+            "on_false"
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let path = self
+            .path
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "{{\"channel\": \"{}\", \"path\": [{}], \"condition\": {}, \"taken\": \"{}\", \"dead_nodes\": {}}}",
+            ['r', 'g', 'b', 'a'][self.channel],
+            path,
+            self.condition,
+            self.taken(),
+            self.dead_nodes
+        )
+    }
+}
+
+impl Display for DeadBranch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let dead = if self.condition {
+            "on_false"
+        } else {
+            "on_true"
+        };
+        let plural = if self.dead_nodes == 1 { "" } else { "s" };
+        write!(
+            f,
+            "{} {:?}: the condition is always {}, so {} is always taken, and {} ({} node{}) is dead",
+            ['R', 'G', 'B', 'A'][self.channel],
+            self.path,
+            self.condition,
+            self.taken(),
+            dead,
+            self.dead_nodes,
+            plural
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gets the static condition of the red channel of an AST whose channels are all `source`
+    fn condition(source: &str) -> Option<bool> {
+        let ast = NodeAst::parse_from_str(&format!("RGB: {}", source)).unwrap();
+        ast.r.static_condition()
+    }
+
+    #[test]
+    fn finds_static_conditions() {
+        assert_eq!(condition("(0.5 < 0.25 ? x : y)"), Some(false));
+        assert_eq!(condition("(sin(x) == sin(x) ? x : y)"), Some(true));
+        assert_eq!(condition("(sin(x) != sin(x) ? x : y)"), Some(true));
+        assert_eq!(condition("(mod(x, 0) < mod(x, 0) ? x : y)"), Some(false));
+        assert_eq!(condition("(x < y ? x : y)"), None);
+    }
+
+    #[test]
+    fn identical_operands_which_can_be_nan_are_not_static() {
+        // mod(x, 0) is NaN everywhere, so the condition is always false, not always true
+        let source = "(mod(x, 0) == mod(x, 0) ? 1 : -1)";
+        assert_eq!(condition(source), None);
+        let ast = NodeAst::parse_from_str(&format!("RGB: {}", source)).unwrap();
+        assert_eq!(ast.r.get_value(0.5, 0.5, 0.), -1.);
+        assert_eq!(condition("(sqrt(x) == sqrt(x) ? 1 : -1)"), None);
+        assert_eq!(condition("(rand == rand ? 1 : -1)"), None);
+    }
+}
@@ -1,8 +1,17 @@
 pub mod ast;
+pub mod bytecode;
+pub mod canonicalize;
+pub mod lint;
+pub mod mutate;
+pub mod simplify;
+pub mod stats;
 
 use std::fmt::Display;
 
-use crate::{grammar::Grammar, rng};
+use crate::{
+    grammar::Grammar,
+    rng::{self, Stream, StreamRng},
+};
 use rand::{Rng, seq::IndexedRandom};
 pub type NodePtr = Box<Node>;
 
@@ -13,7 +22,8 @@ pub enum NodeType {
     X,
     /// The y value of the current pixel
     Y,
-    /// The current time. Goes from 0 to PI. Defaults to 0 if not in gif mode
+    /// The current time. In gif mode, it goes from 0 towards 2 pi over the frames by default,
+    /// see `--t-mode`. Defaults to 0 if not in gif mode
     T,
     /// A random value in the range `-1..=1`
     Rand,
@@ -55,6 +65,31 @@ impl NodeType {
         matches!(self, Self::X | Self::Y | Self::Rand | Self::Literal)
     }
 
+    /// Gets a list of every `NodeType`
+    pub fn as_list() -> [Self; 19] {
+        [
+            Self::X,
+            Self::Y,
+            Self::T,
+            Self::Rand,
+            Self::Literal,
+            Self::Mult,
+            Self::Add,
+            Self::Sub,
+            Self::Div,
+            Self::Pow,
+            Self::Sqrt,
+            Self::Mod,
+            Self::Max,
+            Self::Min,
+            Self::Sin,
+            Self::Cos,
+            Self::Tan,
+            Self::Abs,
+            Self::If,
+        ]
+    }
+
     /// Gets the number of arguments for the `Node` with this `NodeType`
     pub fn arg_num(&self) -> usize {
         match self {
@@ -79,6 +114,15 @@ impl NodeType {
             NodeType::If => 5,
         }
     }
+
+    /// Gets the number of child nodes a `Node` with this `NodeType` will have.
+    /// This differs from `arg_num()` for `If`, since its operator isn't a node
+    pub fn child_num(&self) -> usize {
+        match self {
+            NodeType::If => 4,
+            _ => self.arg_num(),
+        }
+    }
 }
 
 impl TryFrom<&str> for NodeType {
@@ -105,11 +149,69 @@ impl TryFrom<&str> for NodeType {
             "tan" => Ok(Self::Tan),
             "abs" => Ok(Self::Abs),
             "if" => Ok(Self::If),
-            _ => Err(()),
+            name => NODE_TYPE_ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == name)
+                .map(|(_, node_type)| *node_type)
+                .ok_or(()),
         }
     }
 }
 
+/// Alternative names for some node types, which are accepted when parsing grammars and ASTs.
+/// Node types are always displayed with their canonical name
+const NODE_TYPE_ALIASES: [(&str, NodeType); 7] = [
+    ("multiply", NodeType::Mult),
+    ("times", NodeType::Mult),
+    ("plus", NodeType::Add),
+    ("minus", NodeType::Sub),
+    ("power", NodeType::Pow),
+    ("random", NodeType::Rand),
+    ("const", NodeType::Literal),
+];
+
+impl NodeType {
+    /// Gets every name a `NodeType` can be parsed from, including aliases
+    pub fn names() -> impl Iterator<Item = String> {
+        Self::as_list()
+            .into_iter()
+            .map(|node_type| node_type.to_string())
+            .chain(NODE_TYPE_ALIASES.iter().map(|(alias, _)| alias.to_string()))
+    }
+}
+
+/// Finds the name in `candidates` which is closest to `name`, if any is close enough to likely be
+/// what was meant
+pub fn closest_name(name: &str, candidates: impl IntoIterator<Item = String>) -> Option<String> {
+    let name = name.to_lowercase();
+    let max_distance = (name.chars().count() / 3).max(1);
+
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(&name, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Gets the Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_ch) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_ch) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a_ch != *b_ch);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
 impl Display for NodeType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let name = match self {
@@ -137,17 +239,22 @@ impl Display for NodeType {
     }
 }
 
-/// A node which will form a tree, that can be collapsed into a single value
-#[derive(Clone, Debug)]
+/// A node which will form a tree, that can be collapsed into a single value. Trees are walked
+/// with explicit stacks instead of recursion, from generating them to dropping them, so trees of
+/// any depth can be used without overflowing the call stack
+#[derive(Debug)]
 pub enum Node {
     /// The x value of the current pixel
     X,
     /// The y value of the current pixel
     Y,
-    /// The current time. Goes from 0 to PI. Defaults to 0 if not in gif mode
+    /// The current time. In gif mode, it goes from 0 towards 2 pi over the frames by default,
+    /// see `--t-mode`. Defaults to 0 if not in gif mode
     T,
-    /// A random value in the range `-1..=1`. Picked at run time
-    Rand,
+    /// A random value in the range `-1..1`, picked for each point from a hash of its coordinates,
+    /// the seed and the salt of the node. The salt keeps rand nodes of an AST from giving the
+    /// same values, and is set by `NodeAst::salt_rand()`
+    Rand(u32),
     /// A float literal. Picked randomly at creation time
     Literal(f64),
     /// Multiply two values
@@ -183,150 +290,584 @@ pub enum Node {
 impl Node {
     /// If the current node doesn't have child branches, and can therefore be collapsed
     pub fn is_end(&self) -> bool {
-        matches!(self, Self::X | Self::Y | Self::Rand | Self::Literal(_))
+        matches!(self, Self::X | Self::Y | Self::Rand(_) | Self::Literal(_))
     }
 
-    /// Collapse this branch into a value
-    pub fn get_value(&self, x: f64, y: f64, t: f64) -> f64 {
-        let get_val = |node: &Node| node.get_value(x, y, t);
+    /// Gets the `NodeType` of this node
+    pub fn node_type(&self) -> NodeType {
+        match self {
+            Node::X => NodeType::X,
+            Node::Y => NodeType::Y,
+            Node::T => NodeType::T,
+            Node::Rand(_) => NodeType::Rand,
+            Node::Literal(_) => NodeType::Literal,
+            Node::Mult(_, _) => NodeType::Mult,
+            Node::Add(_, _) => NodeType::Add,
+            Node::Sub(_, _) => NodeType::Sub,
+            Node::Div(_, _) => NodeType::Div,
+            Node::Pow(_, _) => NodeType::Pow,
+            Node::Sqrt(_) => NodeType::Sqrt,
+            Node::Mod(_, _) => NodeType::Mod,
+            Node::Max(_, _) => NodeType::Max,
+            Node::Min(_, _) => NodeType::Min,
+            Node::Sin(_) => NodeType::Sin,
+            Node::Cos(_) => NodeType::Cos,
+            Node::Tan(_) => NodeType::Tan,
+            Node::Abs(_) => NodeType::Abs,
+            Node::If(_) => NodeType::If,
+        }
+    }
 
+    /// Gets the child nodes of this node, in the order they are displayed
+    pub fn children(&self) -> Vec<&NodePtr> {
         match self {
-            Node::X => x,
-            Node::Y => y,
-            Node::T => t,
-            Node::Rand => rng::get_rng().random_range(-1.0..=1.0),
-            Node::Literal(float) => *float,
-            Node::Mult(lhs, rhs) => get_val(lhs) * get_val(rhs),
-            Node::Add(rhs, lhs) => get_val(lhs) + get_val(rhs),
-            Node::Sub(rhs, lhs) => get_val(lhs) - get_val(rhs),
-            Node::Div(lhs, rhs) => {
-                let rhs_value = get_val(rhs);
-                get_val(lhs)
-                    / if rhs_value != 0. {
-                        rhs_value
-                    } else {
-                        f64::EPSILON
-                    }
+            Node::X | Node::Y | Node::T | Node::Rand(_) | Node::Literal(_) => vec![],
+            Node::Mult(lhs, rhs)
+            | Node::Add(lhs, rhs)
+            | Node::Sub(lhs, rhs)
+            | Node::Div(lhs, rhs)
+            | Node::Pow(lhs, rhs)
+            | Node::Mod(lhs, rhs)
+            | Node::Max(lhs, rhs)
+            | Node::Min(lhs, rhs) => vec![lhs, rhs],
+            Node::Sqrt(val) | Node::Sin(val) | Node::Cos(val) | Node::Tan(val) | Node::Abs(val) => {
+                vec![val]
             }
-            Node::Pow(lhs, rhs) => get_val(lhs).powf(get_val(rhs)),
-            Node::Sqrt(val) => get_val(val).sqrt(),
-            Node::Mod(lhs, rhs) => get_val(lhs) % get_val(rhs),
-            Node::Max(lhs, rhs) => get_val(lhs).max(get_val(rhs)),
-            Node::Min(lhs, rhs) => get_val(lhs).min(get_val(rhs)),
-            Node::Sin(val) => get_val(val).sin(),
-            Node::Cos(val) => get_val(val).cos(),
-            Node::Tan(val) => get_val(val).tan(),
-            Node::Abs(val) => get_val(val).abs(),
+            Node::If(if_node) => vec![
+                &if_node.lhs,
+                &if_node.rhs,
+                &if_node.on_true,
+                &if_node.on_false,
+            ],
+        }
+    }
+
+    /// Gets mutable references to the child nodes of this node, in the order they are displayed
+    pub fn children_mut(&mut self) -> Vec<&mut NodePtr> {
+        match self {
+            Node::X | Node::Y | Node::T | Node::Rand(_) | Node::Literal(_) => vec![],
+            Node::Mult(lhs, rhs)
+            | Node::Add(lhs, rhs)
+            | Node::Sub(lhs, rhs)
+            | Node::Div(lhs, rhs)
+            | Node::Pow(lhs, rhs)
+            | Node::Mod(lhs, rhs)
+            | Node::Max(lhs, rhs)
+            | Node::Min(lhs, rhs) => vec![lhs, rhs],
+            Node::Sqrt(val) | Node::Sin(val) | Node::Cos(val) | Node::Tan(val) | Node::Abs(val) => {
+                vec![val]
+            }
+            Node::If(if_node) => vec![
+                &mut if_node.lhs,
+                &mut if_node.rhs,
+                &mut if_node.on_true,
+                &mut if_node.on_false,
+            ],
+        }
+    }
+
+    /// Takes the child nodes out of this node, in the order they are displayed
+    pub fn into_children(mut self) -> Vec<NodePtr> {
+        // Nodes can't be moved out of, since they are dropped by hand, so each child is swapped
+        // with a leaf
+        self.children_mut()
+            .into_iter()
+            .map(|child| std::mem::replace(child, Box::new(Node::X)))
+            .collect()
+    }
+
+    /// Creates a node of the given type from its children, without cloning them.
+    /// Returns `None` if the amount of children doesn't match `NodeType::child_num()`, or if the
+    /// node can't be made from children alone, which is the case for `Literal` and `If`
+    pub fn from_children(node_type: NodeType, children: Vec<NodePtr>) -> Option<Node> {
+        if children.len() != node_type.child_num() {
+            return None;
+        }
+
+        let mut children = children.into_iter();
+        let mut next = || children.next().unwrap();
+
+        let node = match node_type {
+            NodeType::X => Node::X,
+            NodeType::Y => Node::Y,
+            NodeType::T => Node::T,
+            NodeType::Rand => Node::Rand(0),
+            NodeType::Literal | NodeType::If => return None,
+            NodeType::Mult => Node::Mult(next(), next()),
+            NodeType::Add => Node::Add(next(), next()),
+            NodeType::Sub => Node::Sub(next(), next()),
+            NodeType::Div => Node::Div(next(), next()),
+            NodeType::Pow => Node::Pow(next(), next()),
+            NodeType::Sqrt => Node::Sqrt(next()),
+            NodeType::Mod => Node::Mod(next(), next()),
+            NodeType::Max => Node::Max(next(), next()),
+            NodeType::Min => Node::Min(next(), next()),
+            NodeType::Sin => Node::Sin(next()),
+            NodeType::Cos => Node::Cos(next()),
+            NodeType::Tan => Node::Tan(next()),
+            NodeType::Abs => Node::Abs(next()),
+        };
+
+        Some(node)
+    }
+
+    /// Creates a node like this one, with `children` in place of its children. The amount of
+    /// children has to match `NodeType::child_num()`
+    fn with_children(&self, children: Vec<NodePtr>) -> Node {
+        match self {
+            Node::Rand(salt) => Node::Rand(*salt),
+            Node::Literal(float) => Node::Literal(*float),
             Node::If(if_node) => {
-                if if_node
-                    .operator
-                    .eval(get_val(&if_node.lhs), get_val(&if_node.rhs))
-                {
-                    get_val(&if_node.on_true)
-                } else {
-                    get_val(&if_node.on_false)
+                let [lhs, rhs, on_true, on_false] = children.try_into().unwrap();
+                Node::If(IfNode {
+                    lhs,
+                    rhs,
+                    operator: if_node.operator.clone(),
+                    on_true,
+                    on_false,
+                })
+            }
+            node => Node::from_children(node.node_type(), children).unwrap(),
+        }
+    }
+
+    /// Calls `f` on this node and every node in its branches, parents before children
+    pub fn visit(&self, f: &mut impl FnMut(&Node)) {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            f(node);
+            stack.extend(node.children().into_iter().rev().map(|x| &**x));
+        }
+    }
+
+    /// Gets the depth of this branch. A node without children has a depth of 1
+    pub fn depth(&self) -> usize {
+        let mut stack = vec![(self, 1)];
+        let mut max = 0;
+        while let Some((node, depth)) = stack.pop() {
+            max = max.max(depth);
+            stack.extend(node.children().into_iter().map(|x| (&**x, depth + 1)));
+        }
+        max
+    }
+
+    /// Gets the amount of nodes in this branch, including this node
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        self.visit(&mut |_| count += 1);
+        count
+    }
+
+    /// Collapse this branch into a value. The branch is walked with an explicit stack instead of
+    /// recursion, so trees of any depth can be collapsed without overflowing the call stack
+    pub fn get_value(&self, x: f64, y: f64, t: f64) -> f64 {
+        let mut steps = vec![Step::Visit(self)];
+        let mut values: Vec<f64> = vec![];
+
+        while let Some(step) = steps.pop() {
+            let node = match step {
+                Step::Visit(node) => node,
+                Step::Apply(node) => {
+                    let value = apply(node, &mut values);
+                    values.push(value);
+                    continue;
+                }
+                Step::Branch(if_node) => {
+                    let [lhs, rhs] = pop_operands(&mut values);
+                    match if_node.operator.eval(lhs, rhs) {
+                        true => steps.push(Step::Visit(&if_node.on_true)),
+                        false => steps.push(Step::Visit(&if_node.on_false)),
+                    }
+                    continue;
+                }
+            };
+
+            let value = match node {
+                Node::X => x,
+                Node::Y => y,
+                Node::T => t,
+                // This locks the shared RNG for every draw, but ASTs drawing from it are never rendered in
+                // parallel, since the image would depend on the order of the pixels. See `img::uses_rand()`
+                Node::Rand(_) if rng::legacy_rand() => {
+                    rng::get_rng(rng::Stream::Runtime).random_range(-1.0..=1.0)
+                }
+                Node::Rand(salt) => rng::noise(x, y, *salt),
+                Node::Literal(float) => *float,
+                Node::If(if_node) => {
+                    steps.push(Step::Branch(if_node));
+                    steps.push(Step::Visit(&if_node.rhs));
+                    steps.push(Step::Visit(&if_node.lhs));
+                    continue;
                 }
+                node => {
+                    // The operands are visited in the order they are evaluated, so the first
+                    // is pushed last
+                    steps.push(Step::Apply(node));
+                    for operand in operands(node).into_iter().rev() {
+                        steps.push(Step::Visit(operand));
+                    }
+                    continue;
+                }
+            };
+            values.push(value);
+        }
+
+        values.pop().unwrap()
+    }
+
+    /// Replaces every `Rand` node in this branch with a literal, picked randomly once. The
+    /// literals are drawn in pre-order
+    pub fn freeze_rand(&mut self, rng: &mut impl StreamRng) {
+        self.visit_mut(&mut |node| {
+            if matches!(node, Node::Rand(_)) {
+                *node =
+                    Node::Literal(rng.draw(Stream::Literals, |rng| rng.random_range(-1.0..=1.0)));
+            }
+        });
+    }
+
+    /// Numbers the `Rand` nodes of this branch in pre-order, starting at `next`, and uses the
+    /// numbers as their salts
+    pub fn salt_rand(&mut self, next: &mut u32) {
+        self.visit_mut(&mut |node| {
+            if let Node::Rand(salt) = node {
+                *salt = *next;
+                *next += 1;
             }
+        });
+    }
+
+    /// Calls `f` on this node and every node in its branches, parents before children. The
+    /// children are visited after `f` has changed their parent
+    fn visit_mut(&mut self, f: &mut impl FnMut(&mut Node)) {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            f(node);
+            stack.extend(node.children_mut().into_iter().rev().map(|x| &mut **x));
         }
     }
 
     /// Get a random terminable node.
+    #[deprecated(note = "draws from the seeded RNG, use `Node::get_rand_end_with()` instead")]
+    #[allow(dead_code)]
     pub fn get_rand_end(grammar: &mut Grammar) -> NodePtr {
+        Self::get_rand_end_with(grammar, &mut rng::Seeded)
+    }
+
+    /// Get a random terminable node, drawing from `rng`. Grammars without one, which parsing only
+    /// allows if they are empty, get a literal, like `Grammar::pick_with()` gives them
+    pub fn get_rand_end_with(grammar: &mut Grammar, rng: &mut impl StreamRng) -> NodePtr {
         let ends = grammar
             .rules
             .iter()
             .filter_map(|x| x.0.is_end().then_some(x.0))
             .collect::<Vec<_>>();
 
-        let Some(choice) = ends.choose(rng::get_rng()) else {
-            eprintln!("[ERROR]: Grammar needs to include at least one element that is terminable");
-            std::process::exit(1);
-        };
+        let choice = rng
+            .draw(Stream::Structure, |rng| ends.choose(rng).copied())
+            .unwrap_or(NodeType::Literal);
 
         match choice {
             NodeType::X => Box::new(Self::X),
             NodeType::Y => Box::new(Self::Y),
-            NodeType::Rand => Box::new(Self::Rand),
-            NodeType::Literal => Box::new(Self::Literal(rng::get_rng().random_range(-1.0..=1.0))),
+            NodeType::Rand => Box::new(Self::Rand(0)),
+            NodeType::Literal => Box::new(Self::Literal(
+                rng.draw(Stream::Literals, |rng| rng.random_range(-1.0..=1.0)),
+            )),
             _ => unreachable!(),
         }
     }
 
+    /// Generate a random tree from the grammar, with a max depth of `curr_depth`, and no limit on
+    /// the amount of nodes
+    #[deprecated(note = "draws from the seeded RNG, use `Node::gen_rand_with()` instead")]
+    #[allow(dead_code)]
     pub fn gen_rand(grammar: &mut Grammar, curr_depth: usize) -> NodePtr {
-        if curr_depth == 0 {
-            return Self::get_rand_end(grammar);
+        Self::gen_rand_with(grammar, curr_depth, &mut None, &mut rng::Seeded)
+    }
+
+    /// Generate a random tree from the grammar, with a max depth of `curr_depth`, drawing from
+    /// `rng`. `budget` is the amount of nodes that can still be added to the tree, besides this
+    /// one. If it is `None`, the amount of nodes is unbounded. Once the budget can't fit the
+    /// children of the picked node, a terminable node is used instead.
+    /// The nodes are drawn depth first, parents before children, and the operator of an if
+    /// statement after its operands
+    pub fn gen_rand_with(
+        grammar: &mut Grammar,
+        curr_depth: usize,
+        budget: &mut Option<usize>,
+        rng: &mut impl StreamRng,
+    ) -> NodePtr {
+        let mut tasks = vec![Gen::Node(curr_depth)];
+        // The finished branches, and the operators of the if statements being generated
+        let mut nodes: Vec<NodePtr> = vec![];
+        let mut operators = vec![];
+
+        while let Some(task) = tasks.pop() {
+            let depth = match task {
+                Gen::Node(depth) => depth,
+                Gen::Operator => {
+                    operators.push(grammar.pick_operator(rng));
+                    continue;
+                }
+                Gen::Build(node_type) => {
+                    let children = nodes.split_off(nodes.len() - node_type.child_num());
+                    let node = match node_type {
+                        NodeType::If => {
+                            let [lhs, rhs, on_true, on_false] = children.try_into().unwrap();
+                            Node::If(IfNode {
+                                lhs,
+                                rhs,
+                                operator: operators.pop().unwrap(),
+                                on_true,
+                                on_false,
+                            })
+                        }
+                        node_type => Node::from_children(node_type, children).unwrap(),
+                    };
+                    nodes.push(Box::new(node));
+                    continue;
+                }
+            };
+
+            if depth == 0 {
+                nodes.push(Self::get_rand_end_with(grammar, rng));
+                continue;
+            }
+
+            let choice = grammar.pick_with(rng);
+
+            if let Some(remaining) = budget {
+                let children = choice.child_num();
+                if children > *remaining {
+                    nodes.push(Self::get_rand_end_with(grammar, rng));
+                    continue;
+                }
+                // Reserve a spot for each child, so the budget is never exceeded
+                *remaining -= children;
+            }
+
+            // The tasks are pushed in reverse, so the first child is generated first
+            let child = Gen::Node(depth - 1);
+            match choice {
+                NodeType::Literal => nodes.push(Box::new(Node::Literal(
+                    rng.draw(Stream::Literals, |rng| rng.random_range(-1.0..=1.0)),
+                ))),
+                NodeType::If => tasks.extend([
+                    Gen::Build(NodeType::If),
+                    child,
+                    child,
+                    Gen::Operator,
+                    child,
+                    child,
+                ]),
+                node_type => {
+                    tasks.push(Gen::Build(node_type));
+                    tasks.extend(std::iter::repeat_n(child, node_type.child_num()));
+                }
+            }
         }
 
-        let choice = grammar.pick();
+        nodes.pop().unwrap()
+    }
+}
 
-        let new_depth = curr_depth - 1;
+/// A task of generating a tree in `Node::gen_rand_with()`
+#[derive(Clone, Copy)]
+enum Gen {
+    /// Generates a node, with the depth left below it
+    Node(usize),
+    /// Picks the operator of an if statement
+    Operator,
+    /// Builds a node of the type from its generated children
+    Build(NodeType),
+}
 
-        let mut gen_node = || Self::gen_rand(grammar, new_depth);
-        let gen_operator = || Operator::as_list().choose(rng::get_rng()).cloned().unwrap();
+impl Clone for Node {
+    fn clone(&self) -> Self {
+        // Each node is visited before its children, and built after them
+        let mut steps = vec![(self, false)];
+        let mut clones: Vec<NodePtr> = vec![];
+        while let Some((node, built)) = steps.pop() {
+            if !built {
+                steps.push((node, true));
+                steps.extend(node.children().into_iter().rev().map(|x| (&**x, false)));
+                continue;
+            }
+            let children = clones.split_off(clones.len() - node.node_type().child_num());
+            clones.push(Box::new(node.with_children(children)));
+        }
+        *clones.pop().unwrap()
+    }
+}
 
-        let node = match choice {
-            NodeType::T => Node::T,
-            NodeType::X => Node::X,
-            NodeType::Y => Node::Y,
-            NodeType::Rand => Node::Rand,
-            NodeType::Literal => Node::Literal(rng::get_rng().random_range(-1.0..=1.0)),
-            NodeType::Mult => Node::Mult(gen_node(), gen_node()),
-            NodeType::Add => Node::Add(gen_node(), gen_node()),
-            NodeType::Sub => Node::Sub(gen_node(), gen_node()),
-            NodeType::Div => Node::Div(gen_node(), gen_node()),
-            NodeType::Pow => Node::Pow(gen_node(), gen_node()),
-            NodeType::Sqrt => Node::Sqrt(gen_node()),
-            NodeType::Mod => Node::Mod(gen_node(), gen_node()),
-            NodeType::Max => Node::Max(gen_node(), gen_node()),
-            NodeType::Min => Node::Min(gen_node(), gen_node()),
-            NodeType::Sin => Node::Sin(gen_node()),
-            NodeType::Cos => Node::Cos(gen_node()),
-            NodeType::Tan => Node::Tan(gen_node()),
-            NodeType::Abs => Node::Abs(gen_node()),
-            NodeType::If => Node::If(IfNode {
-                lhs: gen_node(),
-                rhs: gen_node(),
-                operator: gen_operator().clone(),
-                on_true: gen_node(),
-                on_false: gen_node(),
-            }),
+impl Drop for Node {
+    fn drop(&mut self) {
+        // Each branch is moved out of its box, which keeps a leaf, so the nodes are dropped one at
+        // a time, without dropping their branches in turn
+        let mut branches = vec![];
+        let take_branches = |node: &mut Node, branches: &mut Vec<Node>| {
+            for child in node.children_mut() {
+                if child.node_type().child_num() > 0 {
+                    branches.push(std::mem::replace(&mut **child, Node::X));
+                }
+            }
         };
-
-        Box::new(node)
+        take_branches(self, &mut branches);
+        while let Some(mut node) = branches.pop() {
+            take_branches(&mut node, &mut branches);
+        }
     }
 }
 
 impl Display for Node {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Node::X => write!(f, "x"),
-            Node::Y => write!(f, "y"),
-            Node::T => write!(f, "t"),
-            Node::Rand => write!(f, "RAND"),
-            Node::Literal(float) => write!(f, "{}", float),
-            Node::Mult(lhs, rhs) => write!(f, "mult({}, {})", lhs, rhs),
-            Node::Add(lhs, rhs) => write!(f, "add({}, {})", lhs, rhs),
-            Node::Sub(lhs, rhs) => write!(f, "sub({}, {})", lhs, rhs),
-            Node::Div(lhs, rhs) => write!(f, "div({}, {})", lhs, rhs),
-            Node::Pow(lhs, rhs) => write!(f, "pow({}, {})", lhs, rhs),
-            Node::Sqrt(val) => write!(f, "sqrt({})", val),
-            Node::Mod(lhs, rhs) => write!(f, "mod({}, {})", lhs, rhs),
-            Node::Max(lhs, rhs) => write!(f, "max({}, {})", lhs, rhs),
-            Node::Min(lhs, rhs) => write!(f, "min({}, {})", lhs, rhs),
-            Node::Sin(val) => write!(f, "sin({})", val),
-            Node::Cos(val) => write!(f, "cos({})", val),
-            Node::Tan(val) => write!(f, "tan({})", val),
-            Node::Abs(val) => write!(f, "abs({})", val),
-            Node::If(if_node) => write!(
-                f,
-                "({} {} {} ? {} : {})",
-                if_node.lhs, if_node.operator, if_node.rhs, if_node.on_true, if_node.on_false
-            ),
+        let mut parts = vec![Part::Node(self)];
+        while let Some(part) = parts.pop() {
+            let node = match part {
+                Part::Node(node) => node,
+                Part::Text(text) => {
+                    f.write_str(text)?;
+                    continue;
+                }
+                Part::Operator(operator) => {
+                    write!(f, " {} ", operator)?;
+                    continue;
+                }
+            };
+
+            // The parts are pushed in reverse, so the first is written first
+            match node {
+                Node::X => write!(f, "x")?,
+                Node::Y => write!(f, "y")?,
+                Node::T => write!(f, "t")?,
+                Node::Rand(_) => write!(f, "RAND")?,
+                Node::Literal(float) => write!(f, "{}", float)?,
+                Node::If(if_node) => {
+                    write!(f, "(")?;
+                    parts.extend([
+                        Part::Text(")"),
+                        Part::Node(&if_node.on_false),
+                        Part::Text(" : "),
+                        Part::Node(&if_node.on_true),
+                        Part::Text(" ? "),
+                        Part::Node(&if_node.rhs),
+                        Part::Operator(&if_node.operator),
+                        Part::Node(&if_node.lhs),
+                    ]);
+                }
+                node => {
+                    write!(f, "{}(", node.node_type())?;
+                    parts.push(Part::Text(")"));
+                    for (i, child) in node.children().into_iter().enumerate().rev() {
+                        parts.push(Part::Node(child));
+                        if i > 0 {
+                            parts.push(Part::Text(", "));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A part of a tree written by `Node::fmt()`
+enum Part<'a> {
+    Node(&'a Node),
+    Text(&'static str),
+    Operator(&'a Operator),
+}
+
+/// Compares trees node by node. Literals only have to be approximately equal, so a tree still
+/// equals itself after being written out and read back in
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        let mut pairs = vec![(self, other)];
+        while let Some((lhs, rhs)) = pairs.pop() {
+            let equal = match (lhs, rhs) {
+                (Node::Literal(lhs), Node::Literal(rhs)) => approx_eq(*lhs, *rhs),
+                (Node::If(lhs), Node::If(rhs)) => lhs.operator == rhs.operator,
+                _ => lhs.node_type() == rhs.node_type(),
+            };
+            if !equal {
+                return false;
+            }
+            let children = lhs.children().into_iter().zip(rhs.children());
+            pairs.extend(children.map(|(lhs, rhs)| (&**lhs, &**rhs)));
         }
+        true
+    }
+}
+
+/// Checks if two floats are equal, within a relative error of `1e-9`. NaN equals NaN, since they
+/// are the same literal
+pub fn approx_eq(lhs: f64, rhs: f64) -> bool {
+    if lhs == rhs || (lhs.is_nan() && rhs.is_nan()) {
+        return true;
+    }
+    if !lhs.is_finite() || !rhs.is_finite() {
+        return false;
+    }
+    let scale = lhs.abs().max(rhs.abs()).max(1.);
+    (lhs - rhs).abs() <= scale * 1e-9
+}
+
+/// A step of walking a tree in `Node::get_value()`
+enum Step<'a> {
+    /// Collapses the node, or schedules its operands and itself
+    Visit(&'a Node),
+    /// Applies the node to the values of its operands, which are on the value stack
+    Apply(&'a Node),
+    /// Picks the branch of an if statement from the values of its condition
+    Branch(&'a IfNode),
+}
+
+/// Gets the operands of a node with operands, in the order `Node::get_value()` evaluates them in.
+/// The second operand of add and sub, and the divisor, are evaluated first
+fn operands(node: &Node) -> Vec<&Node> {
+    match node {
+        Node::Add(rhs, lhs) | Node::Sub(rhs, lhs) => vec![lhs, rhs],
+        Node::Div(lhs, rhs) => vec![rhs, lhs],
+        node => node.children().into_iter().map(|x| &**x).collect(),
     }
 }
 
-#[derive(Clone, Debug)]
+/// Applies a node with operands to their values, popping them from the value stack
+fn apply(node: &Node, values: &mut Vec<f64>) -> f64 {
+    if let Node::Sqrt(_) | Node::Sin(_) | Node::Cos(_) | Node::Tan(_) | Node::Abs(_) = node {
+        let value = values.pop().unwrap();
+        return match node {
+            Node::Sqrt(_) => value.sqrt(),
+            Node::Sin(_) => value.sin(),
+            Node::Cos(_) => value.cos(),
+            Node::Tan(_) => value.tan(),
+            _ => value.abs(),
+        };
+    }
+
+    let [first, second] = pop_operands(values);
+    match node {
+        Node::Mult(..) => first * second,
+        Node::Add(..) => first + second,
+        Node::Sub(..) => first - second,
+        Node::Div(..) => second / if first != 0. { first } else { f64::EPSILON },
+        Node::Pow(..) => first.powf(second),
+        Node::Mod(..) => first % second,
+        Node::Max(..) => first.max(second),
+        Node::Min(..) => first.min(second),
+        _ => unreachable!("only nodes with operands are applied"),
+    }
+}
+
+/// Pops the values of two operands, in the order they were evaluated
+fn pop_operands(values: &mut Vec<f64>) -> [f64; 2] {
+    let second = values.pop().unwrap();
+    let first = values.pop().unwrap();
+    [first, second]
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct IfNode {
     /// The first operand
     lhs: NodePtr,
@@ -340,6 +881,13 @@ pub struct IfNode {
     on_false: NodePtr,
 }
 
+impl IfNode {
+    /// Gets the operator used to compare the operands
+    pub fn operator(&self) -> &Operator {
+        &self.operator
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Operator {
     /// `lhs < rhs`
@@ -395,3 +943,53 @@ impl Display for Operator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ast::NodeAst, *};
+
+    #[test]
+    fn floats_are_equal_within_a_relative_tolerance() {
+        assert!(approx_eq(1., 1. + 1e-12));
+        assert!(!approx_eq(1., 1. + 1e-6));
+        assert!(approx_eq(1e12, 1e12 + 1.));
+        assert!(!approx_eq(1e12, 1e12 + 1e4));
+        // Values close to 0 are compared absolutely, so tiny rounding errors are equal
+        assert!(approx_eq(1e-12, 2e-12));
+        assert!(approx_eq(0., -0.));
+
+        assert!(approx_eq(f64::NAN, -f64::NAN));
+        assert!(approx_eq(f64::INFINITY, f64::INFINITY));
+        assert!(!approx_eq(f64::INFINITY, f64::NEG_INFINITY));
+        assert!(!approx_eq(f64::INFINITY, f64::MAX));
+        assert!(!approx_eq(f64::NAN, 0.));
+    }
+
+    #[test]
+    fn trees_are_equal_if_their_nodes_are() {
+        let literal = |x| Box::new(Node::Literal(x));
+        let add = |lhs, rhs| Node::Add(lhs, rhs);
+
+        assert_eq!(
+            add(literal(0.1 + 0.2), literal(1.)),
+            add(literal(0.3), literal(1.))
+        );
+        assert_ne!(
+            add(literal(0.3), literal(1.)),
+            add(literal(1.), literal(0.3))
+        );
+        assert_ne!(
+            add(literal(0.3), literal(1.)),
+            Node::Sub(literal(0.3), literal(1.))
+        );
+        // The salts are numbered after parsing, so they aren't part of the tree
+        assert_eq!(Node::Rand(1), Node::Rand(2));
+
+        let if_node = |source: &str| NodeAst::parse_from_str(source).unwrap().r;
+        assert_eq!(if_node("RGB: (x < y ? 1 : 0)"), if_node("RGB: (x<y?1.0:0)"));
+        assert_ne!(
+            if_node("RGB: (x < y ? 1 : 0)"),
+            if_node("RGB: (x > y ? 1 : 0)")
+        );
+    }
+}
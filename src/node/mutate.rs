@@ -0,0 +1,222 @@
+use rand::seq::IndexedRandom;
+
+use crate::{
+    grammar::Grammar,
+    rng::{Stream, StreamRng},
+};
+
+use super::{Node, NodePtr, ast::NodeAst};
+
+/// The position of a node in an AST. The first element is the channel, and the rest are the
+/// indices of the children to follow, as given by `Node::children()`
+type NodePath = Vec<usize>;
+
+impl Node {
+    /// Gets the paths of every node in this branch, relative to this node
+    fn paths(&self, prefix: &mut NodePath, out: &mut Vec<NodePath>) {
+        out.push(prefix.clone());
+        for (i, child) in self.children().iter().enumerate() {
+            prefix.push(i);
+            child.paths(prefix, out);
+            prefix.pop();
+        }
+    }
+}
+
+impl Node {
+    /// Gets the node at `path`, relative to this node
+    fn at_path(&self, path: &[usize]) -> &Node {
+        path.iter()
+            .fold(self, |node, i| node.children()[*i].as_ref())
+    }
+
+    /// Gets the node at `path`, relative to this node
+    fn at_path_mut(&mut self, path: &[usize]) -> &mut NodePtr {
+        let (first, rest) = path.split_first().expect("path should not be empty");
+        let mut node = self.children_mut().swap_remove(*first);
+        for i in rest {
+            node = node.children_mut().swap_remove(*i);
+        }
+        node
+    }
+
+    /// Replaces every node deeper than `depth` with a terminable node, so the depth of the branch
+    /// is at most `depth`
+    pub fn truncate(&mut self, depth: usize, grammar: &mut Grammar, rng: &mut impl StreamRng) {
+        if depth <= 1 {
+            if !self.children().is_empty() {
+                *self = *Node::get_rand_end_with(grammar, rng);
+            }
+            return;
+        }
+        for child in self.children_mut() {
+            child.truncate(depth - 1, grammar, rng);
+        }
+    }
+}
+
+impl NodeAst {
+    fn channel_mut(&mut self, channel: usize) -> &mut NodePtr {
+        match channel {
+            0 => &mut self.r,
+            1 => &mut self.g,
+            2 => &mut self.b,
+            _ => self.a.as_mut().expect("the AST has no alpha channel"),
+        }
+    }
+
+    /// Replaces `count` randomly selected branches with newly generated ones, with a max depth of
+    /// `depth`. The selected branches never overlap, so exactly `count` branches will differ,
+    /// unless the AST doesn't have enough branches. The root of a channel is only replaced if
+    /// `allow_root` is set.
+    /// Returns the amount of branches that were replaced
+    pub fn mutate(
+        &mut self,
+        grammar: &mut Grammar,
+        count: usize,
+        depth: usize,
+        allow_root: bool,
+        rng: &mut impl StreamRng,
+    ) -> usize {
+        let mut candidates: Vec<NodePath> = vec![];
+        // Grayscale ASTs only have a single tree, which is stored in the r channel
+        for channel in self.tree_channels() {
+            self.channel(channel)
+                .paths(&mut vec![channel], &mut candidates);
+        }
+        if !allow_root {
+            candidates.retain(|path| path.len() > 1);
+        }
+
+        let mut mutated = 0;
+        while mutated < count {
+            let Some(path) = rng.draw(Stream::Structure, |rng| candidates.choose(rng).cloned())
+            else {
+                break;
+            };
+
+            // Remove the branch, and the nodes containing it, so no branch is replaced twice
+            candidates.retain(|other| !other.starts_with(&path) && !path.starts_with(other));
+
+            let root = self.channel_mut(path[0]);
+            let new_node = Node::gen_rand_with(grammar, depth, &mut None, rng);
+            if path.len() == 1 {
+                *root = new_node;
+            } else {
+                *root.at_path_mut(&path[1..]) = new_node;
+            }
+
+            mutated += 1;
+        }
+
+        self.sync_grayscale();
+        mutated
+    }
+}
+
+impl NodeAst {
+    /// Creates a child of this AST and `other`, by replacing a randomly selected branch of each
+    /// channel with a randomly selected branch from the same channel of `other`. The branches are
+    /// picked so the child has a depth of at most `depth`, and if this AST is deeper than that,
+    /// the child is truncated
+    pub fn breed(
+        &self,
+        other: &NodeAst,
+        depth: usize,
+        grammar: &mut Grammar,
+        rng: &mut impl StreamRng,
+    ) -> NodeAst {
+        let depth = depth.max(1);
+        let mut child = self.clone();
+
+        for channel in self.tree_channels() {
+            // The alpha channel can only be bred if both parents have one
+            if channel == 3 && other.a.is_none() {
+                continue;
+            }
+
+            let mut targets = vec![];
+            self.channel(channel).paths(&mut vec![], &mut targets);
+            // A branch at `path.len()` levels down can at most have a depth of `depth - level`
+            targets.retain(|path| path.len() < depth);
+
+            let target = rng
+                .draw(Stream::Structure, |rng| targets.choose(rng).cloned())
+                .unwrap_or_default();
+
+            let donor_root = other.channel(channel);
+            let mut donors = vec![];
+            donor_root.paths(&mut vec![], &mut donors);
+            donors.retain(|path| donor_root.at_path(path).depth() <= depth - target.len());
+
+            // Leaves always fit, so there is always at least one donor
+            let donor = rng
+                .draw(Stream::Structure, |rng| donors.choose(rng).cloned())
+                .unwrap_or_default();
+            let branch = Box::new(donor_root.at_path(&donor).clone());
+
+            let root = child.channel_mut(channel);
+            if target.is_empty() {
+                *root = branch;
+            } else {
+                *root.at_path_mut(&target) = branch;
+            }
+            root.truncate(depth, grammar, rng);
+        }
+
+        child.sync_grayscale();
+        child
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    use crate::node::NodeType;
+
+    use super::*;
+
+    const AST: &str = "R: add(mult(x, y), sin(add(x, t)))
+G: max(abs(y), cos(mult(t, 0.5)))
+B: div(sqrt(abs(x)), add(y, 1))";
+
+    /// Mutates 3 branches of `AST`, drawing from an RNG seeded with `seed`
+    fn mutated(grammar: &mut Grammar, seed: u64) -> NodeAst {
+        let mut ast = NodeAst::parse_from_str(AST).unwrap();
+        let mut rng = ChaCha20Rng::seed_from_u64(seed);
+        assert_eq!(ast.mutate(grammar, 3, 4, false, &mut rng), 3);
+        ast
+    }
+
+    #[test]
+    fn mutations_only_depend_on_the_seed() {
+        let mut grammar = Grammar::new(NodeType::as_list().map(|x| (x, 1)).to_vec());
+        let original = NodeAst::parse_from_str(AST).unwrap();
+        let mutations: Vec<NodeAst> = (0..16).map(|seed| mutated(&mut grammar, seed)).collect();
+
+        for (seed, ast) in mutations.iter().enumerate() {
+            assert_eq!(*ast, mutated(&mut grammar, seed as u64), "seed {}", seed);
+            assert_ne!(*ast, original, "seed {}", seed);
+            // The roots are never replaced, so every channel keeps its root node
+            for channel in ast.tree_channels() {
+                assert_eq!(
+                    ast.channel(channel).node_type(),
+                    original.channel(channel).node_type()
+                );
+            }
+        }
+
+        let distinct = mutations
+            .iter()
+            .enumerate()
+            .filter(|(i, ast)| !mutations[..*i].contains(ast))
+            .count();
+        assert!(
+            distinct > 8,
+            "only {} of 16 seeds gave different ASTs",
+            distinct
+        );
+    }
+}
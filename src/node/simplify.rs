@@ -0,0 +1,146 @@
+use super::{IfNode, Node, NodePtr, ast::NodeAst};
+
+impl Node {
+    /// Simplifies the tree, without changing the value it collapses into.
+    /// This folds constant branches into literals, like `add(0.2, 0.3)` into `0.5`, removes
+    /// identities like `mult(x, 1)` and `add(x, 0)`, collapses if statements with a constant
+    /// condition, and removes doubled `abs` calls. Branches containing `Rand` or `T` are never
+    /// folded
+    pub fn simplify(self) -> Node {
+        if self.node_type().child_num() == 0 {
+            return self;
+        }
+
+        let node_type = self.node_type();
+        let operator = match &self {
+            Node::If(if_node) => Some(if_node.operator.clone()),
+            _ => None,
+        };
+        let mut children: Vec<NodePtr> = self
+            .into_children()
+            .into_iter()
+            .map(|child| Box::new(child.simplify()))
+            .collect();
+
+        let node = match operator {
+            Some(operator) => {
+                if let (Node::Literal(lhs), Node::Literal(rhs)) = (&*children[0], &*children[1]) {
+                    let branch = match operator.eval(*lhs, *rhs) {
+                        true => 2,
+                        false => 3,
+                    };
+                    return *children.swap_remove(branch);
+                }
+
+                let [lhs, rhs, on_true, on_false] = children.try_into().unwrap();
+                Node::If(IfNode {
+                    lhs,
+                    rhs,
+                    operator,
+                    on_true,
+                    on_false,
+                })
+            }
+            None => Node::from_children(node_type, children).unwrap(),
+        };
+
+        // Every child is a literal, so the node will always collapse into the same value
+        if node
+            .children()
+            .iter()
+            .all(|child| matches!(***child, Node::Literal(_)))
+        {
+            return Node::Literal(node.get_value(0., 0., 0.));
+        }
+
+        // The index of the child an identity collapses into
+        let is_literal = |node: &Node, value: f64| matches!(node, Node::Literal(x) if *x == value);
+        let kept = match &node {
+            Node::Mult(_, rhs) if is_literal(rhs, 1.) => Some(0),
+            Node::Mult(lhs, _) if is_literal(lhs, 1.) => Some(1),
+            Node::Add(_, rhs) if is_literal(rhs, 0.) => Some(0),
+            Node::Add(lhs, _) if is_literal(lhs, 0.) => Some(1),
+            Node::Div(_, rhs) if is_literal(rhs, 1.) => Some(0),
+            Node::Pow(_, rhs) if is_literal(rhs, 1.) => Some(0),
+            Node::Abs(val) if matches!(**val, Node::Abs(_)) => Some(0),
+            _ => None,
+        };
+        match kept {
+            Some(index) => *node.into_children().swap_remove(index),
+            None => node,
+        }
+    }
+}
+
+impl NodeAst {
+    /// Simplifies the trees of all channels, via `Node::simplify()`
+    pub fn simplify(self) -> Self {
+        let a = self.a.map(|x| Box::new(x.simplify()));
+        if self.grayscale {
+            return Self {
+                a,
+                ..Self::grayscale(Box::new(self.r.simplify()))
+            };
+        }
+        Self {
+            r: Box::new(self.r.simplify()),
+            g: Box::new(self.g.simplify()),
+            b: Box::new(self.b.simplify()),
+            grayscale: false,
+            a,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Simplifies the tree of an AST, which uses the same tree for all three channels
+    fn simplify(source: &str) -> Node {
+        let ast = NodeAst::parse_from_str(&format!("RGB: {}", source)).unwrap();
+        *ast.simplify().r
+    }
+
+    #[test]
+    fn non_finite_values_are_folded_into_literals() {
+        assert!(matches!(simplify("sqrt(-1)"), Node::Literal(x) if x.is_nan()));
+        assert!(matches!(simplify("pow(0, -1)"), Node::Literal(x) if x == f64::INFINITY));
+        assert!(matches!(simplify("add(-inf, 1)"), Node::Literal(x) if x == f64::NEG_INFINITY));
+
+        // The literals are dumped as words, which are parsed back into the same values
+        let dumped = NodeAst::parse_from_str("RGB: add(x, mult(sqrt(-1), pow(0, -1)))")
+            .unwrap()
+            .simplify()
+            .to_string();
+        let parsed = NodeAst::parse_from_str(&dumped).unwrap();
+        assert!(parsed.r.get_value(0.5, 0.5, 0.).is_nan(), "{}", dumped);
+    }
+
+    #[test]
+    fn identities_with_non_finite_values_are_kept() {
+        // Adding NaN or multiplying by infinity changes x, so the operations can't be removed
+        for source in [
+            "add(x, sqrt(-1))",
+            "mult(x, inf)",
+            "mult(inf, x)",
+            "div(x, NaN)",
+        ] {
+            let tree = NodeAst::parse_from_str(&format!("RGB: {}", source))
+                .unwrap()
+                .r;
+            let node = simplify(source);
+            assert_ne!(node, Node::X, "{} was simplified into x", source);
+            for x in [-1., 0., 0.5] {
+                let (expected, value) = (tree.get_value(x, 0., 0.), node.get_value(x, 0., 0.));
+                assert!(
+                    value.to_bits() == expected.to_bits() || value.is_nan() && expected.is_nan(),
+                    "{} gave {} instead of {}",
+                    source,
+                    value,
+                    expected
+                );
+            }
+        }
+    }
+}
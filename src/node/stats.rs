@@ -0,0 +1,149 @@
+use std::fmt::Display;
+
+use super::{Node, NodeType, ast::NodeAst};
+
+/// Statistics about a single tree, used by `--ast-stats`
+#[derive(Clone, Debug)]
+pub struct NodeStats {
+    /// The amount of nodes in the tree
+    pub node_count: usize,
+    /// The depth of the tree
+    pub max_depth: usize,
+    /// The amount of nodes of each `NodeType`, in the order of `NodeType::as_list()`
+    pub type_counts: Vec<(NodeType, usize)>,
+    /// A rough estimate of how expensive the tree is to evaluate for a single pixel
+    pub cost: usize,
+}
+
+impl NodeType {
+    /// A rough estimate of how expensive a node of this type is to evaluate, compared to an
+    /// addition. Children aren't included
+    pub fn eval_cost(&self) -> usize {
+        match self {
+            NodeType::X | NodeType::Y | NodeType::T | NodeType::Literal => 1,
+            NodeType::Add | NodeType::Sub | NodeType::Mult => 1,
+            NodeType::Max | NodeType::Min | NodeType::Abs | NodeType::If => 2,
+            NodeType::Div => 3,
+            NodeType::Sqrt | NodeType::Mod => 5,
+            NodeType::Sin | NodeType::Cos | NodeType::Tan => 10,
+            NodeType::Pow => 20,
+            NodeType::Rand => 30,
+        }
+    }
+}
+
+impl NodeStats {
+    pub fn from_node(node: &Node) -> Self {
+        let mut type_counts: Vec<(NodeType, usize)> =
+            NodeType::as_list().iter().map(|x| (*x, 0)).collect();
+        let mut node_count = 0;
+        let mut cost = 0;
+
+        node.visit(&mut |node| {
+            let node_type = node.node_type();
+            node_count += 1;
+            cost += node_type.eval_cost();
+            if let Some(count) = type_counts.iter_mut().find(|x| x.0 == node_type) {
+                count.1 += 1;
+            }
+        });
+
+        Self {
+            node_count,
+            max_depth: node.depth(),
+            type_counts,
+            cost,
+        }
+    }
+
+    /// Gets the amount of nodes with the given type
+    pub fn count(&self, node_type: NodeType) -> usize {
+        self.type_counts
+            .iter()
+            .find(|x| x.0 == node_type)
+            .map(|x| x.1)
+            .unwrap_or(0)
+    }
+
+    pub fn to_json(&self) -> String {
+        let types = self
+            .type_counts
+            .iter()
+            .filter(|x| x.1 > 0)
+            .map(|(node_type, count)| format!("\"{}\": {}", node_type, count))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!(
+            "{{\"nodes\": {}, \"max_depth\": {}, \"rand\": {}, \"t\": {}, \"cost\": {}, \"types\": {{{}}}}}",
+            self.node_count,
+            self.max_depth,
+            self.count(NodeType::Rand),
+            self.count(NodeType::T),
+            self.cost,
+            types
+        )
+    }
+}
+
+impl Display for NodeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "  nodes: {}", self.node_count)?;
+        writeln!(f, "  max depth: {}", self.max_depth)?;
+        writeln!(f, "  rand: {}", self.count(NodeType::Rand))?;
+        writeln!(f, "  t: {}", self.count(NodeType::T))?;
+        writeln!(f, "  estimated cost per pixel: {}", self.cost)?;
+        writeln!(f, "  types:")?;
+        for (node_type, count) in &self.type_counts {
+            if *count > 0 {
+                writeln!(f, "    {}: {}", node_type, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Statistics about all channels of an AST
+#[derive(Clone, Debug)]
+pub struct AstStats {
+    pub r: NodeStats,
+    pub g: NodeStats,
+    pub b: NodeStats,
+    /// The statistics of the alpha channel, if the AST has one
+    pub a: Option<NodeStats>,
+}
+
+impl AstStats {
+    pub fn from_ast(ast: &NodeAst) -> Self {
+        Self {
+            r: NodeStats::from_node(&ast.r),
+            g: NodeStats::from_node(&ast.g),
+            b: NodeStats::from_node(&ast.b),
+            a: ast.a.as_ref().map(|x| NodeStats::from_node(x)),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let alpha = match &self.a {
+            Some(a) => format!(", \"a\": {}", a.to_json()),
+            None => String::new(),
+        };
+        format!(
+            "{{\"r\": {}, \"g\": {}, \"b\": {}{}}}",
+            self.r.to_json(),
+            self.g.to_json(),
+            self.b.to_json(),
+            alpha
+        )
+    }
+}
+
+impl Display for AstStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "R:\n{}G:\n{}B:\n{}", self.r, self.g, self.b)?;
+        if let Some(a) = &self.a {
+            write!(f, "A:\n{}", a)?;
+        }
+        Ok(())
+    }
+}
@@ -1,31 +1,427 @@
-use std::cell::{OnceCell, UnsafeCell};
+use std::{
+    fs::File,
+    path::Path,
+    sync::{
+        LazyLock, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
 
 use primitive_types::U256;
-use rand::SeedableRng;
+use rand::{Rng, RngCore, SeedableRng, TryRngCore, rngs::OsRng};
 use rand_chacha::ChaCha20Rng;
 
-static mut SHARED_RAND: OnceCell<UnsafeCell<ChaCha20Rng>> = OnceCell::new();
+use crate::hash;
 
-#[allow(static_mut_refs)]
-pub fn get_rng() -> &'static mut ChaCha20Rng {
-    unsafe {
-        _ = SHARED_RAND.set(ChaCha20Rng::from_os_rng().into());
-        SHARED_RAND.get_mut().unwrap().get_mut()
+/// The RNG everything random is drawn from, seeded from the OS until a seed is set. It is behind
+/// a lock, so it can be used from any thread, but is only drawn from in a fixed order, so a seed
+/// always gives the same draws
+static SHARED_RAND: LazyLock<Mutex<ChaCha20Rng>> = LazyLock::new(|| {
+    let rng = ChaCha20Rng::from_os_rng();
+    set_noise_key(&rng);
+    Mutex::new(rng)
+});
+
+/// The RNGs of the streams, derived from the seed of the shared RNG, which are drawn from instead
+/// of it when the streams are separate. See `set_separate_streams()`
+static STREAM_RAND: LazyLock<[Mutex<ChaCha20Rng>; 3]> =
+    LazyLock::new(|| stream_rngs(get_seed()).map(Mutex::new));
+
+/// If each stream has its own RNG, instead of every draw coming from the shared RNG
+static SEPARATE_STREAMS: AtomicBool = AtomicBool::new(false);
+
+/// The key rand nodes are hashed with, derived from the seed by `set_noise_key()`
+static NOISE_KEY: AtomicU64 = AtomicU64::new(0);
+
+/// If rand nodes draw from the shared RNG, like before they were hashed
+static LEGACY_RAND: AtomicBool = AtomicBool::new(false);
+
+/// The phrase of the last seed set by `set_seed_str()`, if it was hashed from one, and the seed
+static SEED_PHRASE: Mutex<Option<(U256, String)>> = Mutex::new(None);
+
+/// Held by the tests which draw from the global RNGs, or depend on the seed, since the tests run at
+/// the same time
+#[cfg(test)]
+pub static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// The parts of generation which draw from their own RNG when the streams are separate, so a
+/// change to one of them, like another literal, doesn't change the draws of the others
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stream {
+    /// The node types picked from the grammar, and the branches picked by mutation and breeding
+    Structure,
+    /// The values of literals, and the operators of if statements
+    Literals,
+    /// The values of rand nodes with --legacy-rand
+    Runtime,
+}
+
+impl Stream {
+    /// Gets the index of the stream in `STREAM_RAND`
+    fn index(&self) -> usize {
+        match self {
+            Stream::Structure => 0,
+            Stream::Literals => 1,
+            Stream::Runtime => 2,
+        }
+    }
+
+    /// Gets the tag the seed of the stream is derived with
+    fn tag(&self) -> &'static [u8] {
+        match self {
+            Stream::Structure => b"structure",
+            Stream::Literals => b"literals",
+            Stream::Runtime => b"runtime",
+        }
     }
 }
 
-#[allow(static_mut_refs)]
-pub fn set_seed(seed: U256) {
-    let seed_bytes = seed.to_little_endian();
-    unsafe {
-        let seeded_rng = ChaCha20Rng::from_seed(seed_bytes);
-        if SHARED_RAND.set(seeded_rng.clone().into()).is_err() {
-            *SHARED_RAND.get_mut().unwrap().get_mut() = ChaCha20Rng::from_seed(seed_bytes);
+/// Something generation draws from, for each stream. Every `Rng` is one, which every stream
+/// draws from in turn, so generation only needs a seeded RNG, like `ChaCha20Rng`. Use `Seeded` to
+/// draw from the RNGs seeded by `set_seed()`
+pub trait StreamRng {
+    /// Draws from the RNG of the stream with `draw`
+    fn draw<T>(&mut self, stream: Stream, draw: impl FnOnce(&mut dyn RngCore) -> T) -> T;
+}
+
+impl<R: Rng> StreamRng for R {
+    fn draw<T>(&mut self, _stream: Stream, draw: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+        draw(self)
+    }
+}
+
+/// The RNGs seeded by `set_seed()`, which draws through `get_rng()`, so the streams are separate
+/// if `set_separate_streams()` made them
+pub struct Seeded;
+
+impl StreamRng for Seeded {
+    fn draw<T>(&mut self, stream: Stream, draw: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+        draw(&mut *get_rng(stream))
+    }
+}
+
+/// The RNGs of a seed, which generation draws from, seeded like `set_seed()` seeds the global
+/// ones. Every stream draws from the shared RNG, unless the streams are separate
+pub struct Streams {
+    shared: ChaCha20Rng,
+    separate: Option<[ChaCha20Rng; 3]>,
+}
+
+impl Streams {
+    /// Creates the RNGs of the seed. See `set_separate_streams()`
+    pub fn new(seed: U256, separate: bool) -> Self {
+        Self {
+            shared: ChaCha20Rng::from_seed(seed.to_little_endian()),
+            separate: separate.then(|| stream_rngs(seed)),
+        }
+    }
+}
+
+impl StreamRng for Streams {
+    fn draw<T>(&mut self, stream: Stream, draw: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+        match &mut self.separate {
+            Some(rngs) => draw(&mut rngs[stream.index()]),
+            None => draw(&mut self.shared),
         }
     }
 }
 
+/// Makes rand nodes with --legacy-rand draw from the runtime stream of `streams`, where
+/// generation left it. Before generation had its own RNGs, they drew from the global ones, right
+/// after generation, so this keeps their values the same
+pub fn resume_runtime(streams: Streams) {
+    match streams.separate {
+        Some([_, _, runtime]) => {
+            let stream = &STREAM_RAND[Stream::Runtime.index()];
+            *stream.lock().unwrap_or_else(PoisonError::into_inner) = runtime;
+        }
+        None => *SHARED_RAND.lock().unwrap_or_else(PoisonError::into_inner) = streams.shared,
+    }
+}
+
+/// Locks the RNG of the stream, which is the shared RNG unless the streams are separate. The
+/// lock is held until the guard is dropped, so the guard should only live for the draw it is
+/// used for, like `get_rng(Stream::Literals).random_range(..)`, and never while something else
+/// may draw from it
+pub fn get_rng(stream: Stream) -> MutexGuard<'static, ChaCha20Rng> {
+    // A panic while drawing can't leave the RNG in an invalid state
+    match SEPARATE_STREAMS.load(Ordering::Relaxed) {
+        true => STREAM_RAND[stream.index()].lock(),
+        false => SHARED_RAND.lock(),
+    }
+    .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Seeds the shared RNG and the RNGs of the streams, and derives the key of rand nodes from the
+/// seed
+pub fn set_seed(seed: U256) {
+    let rng = ChaCha20Rng::from_seed(seed.to_little_endian());
+    {
+        let mut shared = SHARED_RAND.lock().unwrap_or_else(PoisonError::into_inner);
+        set_noise_key(&rng);
+        *shared = rng;
+    }
+
+    // The shared RNG is unlocked first, since the streams are derived from it when they are
+    // first used
+    for (stream, rng) in STREAM_RAND.iter().zip(stream_rngs(seed)) {
+        *stream.lock().unwrap_or_else(PoisonError::into_inner) = rng;
+    }
+}
+
+/// Picks a seed from the OS, for runs without one. The seed is picked before anything is drawn,
+/// so it always recreates the run
+pub fn os_seed() -> Result<U256, String> {
+    let mut seed = [0; 32];
+    OsRng.try_fill_bytes(&mut seed).map_err(|e| e.to_string())?;
+    Ok(U256::from_little_endian(&seed))
+}
+
 pub fn get_seed() -> U256 {
-    let seed_bytes = get_rng().get_seed();
+    let seed_bytes = SHARED_RAND
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_seed();
     U256::from_little_endian(&seed_bytes)
 }
+
+/// Makes each stream draw from its own RNG, derived from the seed, instead of every draw coming
+/// from the shared RNG. This is used by grammars of version 3 and up, since it changes the images
+/// of every seed
+pub fn set_separate_streams(separate: bool) {
+    SEPARATE_STREAMS.store(separate, Ordering::Relaxed);
+}
+
+/// Creates the RNGs of the streams, in the order of `Stream::index()`. Each is seeded with a
+/// hash of the seed and the tag of the stream
+fn stream_rngs(seed: U256) -> [ChaCha20Rng; 3] {
+    [Stream::Structure, Stream::Literals, Stream::Runtime].map(|stream| {
+        let mut bytes = b"kroyer-stream-".to_vec();
+        bytes.extend(stream.tag());
+        bytes.extend(seed.to_little_endian());
+        ChaCha20Rng::from_seed(hash::sha256(&bytes))
+    })
+}
+
+/// Parses a seed, which is either a number or a phrase. Decimal numbers, and hex numbers with a
+/// `0x` prefix, are read as numbers. Anything else is a phrase, like `purple mountains 3`, which
+/// is hashed into a seed with SHA-256. Hex needs the prefix, so words like `face` are phrases.
+/// Whitespace around the seed is ignored. Gets the seed, and the phrase, if it was one
+pub fn parse_seed(text: &str) -> Result<(U256, Option<String>), String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err("the seed is empty".to_owned());
+    }
+
+    if let Some(hex) = text.strip_prefix("0x").or(text.strip_prefix("0X")) {
+        return U256::from_str_radix(hex, 16)
+            .map(|x| (x, None))
+            .map_err(|e| format!("invalid hex number: {}", e));
+    }
+    if text.bytes().all(|x| x.is_ascii_digit()) {
+        return U256::from_dec_str(text)
+            .map(|x| (x, None))
+            .map_err(|e| format!("invalid decimal number: {}", e));
+    }
+    let seed = U256::from_little_endian(&hash::sha256(text.as_bytes()));
+    Ok((seed, Some(text.to_owned())))
+}
+
+/// Hashes the contents of a file into a seed with SHA-256, like a phrase. The file is read a part
+/// at a time, so it can be of any size
+pub fn seed_from_file(path: &Path) -> Result<U256, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let hash = hash::sha256_reader(file).map_err(|e| e.to_string())?;
+    Ok(U256::from_little_endian(&hash))
+}
+
+/// Seeds the shared RNG with a seed parsed by `parse_seed()`, and remembers its phrase, if it was
+/// one
+pub fn set_seed_str(text: &str) -> Result<(), String> {
+    let (seed, phrase) = parse_seed(text)?;
+    set_seed(seed);
+    *SEED_PHRASE.lock().unwrap_or_else(PoisonError::into_inner) = phrase.map(|x| (seed, x));
+    Ok(())
+}
+
+/// Gets the phrase the current seed was hashed from, if it was set from one by `set_seed_str()`.
+/// Seeds derived from it, like those of a batch, have no phrase
+pub fn seed_phrase() -> Option<String> {
+    let seed = get_seed();
+    let phrase = SEED_PHRASE.lock().unwrap_or_else(PoisonError::into_inner);
+    phrase
+        .as_ref()
+        .filter(|(phrase_seed, _)| *phrase_seed == seed)
+        .map(|(_, phrase)| phrase.clone())
+}
+
+/// The domain of the seeds of the images of a batch, and the cells of a contact sheet
+pub const BATCH_DOMAIN: &str = "batch";
+
+/// Derives the seed at `index` of `domain` from `master`, which is a SHA-256 of `kroyer-`, the
+/// domain, the master seed and the index, where the numbers are little-endian, and the hash is
+/// read as a little-endian number. Each domain, like `BATCH_DOMAIN`, gives its own seeds, so seeds
+/// derived for one use never repeat those of another. The derivation must never change, since
+/// it would change every image made from a derived seed
+pub fn derive_seed(master: U256, domain: &str, index: u64) -> U256 {
+    let mut bytes = b"kroyer-".to_vec();
+    bytes.extend(domain.as_bytes());
+    bytes.extend(master.to_little_endian());
+    bytes.extend(index.to_le_bytes());
+    U256::from_little_endian(&hash::sha256(&bytes))
+}
+
+/// Derives the key rand nodes are hashed with from the seed of `rng`
+fn set_noise_key(rng: &ChaCha20Rng) {
+    let mut bytes = b"kroyer-noise".to_vec();
+    bytes.extend(rng.get_seed());
+    let hash = hash::sha256(&bytes);
+    let key = u64::from_le_bytes(hash[..8].try_into().unwrap());
+    NOISE_KEY.store(key, Ordering::Relaxed);
+}
+
+/// Makes rand nodes draw from the shared RNG for every pixel, instead of hashing
+pub fn set_legacy_rand(legacy: bool) {
+    LEGACY_RAND.store(legacy, Ordering::Relaxed);
+}
+
+/// Checks if rand nodes draw from the shared RNG. See `set_legacy_rand()`
+pub fn legacy_rand() -> bool {
+    LEGACY_RAND.load(Ordering::Relaxed)
+}
+
+/// Gets a value in the range `-1..1` for the point at `x` and `y`, which only depends on the
+/// point, the seed and `salt`, so it is the same regardless of what else is rendered, or in which
+/// order. The bits are mixed with the finalizer of SplitMix64
+pub fn noise(x: f64, y: f64, salt: u32) -> f64 {
+    let mix = |mut z: u64| {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+
+    let mut hash = mix(NOISE_KEY.load(Ordering::Relaxed) ^ salt as u64);
+    for value in [x, y] {
+        hash = mix(hash.wrapping_add(0x9e3779b97f4a7c15) ^ value.to_bits());
+    }
+    (hash >> 11) as f64 / (1u64 << 53) as f64 * 2. - 1.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // These tests also run under Miri, with `cargo +nightly miri test --bin kroyer rng::`. The
+    // seed isn't set here, since that would change the values of rand nodes in the tests which
+    // run at the same time
+
+    fn lock() -> MutexGuard<'static, ()> {
+        TEST_LOCK.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[test]
+    fn threads_never_draw_the_same_values() {
+        let _lock = lock();
+        // Every thread draws from the same RNG, so together they draw its next values
+        let mut copy = get_rng(Stream::Structure).clone();
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    (0..16)
+                        .map(|_| get_rng(Stream::Structure).next_u64())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut draws: Vec<u64> = threads
+            .into_iter()
+            .flat_map(|x| x.join().unwrap())
+            .collect();
+        let mut expected: Vec<u64> = (0..64).map(|_| copy.next_u64()).collect();
+
+        draws.sort();
+        expected.sort();
+        assert_eq!(draws, expected);
+    }
+
+    #[test]
+    fn noise_only_depends_on_the_point_and_salt() {
+        let _lock = lock();
+        let noise_of = |salt| [(0., 0.), (0.5, -0.25), (1., 1.)].map(|(x, y)| noise(x, y, salt));
+        assert_eq!(noise_of(1), noise_of(1));
+        assert_ne!(noise_of(1), noise_of(2));
+        assert!(noise_of(3).iter().all(|x| (-1. ..1.).contains(x)));
+    }
+
+    #[test]
+    fn numbers_are_read_as_seeds() {
+        let number = U256::from(0x2a);
+        assert_eq!(parse_seed("42"), Ok((number, None)));
+        assert_eq!(parse_seed(" 0x2a\n"), Ok((number, None)));
+        assert_eq!(parse_seed("0X2A"), Ok((number, None)));
+        assert!(parse_seed("0xzz").is_err());
+        assert!(parse_seed("  ").is_err());
+    }
+
+    #[test]
+    fn phrases_are_hashed_into_seeds() {
+        // Computed with Python's hashlib, as the little-endian number of the SHA-256 of the phrase
+        let phrases = [
+            (
+                "purple mountains 3",
+                "d00445ff3cccb85c2ca6c73799a295dcf5e0566d78149ef7b5f8ad8fed0ddd4b",
+            ),
+            // Hex needs the prefix, so a word of hex digits is a phrase
+            (
+                "face",
+                "752788aed09669f6b2009aaed8e53ffcaa16ddf20fb250154cc7429fb7d98202",
+            ),
+        ];
+        for (phrase, seed) in phrases {
+            let seed = U256::from_str_radix(seed, 16).unwrap();
+            assert_eq!(parse_seed(phrase), Ok((seed, Some(phrase.to_owned()))));
+        }
+    }
+
+    #[test]
+    fn derived_seeds_never_change() {
+        let seed = |hex: &str| U256::from_str_radix(hex, 16).unwrap();
+        // Computed with Python's hashlib, from the layout documented on `derive_seed()`
+        let vectors = [
+            (
+                seed("2a"),
+                BATCH_DOMAIN,
+                0,
+                "603c362a0f90973e4ea44110d404a6b00bb587a4ac0c910e31fa77f5dcdb111",
+            ),
+            (
+                seed("2a"),
+                BATCH_DOMAIN,
+                1,
+                "4c29b08e14a1dd6a2bed78bce5d4d8cf4ae33a69db42f8ae7458218713c1f6b3",
+            ),
+            (
+                seed("2a"),
+                "frame",
+                0,
+                "c75ea0e70fa0512d26cd2145085731be162e80b295789d1d0d432a6fe81e5f98",
+            ),
+            (
+                U256::MAX,
+                BATCH_DOMAIN,
+                u64::MAX,
+                "5b386f4370df55f9c3309c7b60a1ccb559d42480e79f68c11ac700b8f6703ea4",
+            ),
+        ];
+        for (master, domain, index, expected) in vectors {
+            assert_eq!(
+                derive_seed(master, domain, index),
+                seed(expected),
+                "{} {} {}",
+                master,
+                domain,
+                index
+            );
+        }
+    }
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use common::{kroyer, stderr, tmp_file};
+
+#[test]
+fn too_deep_ast_exits_with_the_ast_code() {
+    let depth = 1500;
+    let ast = format!("RGB: {}x{}", "sin(".repeat(depth), ")".repeat(depth));
+    let path = tmp_file("limits-deep.ast", &ast);
+
+    let output = kroyer(&["--validate-ast", &path]);
+    assert_eq!(output.status.code(), Some(4));
+    let message = stderr(&output);
+    assert!(message.contains("nested at least 1001 deep"), "{}", message);
+    assert!(message.contains("the limit of 1000"), "{}", message);
+}
+
+#[test]
+fn too_big_ast_exits_with_the_ast_code() {
+    let length = 600;
+    let ast = format!("RGB: {}y{}", "max(x, ".repeat(length), ")".repeat(length));
+    let path = tmp_file("limits-big.ast", &ast);
+
+    let output = kroyer(&["--validate-ast", &path, "--max-ast-nodes", "1000"]);
+    assert_eq!(output.status.code(), Some(4));
+    let message = stderr(&output);
+    assert!(message.contains("at least 1001 nodes"), "{}", message);
+    assert!(message.contains("the limit of 1000"), "{}", message);
+
+    let output = kroyer(&["--validate-ast", &path]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+}
+
+#[test]
+fn definitions_count_towards_the_node_limit() {
+    // Each definition doubles the size of the last one, so the AST would have 2^40 nodes
+    let mut ast = "def d0 = add(x, y)\n".to_owned();
+    for i in 1..40 {
+        ast += &format!("def d{} = add(d{}, d{})\n", i, i - 1, i - 1);
+    }
+    ast += "RGB: d39\n";
+    let path = tmp_file("limits-definitions.ast", &ast);
+
+    let output = kroyer(&["--validate-ast", &path]);
+    assert_eq!(output.status.code(), Some(4));
+    let message = stderr(&output);
+    assert!(message.contains("the limit of 1000000"), "{}", message);
+}
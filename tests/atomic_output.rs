@@ -0,0 +1,51 @@
+mod common;
+
+use std::path::Path;
+
+use common::{kroyer, stderr, tmp_path};
+
+/// Gets the names of the files in `dir`
+fn files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|x| x.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Renders an icon wider than the 256 pixels icons can be, so the encoder fails after the file
+/// is created
+fn failing_encode(path: &Path) {
+    let path = path.to_str().unwrap();
+    let output = kroyer(&["--width", "300", "--height", "8", "--out", path]);
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("Failed to save image"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn failed_encode_leaves_no_file() {
+    let dir = tmp_path("atomic-new");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    failing_encode(&dir.join("out.ico"));
+    assert_eq!(files(&dir), Vec::<String>::new());
+}
+
+#[test]
+fn failed_encode_keeps_the_old_file() {
+    let dir = tmp_path("atomic-old");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("out.ico");
+    std::fs::write(&path, "the last render").unwrap();
+
+    failing_encode(&path);
+    assert_eq!(files(&dir), ["out.ico"]);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "the last render");
+}
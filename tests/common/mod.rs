@@ -0,0 +1,66 @@
+//! Helpers for running the kroyer binary in integration tests
+
+#![allow(dead_code)]
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+/// Runs kroyer with the arguments, with STDIN closed, so it is never read from
+pub fn kroyer(args: &[&str]) -> Output {
+    kroyer_with_home(args, &tmp_path("home"))
+}
+
+/// Runs kroyer like `kroyer()`, with `home` as the home directory, where the config file is
+pub fn kroyer_with_home(args: &[&str], home: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kroyer"))
+        .args(args)
+        .stdin(Stdio::null())
+        .env_remove("XDG_CONFIG_HOME")
+        .env("HOME", home)
+        .output()
+        .expect("failed to run kroyer")
+}
+
+/// Runs kroyer with the arguments, with `input` piped into STDIN
+pub fn kroyer_with_stdin(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kroyer"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .env_remove("XDG_CONFIG_HOME")
+        .env("HOME", tmp_path("home"))
+        .spawn()
+        .expect("failed to run kroyer");
+    // kroyer may exit without reading STDIN, so failing to write all of it is fine
+    _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    child.wait_with_output().expect("failed to run kroyer")
+}
+
+/// Gets a path in the temporary directory of the tests. Each test should use its own names, since
+/// the tests run in parallel
+pub fn tmp_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name)
+}
+
+/// Writes a file into the temporary directory of the tests, and gets its path as a string
+pub fn tmp_file(name: &str, content: &str) -> String {
+    let path = tmp_path(name);
+    std::fs::write(&path, content).expect("failed to write a test file");
+    path.to_string_lossy().into_owned()
+}
+
+/// Gets what kroyer wrote to STDERR
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// Hashes bytes with 64-bit FNV-1a, to pin rendered images in tests without committing them
+pub fn fnv(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
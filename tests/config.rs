@@ -0,0 +1,112 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::{kroyer_with_home, stderr, tmp_file, tmp_path};
+
+const GRAMMAR: &str = "x: 1\ny: 1\nadd: 2\n";
+
+/// Makes a home directory for a test, with `config` as the config file in it
+fn home(name: &str, config: &str) -> PathBuf {
+    let home = tmp_path(name);
+    let dir = home.join(".config").join("kroyer");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("config.toml"), config).unwrap();
+    home
+}
+
+/// Gets the width and height of a PNG, from its header
+fn png_size(path: &Path) -> (u32, u32) {
+    let bytes = std::fs::read(path).unwrap();
+    assert_eq!(&bytes[..8], b"\x89PNG\r\n\x1a\n", "{:?} isn't a PNG", path);
+    let number = |at: usize| u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap());
+    (number(16), number(20))
+}
+
+/// Runs `kroyer config dump` in `home`, with the arguments
+fn dump(home: &Path, args: &[&str]) -> String {
+    let output = kroyer_with_home(&[&["config", "dump"], args].concat(), home);
+    assert!(output.status.success(), "{}", stderr(&output));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn flags_win_over_the_config_file() {
+    let out_dir = tmp_path("config-flags-out");
+    let config = format!(
+        "width = 6\nheight = 5 # a comment\nformat = \"gif\"\nout_dir = {:?}\n",
+        out_dir
+    );
+    let home = home("config-flags-home", &config);
+    let grammar = tmp_file("config-flags.kroyer", GRAMMAR);
+
+    // The directory and size are from the config file, but --format wins over its format
+    let output = kroyer_with_home(&[&grammar, "--format", "still", "--force"], &home);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(png_size(&out_dir.join("out.png")), (6, 5));
+
+    // The extension of --out decides the format too, so the format of the config isn't used
+    let out = tmp_path("config-flags.png");
+    let output = kroyer_with_home(
+        &[&grammar, "--width", "3", "--out", out.to_str().unwrap()],
+        &home,
+    );
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(png_size(&out), (3, 5));
+}
+
+#[test]
+fn dumps_tell_where_each_setting_is_from() {
+    let home = home("config-dump-home", "width = 6\ntone_map = 'tanh'\n");
+    let path = home.join(".config").join("kroyer").join("config.toml");
+    assert_eq!(
+        dump(&home, &[]),
+        format!(
+            "# The settings from the config file {:?}\n\
+             width = 6  # config\n\
+             height = 512  # default\n\
+             depth = 10  # default\n\
+             frames = 255  # default\n\
+             # out_dir isn't set\n\
+             tone_map = \"tanh\"  # config\n\
+             # format isn't set\n",
+            path
+        )
+    );
+
+    // --config is read instead of the file in the home directory
+    let other = tmp_file("config-dump-other.toml", "depth = 4\n");
+    let text = dump(&home, &["--config", &other]);
+    assert!(text.contains("width = 512  # default\n"), "{}", text);
+    assert!(text.contains("depth = 4  # config\n"), "{}", text);
+
+    let empty = tmp_path("config-dump-empty-home");
+    std::fs::create_dir_all(&empty).unwrap();
+    let text = dump(&empty, &[]);
+    assert!(
+        text.starts_with("# The built-in settings, since no config file was found\n"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn bad_config_files_are_errors() {
+    let grammar = tmp_file("config-bad.kroyer", GRAMMAR);
+    let home = home("config-bad-home", "width = \"wide\"\n");
+    let output = kroyer_with_home(&[&grammar], &home);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("Line 1: `width` has to be a whole number"),
+        "{}",
+        stderr(&output)
+    );
+
+    // A missing --config is an error, but a missing file in the home directory isn't
+    let missing = tmp_path("config-missing.toml");
+    let output = kroyer_with_home(
+        &["config", "dump", "--config", missing.to_str().unwrap()],
+        &home,
+    );
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+}
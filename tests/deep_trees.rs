@@ -0,0 +1,24 @@
+mod common;
+
+use common::{kroyer, stderr, tmp_file};
+
+#[test]
+fn very_deep_trees_are_generated_rendered_and_hashed() {
+    // The grammar only picks sin, until the depth runs out and x ends the chain
+    let grammar = tmp_file("deep.kroyer", "sin: 1\nx: 0\n");
+    let output = kroyer(&[
+        &grammar,
+        "--depth",
+        "100000",
+        "--width",
+        "4",
+        "--height",
+        "4",
+        "--format",
+        "ppm",
+        "--dump-hash",
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(output.stdout.starts_with(b"P6\n4 4\n255\n"));
+    assert!(stderr(&output).contains("HASH: "), "{}", stderr(&output));
+}
@@ -0,0 +1,40 @@
+mod common;
+
+use common::{fnv, kroyer, stderr, tmp_file};
+
+/// The default grammar of kroyer before the RNG was put behind a lock. It has no rand nodes,
+/// whose values were changed on purpose later, so only the draws of generation are pinned
+const OLD_DEFAULT_GRAMMAR: &str = "\
+x: 1
+y: 1
+literal: 1
+mod: 3
+sin: 5
+tan: 4
+mult: 3
+add: 3
+sqrt: 3
+max: 3
+if: 1
+";
+
+/// Images rendered by kroyer before the shared RNG was put behind a lock, hashed as PPMs. Older
+/// versions read seeds as hex without the prefix, like `2a`
+const OLD_IMAGES: [(&str, u64); 3] = [
+    ("0x2a", 0xb6ce8c78ce3c1ddc),
+    ("0xbeef", 0xf01828ba6f866ff8),
+    ("0xc0ffee", 0x052e6a38c7c9dd1a),
+];
+
+#[test]
+fn seeds_give_the_images_of_older_versions() {
+    let grammar = tmp_file("determinism.kroyer", OLD_DEFAULT_GRAMMAR);
+    for (seed, hash) in OLD_IMAGES {
+        let output = kroyer(&[
+            &grammar, "--seed", seed, "--width", "32", "--height", "24", "--format", "ppm",
+            "--out", "-",
+        ]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(fnv(&output.stdout), hash, "seed {}", seed);
+    }
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::{kroyer, stderr, tmp_file, tmp_path};
+
+const GRAMMAR: &str = "x: 1\ny: 1\nsin: 2\nadd: 2\n";
+
+/// Renders the grammar with the seed into `name`, with the extra arguments, and gets STDOUT
+fn render(name: &str, grammar: &str, args: &[&str]) -> String {
+    let out = tmp_path(name);
+    let out = out.to_string_lossy();
+    let size = ["--width", "8", "--height", "8", "--force", "--out", &out];
+    let output = kroyer(&[&[grammar, "--seed", "0x2a"], &size[..], args].concat());
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn dump_files_have_the_content_of_the_dumps() {
+    let grammar = tmp_file("dump-files.kroyer", GRAMMAR);
+    let dir = tmp_path("dump-files");
+    _ = std::fs::remove_dir_all(&dir);
+    let [seed, ast, grammar_dump] = ["seed.txt", "ast.txt", "grammar.txt"]
+        .map(|x| dir.join("nested").join(x).to_string_lossy().into_owned());
+
+    render(
+        "dump-files.png",
+        &grammar,
+        &[
+            "--dump-seed-file",
+            &seed,
+            "--dump-ast-file",
+            &ast,
+            "--dump-grammar-file",
+            &grammar_dump,
+        ],
+    );
+    let read = |path: &str| std::fs::read_to_string(path).unwrap();
+
+    assert_eq!(read(&seed), "0x2a\n");
+    assert_eq!(
+        read(&seed),
+        render("dump-seed.png", &grammar, &["--dump-seed"])
+    );
+    assert_eq!(
+        read(&ast),
+        render("dump-ast.png", &grammar, &["--dump-ast"])
+    );
+    assert_eq!(
+        format!("# CURRENT GRAMMAR\n{}\n", read(&grammar_dump)),
+        render("dump-grammar.png", &grammar, &["--dump-grammar"])
+    );
+
+    // The dumped AST renders the same image
+    render("dump-files-ast.png", &grammar, &["--ast", &ast]);
+    assert_eq!(
+        std::fs::read(tmp_path("dump-files.png")).unwrap(),
+        std::fs::read(tmp_path("dump-files-ast.png")).unwrap()
+    );
+
+    // Existing dumps are only overwritten with --force, which `render()` always passes
+    let out = tmp_path("dump-files-again.png");
+    let output = kroyer(&[
+        &grammar,
+        "--seed",
+        "0x2a",
+        "--dump-seed-file",
+        &seed,
+        "--out",
+        &out.to_string_lossy(),
+    ]);
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+}
+
+#[test]
+fn batches_dump_a_numbered_file_per_image() {
+    let grammar = tmp_file("dump-batch.kroyer", GRAMMAR);
+    let dir = tmp_path("dump-batch");
+    _ = std::fs::remove_dir_all(&dir);
+    let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+    render(
+        "dump-batch.png",
+        &grammar,
+        &[
+            "--count",
+            "2",
+            "--dump-seed-file",
+            &path("seed.txt"),
+            "--dump-grammar-file",
+            &path("grammar.txt"),
+        ],
+    );
+
+    let mut names: Vec<String> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|x| x.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["grammar.txt", "seed-001.txt", "seed-002.txt"]);
+
+    let seeds = ["seed-001.txt", "seed-002.txt"].map(|x| std::fs::read_to_string(path(x)).unwrap());
+    assert_ne!(seeds[0], seeds[1]);
+    assert!(
+        seeds
+            .iter()
+            .all(|x| x.starts_with("0x") && x.ends_with('\n'))
+    );
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use common::{kroyer, stderr, tmp_file, tmp_path};
+
+/// Renders the grammar into an 8 by 6 image with the arguments, and gets STDOUT
+fn render(grammar: &str, args: &[&str]) -> Vec<u8> {
+    let size = ["--seed", "0x2a", "--width", "8", "--height", "6"];
+    let output = kroyer(&[&[grammar], &size[..], args].concat());
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    output.stdout
+}
+
+#[test]
+fn stills_are_dumped_as_the_png_which_would_be_saved() {
+    let grammar = tmp_file("dump-raw.kroyer", "x: 1\ny: 1\nsin: 2\nadd: 2\n");
+    let raw = render(&grammar, &["--dump-raw"]);
+
+    // The signature, then the IHDR chunk with the size, 8 bits per channel, and RGBA pixels
+    assert_eq!(raw[..8], *b"\x89PNG\r\n\x1a\n");
+    assert_eq!(raw[8..16], *b"\0\0\0\x0dIHDR");
+    assert_eq!(raw[16..24], [0, 0, 0, 8, 0, 0, 0, 6]);
+    assert_eq!(raw[24..26], [8, 6]);
+    assert_eq!(raw[raw.len() - 12..], *b"\0\0\0\0IEND\xae\x42\x60\x82");
+
+    let out = tmp_path("dump-raw.png");
+    render(&grammar, &["--force", "--out", &out.to_string_lossy()]);
+    assert_eq!(raw.len(), std::fs::metadata(&out).unwrap().len() as usize);
+    assert_eq!(raw, std::fs::read(&out).unwrap());
+
+    // The pixels are the ones of every other format
+    let ppm = render(&grammar, &["--format", "ppm"]);
+    let png = image::load_from_memory(&raw).unwrap().into_rgb8();
+    assert_eq!(png.dimensions(), (8, 6));
+    assert_eq!(png.as_raw()[..], ppm[b"P6\n8 6\n255\n".len()..]);
+}
+
+#[test]
+fn animations_are_dumped_as_a_gif() {
+    let grammar = tmp_file("dump-raw-t.kroyer", "x: 1\nt: 1\nsin: 2\nadd: 2\n");
+    let raw = render(&grammar, &["--dump-raw", "--frames", "3"]);
+
+    // The header, then the logical screen size as little endian, and the trailer at the end
+    assert_eq!(raw[..6], *b"GIF89a");
+    assert_eq!(raw[6..10], [8, 0, 6, 0]);
+    assert_eq!(raw.last(), Some(&0x3b));
+
+    let decoder = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(&raw)).unwrap();
+    let frames = image::AnimationDecoder::into_frames(decoder)
+        .collect_frames()
+        .unwrap();
+    assert_eq!(frames.len(), 3);
+    assert!(frames.iter().all(|x| x.buffer().dimensions() == (8, 6)));
+}
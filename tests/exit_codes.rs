@@ -0,0 +1,90 @@
+mod common;
+
+use common::{kroyer, stderr, tmp_file, tmp_path};
+
+/// A grammar which can be rendered
+const GRAMMAR: &str = "x: 1\ny: 1\nadd: 2\n";
+
+#[test]
+fn success_exits_with_0() {
+    let grammar = tmp_file("exit-ok.kroyer", GRAMMAR);
+    let output = kroyer(&[
+        &grammar, "--width", "8", "--height", "8", "--format", "ppm", "--out", "-",
+    ]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+
+    let ast = tmp_file("exit-ok.ast", "RGB: add(x, y)\n");
+    let output = kroyer(&["--validate-ast", &ast]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+}
+
+#[test]
+fn invalid_arguments_exit_with_2() {
+    let grammar = tmp_file("exit-usage.kroyer", GRAMMAR);
+    let output = kroyer(&[&grammar, "--compat", "9"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+
+    let output = kroyer(&[&grammar, "--no-such-flag"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+
+    // Regions have to be within the image
+    let region = ["--width", "8", "--height", "8", "--region", "6,0,4,4"];
+    let output = kroyer(&[&[grammar.as_str()], &region[..], &["--format", "ppm"]].concat());
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("reaches outside"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn unusable_grammars_exit_with_3() {
+    let grammar = tmp_file("exit-grammar.kroyer", "sin: 3\nadd: 2\n");
+    let output = kroyer(&[&grammar, "--out", "-", "--format", "ppm"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+
+    let grammar = tmp_file("exit-version.kroyer", "@kroyer-version: 999\nx: 1\n");
+    let output = kroyer(&[&grammar, "--out", "-", "--format", "ppm"]);
+    assert_eq!(output.status.code(), Some(3), "{}", stderr(&output));
+}
+
+#[test]
+fn unparsable_asts_exit_with_4() {
+    let ast = tmp_file("exit-ast.ast", "RGB: sin(");
+    let output = kroyer(&["--validate-ast", &ast]);
+    assert_eq!(output.status.code(), Some(4), "{}", stderr(&output));
+
+    let output = kroyer(&["--ast", &ast, "--out", "-", "--format", "ppm"]);
+    assert_eq!(output.status.code(), Some(4), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn files_which_cant_be_read_or_written_exit_with_5() {
+    let missing = tmp_path("exit-missing.kroyer");
+    let output = kroyer(&[&missing.to_string_lossy()]);
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+
+    let grammar = tmp_file("exit-io.kroyer", GRAMMAR);
+    let out = tmp_path("exit-no-such-dir").join("out.png");
+    let output = kroyer(&[
+        &grammar,
+        "--width",
+        "8",
+        "--height",
+        "8",
+        "--out",
+        &out.to_string_lossy(),
+    ]);
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+    assert!(!out.exists());
+
+    // An AST file which opens, but isn't text, can't be read
+    let ast = tmp_path("exit-binary.ast");
+    std::fs::write(&ast, b"RGB: \xff\xfe").unwrap();
+    let output = kroyer(&["--validate-ast", &ast.to_string_lossy()]);
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+    assert!(stderr(&output).contains("Failed to read AST file"));
+}
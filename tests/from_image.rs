@@ -0,0 +1,56 @@
+mod common;
+
+use common::{kroyer, stderr, tmp_file, tmp_path};
+
+const GRAMMAR: &str = "x: 1\ny: 1\nrand: 1\nsin: 2\nadd: 2\n";
+
+/// Runs kroyer with the arguments, saving a small image to `name`, and gets the image
+fn render(name: &str, args: &[&str]) -> Vec<u8> {
+    let out = tmp_path(name);
+    let size = [
+        "--width",
+        "4",
+        "--height",
+        "4",
+        "--force",
+        "--out",
+        out.to_str().unwrap(),
+    ];
+    let output = kroyer(&[args, &size[..]].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    std::fs::read(out).unwrap()
+}
+
+#[test]
+fn images_render_again_from_their_seed() {
+    let grammar = tmp_file("from-image.kroyer", GRAMMAR);
+    let first = render("from-image-first.png", &[&grammar, "--seed", "0xface"]);
+    let path = tmp_path("from-image-first.png");
+    let again = render(
+        "from-image-again.png",
+        &["--from-image", path.to_str().unwrap()],
+    );
+    assert_eq!(again, first);
+}
+
+#[test]
+fn seeds_without_a_prefix_are_still_read_as_hex() {
+    let grammar = tmp_file("from-sidecar.kroyer", GRAMMAR);
+    let first = render(
+        "from-sidecar-first.png",
+        &[&grammar, "--seed", "0xface", "--metadata"],
+    );
+
+    // Older sidecars and images have seeds without the 0x prefix
+    let sidecar = tmp_path("from-sidecar-first.png.json");
+    let json = std::fs::read_to_string(&sidecar).unwrap();
+    assert!(json.contains("\"seed\": \"0xface\""), "{}", json);
+    std::fs::write(&sidecar, json.replace("\"0xface\"", "\"face\"")).unwrap();
+
+    let path = tmp_path("from-sidecar-first.png");
+    let again = render(
+        "from-sidecar-again.png",
+        &["--from-image", path.to_str().unwrap()],
+    );
+    assert_eq!(again, first);
+}
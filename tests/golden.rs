@@ -0,0 +1,48 @@
+mod common;
+
+use common::{fnv, kroyer, stderr, tmp_file};
+
+/// A grammar with rand nodes, so the golden images also pin how their values are drawn
+const GRAMMAR: &str = "\
+x: 1
+y: 1
+rand: 2
+literal: 1
+sin: 3
+mult: 2
+add: 2
+max: 1
+if: 1
+";
+
+/// The images of each grammar version, as the version, the seed and the hash of the PPM. If
+/// generation changes, these fail. A change to the images of a seed needs a new grammar version,
+/// with the old versions still giving these images. The images of version 1 are the ones the
+/// first release of kroyer rendered
+const GOLDEN: [(&str, &str, u64); 6] = [
+    ("1", "0x2a", 0x7faf6cc6181bd467),
+    ("1", "0xbeef", 0x1037af6181ae2793),
+    ("2", "0x2a", 0x2484593b9f503a9e),
+    ("2", "0xbeef", 0x674c351e774790f5),
+    ("3", "0x2a", 0x746d73a3160ad53a),
+    ("3", "0xbeef", 0x65200af36372ca92),
+];
+
+#[test]
+fn seeds_give_the_golden_images_of_each_version() {
+    let grammar = tmp_file("golden.kroyer", GRAMMAR);
+    for (version, seed, hash) in GOLDEN {
+        let output = kroyer(&[
+            &grammar, "--compat", version, "--seed", seed, "--width", "32", "--height", "24",
+            "--format", "ppm", "--out", "-",
+        ]);
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(
+            fnv(&output.stdout),
+            hash,
+            "version {} with seed {} gave another image. Generation changed without a new grammar version",
+            version,
+            seed
+        );
+    }
+}
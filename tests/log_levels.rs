@@ -0,0 +1,79 @@
+mod common;
+
+use common::{kroyer, stderr, tmp_file, tmp_path};
+
+/// A grammar with a label which isn't a node, so parsing it logs a warning
+const GRAMMAR: &str = "x: 1\ny: 1\nfoo: 1\n";
+
+/// Renders the grammar with the flags, and gets what kroyer logged
+fn logged(name: &str, flags: &[&str]) -> String {
+    let grammar = tmp_file(&format!("{}.kroyer", name), GRAMMAR);
+    let out = tmp_path(&format!("{}.png", name));
+    let size = [
+        "--width",
+        "4",
+        "--height",
+        "4",
+        "--seed",
+        "0x2a",
+        "--out",
+        out.to_str().unwrap(),
+    ];
+    let output = kroyer(&[&[grammar.as_str()], &size[..], flags].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    stderr(&output)
+}
+
+/// Gets the tags of the logged messages, in order, without repeats
+fn tags(log: &str) -> Vec<&str> {
+    let mut tags = vec![];
+    for tag in log
+        .lines()
+        .filter_map(|x| x.strip_prefix('[')?.split_once("]: "))
+    {
+        if !tags.contains(&tag.0) {
+            tags.push(tag.0);
+        }
+    }
+    tags
+}
+
+#[test]
+fn quiet_hides_everything_but_errors() {
+    assert_eq!(logged("log-quiet", &["--quiet"]), "");
+    let output = kroyer(&["--quiet", "-v"]);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+
+    let output = kroyer(&[&tmp_path("log-missing.kroyer").to_string_lossy(), "--quiet"]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(
+        stderr(&output).starts_with("[ERROR]: Failed to open grammar file"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn warnings_are_logged_by_default() {
+    let log = logged("log-default", &[]);
+    assert_eq!(tags(&log), ["WARNING"], "{}", log);
+    assert!(log.contains("not recognized label \"foo\""), "{}", log);
+}
+
+#[test]
+fn verbose_logs_what_kroyer_does() {
+    let log = logged("log-verbose", &["-v"]);
+    assert_eq!(tags(&log), ["INFO", "WARNING"], "{}", log);
+    assert!(log.contains("[INFO]: Using the grammar in"), "{}", log);
+    assert!(log.contains("[INFO]: Using the seed 0x2a\n"), "{}", log);
+    assert!(
+        log.contains("[INFO]: The AST has 3 nodes (R: 1, G: 1, B: 1)\n"),
+        "{}",
+        log
+    );
+    assert!(log.contains("[INFO]: Rendered the image to"), "{}", log);
+
+    let log = logged("log-debug", &["-vv"]);
+    assert_eq!(tags(&log), ["INFO", "WARNING", "DEBUG"], "{}", log);
+    assert!(log.contains("[DEBUG]: Rendering on"), "{}", log);
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use common::{kroyer, kroyer_with_stdin, stderr, tmp_file, tmp_path};
+
+/// A grammar which can be rendered
+const GRAMMAR: &str = "x: 1\ny: 1\nrand: 1\nsin: 2\nadd: 2\n";
+
+/// Runs kroyer with the grammar and the arguments, saving the image to `name`, and gets what it
+/// printed
+fn run(name: &str, args: &[&str]) -> String {
+    run_with_stdin(name, args, None)
+}
+
+/// Runs kroyer like `run()`, with `input` piped into STDIN if there is any
+fn run_with_stdin(name: &str, args: &[&str], input: Option<&str>) -> String {
+    let grammar = tmp_file(&format!("{}.kroyer", name), GRAMMAR);
+    let out = tmp_path(&format!("{}.png", name));
+    let size = [
+        "--width",
+        "4",
+        "--height",
+        "4",
+        "--out",
+        out.to_str().unwrap(),
+    ];
+    let args = [&[grammar.as_str()], &size[..], args].concat();
+    let output = match input {
+        Some(input) => kroyer_with_stdin(&args, input),
+        None => kroyer(&args),
+    };
+    assert!(output.status.success(), "{}", stderr(&output));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Gets the image which `run()` saved for `name`
+fn image(name: &str) -> Vec<u8> {
+    std::fs::read(tmp_path(&format!("{}.png", name))).unwrap()
+}
+
+#[test]
+fn hex_needs_a_prefix_to_be_a_number() {
+    assert_eq!(
+        run("seeds-hex", &["--seed", "0xface", "--dump-seed"]),
+        "0xface\n"
+    );
+    assert_eq!(
+        run("seeds-decimal", &["--seed", "64206", "--dump-seed"]),
+        "0xface\n"
+    );
+
+    let phrase = run("seeds-phrase", &["--seed", "face", "--dump-seed", "-v"]);
+    assert!(phrase.contains("SEED PHRASE: face\n"), "{}", phrase);
+    assert!(
+        phrase
+            .contains("SEED: 0x752788aed09669f6b2009aaed8e53ffcaa16ddf20fb250154cc7429fb7d98202\n"),
+        "{}",
+        phrase
+    );
+}
+
+#[test]
+fn dumped_seeds_can_be_piped_into_seed() {
+    let seed = run("seeds-dumped", &["--seed", "a phrase", "--dump-seed"]);
+    assert!(seed.starts_with("0x") && seed.ends_with('\n'), "{:?}", seed);
+
+    let piped = run_with_stdin("seeds-piped", &["--stdin-as", "seed"], Some(&seed));
+    assert!(piped.is_empty(), "{:?}", piped);
+    assert_eq!(image("seeds-piped"), image("seeds-dumped"));
+}
+
+#[test]
+fn unseeded_runs_report_the_seed_they_used() {
+    let seed = run("seeds-unseeded", &["--dump-seed"]);
+    let seed = seed.trim();
+    assert!(seed.starts_with("0x"), "{:?}", seed);
+
+    run("seeds-reported", &["--seed", seed]);
+    assert_eq!(image("seeds-reported"), image("seeds-unseeded"));
+
+    // The labelled dump of --verbose reports the same seed
+    let verbose = run("seeds-unseeded-verbose", &["--dump-seed", "-v"]);
+    let seed = verbose
+        .lines()
+        .find_map(|x| x.strip_prefix("SEED: "))
+        .unwrap_or_else(|| panic!("{:?}", verbose));
+    run("seeds-reported-verbose", &["--seed", seed]);
+    assert_eq!(
+        image("seeds-reported-verbose"),
+        image("seeds-unseeded-verbose")
+    );
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use common::{kroyer_with_stdin, stderr, tmp_file};
+
+const GRAMMAR: &str = "x: 1\ny: 1\nadd: 2\n";
+
+/// Renders a tiny PPM to STDOUT, with `input` piped into STDIN
+fn render(args: &[&str], input: &str) -> std::process::Output {
+    let size = [
+        "--width", "4", "--height", "4", "--format", "ppm", "--out", "-",
+    ];
+    kroyer_with_stdin(&[args, &size[..]].concat(), input)
+}
+
+#[test]
+fn piped_data_which_nothing_reads_is_an_error() {
+    let grammar = tmp_file("stdin-ignored.kroyer", GRAMMAR);
+    let output = render(&[&grammar, "--seed", "0x2a"], "RGB: x\n");
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("--stdin-as"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(output.stdout.is_empty());
+
+    // Whitespace isn't data, so it is ignored
+    let output = render(&[&grammar, "--seed", "0x2a"], "\n \n");
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+}
+
+#[test]
+fn piped_data_is_read_as_what_stdin_as_picks() {
+    let grammar = tmp_file("stdin-as.kroyer", GRAMMAR);
+    let ast = tmp_file("stdin-as.ast", "RGB: add(x, y)\n");
+
+    let from_file = render(&["--ast", &ast], "");
+    let from_stdin = render(&[&grammar, "--stdin-as", "ast"], "RGB: add(x, y)\n");
+    assert_eq!(from_stdin.status.code(), Some(0), "{}", stderr(&from_stdin));
+    assert_eq!(from_stdin.stdout, from_file.stdout);
+
+    let from_flag = render(&[&grammar, "--seed", "0x2a"], "");
+    let from_stdin = render(&[&grammar, "--stdin-as", "seed"], "0x2a\n");
+    assert_eq!(from_stdin.status.code(), Some(0), "{}", stderr(&from_stdin));
+    assert_eq!(from_stdin.stdout, from_flag.stdout);
+
+    // Contradicting flags fail before anything is rendered
+    let output = render(&[&grammar, "--stdin-as", "grammar"], GRAMMAR);
+    assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::{kroyer, stderr, tmp_file, tmp_path};
+
+/// A grammar which can be rendered
+const GRAMMAR: &str = "x: 1\ny: 1\nsin: 2\nadd: 2\n";
+
+/// The flags which print text, which must never be mixed into an image on STDOUT
+const DUMPS: [&str; 6] = [
+    "--dump-seed",
+    "--dump-ast",
+    "--dump-hash",
+    "--dump-grammar",
+    "--lint-ast",
+    "--ast-stats",
+];
+
+/// Renders a small image with every dump, and gets what was written to STDOUT and STDERR
+fn render_with_dumps(name: &str, args: &[&str]) -> (Vec<u8>, String) {
+    let grammar = tmp_file(name, GRAMMAR);
+    let size = ["--seed", "0x2a", "--width", "8", "--height", "8"];
+    let output = kroyer(&[&[grammar.as_str()], &size[..], &DUMPS[..], args].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    (output.stdout.clone(), stderr(&output))
+}
+
+#[test]
+fn dumps_go_to_stderr_when_netpbm_images_are_piped() {
+    // Netpbm images go to STDOUT without --out
+    let (stdout, stderr) = render_with_dumps("stdout-ppm.kroyer", &["--format", "ppm"]);
+    assert!(stdout.starts_with(b"P6\n8 8\n255\n"), "{:?}", &stdout[..16]);
+    assert_eq!(stdout.len(), 11 + 8 * 8 * 3);
+    assert!(stderr.contains("0x2a\n"), "{}", stderr);
+    assert!(stderr.contains("HASH: "), "{}", stderr);
+    assert!(stderr.contains("# CURRENT GRAMMAR"), "{}", stderr);
+
+    let (stdout, _) = render_with_dumps("stdout-pam.kroyer", &["--format", "pam"]);
+    assert!(stdout.starts_with(b"P7\n"));
+}
+
+#[test]
+fn dumps_go_to_stderr_when_video_is_piped() {
+    // Video streams go to STDOUT without --out
+    let (stdout, _) = render_with_dumps("stdout-y4m.kroyer", &["--format", "y4m", "--frames", "2"]);
+    let header = b"YUV4MPEG2 W8 H8 F";
+    assert!(stdout.starts_with(header), "{:?}", &stdout[..16]);
+    let header_len = stdout.split(|x| *x == b'\n').next().unwrap().len() + 1;
+    assert_eq!(stdout.len(), header_len + 2 * (6 + 8 * 8 * 3));
+}
+
+#[test]
+fn dumps_go_to_stderr_when_the_out_is_stdout() {
+    let (stdout, _) = render_with_dumps("stdout-png.kroyer", &["--out", "-"]);
+    assert!(stdout.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+    let (stdout, _) = render_with_dumps("stdout-gif.kroyer", &["--out", "-", "--format", "gif"]);
+    assert!(stdout.starts_with(b"GIF89a"));
+
+    let also = tmp_path("stdout-also.gif");
+    let also = also.to_str().unwrap();
+    let (stdout, _) =
+        render_with_dumps("stdout-also.kroyer", &["--out", also, "--also-still", "-"]);
+    assert!(stdout.starts_with(b"\x89PNG\r\n\x1a\n"));
+}
+
+#[test]
+fn dumps_go_to_stdout_when_the_image_is_saved() {
+    let out = tmp_path("stdout-saved.png");
+    let (stdout, stderr) =
+        render_with_dumps("stdout-saved.kroyer", &["--out", out.to_str().unwrap()]);
+    let stdout = String::from_utf8(stdout).unwrap();
+    assert!(stdout.starts_with("0x2a\n"), "{}", stdout);
+    assert!(!stderr.contains("0x2a"), "{}", stderr);
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::{kroyer, stderr, tmp_file};
+
+/// Renders an image to STDOUT with the arguments and the amount of threads
+fn render(args: &[&str], threads: &str) -> Vec<u8> {
+    let output = kroyer(&[args, &["--threads", threads, "--out", "-"]].concat());
+    assert!(output.status.success(), "{}", stderr(&output));
+    output.stdout
+}
+
+#[test]
+fn stills_are_the_same_on_any_amount_of_threads() {
+    // The default grammar has no rand nodes. STDIN isn't a terminal in tests, so it would be
+    // read as the grammar, unless a grammar file is given
+    let grammar = tmp_file(
+        "threads-default.kroyer",
+        &String::from_utf8(kroyer(&["--dump-default-grammar"]).stdout).unwrap(),
+    );
+    for seed in ["0x1", "0x2a", "0xbeef"] {
+        let args = [
+            &grammar, "--seed", seed, "--width", "48", "--height", "40", "--format", "ppm",
+        ];
+        let single = render(&args, "1");
+        assert!(!is_flat(&single), "seed {} gave a flat image", seed);
+        assert_eq!(single, render(&args, "4"), "seed {}", seed);
+        assert_eq!(single, render(&args, "0"), "seed {}", seed);
+    }
+}
+
+/// Checks if every pixel of a 48x40 PPM image has the same color
+fn is_flat(ppm: &[u8]) -> bool {
+    let pixels = &ppm[ppm.len() - 48 * 40 * 3..];
+    pixels.chunks(3).all(|x| x == &pixels[..3])
+}
+
+#[test]
+fn gif_frames_are_the_same_on_any_amount_of_threads() {
+    // The grammar uses t, so every frame is different, and has no rand nodes
+    let grammar = tmp_file(
+        "threads-t.kroyer",
+        "x: 1\ny: 1\nt: 2\nliteral: 1\nsin: 3\nmult: 2\nadd: 2\nmax: 1\nif: 1\n",
+    );
+    for seed in ["0x3", "0x1f"] {
+        let args = [
+            &grammar, "--seed", seed, "--width", "24", "--height", "24", "--frames", "12",
+            "--format", "gif",
+        ];
+        let two = render(&args, "2");
+        assert_eq!(two, render(&args, "8"), "seed {}", seed);
+    }
+}